@@ -0,0 +1,154 @@
+//! Optimized Asset Cache
+//! Keeps recent combined CSS/JS by filename so `/api/v1/resource/:filename` can serve them
+//! (e.g. to a CDN pulling from this server) instead of the plugin writing them to disk
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct CachedAsset {
+    content: Arc<str>,
+    expires: Instant,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct Assets {
+    entries: HashMap<String, CachedAsset>,
+    bytes: usize,
+}
+
+impl Assets {
+    fn remove(&mut self, filename: &str) {
+        if let Some(asset) = self.entries.remove(filename) {
+            self.bytes -= asset.content.len();
+        }
+    }
+}
+
+/// In-memory store of optimized assets keyed by their content-hash filename, bounded by a TTL
+/// and a byte budget (least recently used assets are evicted first)
+#[derive(Clone)]
+pub struct AssetCache {
+    assets: Arc<Mutex<Assets>>,
+    ttl: Duration,
+    max_bytes: usize,
+}
+
+impl AssetCache {
+    /// A zero `ttl` or `max_bytes` disables the cache (nothing is stored)
+    pub fn new(ttl: Duration, max_bytes: usize) -> Self {
+        Self {
+            assets: Arc::new(Mutex::new(Assets::default())),
+            ttl,
+            max_bytes,
+        }
+    }
+
+    /// Store `content` under `filename` for the TTL, dropping expired entries and then the least
+    /// recently used ones until it fits the byte budget. Assets larger than the budget are not stored
+    pub fn insert(&self, filename: &str, content: &str) {
+        if self.ttl.is_zero() || content.len() > self.max_bytes {
+            return;
+        }
+        let now = Instant::now();
+        let mut assets = self.assets.lock().unwrap();
+        assets.remove(filename);
+        let expired: Vec<String> = assets.entries.iter()
+            .filter(|(_, asset)| asset.expires <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in expired {
+            assets.remove(&name);
+        }
+        while assets.bytes + content.len() > self.max_bytes {
+            let Some(oldest) = assets.entries.iter().min_by_key(|(_, asset)| asset.last_used).map(|(name, _)| name.clone()) else { break };
+            assets.remove(&oldest);
+        }
+        assets.bytes += content.len();
+        assets.entries.insert(filename.to_string(), CachedAsset {
+            content: Arc::from(content),
+            expires: now + self.ttl,
+            last_used: now,
+        });
+    }
+
+    /// Store the combined stylesheet and script of a resource optimization
+    pub fn insert_resources(&self, resources: &crate::handlers::ResourcesResponse) {
+        if let Some(css) = &resources.combined_css {
+            self.insert(&resources.combined_css_filename, css);
+        }
+        if let Some(js) = &resources.combined_js {
+            self.insert(&resources.combined_js_filename, js);
+        }
+    }
+
+    /// The asset stored under `filename`, unless it has expired
+    pub fn get(&self, filename: &str) -> Option<Arc<str>> {
+        let now = Instant::now();
+        let mut assets = self.assets.lock().unwrap();
+        match assets.entries.get_mut(filename) {
+            Some(asset) if asset.expires > now => {
+                asset.last_used = now;
+                Some(asset.content.clone())
+            }
+            Some(_) => {
+                assets.remove(filename);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+/// `Content-Type` for a served asset, by extension (None for anything but CSS/JS)
+pub fn content_type(filename: &str) -> Option<&'static str> {
+    match filename.rsplit_once('.')?.1 {
+        "css" => Some("text/css; charset=utf-8"),
+        "js" => Some("application/javascript; charset=utf-8"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_expire_after_ttl() {
+        let cache = AssetCache::new(Duration::from_millis(20), 1024);
+        cache.insert("styles.0badf00d.min.css", ".a{color:red}");
+        assert_eq!(cache.get("styles.0badf00d.min.css").as_deref(), Some(".a{color:red}"));
+        assert!(cache.get("scripts.0badf00d.min.js").is_none());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(cache.get("styles.0badf00d.min.css").is_none());
+
+        let disabled = AssetCache::new(Duration::ZERO, 1024);
+        disabled.insert("styles.0badf00d.min.css", ".a{color:red}");
+        assert!(disabled.get("styles.0badf00d.min.css").is_none());
+    }
+
+    #[test]
+    fn test_least_recently_used_evicted_over_budget() {
+        let cache = AssetCache::new(Duration::from_secs(60), 10);
+        cache.insert("a.css", "aaaa");
+        std::thread::sleep(Duration::from_millis(2));
+        cache.insert("b.css", "bbbb");
+        std::thread::sleep(Duration::from_millis(2));
+        // Reading a.css makes b.css the least recently used
+        assert!(cache.get("a.css").is_some());
+        cache.insert("c.css", "cccc");
+
+        assert!(cache.get("b.css").is_none());
+        assert_eq!(cache.get("a.css").as_deref(), Some("aaaa"));
+        assert_eq!(cache.get("c.css").as_deref(), Some("cccc"));
+
+        // Replacing an entry frees its old bytes; oversized assets are never stored
+        cache.insert("c.css", "cc");
+        assert_eq!(cache.assets.lock().unwrap().bytes, 6);
+        cache.insert("big.css", "0123456789a");
+        assert!(cache.get("big.css").is_none());
+        assert!(cache.get("a.css").is_some());
+    }
+}
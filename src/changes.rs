@@ -0,0 +1,120 @@
+//! Element-level change records
+//! Passes tag every element they record a change for with a marker attribute, so the
+//! record can be matched to the element again once all later passes have run.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::html_utils::{get_attribute, remove_attribute, rewrite_start_tags, set_attribute};
+
+/// Marker attribute carrying the ids of the changes recorded for an element (reserved name)
+const MARKER: &str = "data-htmlwp-change";
+
+/// Source of change ids; only uniqueness matters, not the values
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// One element-level change, for clients that present or undo changes individually
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct Change {
+    /// What was done: `alt_added`, `script_deferred`, `image_lazy_loaded`, `canonical_added`, `canonical_updated`
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// The URL the element refers to (image/script `src`, canonical `href`)
+    pub target: String,
+    /// Markup before the pass and in the final output (`before` is empty for added elements)
+    pub before: String,
+    pub after: String,
+    /// Negative when the markup grew
+    pub bytes_saved: i64,
+    /// Matches the element's marker until `finalize` runs
+    #[serde(skip)]
+    id: u64,
+}
+
+impl Change {
+    pub fn new(kind: &'static str, target: impl Into<String>, before: impl Into<String>, after: impl Into<String>) -> Self {
+        let (before, after) = (before.into(), after.into());
+        Self {
+            kind,
+            target: target.into(),
+            bytes_saved: before.len() as i64 - after.len() as i64,
+            before,
+            after,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// `tag` marked as the element this change applies to; the pass emits this instead of `tag`
+    pub fn mark(&self, tag: &str) -> String {
+        let ids = match get_attribute(tag, MARKER) {
+            Some(ids) => format!("{} {}", ids, self.id),
+            None => self.id.to_string(),
+        };
+        set_attribute(tag, MARKER, &ids)
+    }
+
+    fn set_after(&mut self, after: &str) {
+        self.after = after.to_string();
+        self.bytes_saved = self.before.len() as i64 - after.len() as i64;
+    }
+}
+
+/// Point every change's `after` at its element in the finished `html` and remove the markers.
+/// Changes whose element a later pass removed are dropped.
+pub fn finalize(html: &mut String, changes: &mut Vec<Change>) {
+    if changes.is_empty() {
+        return;
+    }
+
+    let mut found = std::collections::HashSet::new();
+    *html = rewrite_start_tags(html, |_, tag| {
+        let ids = get_attribute(tag, MARKER)?;
+        let clean = remove_attribute(tag, MARKER);
+        for id in ids.split_ascii_whitespace().filter_map(|id| id.parse::<u64>().ok()) {
+            if let Some(change) = changes.iter_mut().find(|change| change.id == id) {
+                change.set_after(&clean);
+                found.insert(id);
+            }
+        }
+        Some(clean)
+    });
+    changes.retain(|change| found.contains(&change.id));
+}
+
+/// Bytes the markers add to `html`
+pub fn marker_len(html: &str) -> usize {
+    let mut len = 0;
+    rewrite_start_tags(html, |_, tag| {
+        if get_attribute(tag, MARKER).is_some() {
+            len += tag.len() - remove_attribute(tag, MARKER).len();
+        }
+        None
+    });
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finalize_reports_final_markup() {
+        let lazy = Change::new("image_lazy_loaded", "/a.jpg", "<img src=/a.jpg>", "<img loading=\"lazy\" src=/a.jpg>");
+        let alt = Change::new("alt_added", "/a.jpg", "<img loading=\"lazy\" src=/a.jpg>", "<img alt=\"A\" loading=\"lazy\" src=/a.jpg>");
+        let tag = lazy.mark("<img loading=\"lazy\" src=/a.jpg>");
+        let tag = alt.mark(&tag.replacen("<img", "<img alt=\"A\"", 1));
+        let mut html = format!("<p>{} decoding</p>", tag.replacen("<img", "<img decoding=\"async\"", 1));
+        let removed = Change::new("script_deferred", "/t.js", "<script src=/t.js>", "<script defer src=/t.js>");
+
+        let mut changes = vec![lazy, alt, removed];
+        let final_tag = "<img decoding=\"async\" alt=\"A\" loading=\"lazy\" src=/a.jpg>";
+        let marked_len = html.len();
+        let markers = marker_len(&html);
+        finalize(&mut html, &mut changes);
+
+        assert_eq!(html.len(), marked_len - markers);
+        assert_eq!(html, format!("<p>{} decoding</p>", final_tag));
+        assert_eq!(changes.len(), 2, "the script's element is gone");
+        assert!(changes.iter().all(|change| change.after == final_tag));
+        assert_eq!(changes[0].bytes_saved, "<img src=/a.jpg>".len() as i64 - final_tag.len() as i64);
+    }
+}
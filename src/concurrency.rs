@@ -0,0 +1,143 @@
+//! Concurrency Limit
+//! Caps simultaneous in-flight optimize requests; excess requests get 503 instead of queuing
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::config::AppState;
+use crate::error::AppError;
+
+/// Shared permit pool for optimize requests
+#[derive(Clone)]
+pub struct ConcurrencyLimiter {
+    /// `None` when unlimited
+    permits: Option<Arc<Semaphore>>,
+}
+
+impl ConcurrencyLimiter {
+    /// `max_in_flight == 0` disables the limit
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            permits: (max_in_flight > 0).then(|| Arc::new(Semaphore::new(max_in_flight))),
+        }
+    }
+}
+
+/// The permit of a limited request, as a request extension. Handlers whose work outlives the
+/// response (WebSocket sessions, streamed bulk jobs) move a clone into that work, so the permit
+/// is only released once it finishes
+#[derive(Clone, Default)]
+pub struct InFlight {
+    /// Only held, never read: dropping the last clone releases the permit
+    _permit: Option<Arc<OwnedSemaphorePermit>>,
+}
+
+/// Only the optimize endpoints do expensive work; health, metrics, job streams and cached
+/// assets (`/api/v1/resource/:filename`) bypass the limit
+fn is_limited(path: &str) -> bool {
+    path.starts_with("/api/v1/optimize")
+}
+
+/// Middleware holding a permit for the lifetime of each optimize request (and of any work the
+/// handler hands its `InFlight` to)
+pub async fn enforce(State(state): State<AppState>, mut request: Request, next: Next) -> Response {
+    let Some(permits) = state.concurrency.permits.as_ref().filter(|_| is_limited(request.uri().path())) else {
+        return next.run(request).await;
+    };
+
+    match permits.clone().try_acquire_owned() {
+        Ok(permit) => {
+            // The request (and its extensions) is dropped once the handler has extracted its
+            // arguments, so the middleware keeps a reference for the handler's duration
+            let permit = Arc::new(permit);
+            request.extensions_mut().insert(InFlight { _permit: Some(permit.clone()) });
+            let response = next.run(request).await;
+            drop(permit);
+            response
+        }
+        Err(_) => {
+            tracing::warn!(path = request.uri().path(), "Concurrency limit reached, rejecting request");
+            AppError::ServiceUnavailable("Server busy, retry shortly".to_string()).into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_state;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_excess_concurrent_requests_get_503() {
+        let state = AppState {
+            concurrency: ConcurrencyLimiter::new(2),
+            ..test_state()
+        };
+        let slow = || async {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            "ok"
+        };
+        let app = axum::Router::new()
+            .route("/health", axum::routing::get(slow))
+            .route("/api/v1/resource/:filename", axum::routing::get(slow))
+            .route("/api/v1/optimize", axum::routing::post(slow))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), enforce))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let optimize = (0..5).map(|_| client.post(format!("http://{}/api/v1/optimize", addr)).send());
+        let health = (0..5).map(|_| client.get(format!("http://{}/health", addr)).send());
+        let assets = (0..5).map(|_| client.get(format!("http://{}/api/v1/resource/styles.0badf00d.min.css", addr)).send());
+        let (optimize, health, assets) = tokio::join!(
+            futures::future::join_all(optimize),
+            futures::future::join_all(health),
+            futures::future::join_all(assets),
+        );
+
+        let statuses: Vec<u16> = optimize.into_iter().map(|r| r.unwrap().status().as_u16()).collect();
+        assert_eq!(statuses.iter().filter(|&&s| s == 200).count(), 2);
+        assert_eq!(statuses.iter().filter(|&&s| s == 503).count(), 3);
+        assert!(health.into_iter().all(|r| r.unwrap().status() == 200));
+        assert!(assets.into_iter().all(|r| r.unwrap().status() == 200));
+    }
+
+    #[tokio::test]
+    async fn test_permit_follows_work_past_the_response() {
+        let state = AppState {
+            api_key: None,
+            concurrency: ConcurrencyLimiter::new(1),
+            ..test_state()
+        };
+        // Like a streamed bulk job: respond at once, keep working in the background
+        let background = |axum::Extension(in_flight): axum::Extension<InFlight>| async move {
+            tokio::spawn(async move {
+                let _in_flight = in_flight;
+                tokio::time::sleep(Duration::from_millis(300)).await;
+            });
+            "started"
+        };
+        let app = axum::Router::new()
+            .route("/api/v1/optimize/bulk", axum::routing::post(background))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), enforce))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let url = format!("http://{}/api/v1/optimize/bulk", addr);
+        assert_eq!(client.post(&url).send().await.unwrap().status(), 200);
+        assert_eq!(client.post(&url).send().await.unwrap().status(), 503, "first job still running");
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(client.post(&url).send().await.unwrap().status(), 200);
+    }
+}
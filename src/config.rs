@@ -1,20 +1,82 @@
 //! Configuration module
 
 use std::env;
+use std::time::Duration;
+
+/// Default endpoint used by the readiness probe (returns an empty 204)
+const DEFAULT_READY_PROBE_URL: &str = "https://www.gstatic.com/generate_204";
 
 pub struct Config {
     pub host: String,
     pub port: u16,
     pub api_key: Option<String>,
+    /// URL the readiness check sends a HEAD request to
+    pub ready_probe_url: String,
+    /// Readiness probe timeout in milliseconds
+    pub ready_probe_timeout_ms: u64,
+    /// Sustained requests per minute per client IP (0, the default, disables limiting)
+    pub rate_limit_per_minute: u32,
+    /// Requests a client may send at once before the per-minute rate applies
+    pub rate_limit_burst: u32,
+    /// Optimize requests processed at once; further ones get 503 (0 disables the cap)
+    pub max_concurrent_optimizations: usize,
+    /// Asset downloads (images, CSS, JS) in flight at once across all requests (0 disables the cap)
+    pub max_concurrent_downloads: usize,
+    /// Minimum milliseconds between asset downloads from the same host (0 disables)
+    pub crawl_delay_ms: u64,
+    /// Seconds combined CSS/JS stay servable from `/api/v1/resource/:filename` (0 disables)
+    pub asset_cache_ttl_secs: u64,
+    /// Bytes of combined CSS/JS kept for `/api/v1/resource/:filename` (0 disables)
+    pub asset_cache_max_bytes: usize,
+    /// Browser origins allowed by CORS (empty = any origin)
+    pub cors_origins: Vec<String>,
+    /// HTTP/2 and keep-alive tuning
+    pub server: crate::server::ServerSettings,
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub api_key: Option<String>,
+    pub jobs: crate::jobs::JobRegistry,
+    /// Shared outbound HTTP client
+    pub http_client: reqwest::Client,
+    pub ready_probe_url: String,
+    pub ready_probe_timeout: Duration,
+    /// Renders the Prometheus exposition for `/metrics`
+    pub metrics: metrics_exporter_prometheus::PrometheusHandle,
+    pub rate_limiter: crate::rate_limit::RateLimiter,
+    pub concurrency: crate::concurrency::ConcurrencyLimiter,
+    /// Outbound download cap; the same limiter is installed process-wide for the downloaders
+    pub downloads: crate::url_utils::DownloadLimiter,
+    /// Recent combined CSS/JS served by `/api/v1/resource/:filename`
+    pub assets: crate::asset_cache::AssetCache,
+}
+
+/// State for handler and middleware tests: API key `secret`, no limits, nothing cached.
+/// Tests override single fields with `AppState { field, ..test_state() }`
+#[cfg(test)]
+pub(crate) fn test_state() -> AppState {
+    AppState {
+        api_key: Some("secret".to_string()),
+        jobs: crate::jobs::JobRegistry::new(),
+        http_client: reqwest::Client::new(),
+        ready_probe_url: String::new(),
+        ready_probe_timeout: Duration::from_secs(1),
+        metrics: crate::metrics::install(),
+        rate_limiter: crate::rate_limit::RateLimiter::new(0, 0),
+        concurrency: crate::concurrency::ConcurrencyLimiter::new(0),
+        downloads: crate::url_utils::DownloadLimiter::new(0),
+        assets: crate::asset_cache::AssetCache::new(Duration::ZERO, 0),
+    }
 }
 
 impl Config {
     pub fn from_env() -> Self {
+        let rate_limit_per_minute = env::var("RATE_LIMIT_PER_MINUTE")
+            .ok()
+            .and_then(|r| r.parse().ok())
+            .unwrap_or(0);
+
         Self {
             host: env::var("HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
             port: env::var("PORT")
@@ -22,6 +84,41 @@ impl Config {
                 .and_then(|p| p.parse().ok())
                 .unwrap_or(3000),
             api_key: env::var("API_KEY").ok(),
+            ready_probe_url: env::var("READY_PROBE_URL")
+                .unwrap_or_else(|_| DEFAULT_READY_PROBE_URL.to_string()),
+            ready_probe_timeout_ms: env::var("READY_PROBE_TIMEOUT_MS")
+                .ok()
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(3000),
+            rate_limit_per_minute,
+            rate_limit_burst: env::var("RATE_LIMIT_BURST")
+                .ok()
+                .and_then(|b| b.parse().ok())
+                .unwrap_or(rate_limit_per_minute),
+            max_concurrent_optimizations: env::var("MAX_CONCURRENT_OPTIMIZATIONS")
+                .ok()
+                .and_then(|m| m.parse().ok())
+                .unwrap_or(32),
+            max_concurrent_downloads: env::var("MAX_CONCURRENT_DOWNLOADS")
+                .ok()
+                .and_then(|m| m.parse().ok())
+                .unwrap_or(16),
+            crawl_delay_ms: env::var("CRAWL_DELAY_MS")
+                .ok()
+                .and_then(|d| d.parse().ok())
+                .unwrap_or(0),
+            asset_cache_ttl_secs: env::var("ASSET_CACHE_TTL_SECS")
+                .ok()
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(900),
+            asset_cache_max_bytes: env::var("ASSET_CACHE_MAX_BYTES")
+                .ok()
+                .and_then(|b| b.parse().ok())
+                .unwrap_or(64 * 1024 * 1024),
+            cors_origins: env::var("CORS_ORIGINS")
+                .map(|origins| crate::cors::parse_origins(&origins))
+                .unwrap_or_default(),
+            server: crate::server::ServerSettings::from_env(),
         }
     }
 
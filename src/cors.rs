@@ -0,0 +1,77 @@
+//! CORS Policy
+//! Any origin by default; `CORS_ORIGINS` restricts browsers to an allowlist (with credentials)
+
+use axum::http::HeaderValue;
+use tower_http::cors::{AllowHeaders, AllowMethods, Any, CorsLayer};
+
+/// Build the CORS layer: an empty list (or `*`) allows any origin without credentials,
+/// otherwise only the listed origins get `Access-Control-Allow-Origin`, with credentials allowed
+pub fn layer(origins: &[String]) -> CorsLayer {
+    if origins.is_empty() || origins.iter().any(|origin| origin == "*") {
+        return CorsLayer::new()
+            .allow_origin(Any)
+            .allow_methods(Any)
+            .allow_headers(Any);
+    }
+
+    let allowed: Vec<HeaderValue> = origins
+        .iter()
+        .filter_map(|origin| match HeaderValue::from_str(origin.trim_end_matches('/')) {
+            Ok(value) => Some(value),
+            Err(_) => {
+                tracing::warn!(origin = %origin, "Ignoring invalid CORS origin");
+                None
+            }
+        })
+        .collect();
+
+    // Credentials rule out `*` for methods and headers, so mirror the preflight instead
+    CorsLayer::new()
+        .allow_origin(allowed)
+        .allow_methods(AllowMethods::mirror_request())
+        .allow_headers(AllowHeaders::mirror_request())
+        .allow_credentials(true)
+}
+
+/// Parse the comma-separated `CORS_ORIGINS` value
+pub fn parse_origins(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_disallowed_origin_gets_no_allow_origin_header() {
+        let app = axum::Router::new()
+            .route("/health", axum::routing::get(|| async { "ok" }))
+            .layer(layer(&parse_origins("https://shop.example.com, https://example.com/")));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let get = |origin: &'static str| client.get(format!("http://{}/health", addr)).header("Origin", origin).send();
+
+        let allowed = get("https://example.com").await.unwrap();
+        assert_eq!(allowed.headers()["access-control-allow-origin"], "https://example.com");
+        assert_eq!(allowed.headers()["access-control-allow-credentials"], "true");
+
+        let denied = get("https://evil.example.net").await.unwrap();
+        assert!(denied.headers().get("access-control-allow-origin").is_none());
+    }
+
+    #[test]
+    fn test_default_allows_any_origin() {
+        assert!(parse_origins(" ,").is_empty());
+        // Building must not panic (credentials with wildcards would)
+        let _ = layer(&[]);
+        let _ = layer(&["*".to_string()]);
+    }
+}
@@ -5,6 +5,7 @@ use scraper::{Html, Selector};
 use std::collections::HashSet;
 use lightningcss::stylesheet::{StyleSheet, ParserOptions, MinifyOptions, PrinterOptions};
 use lightningcss::rules::CssRule;
+use lightningcss::targets::{Browsers, Targets};
 
 /// CSS optimization result
 pub struct CssResult {
@@ -26,12 +27,15 @@ pub struct CssOptimizer {
     used_selectors: HashSet<String>,
     /// Class whitelist patterns (page builders, etc)
     whitelist_patterns: Vec<String>,
+    /// Selector substrings that are always removed, even when used
+    blocklist_patterns: Vec<String>,
 }
 
 impl CssOptimizer {
     pub fn new() -> Self {
         Self {
             used_selectors: HashSet::new(),
+            blocklist_patterns: Vec::new(),
             whitelist_patterns: vec![
                 // WordPress core
                 "wp-".to_string(),
@@ -99,6 +103,74 @@ impl CssOptimizer {
                 self.used_selectors.insert(el.name().to_string());
             }
         }
+
+        // Runtime classes declared by the theme: data-keep-css=".menu-open .modal-active"
+        if let Ok(selector) = Selector::parse("[data-keep-css]") {
+            for element in document.select(&selector) {
+                if let Some(hint) = element.value().attr("data-keep-css") {
+                    for name in hint.split_whitespace() {
+                        if name.starts_with('.') || name.starts_with('#') {
+                            self.used_selectors.insert(name.to_string());
+                        } else {
+                            self.used_selectors.insert(format!(".{}", name));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Treat classes and IDs referenced from inline `<script>` blocks as used
+    pub fn extract_inline_script_classes(&mut self, html: &str) {
+        let document = Html::parse_document(html);
+        if let Ok(selector) = Selector::parse("script:not([src])") {
+            for script in document.select(&selector) {
+                let js: String = script.text().collect();
+                self.extract_js_class_references(&js);
+            }
+        }
+    }
+
+    /// Treat classes and IDs referenced from JavaScript as used
+    /// (also usable with external JS once it has been downloaded)
+    /// Picks up string arguments of `classList.*()` / jQuery `*Class()` / `className =`,
+    /// and `.class` / `#id` tokens inside selector-like string literals
+    pub fn extract_js_class_references(&mut self, js: &str) {
+        const CLASS_CALLS: [&str; 9] = [
+            "classlist.add(", "classlist.remove(", "classlist.toggle(", "classlist.replace(",
+            "addclass(", "removeclass(", "toggleclass(", "classname =", "classname=",
+        ];
+
+        for (literal, before) in js_string_literals(js) {
+            let context = before.trim_end().to_lowercase();
+            let is_class_argument = CLASS_CALLS.iter().any(|call| context.ends_with(call))
+                || (context.ends_with(',') && CLASS_CALLS[..6].iter().any(|call| {
+                    context.rfind(call).is_some_and(|pos| !context[pos..].contains(')'))
+                }));
+
+            if is_class_argument {
+                for class in literal.split_whitespace().filter(|c| is_css_identifier(c)) {
+                    self.used_selectors.insert(format!(".{}", class));
+                }
+                continue;
+            }
+
+            // Selector strings: querySelector('.menu .item'), $('#nav')
+            for (index, prefix) in literal.match_indices(['.', '#']) {
+                let name: String = literal[index + 1..]
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+                    .collect();
+                if is_css_identifier(&name) {
+                    self.used_selectors.insert(format!("{}{}", prefix, name));
+                }
+            }
+        }
+    }
+
+    /// Selectors collected so far (`.class`, `#id` and tag names)
+    pub fn used_selectors(&self) -> &HashSet<String> {
+        &self.used_selectors
     }
 
     /// Static helper: Extract used selectors from HTML and return as Vec
@@ -117,12 +189,25 @@ impl CssOptimizer {
         optimizer
     }
 
+    /// Always treat selectors containing any of `patterns` as unused (takes precedence over the whitelist)
+    pub fn set_blocklist(&mut self, patterns: &[String]) {
+        self.blocklist_patterns = patterns.iter()
+            .map(|p| p.trim().to_lowercase())
+            .filter(|p| !p.is_empty())
+            .collect();
+    }
+
     /// Check if a selector is used or whitelisted
     fn is_selector_used(&self, selector: &str) -> bool {
         let selector_trimmed = selector.trim();
-        
-        // Check whitelist patterns
         let selector_lower = selector_trimmed.to_lowercase();
+
+        // Blocklisted selectors go regardless of usage
+        if self.blocklist_patterns.iter().any(|pattern| selector_lower.contains(pattern.as_str())) {
+            return false;
+        }
+
+        // Check whitelist patterns
         for pattern in &self.whitelist_patterns {
             if selector_lower.contains(pattern) {
                 return true;
@@ -215,6 +300,11 @@ impl CssOptimizer {
 
     /// Remove unused CSS rules - aggressive tree-shaking
     pub fn remove_unused_css(&self, css: &str) -> Result<String, String> {
+        self.remove_unused_css_with_report(css).map(|(result, _)| result)
+    }
+
+    /// Same as `remove_unused_css`, also returning the selectors of the removed rules
+    pub fn remove_unused_css_with_report(&self, css: &str) -> Result<(String, Vec<String>), String> {
         // Parse CSS into rules using a simple regex-based approach
         // This handles: .class { }, #id { }, tag { }, .class .child { }
         let mut result = String::with_capacity(css.len());
@@ -224,6 +314,7 @@ impl CssOptimizer {
         let original_len = css.len();
         let mut removed_rules = 0;
         let mut kept_rules = 0;
+        let mut removed_selectors = Vec::new();
 
         while !remaining.is_empty() {
             // Look for start of a rule (selector {) or at-rule (@)
@@ -258,6 +349,7 @@ impl CssOptimizer {
                     } else {
                         // Skip this rule - it's unused
                         removed_rules += 1;
+                        removed_selectors.push(selector.split_whitespace().collect::<Vec<_>>().join(" "));
                     }
                     
                     remaining = &remaining[full_rule.len()..];
@@ -273,6 +365,9 @@ impl CssOptimizer {
             }
         }
 
+        // Rules whose declarations were all stripped, and @media blocks left empty
+        let result = remove_empty_rules(&result);
+
         tracing::debug!(
             "CSS tree-shake: {} rules removed, {} kept, {}% reduction",
             removed_rules,
@@ -284,7 +379,7 @@ impl CssOptimizer {
             }
         );
 
-        Ok(result)
+        Ok((result, removed_selectors))
     }
 
     /// Extract at-rule including nested braces
@@ -364,27 +459,566 @@ impl CssOptimizer {
     }
 }
 
-/// Minify CSS using lightningcss
-pub fn minify_css(css: &str) -> Result<String, String> {
+/// Minify CSS using lightningcss, adding the vendor prefixes and fallbacks `targets` need
+/// (`Targets::default()` for none)
+pub fn minify_css(css: &str, targets: Targets) -> Result<String, String> {
     let opts = ParserOptions::default();
     let printer_opts = PrinterOptions {
         minify: true,
+        targets,
         ..Default::default()
     };
 
-    let stylesheet = StyleSheet::parse(css, opts)
+    let mut stylesheet = StyleSheet::parse(css, opts)
         .map_err(|e| format!("CSS parse error: {:?}", e))?;
 
+    // Structural optimizations (merging longhands, dropping duplicates) beyond the printer's whitespace removal
+    stylesheet.minify(MinifyOptions { targets, ..MinifyOptions::default() })
+        .map_err(|e| format!("CSS minify error: {:?}", e))?;
+
     let result = stylesheet.to_css(printer_opts)
         .map_err(|e| format!("CSS print error: {:?}", e))?;
 
     Ok(result.code)
 }
 
+/// String literals in a script with the source text preceding each one
+fn js_string_literals(js: &str) -> Vec<(&str, &str)> {
+    let mut literals = Vec::new();
+    let mut chars = js.char_indices();
+
+    while let Some((start, c)) = chars.next() {
+        if c != '\'' && c != '"' && c != '`' {
+            continue;
+        }
+        let mut end = None;
+        while let Some((pos, next)) = chars.next() {
+            if next == '\\' {
+                chars.next();
+            } else if next == c {
+                end = Some(pos);
+                break;
+            }
+        }
+        let Some(end) = end else { break };
+        literals.push((&js[start + 1..end], &js[..start]));
+    }
+
+    literals
+}
+
+/// Class, ID and attribute names a stylesheet can match: `.class`, `#id` and `[attr`
+/// Over-inclusive by design (a `#fff` color counts as an ID) - callers use it to decide what is safe to drop
+pub fn referenced_names(css: &str) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for (index, prefix) in css.match_indices(['.', '#', '[']) {
+        let name: String = css[index + 1..]
+            .chars()
+            .skip_while(|c| prefix == "[" && c.is_whitespace())
+            .take_while(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+            .collect();
+        if is_css_identifier(&name) {
+            names.insert(format!("{}{}", prefix, name.to_lowercase()));
+        }
+    }
+    names
+}
+
+/// Whether `name` is a plausible class/ID name (not a number, not empty)
+fn is_css_identifier(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '-')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Family-name fragments of icon fonts, which are referenced from pseudo-element rules that
+/// tree-shaking can't see through
+const ICON_FONT_HINTS: [&str; 9] = ["icon", "awesome", "dashicons", "glyph", "eicons", "fontello", "material", "genericons", "fa-"];
+
+/// Lowercased values of every `font`/`font-family` declaration outside `@font-face` rules
+pub fn font_family_references(css: &str) -> String {
+    let mut references = String::new();
+    for rule in split_top_level_rules(css) {
+        let Some(open) = rule.find('{') else { continue };
+        let prelude = rule[..open].trim().to_ascii_lowercase();
+        let body = rule[open + 1..].strip_suffix('}').unwrap_or(&rule[open + 1..]);
+        if prelude.starts_with("@font-face") {
+            continue;
+        }
+        if prelude.starts_with('@') {
+            references.push_str(&font_family_references(body));
+            continue;
+        }
+        for declaration in body.split(';') {
+            if let Some((name, value)) = declaration.split_once(':') {
+                if matches!(name.trim().to_ascii_lowercase().as_str(), "font" | "font-family") {
+                    references.push_str(&value.to_ascii_lowercase());
+                    references.push('\n');
+                }
+            }
+        }
+    }
+    references
+}
+
+/// `font_family_references` of the page's inline `<style>` blocks, plus its `style` attributes
+pub fn inline_font_references(html: &str) -> String {
+    let mut references = String::new();
+    crate::html_utils::rewrite_start_tags(html, |_, tag| {
+        if let Some(style) = crate::html_utils::get_attribute(tag, "style") {
+            references.push_str(&style.to_ascii_lowercase());
+            references.push('\n');
+        }
+        None
+    });
+    crate::html_utils::rewrite_raw_text(html, "style", |_, css| {
+        references.push_str(&font_family_references(css));
+        None
+    });
+    references
+}
+
+/// Drop top-level `@font-face` rules whose family doesn't appear in `references`
+/// (see `font_family_references`). Icon fonts are always kept.
+/// Returns the CSS and the number of rules removed
+pub fn remove_unused_font_faces(css: &str, references: &str) -> (String, usize) {
+    let rules = split_top_level_rules(css);
+    let mut removed = 0;
+    let kept: Vec<&str> = rules.into_iter()
+        .filter(|rule| {
+            if !rule.to_ascii_lowercase().starts_with("@font-face") {
+                return true;
+            }
+            let family = rule.split(['{', ';', '}'])
+                .filter_map(|declaration| declaration.split_once(':'))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("font-family"))
+                .map(|(_, value)| value.trim().trim_matches(|c| c == '"' || c == '\'').to_ascii_lowercase());
+            let used = family.is_none_or(|family| {
+                references.contains(&family) || ICON_FONT_HINTS.iter().any(|hint| family.contains(hint))
+            });
+            if !used {
+                removed += 1;
+            }
+            used
+        })
+        .collect();
+
+    if removed == 0 {
+        return (css.to_string(), 0);
+    }
+    (kept.concat(), removed)
+}
+
+/// What an `@font-face` rule loads
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontFaceInfo {
+    /// Unquoted `font-family`, if declared
+    pub family: Option<String>,
+    /// `(url, format)` per `src` entry; the format comes from `format()` or the file extension
+    pub sources: Vec<(String, String)>,
+    pub font_display: Option<String>,
+}
+
+impl FontFaceInfo {
+    /// Analysis warnings for this font
+    pub fn warnings(&self) -> Vec<String> {
+        let family = self.family.as_deref().unwrap_or("(unnamed)");
+        let mut warnings = Vec::new();
+        match self.font_display.as_deref() {
+            None => warnings.push(format!("{} has no font-display; add `font-display: swap` to avoid invisible text", family)),
+            Some("block" | "auto") => warnings.push(format!(
+                "{} uses font-display: {}; `swap` shows fallback text while it loads",
+                family,
+                self.font_display.as_deref().unwrap_or_default()
+            )),
+            Some(_) => {}
+        }
+        if !self.sources.is_empty() && !self.sources.iter().any(|(_, format)| format == "woff2") {
+            let formats: Vec<&str> = self.sources.iter().map(|(_, format)| format.as_str()).collect();
+            warnings.push(format!("{} has no woff2 source ({}); woff2 is smallest", family, formats.join(", ")));
+        }
+        warnings
+    }
+}
+
+/// Characters a page's visible text needs, for requesting subset fonts
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct FontSubsetHint {
+    /// Every distinct character, in code point order
+    pub charset: String,
+    /// The same characters as CSS `unicode-range` values (`U+0020-007E`)
+    pub unicode_ranges: Vec<String>,
+}
+
+/// Collect the characters of the visible text (body text and `placeholder`s, not script/style
+/// content). Report-only groundwork for subsetting; `content:` strings in CSS aren't included
+pub fn font_subset_hint(html: &str, fragment: bool) -> FontSubsetHint {
+    const HIDDEN: [&str; 5] = ["script", "style", "noscript", "template", "head"];
+
+    let document = if fragment { Html::parse_fragment(html) } else { Html::parse_document(html) };
+    let mut chars = std::collections::BTreeSet::new();
+    for node in document.tree.nodes() {
+        let hidden = node.ancestors().any(|ancestor| {
+            ancestor.value().as_element().is_some_and(|el| HIDDEN.contains(&el.name()))
+        });
+        if hidden {
+            continue;
+        }
+        match node.value() {
+            scraper::Node::Text(text) => chars.extend(text.chars()),
+            scraper::Node::Element(el) => {
+                if let Some(placeholder) = el.attr("placeholder") {
+                    chars.extend(placeholder.chars());
+                }
+            }
+            _ => {}
+        }
+    }
+    // Whitespace renders as a space; control characters need no glyph
+    let chars: std::collections::BTreeSet<char> = chars.into_iter()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .filter(|c| !c.is_control())
+        .collect();
+
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for c in &chars {
+        let code = *c as u32;
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == code => *end = code,
+            _ => ranges.push((code, code)),
+        }
+    }
+
+    FontSubsetHint {
+        charset: chars.into_iter().collect(),
+        unicode_ranges: ranges.into_iter()
+            .map(|(start, end)| if start == end {
+                format!("U+{:04X}", start)
+            } else {
+                format!("U+{:04X}-{:04X}", start, end)
+            })
+            .collect(),
+    }
+}
+
+/// Start and end (index of the closing `}`) of every `@font-face` block, including nested ones
+fn font_face_blocks(css: &str) -> Vec<(usize, usize)> {
+    let lower = css.to_ascii_lowercase();
+    let mut blocks = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = lower[pos..].find("@font-face") {
+        let start = pos + offset;
+        let Some((open, '{')) = find_unquoted(css, start, &['{', ';']) else { break };
+        let Some(close) = find_block_end(css, open) else { break };
+        blocks.push((open, close));
+        pos = close + 1;
+    }
+    blocks
+}
+
+/// Report the fonts each `@font-face` rule loads
+pub fn analyze_font_faces(css: &str) -> Vec<FontFaceInfo> {
+    font_face_blocks(css)
+        .into_iter()
+        .map(|(open, close)| {
+            let mut info = FontFaceInfo { family: None, sources: Vec::new(), font_display: None };
+            for declaration in css[open + 1..close].split(';') {
+                let Some((name, value)) = declaration.split_once(':') else { continue };
+                let value = value.trim();
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "font-family" => info.family = Some(value.trim_matches(|c| c == '"' || c == '\'').to_string()),
+                    "font-display" => info.font_display = Some(value.to_ascii_lowercase()),
+                    "src" => info.sources.extend(value.split("url(").skip(1).filter_map(|entry| {
+                        let (url, rest) = entry.split_once(')')?;
+                        let url = url.trim().trim_matches(|c| c == '"' || c == '\'');
+                        let hinted = rest.split_once("format(").and_then(|(_, f)| f.split_once(')')).map(|(f, _)| f);
+                        let format = match hinted {
+                            Some(format) => format.trim().trim_matches(|c| c == '"' || c == '\'').to_ascii_lowercase(),
+                            None => url.split(['?', '#']).next().unwrap_or(url).rsplit_once('.')
+                                .map(|(_, ext)| ext.to_ascii_lowercase())
+                                .unwrap_or_default(),
+                        };
+                        (!url.starts_with("data:")).then(|| (url.to_string(), format))
+                    })),
+                    _ => {}
+                }
+            }
+            info
+        })
+        .collect()
+}
+
+/// Add `font-display:swap` to `@font-face` rules that don't set font-display
+/// Returns the CSS and the number of rules changed
+pub fn add_font_display_swap(css: &str) -> (String, usize) {
+    let mut result = String::with_capacity(css.len());
+    let mut pos = 0;
+    let mut count = 0;
+    for (open, close) in font_face_blocks(css) {
+        if css[open..close].to_ascii_lowercase().contains("font-display") {
+            continue;
+        }
+        result.push_str(&css[pos..=open]);
+        result.push_str("font-display:swap;");
+        pos = open + 1;
+        count += 1;
+    }
+    result.push_str(&css[pos..]);
+    (result, count)
+}
+
+/// Remove `/* */` comments, leaving strings alone. With `keep_license`, `/*! ... */` comments stay.
+/// A lighter alternative to minification for CSS that passes through un-minified
+pub fn strip_css_comments(css: &str, keep_license: bool) -> String {
+    let bytes = css.as_bytes();
+    let mut result = String::with_capacity(css.len());
+    let mut copied = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            quote @ (b'"' | b'\'') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = css[i + 2..].find("*/").map(|e| i + 2 + e + 2).unwrap_or(css.len());
+                if !(keep_license && bytes.get(i + 2) == Some(&b'!')) {
+                    result.push_str(&css[copied..i]);
+                    result.push_str(comment_gap(&css[..i], &css[end..], false));
+                    copied = end;
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+
+    result.push_str(&css[copied.min(css.len())..]);
+    result
+}
+
+/// What replaces a removed block comment: nothing next to whitespace, otherwise a separator
+/// so the tokens on either side don't merge (a newline when `newline` is set)
+pub(crate) fn comment_gap(before: &str, after: &str, newline: bool) -> &'static str {
+    let spaced = before.chars().next_back().is_none_or(char::is_whitespace)
+        || after.chars().next().is_none_or(char::is_whitespace);
+    match (newline, spaced) {
+        (true, _) => "\n",
+        (false, true) => "",
+        (false, false) => " ",
+    }
+}
+
+/// Drop rules with an empty body (`.foo{}`) and collapse conditional at-rules
+/// (`@media`, `@supports`, ...) that end up empty. `@layer` blocks are kept since
+/// even an empty layer fixes cascade order.
+pub fn remove_empty_rules(css: &str) -> String {
+    let mut result = String::with_capacity(css.len());
+    let mut pos = 0;
+
+    while pos < css.len() {
+        let Some((delim_pos, delim)) = find_unquoted(css, pos, &['{', ';', '}']) else {
+            result.push_str(&css[pos..]);
+            break;
+        };
+        if delim != '{' {
+            // Statement at-rule (`@import ...;`) or a stray brace: keep verbatim
+            result.push_str(&css[pos..=delim_pos]);
+            pos = delim_pos + 1;
+            continue;
+        }
+        let Some(block_end) = find_block_end(css, delim_pos) else {
+            result.push_str(&css[pos..]);
+            break;
+        };
+
+        let prelude = &css[pos..delim_pos];
+        let body = &css[delim_pos + 1..block_end];
+        let at_rule = prelude.trim_start().starts_with('@');
+        let is_layer = prelude.trim_start().to_ascii_lowercase().starts_with("@layer");
+
+        if at_rule && find_unquoted(body, 0, &['{']).is_some() {
+            // Block of nested rules: clean it, then drop it if nothing is left
+            let inner = remove_empty_rules(body);
+            if !inner.trim().is_empty() || is_layer {
+                result.push_str(prelude);
+                result.push('{');
+                result.push_str(&inner);
+                result.push('}');
+            }
+        } else if !body.trim_matches(|c: char| c.is_whitespace() || c == ';').is_empty() || is_layer {
+            result.push_str(&css[pos..=block_end]);
+        }
+        pos = block_end + 1;
+    }
+
+    result
+}
+
+/// Split a stylesheet into its top-level rules (nested at-rule blocks stay whole)
+pub fn split_top_level_rules(css: &str) -> Vec<&str> {
+    let mut rules = Vec::new();
+    let mut pos = 0;
+
+    while pos < css.len() {
+        let end = match find_unquoted(css, pos, &['{', ';']) {
+            Some((open, '{')) => find_block_end(css, open).map(|close| close + 1),
+            Some((semicolon, _)) => Some(semicolon + 1),
+            None => None,
+        }
+        .unwrap_or(css.len());
+
+        let rule = css[pos..end].trim();
+        if !rule.is_empty() {
+            rules.push(rule);
+        }
+        pos = end;
+    }
+
+    rules
+}
+
+/// Find the first of `targets` at or after `from`, skipping quoted strings and comments
+fn find_unquoted(css: &str, from: usize, targets: &[char]) -> Option<(usize, char)> {
+    let mut quote: Option<char> = None;
+    let mut chars = css[from..].char_indices().peekable();
+
+    while let Some((offset, c)) = chars.next() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) if c == '\\' => {
+                chars.next();
+            }
+            Some(_) => {}
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None if c == '/' && chars.peek().map(|(_, n)| *n) == Some('*') => {
+                let comment_end = css[from + offset + 2..].find("*/")?;
+                let resume = from + offset + 2 + comment_end + 2;
+                while chars.peek().is_some_and(|(o, _)| from + o < resume) {
+                    chars.next();
+                }
+            }
+            None if targets.contains(&c) => return Some((from + offset, c)),
+            None => {}
+        }
+    }
+    None
+}
+
+/// Index of the `}` matching the `{` at `open`
+fn find_block_end(css: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut pos = open;
+    while let Some((found, c)) = find_unquoted(css, pos, &['{', '}']) {
+        if c == '{' {
+            depth += 1;
+        } else {
+            depth -= 1;
+            if depth == 0 {
+                return Some(found);
+            }
+        }
+        pos = found + 1;
+    }
+    None
+}
+
+/// Build lightningcss targets from browserslist-style entries
+/// Only explicit `<browser> <version>` / `<browser> >= <version>` entries are understood;
+/// usage queries like `> 1%` need caniuse data. Returns the targets and a warning for every
+/// entry that was dropped
+pub fn parse_css_targets(entries: &[String]) -> (Targets, Vec<String>) {
+    let mut browsers = Browsers::default();
+    let mut any = false;
+    let mut warnings = Vec::new();
+
+    for entry in entries {
+        let lower = entry.trim().to_lowercase();
+        if is_usage_query(&lower) {
+            warnings.push(format!(
+                "CSS target '{}' ignored: usage-based queries aren't supported, list browsers instead (e.g. 'safari 12')",
+                entry
+            ));
+            continue;
+        }
+        let mut parts = lower.split_whitespace().filter(|p| *p != ">=");
+        let (Some(name), Some(version)) = (parts.next(), parts.next()) else {
+            warnings.push(format!("CSS target '{}' ignored: expected '<browser> <version>'", entry));
+            continue;
+        };
+        let Some(version) = parse_browser_version(version) else {
+            warnings.push(format!("CSS target '{}' ignored: unsupported version", entry));
+            continue;
+        };
+
+        let slot = match name {
+            "chrome" => &mut browsers.chrome,
+            "edge" => &mut browsers.edge,
+            "firefox" | "ff" => &mut browsers.firefox,
+            "safari" => &mut browsers.safari,
+            "ios" | "ios_saf" => &mut browsers.ios_saf,
+            "opera" => &mut browsers.opera,
+            "samsung" => &mut browsers.samsung,
+            "android" | "and_chr" => &mut browsers.android,
+            "ie" => &mut browsers.ie,
+            _ => {
+                warnings.push(format!("CSS target '{}' ignored: unknown browser '{}'", entry, name));
+                continue;
+            }
+        };
+        // Keep the oldest version requested for each browser
+        *slot = Some(slot.map_or(version, |existing| existing.min(version)));
+        any = true;
+    }
+
+    for warning in &warnings {
+        tracing::warn!("{}", warning);
+    }
+    let targets = if any { Targets::from(browsers) } else { Targets::default() };
+    (targets, warnings)
+}
+
+/// Browserslist queries that select browsers by usage or release data (`> 1%`, `last 2 versions`,
+/// `defaults`, `not dead`) rather than by name
+fn is_usage_query(query: &str) -> bool {
+    query.contains('%')
+        || ["defaults", "last ", "not ", "dead", "cover ", "since ", "unreleased"].iter().any(|keyword| query.starts_with(keyword))
+}
+
+/// Encode `major.minor.patch` the way lightningcss expects (one byte each)
+fn parse_browser_version(version: &str) -> Option<u32> {
+    let mut numbers = version.split('.').map(|n| n.parse::<u32>());
+    let major = numbers.next()?.ok()?;
+    let minor = numbers.next().and_then(|n| n.ok()).unwrap_or(0);
+    let patch = numbers.next().and_then(|n| n.ok()).unwrap_or(0);
+    Some((major << 16) | (minor << 8) | patch)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_css_targets_add_prefixes() {
+        let css = ".a { user-select: none; }";
+        let (targets, warnings) = parse_css_targets(&["safari 12".to_string(), "> 1%".to_string(), "last 2 versions".to_string()]);
+        assert!(targets.browsers.is_some());
+        assert_eq!(warnings.len(), 2, "usage queries are reported: {:?}", warnings);
+        assert!(warnings[0].contains("'> 1%'"));
+
+        let prefixed = minify_css(css, targets).unwrap();
+        assert!(prefixed.contains("-webkit-user-select:none"), "got {}", prefixed);
+
+        let plain = minify_css(css, Targets::default()).unwrap();
+        assert!(!plain.contains("-webkit-"));
+    }
+
     #[test]
     fn test_minify_css() {
         let css = r#"
@@ -394,11 +1028,148 @@ mod tests {
             }
         "#;
         
-        let result = minify_css(css).unwrap();
+        let result = minify_css(css, Targets::default()).unwrap();
         assert!(result.len() < css.len());
         assert!(result.contains(".test"));
     }
 
+    #[test]
+    fn test_minify_css_merges_longhands() {
+        let css = ".box { margin-top: 1px; margin-right: 2px; margin-bottom: 3px; margin-left: 4px; }";
+
+        let printer_only = StyleSheet::parse(css, ParserOptions::default()).unwrap()
+            .to_css(PrinterOptions { minify: true, ..Default::default() }).unwrap()
+            .code;
+        let result = minify_css(css, Targets::default()).unwrap();
+
+        assert_eq!(result, ".box{margin:1px 2px 3px 4px}");
+        assert!(result.len() < printer_only.len());
+    }
+
+    #[test]
+    fn test_remove_unused_font_faces() {
+        let css = concat!(
+            "@font-face{font-family:\"Brand Sans\";src:url(brand.woff2)}",
+            "@font-face{font-family:Unused Serif;src:url(unused.woff2)}",
+            "@font-face{font-family:\"Theme Icons\";src:url(icons.woff2)}",
+            "body{font:16px/1.5 \"Brand Sans\",sans-serif}"
+        );
+        let references = font_family_references(css);
+        let (pruned, removed) = remove_unused_font_faces(css, &references);
+        assert_eq!(removed, 1);
+        assert!(!pruned.contains("Unused Serif"));
+        assert!(pruned.contains("Brand Sans") && pruned.contains("Theme Icons"));
+
+        // A family referenced only from an inline style survives
+        let (_, removed) = remove_unused_font_faces(css, &format!("{}font-family: unused serif", references));
+        assert_eq!(removed, 0);
+    }
+
+    #[test]
+    fn test_font_display_swap_added_when_missing() {
+        let css = concat!(
+            "@font-face{font-family:\"Brand\";src:url(brand.ttf) format(\"truetype\")}",
+            "@font-face{font-family:Body;font-display:optional;src:url(body.woff2)}"
+        );
+        let fonts = analyze_font_faces(css);
+        assert_eq!(fonts[0].family.as_deref(), Some("Brand"));
+        assert_eq!(fonts[0].sources, vec![("brand.ttf".to_string(), "truetype".to_string())]);
+        assert_eq!(fonts[0].warnings().len(), 2);
+        assert!(fonts[1].warnings().is_empty());
+
+        let (swapped, count) = add_font_display_swap(css);
+        assert_eq!(count, 1);
+        assert!(swapped.starts_with("@font-face{font-display:swap;font-family:\"Brand\";"));
+        assert!(swapped.contains("font-display:optional;src:url(body.woff2)"));
+    }
+
+    #[test]
+    fn test_strip_css_comments() {
+        let css = "/*! License MIT */\n/* header */.a{content:\"/* not a comment */\";margin:0/**/auto}\n.b{color:red} /* trailing */";
+        assert_eq!(
+            strip_css_comments(css, true),
+            "/*! License MIT */\n.a{content:\"/* not a comment */\";margin:0 auto}\n.b{color:red} "
+        );
+        assert!(!strip_css_comments(css, false).contains("License"));
+    }
+
+    #[test]
+    fn test_font_subset_hint_uses_visible_text() {
+        let html = r#"<html><head><title>Zzz</title><style>.q{content:"Q"}</style></head>
+            <body><h1>Café abc</h1><script>var xyz = 1;</script><input placeholder="d"></body></html>"#;
+        let hint = font_subset_hint(html, false);
+        assert_eq!(hint.charset, " Cabcdfé");
+        assert_eq!(hint.unicode_ranges, vec!["U+0020", "U+0043", "U+0061-0064", "U+0066", "U+00E9"]);
+    }
+
+    #[test]
+    fn test_remove_empty_rules() {
+        assert_eq!(remove_empty_rules(".a{}.b{color:red}"), ".b{color:red}");
+        assert_eq!(
+            remove_empty_rules("@media (min-width:600px){.a{ ; }}.c{content:\"{}\"}@layer base{}"),
+            ".c{content:\"{}\"}@layer base{}"
+        );
+
+        let optimizer = CssOptimizer::with_selectors(&[".a".to_string(), ".b".to_string()]);
+        let shaken = optimizer.remove_unused_css(".a{}.b{color:red}").unwrap();
+        assert!(!shaken.contains(".a"));
+        assert!(shaken.contains(".b{color:red"));
+    }
+
+    #[test]
+    fn test_keep_css_hint_survives_treeshake() {
+        let html = r#"<html><body data-keep-css=".menu-open modal-active #overlay"><nav class="menu">Menu</nav></body></html>"#;
+        let css = ".menu{display:flex}.menu-open .menu{display:block}.modal-active{overflow:hidden}#overlay{opacity:.5}.unused{color:red}";
+
+        let mut optimizer = CssOptimizer::new();
+        optimizer.extract_used_selectors(html);
+        let shaken = optimizer.remove_unused_css(css).unwrap();
+
+        assert!(shaken.contains(".menu-open .menu"));
+        assert!(shaken.contains(".modal-active"));
+        assert!(shaken.contains("#overlay"));
+        assert!(!shaken.contains(".unused"));
+    }
+
+    #[test]
+    fn test_js_referenced_classes_survive_treeshake() {
+        let html = r#"<html><body><button class="toggle">Menu</button>
+            <script>
+                document.querySelector('.toggle').addEventListener('click', function () {
+                    document.body.classList.add('menu-expanded', "no-scroll");
+                    jQuery('#drawer').toggleClass('drawer--slid');
+                });
+            </script></body></html>"#;
+        let css = ".menu-expanded{overflow:hidden}.no-scroll{position:fixed}.drawer--slid{left:0}#drawer{width:80%}.unused{color:red}";
+
+        let mut optimizer = CssOptimizer::new();
+        optimizer.extract_used_selectors(html);
+        optimizer.extract_inline_script_classes(html);
+        let shaken = optimizer.remove_unused_css(css).unwrap();
+
+        assert!(shaken.contains(".menu-expanded"));
+        assert!(shaken.contains(".no-scroll"));
+        assert!(shaken.contains(".drawer--slid"));
+        assert!(shaken.contains("#drawer"));
+        assert!(!shaken.contains(".unused"));
+    }
+
+    #[test]
+    fn test_blocklist_removes_used_selectors() {
+        let html = r#"<html><body><div class="cookie-banner elementor-popup">Hi</div><p class="text">x</p></body></html>"#;
+        let css = ".cookie-banner{position:fixed}.elementor-popup{z-index:9}.text{color:red}";
+
+        let mut optimizer = CssOptimizer::new();
+        optimizer.extract_used_selectors(html);
+        optimizer.set_blocklist(&["cookie-banner".to_string(), "Elementor-Popup".to_string()]);
+        let shaken = optimizer.remove_unused_css(css).unwrap();
+
+        assert!(!shaken.contains(".cookie-banner"));
+        // Blocklist wins over the page-builder whitelist
+        assert!(!shaken.contains(".elementor-popup"));
+        assert!(shaken.contains(".text{color:red"));
+    }
+
     #[test]
     fn test_extract_selectors() {
         let html = r#"<div class="hero main" id="content"><p class="text">Hello</p></div>"#;
@@ -1,7 +1,7 @@
 //! Error types
 
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
@@ -11,6 +11,9 @@ use serde_json::json;
 pub enum AppError {
     BadRequest(String),
     Unauthorized,
+    TooManyRequests { retry_after_secs: u64 },
+    NotFound(String),
+    ServiceUnavailable(String),
     Internal(String),
     Optimization(String),
 }
@@ -20,6 +23,9 @@ impl std::fmt::Display for AppError {
         match self {
             AppError::BadRequest(msg) => write!(f, "Bad request: {}", msg),
             AppError::Unauthorized => write!(f, "Unauthorized"),
+            AppError::TooManyRequests { retry_after_secs } => write!(f, "Too many requests, retry after {}s", retry_after_secs),
+            AppError::NotFound(msg) => write!(f, "Not found: {}", msg),
+            AppError::ServiceUnavailable(msg) => write!(f, "Service unavailable: {}", msg),
             AppError::Internal(msg) => write!(f, "Internal error: {}", msg),
             AppError::Optimization(msg) => write!(f, "Optimization error: {}", msg),
         }
@@ -28,9 +34,25 @@ impl std::fmt::Display for AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        if let AppError::TooManyRequests { retry_after_secs } = self {
+            let body = Json(json!({
+                "error": true,
+                "message": "Too many requests",
+                "retry_after": retry_after_secs,
+            }));
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                body,
+            ).into_response();
+        }
+
         let (status, message) = match self {
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
             AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+            AppError::TooManyRequests { .. } => (StatusCode::TOO_MANY_REQUESTS, "Too many requests".to_string()),
+            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg),
+            AppError::ServiceUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
             AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
             AppError::Optimization(msg) => (StatusCode::UNPROCESSABLE_ENTITY, msg),
         };
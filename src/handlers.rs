@@ -1,15 +1,31 @@
 //! API Handlers
 
+use std::convert::Infallible;
+
 use axum::{
-    extract::{Json, State},
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Extension, Json, Path, State,
+    },
     http::{HeaderMap, StatusCode},
-    response::IntoResponse,
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
 };
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
 
+use crate::asset_cache::AssetCache;
+use crate::concurrency::InFlight;
 use crate::error::AppError;
 use crate::config::AppState;
-use crate::optimizer;
+use crate::jobs::{JobEvent, JOB_RETENTION};
+use crate::progress::{report, ProgressSender};
+use crate::optimizer::{self, OptimizeResult};
+use crate::resource_optimizer::PrecompressedAsset;
 
 /// Health check response
 #[derive(Serialize)]
@@ -28,6 +44,79 @@ pub async fn health(State(state): State<AppState>) -> impl IntoResponse {
     })
 }
 
+/// Readiness check response
+#[derive(Serialize)]
+pub struct ReadyResponse {
+    ready: bool,
+    probe_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Send a HEAD request to the probe URL and measure the round trip
+async fn probe_connectivity(client: &reqwest::Client, probe_url: &str, timeout: std::time::Duration) -> ReadyResponse {
+    let started = std::time::Instant::now();
+    let result = client.head(probe_url).timeout(timeout).send().await;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(_) => ReadyResponse {
+            ready: true,
+            probe_url: probe_url.to_string(),
+            latency_ms: Some(latency_ms),
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Readiness probe to {} failed: {}", probe_url, e);
+            ReadyResponse {
+                ready: false,
+                probe_url: probe_url.to_string(),
+                latency_ms: None,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+/// Readiness check endpoint - verifies outbound connectivity
+/// `/health` stays a pure liveness check
+pub async fn ready(State(state): State<AppState>) -> impl IntoResponse {
+    let response = probe_connectivity(&state.http_client, &state.ready_probe_url, state.ready_probe_timeout).await;
+    let status = if response.ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(response))
+}
+
+/// Verify the Bearer token against the configured API key
+fn check_api_key(state: &AppState, headers: &HeaderMap) -> Result<(), AppError> {
+    if let Some(ref key) = state.api_key {
+        let auth_header = headers
+            .get("Authorization")
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or("");
+        
+        if auth_header != format!("Bearer {}", key) {
+            return Err(AppError::Unauthorized);
+        }
+        Ok(())
+    } else {
+        tracing::error!("Security Error: No API Key configured on server");
+        Err(AppError::Internal("Server misconfiguration: API_KEY must be set".to_string()))
+    }
+}
+
+/// Parse the page URL, accepting only absolute http(s) URLs
+fn parse_page_url(url: &str) -> Result<url::Url, AppError> {
+    let parsed = url::Url::parse(url)
+        .map_err(|e| AppError::BadRequest(format!("Invalid URL '{}': {}", url, e)))?;
+
+    match parsed.scheme() {
+        "http" | "https" => Ok(parsed),
+        scheme => Err(AppError::BadRequest(format!("Unsupported URL scheme: {}", scheme))),
+    }
+}
+
 /// Optimization request
 #[derive(Deserialize)]
 pub struct OptimizeRequest {
@@ -35,46 +124,412 @@ pub struct OptimizeRequest {
     pub url: String,
     #[serde(default)]
     pub options: OptimizeOptions,
+    /// Minify only: skip every network step (WebP conversion, external CSS/JS) regardless of
+    /// `options`. The response's `images` and `resources` are always None.
+    #[serde(default)]
+    pub offline: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct OptimizeOptions {
+    /// Low-risk preset: switches off every pass known to occasionally break pages
+    /// (see `apply_safe_mode` for exactly what still runs)
+    #[serde(default)]
+    pub safe_mode: bool,
     #[serde(default = "default_true")]
     pub minify_html: bool,
     #[serde(default = "default_true")]
     pub minify_css: bool,
     #[serde(default = "default_true")]
     pub minify_js: bool,
+    /// Remove comments from external CSS/JS that isn't minified (minification off, failed or
+    /// fell back to the original)
+    #[serde(default)]
+    pub strip_comments: bool,
+    /// Let `strip_comments` also remove `/*! ... */` license comments
+    #[serde(default)]
+    pub strip_license_comments: bool,
     #[serde(default = "default_true")]
     pub remove_unused_css: bool,
+    /// Drop `@font-face` rules whose family no remaining rule or inline style uses (icon fonts kept);
+    /// for external stylesheets only the critical and combined CSS are pruned
+    #[serde(default)]
+    pub remove_unused_fonts: bool,
+    /// Add `font-display: swap` to inline and external `@font-face` rules that don't set font-display
+    #[serde(default)]
+    pub font_display_swap: bool,
     #[serde(default = "default_true")]
     pub convert_webp: bool,
     #[serde(default = "default_true")]
     pub resize_images: bool,
+    /// WebP quality selection: "fixed" (default) or "ssim" (lowest quality meeting `ssim_threshold`)
+    #[serde(default)]
+    pub quality_mode: crate::webp_converter::QualityMode,
+    /// Minimum SSIM (0-1) against the original for `quality_mode: "ssim"`
+    #[serde(default = "default_ssim_threshold")]
+    pub ssim_threshold: f32,
     #[serde(default = "default_true")]
     pub defer_js: bool,
     #[serde(default = "default_true")]
     pub lazy_images: bool,
+    /// Follow each JS lazy-loaded image we lazify (`data-src`/`data-lazy-src` holding the URL)
+    /// with a `<noscript>` copy that loads it directly
+    #[serde(default)]
+    pub noscript_fallback: bool,
+    /// `decoding="async"` on images, except the LCP image (`decoding="sync"`, `fetchpriority="high"`)
+    #[serde(default)]
+    pub image_decoding_hints: bool,
+    /// Replace YouTube/Vimeo iframes with a thumbnail that loads the player on click (Vimeo
+    /// thumbnails come from vumbnail.com, an unofficial third-party service)
+    #[serde(default)]
+    pub lite_embeds: bool,
     #[serde(default = "default_true")]
     pub optimize_resources: bool,
+    /// Strip `ver` cache-busters and lowercase the host before hashing asset filenames
+    #[serde(default = "default_true")]
+    pub normalize_asset_urls: bool,
+    /// Rewrite http:// subresources on an https page to https:// (own host and known CDNs only)
+    #[serde(default)]
+    pub upgrade_insecure: bool,
+    /// Generated asset names: "url-hash" (per source URL) or "content-hash" (per content, cache forever)
+    #[serde(default)]
+    pub filename_scheme: crate::url_utils::FilenameScheme,
+    /// Input is a partial template (widget, block render) rather than a full page:
+    /// head-insertion passes are skipped and only safe in-place transforms run
+    #[serde(default)]
+    pub fragment: bool,
+    /// Where rewritten image URLs point. Variables: `{base}` (upload base),
+    /// `{site}` (page origin), `{cdn}` (`cdn_url`, or `{base}` when unset), `{filename}`
+    #[serde(default = "default_image_path_template")]
+    pub image_path_template: String,
+    /// Where the combined CSS/JS URLs point; same variables as `image_path_template`
+    #[serde(default = "default_asset_path_template")]
+    pub asset_path_template: String,
+    /// CDN host substituted for `{cdn}` in path templates
+    #[serde(default)]
+    pub cdn_url: Option<String>,
+    /// Maximum images converted per page (first N in document order)
+    #[serde(default = "default_max_images")]
+    pub max_images: usize,
+    /// Maximum CSS files and JS files (each) optimized per page
+    #[serde(default = "default_max_resources")]
+    pub max_resources: usize,
+    /// Keep the original image alongside the WebP and serve both via `<picture>`
+    #[serde(default)]
+    pub keep_original: bool,
+    /// Turn single `<img>` tags into a responsive `<picture>` with a WebP per breakpoint
+    #[serde(default)]
+    pub responsive_images: bool,
+    /// Widths (px) generated for `responsive_images`; only those narrower than the image are used
+    #[serde(default = "default_responsive_breakpoints")]
+    pub responsive_breakpoints: Vec<u32>,
+    /// Also return brotli/gzip versions of the combined CSS/JS
+    #[serde(default)]
+    pub precompress: bool,
+    /// Replace inline `style` attributes repeated 3+ times with generated classes
+    #[serde(default)]
+    pub consolidate_inline_styles: bool,
+    /// Browser targets for CSS prefixing/down-levelling, e.g. `["safari 12", "chrome >= 80"]`
+    #[serde(default)]
+    pub css_targets: Option<Vec<String>>,
+    /// Force the Schema.org page type ("article", "product", "page"), bypassing detection
+    #[serde(default)]
+    pub schema_type: Option<String>,
+    /// URL path patterns (`*` wildcard) mapped to page types, e.g. `{"/shop/*": "product"}`
+    /// The longest matching pattern wins; `schema_type` takes precedence
+    #[serde(default)]
+    pub schema_type_patterns: std::collections::HashMap<String, String>,
+    /// Emit the generated schemas as one interlinked `@graph` instead of separate objects
+    #[serde(default = "default_true", alias = "use_graph")]
+    pub schema_graph: bool,
+    /// ISO 4217 currency for Product offers; detected from the price markup when unset. Ambiguous
+    /// symbols (`$`, `kr`) are not guessed: `priceCurrency` is left out unless this is set
+    #[serde(default)]
+    pub currency: Option<String>,
+    /// Inline all CSS and drop the combined stylesheet when critical CSS covers everything
+    /// (up to 50KB; the critical budget is raised to match)
+    #[serde(default)]
+    pub inline_all_css: bool,
+    /// Keep classes referenced from inline scripts (`classList.add('x')`, `addClass('y')`) during tree-shaking
+    #[serde(default = "default_true")]
+    pub scan_js_for_classes: bool,
+    /// How many leading body elements count as above the fold for critical CSS
+    /// (roughly 40 for a 640px mobile viewport, 80+ for a 900px desktop one)
+    #[serde(default = "default_fold_element_count")]
+    pub fold_element_count: usize,
+    /// Report the selectors removed by tree-shaking in `removed_selectors`
+    #[serde(default)]
+    pub debug_removed: bool,
+    /// Report the characters the visible text uses in `font_subset_hint` (for font subsetting)
+    #[serde(default)]
+    pub font_subset_hint: bool,
+    /// Return a unified diff of the HTML changes in `diff` (capped in size)
+    #[serde(default)]
+    pub include_diff: bool,
+    /// Optimizations saving less than this percentage are discarded and the original kept
+    #[serde(default = "default_min_reduction_percent")]
+    pub min_reduction_percent: f32,
+    /// External CSS files larger than this are left untouched
+    #[serde(default = "default_max_css_bytes")]
+    pub max_css_bytes: usize,
+    /// External JS files larger than this are left untouched
+    #[serde(default = "default_max_js_bytes")]
+    pub max_js_bytes: usize,
+    /// Inline `<style>` blocks larger than this skip tree-shaking
+    #[serde(default = "default_max_inline_css_bytes")]
+    pub max_inline_css_bytes: usize,
+    /// Debugging aid: indent the output one tag per line instead of minifying (overrides `minify_html`).
+    /// Makes the HTML larger and may change whitespace between inline elements - not for production
+    #[serde(default)]
+    pub prettify: bool,
+    /// How the combined stylesheet is loaded: "async" (media swap, alias "print-onload"),
+    /// "preload" (rel swap, alias "preload-onload"), "body-end" or "preserve" (alias "blocking").
+    /// Also accepted as `css_load_strategy`
+    #[serde(default, alias = "css_load_strategy")]
+    pub css_loading: crate::resource_optimizer::CssLoading,
+    /// Merge external CSS/JS into one file each; false serves every minified file under its own name
+    #[serde(default = "default_true")]
+    pub combine: bool,
+    /// Download `@font-face` files and point the CSS at local copies
+    #[serde(default)]
+    pub localize_fonts: bool,
+    /// Keep eot/ttf/otf/svg font sources even when a woff2 is available
+    #[serde(default)]
+    pub keep_legacy_fonts: bool,
+    /// Return `Link: rel=preload` header values for the rewritten CSS/JS and fonts
+    #[serde(default)]
+    pub emit_preload_headers: bool,
+    /// Preload the woff2 files of fonts the injected critical CSS uses
+    #[serde(default)]
+    pub preload_fonts: bool,
+    /// Follow the async combined stylesheet with a `<noscript>` link for visitors without JS
+    #[serde(default = "default_true")]
+    pub noscript_css_fallback: bool,
+    /// Match `<link>`/`<script>` tags to downloaded files ignoring the `ver` query parameter, so
+    /// `style.css?ver=1` and `style.css?ver=2` (or a version the plugin later strips) are both rewritten
+    #[serde(default)]
+    pub match_unversioned_urls: bool,
+    /// Where the combined script goes: "preserve" (first script's position) or "body-end"
+    #[serde(default)]
+    pub js_placement: crate::resource_optimizer::JsPlacement,
+    /// Attributes to strip, as `name` or `name=value` with `*` wildcards
+    /// (e.g. `class=wp-block-*`, `id`). Classes and IDs still referenced by inline CSS,
+    /// inline scripts or in-page links are kept, and are never touched when external stylesheets
+    /// are present (their selectors can't be checked here). Interactivity API directives
+    /// (`data-wp-*`) are kept unless a pattern starts with `data-wp-`. Empty = off
+    #[serde(default)]
+    pub prune_attributes: Vec<String>,
+    /// Selector substrings whose inline CSS rules are always removed during tree-shaking,
+    /// even when the page uses them (e.g. `cookie-banner`). Takes precedence over the built-in whitelist.
+    /// Also accepted as `css_blacklist`
+    #[serde(default, alias = "css_blacklist")]
+    pub css_blocklist: Vec<String>,
+    /// Previous/next page of a paginated archive: emitted as `<link rel="prev/next">`, and the
+    /// canonical is pointed at this page instead of the archive's first page
+    #[serde(default)]
+    pub prev_url: Option<String>,
+    #[serde(default)]
+    pub next_url: Option<String>,
+    /// Twitter handles for `twitter:site` / `twitter:creator` (with or without the `@`)
+    #[serde(default)]
+    pub twitter_site: Option<String>,
+    #[serde(default)]
+    pub twitter_creator: Option<String>,
+    /// Remove analytics/ad tracking scripts (consent-management scripts are kept)
+    #[serde(default)]
+    pub strip_trackers: bool,
+    /// Hosts treated as trackers by `strip_trackers` (subdomains included); an entry with a
+    /// path (`facebook.com/tr`) only matches URLs under that path
+    #[serde(default = "default_tracker_domains")]
+    pub tracker_domains: Vec<String>,
+    /// Inline scripts containing any of these substrings (e.g. `gtag`, `dataLayer`) are never
+    /// minified, deferred, moved by the HTML passes or removed; they come back byte-for-byte
+    #[serde(default)]
+    pub preserve_scripts: Vec<String>,
 }
 
 impl Default for OptimizeOptions {
     fn default() -> Self {
         Self {
+            safe_mode: false,
             minify_html: true,
             minify_css: true,
             minify_js: true,
+            strip_comments: false,
+            strip_license_comments: false,
             remove_unused_css: true,
+            remove_unused_fonts: false,
+            font_display_swap: false,
             convert_webp: true,
             resize_images: true,
+            quality_mode: crate::webp_converter::QualityMode::default(),
+            ssim_threshold: default_ssim_threshold(),
             defer_js: true,
             lazy_images: true,
+            noscript_fallback: false,
+            image_decoding_hints: false,
+            lite_embeds: false,
             optimize_resources: true,
+            normalize_asset_urls: true,
+            upgrade_insecure: false,
+            filename_scheme: crate::url_utils::FilenameScheme::default(),
+            fragment: false,
+            image_path_template: default_image_path_template(),
+            asset_path_template: default_asset_path_template(),
+            cdn_url: None,
+            max_images: default_max_images(),
+            max_resources: default_max_resources(),
+            keep_original: false,
+            responsive_images: false,
+            responsive_breakpoints: default_responsive_breakpoints(),
+            precompress: false,
+            consolidate_inline_styles: false,
+            css_targets: None,
+            schema_type: None,
+            schema_type_patterns: std::collections::HashMap::new(),
+            schema_graph: true,
+            currency: None,
+            inline_all_css: false,
+            scan_js_for_classes: true,
+            fold_element_count: default_fold_element_count(),
+            debug_removed: false,
+            font_subset_hint: false,
+            include_diff: false,
+            min_reduction_percent: default_min_reduction_percent(),
+            max_css_bytes: default_max_css_bytes(),
+            max_js_bytes: default_max_js_bytes(),
+            max_inline_css_bytes: default_max_inline_css_bytes(),
+            prettify: false,
+            css_loading: crate::resource_optimizer::CssLoading::default(),
+            combine: true,
+            localize_fonts: false,
+            keep_legacy_fonts: false,
+            emit_preload_headers: false,
+            preload_fonts: false,
+            noscript_css_fallback: true,
+            match_unversioned_urls: false,
+            js_placement: crate::resource_optimizer::JsPlacement::default(),
+            prune_attributes: Vec::new(),
+            css_blocklist: Vec::new(),
+            prev_url: None,
+            next_url: None,
+            twitter_site: None,
+            twitter_creator: None,
+            strip_trackers: false,
+            tracker_domains: default_tracker_domains(),
+            preserve_scripts: Vec::new(),
         }
     }
 }
 
+/// Upload base used in rewritten URLs (WordPress replaces it with the real path)
+const UPLOAD_BASE: &str = ".";
+
+fn default_max_images() -> usize {
+    200
+}
+
+fn default_ssim_threshold() -> f32 {
+    0.95
+}
+
+fn default_responsive_breakpoints() -> Vec<u32> {
+    vec![480, 768, 1024, 1600]
+}
+
+fn default_tracker_domains() -> Vec<String> {
+    [
+        "google-analytics.com", "googletagmanager.com", "connect.facebook.net", "static.hotjar.com",
+        "snap.licdn.com", "analytics.tiktok.com", "bat.bing.com", "static.ads-twitter.com",
+        "facebook.com/tr", "px.ads.linkedin.com",
+    ]
+    .iter()
+    .map(|domain| domain.to_string())
+    .collect()
+}
+
+fn default_max_resources() -> usize {
+    100
+}
+
+fn default_max_css_bytes() -> usize {
+    500_000
+}
+
+fn default_max_js_bytes() -> usize {
+    1_000_000
+}
+
+fn default_max_inline_css_bytes() -> usize {
+    100_000
+}
+
+fn default_fold_element_count() -> usize {
+    60
+}
+
+fn default_min_reduction_percent() -> f32 {
+    2.0
+}
+
+fn default_image_path_template() -> String {
+    "{base}/images/{filename}".to_string()
+}
+
+fn default_asset_path_template() -> String {
+    "{base}/{filename}".to_string()
+}
+
+impl OptimizeOptions {
+    /// Expand everything but `{filename}` in a path template
+    pub(crate) fn path_template(&self, template: &str, page_url: &url::Url) -> String {
+        let site = page_url.origin().ascii_serialization();
+        let cdn = self.cdn_url.as_deref().unwrap_or(UPLOAD_BASE);
+        crate::url_utils::expand_path_template(template, &[
+            ("base", UPLOAD_BASE),
+            ("site", &site),
+            ("cdn", cdn),
+        ])
+    }
+
+    /// Apply the `safe_mode` preset (no-op when it's off). What still runs: conservative HTML
+    /// minification (inline `<style>`/`<script>` contents left untouched), lazy loading, alt tags,
+    /// meta/Open Graph/canonical insertion and WebP conversion. Switched off: CSS tree-shaking,
+    /// unused font removal, CSS/JS minification and combining (external files are left alone),
+    /// script deferral, inline style consolidation, attribute pruning, tracker stripping, https
+    /// upgrades, responsive `<picture>` generation and font localization.
+    /// Reporting, preconnect hints and Schema.org are unaffected
+    pub fn apply_safe_mode(&mut self) {
+        if !self.safe_mode {
+            return;
+        }
+        self.remove_unused_css = false;
+        self.remove_unused_fonts = false;
+        self.inline_all_css = false;
+        self.minify_css = false;
+        self.minify_js = false;
+        self.optimize_resources = false;
+        self.combine = false;
+        self.defer_js = false;
+        self.consolidate_inline_styles = false;
+        self.prune_attributes.clear();
+        self.strip_trackers = false;
+        self.upgrade_insecure = false;
+        self.responsive_images = false;
+        self.localize_fonts = false;
+        self.lite_embeds = false;
+    }
+
+    /// Whether shrinking `original_size` to `optimized_size` clears `min_reduction_percent`
+    pub fn is_worthwhile_reduction(&self, original_size: usize, optimized_size: usize) -> bool {
+        if optimized_size >= original_size {
+            return false;
+        }
+        let reduction = (original_size - optimized_size) as f32 / original_size as f32 * 100.0;
+        reduction >= self.min_reduction_percent
+    }
+}
+
 fn default_level() -> String {
     "balanced".to_string()
 }
@@ -91,11 +546,37 @@ pub struct OptimizeResponse {
     pub original_size: usize,
     pub optimized_size: usize,
     pub reduction_percent: f64,
+    /// Bytes saved on the HTML plus converted images and optimized CSS/JS files
+    pub saved_bytes: usize,
+    /// Elements before and after the HTML passes
+    pub original_node_count: usize,
+    pub optimized_node_count: usize,
     pub optimizations: Vec<String>,
+    /// Structured records of element-level changes (alt tags, defer, lazy loading, canonical)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changes_detail: Vec<crate::changes::Change>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub images: Option<WebpImagesResponse>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub resources: Option<ResourcesResponse>,
+    /// Selectors dropped by tree-shaking (only with `debug_removed`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed_selectors: Vec<String>,
+    /// Characters and unicode-ranges the visible text needs (only with `font_subset_hint`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub font_subset_hint: Option<crate::css_optimizer::FontSubsetHint>,
+    /// Id of the HTTP request (also in the `X-Request-Id` response header)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Unified diff between the original and optimized HTML (only with `include_diff`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    /// Third-party hosts the optimized page loads from, with what it loads (report only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub external_hosts: Vec<crate::url_utils::ExternalHost>,
+    /// Why this page failed (bulk results only; the original HTML is returned as-is)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
 }
 
 /// WebP images response
@@ -105,6 +586,8 @@ pub struct WebpImagesResponse {
     pub total_original_kb: f32,
     pub total_webp_kb: f32,
     pub total_savings_kb: f32,
+    /// Images not converted because of `max_images`
+    pub skipped_count: usize,
 }
 
 #[derive(Serialize)]
@@ -115,6 +598,17 @@ pub struct WebpImageData {
     pub original_size: usize,
     pub webp_size: usize,
     pub reduction_percent: f32,
+    /// Local copy of the original image (only with `keep_original`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_filename: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_base64: Option<String>,
+    /// Generated breakpoint widths (only with `responsive_images`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<crate::webp_converter::ResponsiveVariant>,
+    /// Lossy quality chosen by `quality_mode: "ssim"`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
 }
 
 /// Optimized CSS/JS resources response
@@ -132,8 +626,27 @@ pub struct ResourcesResponse {
     pub combined_js: Option<String>,
     pub combined_css_filename: String,
     pub combined_js_filename: String,
+    /// Precompressed combined files (only with `precompress`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined_css_br: Option<PrecompressedAsset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined_css_gz: Option<PrecompressedAsset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined_js_br: Option<PrecompressedAsset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub combined_js_gz: Option<PrecompressedAsset>,
     pub total_css_savings_kb: f32,
     pub total_js_savings_kb: f32,
+    /// CSS/JS files not optimized because of `max_resources`
+    pub skipped_count: usize,
+    /// Whether the combined stylesheet was dropped in favour of inlined CSS (`inline_all_css`)
+    pub external_css_eliminated: bool,
+    /// `@font-face` files to save alongside the combined CSS (only with `localize_fonts`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fonts: Vec<crate::resource_optimizer::LocalizedFont>,
+    /// `Link` header values for the plugin to send (only with `emit_preload_headers`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub preload_headers: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -156,44 +669,255 @@ pub struct JsFileData {
     pub reduction_percent: f32,
 }
 
+/// Prometheus metrics (unauthenticated, for scrapers)
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
+
 /// Single page optimization
 pub async fn optimize(
     State(state): State<AppState>,
     headers: HeaderMap,
     Json(req): Json<OptimizeRequest>,
 ) -> Result<Json<OptimizeResponse>, AppError> {
-    // Check API Key
-    if let Some(ref key) = state.api_key {
-        let auth_header = headers
-            .get("Authorization")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("");
-        
-        if auth_header != format!("Bearer {}", key) {
-            return Err(AppError::Unauthorized);
-        }
-    } else {
-        tracing::error!("Security Error: No API Key configured on server");
-        return Err(AppError::Internal("Server misconfiguration: API_KEY must be set".to_string()));
+    check_api_key(&state, &headers)?;
+    let mut response = optimize_request(req, "single", None).await?;
+    if let Some(resources) = &response.resources {
+        state.assets.insert_resources(resources);
     }
+    response.request_id = crate::request_id::from_headers(&headers);
+    Ok(Json(response))
+}
+
+/// Combined CSS/JS from a recent optimize run, by filename (unauthenticated, for CDNs)
+/// Filenames carry a content hash, so responses are cacheable indefinitely
+pub async fn resource(
+    State(state): State<AppState>,
+    Path(filename): Path<String>,
+) -> Result<Response, AppError> {
+    let not_found = || AppError::NotFound(format!("Unknown resource: {}", filename));
+    let content_type = crate::asset_cache::content_type(&filename).ok_or_else(not_found)?;
+    let content = state.assets.get(&filename).ok_or_else(not_found)?;
+    Ok((
+        [
+            (axum::http::header::CONTENT_TYPE, content_type),
+            (axum::http::header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+        ],
+        content.to_string(),
+    ).into_response())
+}
+
+/// Full single-page pipeline shared by the REST and WebSocket endpoints
+async fn optimize_request(
+    mut req: OptimizeRequest,
+    endpoint: &'static str,
+    progress: Option<&ProgressSender>,
+) -> Result<OptimizeResponse, AppError> {
+    let page_url = parse_page_url(&req.url)?;
+    let url = page_url.as_str();
 
     if req.html.is_empty() {
         return Err(AppError::BadRequest("HTML is required".to_string()));
     }
 
-    tracing::info!("Optimizing: {} ({} bytes)", req.url, req.html.len());
+    tracing::info!(url, html_bytes = req.html.len(), "Optimizing");
+    req.options.apply_safe_mode();
+
+    // The HTML passes are CPU-bound: keep them off the async workers
+    let page = url.to_string();
+    let (mut result, req) = tokio::task::spawn_blocking(move || {
+        let result = optimizer::optimize_html_marked(&req.html, &page, &req.options);
+        (result, req)
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("Optimization task failed: {}", e)))?;
+    report(progress, "html", 1, 1);
+
+    if req.offline {
+        result.finalize_changes();
+        crate::metrics::record_optimization(endpoint, true, result.original_size, result.optimized_size);
+        tracing::info!(url, original_size = result.original_size, optimized_size = result.optimized_size, "Optimized offline");
+        let mut response = optimize_response(result, None, None);
+        if req.options.include_diff {
+            response.diff = Some(optimizer::html_diff(&req.html, &response.optimized_html));
+        }
+        response.external_hosts = crate::url_utils::external_hosts(&response.optimized_html, url);
+        return Ok(response);
+    }
+
+    // Network steps, composed on top of the synchronous core
+    let started = std::time::Instant::now();
+    // Timings are recorded for every run, empty or failed stages included, so the histograms
+    // show the true distribution
+    let images = apply_webp_conversion(&mut result, &page_url, &req.options, progress).await;
+    crate::metrics::record_stage("images", started);
+    let images_ms = started.elapsed().as_millis() as u64;
+    let started = std::time::Instant::now();
+    let resources = apply_resource_optimization(&mut result, &page_url, &req.options, progress).await;
+    crate::metrics::record_stage("resources", started);
+    let resources_ms = started.elapsed().as_millis() as u64;
+    // Changes describe the elements as shipped, WebP and resource rewrites included
+    result.finalize_changes();
+    crate::metrics::record_optimization(endpoint, true, result.original_size, result.optimized_size);
+
+    let mut response = optimize_response(result, images, resources);
+    if req.options.include_diff {
+        response.diff = Some(optimizer::html_diff(&req.html, &response.optimized_html));
+    }
+    response.external_hosts = crate::url_utils::external_hosts(&response.optimized_html, url);
+
+    tracing::info!(
+        url,
+        original_size = response.original_size,
+        optimized_size = response.optimized_size,
+        reduction_percent = response.reduction_percent,
+        images_ms,
+        resources_ms,
+        "Optimized"
+    );
+
+    Ok(response)
+}
+
+fn optimize_response(
+    result: OptimizeResult,
+    images: Option<WebpImagesResponse>,
+    resources: Option<ResourcesResponse>,
+) -> OptimizeResponse {
+    let saved_bytes = saved_bytes(&result, images.as_ref(), resources.as_ref());
+    OptimizeResponse {
+        success: true,
+        optimized_html: result.html,
+        original_size: result.original_size,
+        optimized_size: result.optimized_size,
+        reduction_percent: result.reduction_percent,
+        saved_bytes,
+        original_node_count: result.original_node_count,
+        optimized_node_count: result.optimized_node_count,
+        optimizations: result.optimizations,
+        changes_detail: result.changes,
+        images,
+        resources,
+        removed_selectors: result.removed_selectors,
+        font_subset_hint: result.font_subset_hint,
+        request_id: None,
+        diff: None,
+        external_hosts: Vec::new(),
+        error: None,
+    }
+}
+
+/// Bytes saved across the HTML, WebP images and external CSS/JS of one page
+fn saved_bytes(
+    result: &OptimizeResult,
+    images: Option<&WebpImagesResponse>,
+    resources: Option<&ResourcesResponse>,
+) -> usize {
+    let html = result.original_size.saturating_sub(result.optimized_size);
+    let images: usize = images
+        .map(|images| images.images.iter().map(|img| img.original_size.saturating_sub(img.webp_size)).sum())
+        .unwrap_or(0);
+    let resources: usize = resources
+        .map(|resources| {
+            let css = resources.css_files.iter().map(|f| f.original_size.saturating_sub(f.optimized_size));
+            let js = resources.js_files.iter().map(|f| f.original_size.saturating_sub(f.optimized_size));
+            css.chain(js).sum()
+        })
+        .unwrap_or(0);
+    html + images + resources
+}
+
+/// Single page optimization over a WebSocket
+/// The client sends one `OptimizeRequest` as a text frame and receives
+/// `{"type":"progress",stage,current,total}` frames, then `{"type":"result",...}` (or `{"type":"error"}`)
+pub async fn optimize_ws(
+    State(state): State<AppState>,
+    in_flight: Option<Extension<InFlight>>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response, AppError> {
+    check_api_key(&state, &headers)?;
+    // The session does the work, so it holds the concurrency permit
+    Ok(ws.on_upgrade(move |socket| async move {
+        let _in_flight = in_flight;
+        handle_optimize_socket(socket, state).await
+    }))
+}
+
+async fn handle_optimize_socket(mut socket: WebSocket, state: AppState) {
+    let req = loop {
+        match socket.recv().await {
+            Some(Ok(Message::Text(text))) => break serde_json::from_str::<OptimizeRequest>(&text),
+            Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+            Some(Ok(_)) => continue,
+        }
+    };
+    let req = match req {
+        Ok(req) => req,
+        Err(e) => {
+            let frame = json!({ "type": "error", "error": format!("Invalid request: {}", e) });
+            let _ = socket.send(Message::Text(frame.to_string())).await;
+            return;
+        }
+    };
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let task = tokio::spawn(async move { optimize_request(req, "single", Some(&sender)).await });
 
-    let mut result = optimizer::optimize_html(&req.html, &req.url, &req.options)?;
+    // Channel closes once the task finishes and drops the sender
+    while let Some(progress) = receiver.recv().await {
+        let frame = json!({
+            "type": "progress",
+            "stage": progress.stage,
+            "current": progress.current,
+            "total": progress.total,
+        });
+        if socket.send(Message::Text(frame.to_string())).await.is_err() {
+            task.abort();
+            return;
+        }
+    }
 
-    // WebP conversion if enabled
-    let images = if req.options.convert_webp {
-        tracing::info!("WebP conversion: Starting for {}", req.url);
-        let webp_result = crate::webp_converter::convert_images_in_html(&result.html, &req.url, req.options.resize_images).await;
+    let frame = match task.await {
+        Ok(Ok(response)) => {
+            if let Some(resources) = &response.resources {
+                state.assets.insert_resources(resources);
+            }
+            let mut frame = serde_json::to_value(&response).unwrap_or_default();
+            frame["type"] = json!("result");
+            frame
+        }
+        Ok(Err(e)) => json!({ "type": "error", "error": e.to_string() }),
+        Err(e) => json!({ "type": "error", "error": format!("Optimization task failed: {}", e) }),
+    };
+    let _ = socket.send(Message::Text(frame.to_string())).await;
+    let _ = socket.close().await;
+}
+
+/// Convert the page's images to WebP and rewrite `result.html` to use them (downloads images)
+pub async fn apply_webp_conversion(
+    result: &mut OptimizeResult,
+    page_url: &url::Url,
+    options: &OptimizeOptions,
+    progress: Option<&ProgressSender>,
+) -> Option<WebpImagesResponse> {
+    if options.convert_webp {
+        tracing::info!(url = %page_url, "WebP conversion: Starting");
+        let webp_result = crate::webp_converter::convert_images_in_html(&result.html, page_url.as_str(), options, progress).await;
+        if webp_result.skipped_count > 0 {
+            result.optimizations.push(format!(
+                "Warning: {} images not converted (max_images is {})",
+                webp_result.skipped_count, options.max_images
+            ));
+        }
         
         if !webp_result.images.is_empty() {
             // Rewrite HTML with placeholder paths (WordPress will replace with actual paths)
-            let upload_base = ".".to_string();
-            crate::webp_converter::rewrite_html_with_webp(&mut result.html, &webp_result.images, &upload_base);
+            let image_template = options.path_template(&options.image_path_template, page_url);
+            crate::webp_converter::rewrite_html_with_webp(&mut result.html, &webp_result.images, &image_template, options.keep_original);
             
             result.optimizations.push(format!(
                 "{} images converted to WebP (saved {:.1} KB)",
@@ -209,30 +933,54 @@ pub async fn optimize(
                     original_size: img.original_size,
                     webp_size: img.webp_size,
                     reduction_percent: img.reduction_percent,
+                    original_filename: img.original_filename,
+                    original_base64: img.original_base64,
+                    variants: img.variants,
+                    quality: img.quality,
                 }).collect(),
                 total_original_kb: webp_result.total_original_kb,
                 total_webp_kb: webp_result.total_webp_kb,
                 total_savings_kb: webp_result.total_savings_kb,
+                skipped_count: webp_result.skipped_count,
             })
         } else {
             None
         }
     } else {
         None
-    };
+    }
+}
 
-    // External resource optimization if enabled
-    let resources = if req.options.optimize_resources {
-        tracing::info!("Resource optimization: Starting for {}", req.url);
+/// Optimize and combine external CSS/JS and rewrite `result.html` to use them (downloads resources)
+pub async fn apply_resource_optimization(
+    result: &mut OptimizeResult,
+    page_url: &url::Url,
+    options: &OptimizeOptions,
+    progress: Option<&ProgressSender>,
+) -> Option<ResourcesResponse> {
+    // External resource optimization if enabled (never for fragments - there is no <head> to own)
+    if options.optimize_resources && !options.fragment {
+        tracing::info!(url = %page_url, "Resource optimization: Starting");
         
         // Get used selectors from CSS optimizer for tree-shaking
         let used_selectors = crate::css_optimizer::CssOptimizer::extract_used_selectors_static(&result.html);
-        let res_result = crate::resource_optimizer::optimize_external_resources(&result.html, &req.url, &used_selectors, &req.options).await;
+        let res_result = crate::resource_optimizer::optimize_external_resources(&result.html, page_url.as_str(), &used_selectors, options, progress).await;
+        if res_result.skipped_count > 0 {
+            result.optimizations.push(format!(
+                "Warning: {} CSS/JS files not optimized (max_resources is {})",
+                res_result.skipped_count, options.max_resources
+            ));
+        }
         
         if !res_result.css_files.is_empty() || !res_result.js_files.is_empty() {
             // Rewrite HTML with local paths
-            let upload_base = ".".to_string();
-            crate::resource_optimizer::rewrite_html_with_optimized_resources(&mut result.html, &res_result, &upload_base);
+            let asset_template = options.path_template(&options.asset_path_template, page_url);
+            crate::resource_optimizer::rewrite_html_with_optimized_resources(&mut result.html, &res_result, &asset_template, options);
+            let preload_headers = if options.emit_preload_headers {
+                crate::resource_optimizer::preload_headers(&res_result, &asset_template, options)
+            } else {
+                Vec::new()
+            };
             
             result.optimizations.push(format!(
                 "{} CSS files optimized (saved {:.1} KB), {} JS files optimized (saved {:.1} KB)",
@@ -240,6 +988,9 @@ pub async fn optimize(
                 res_result.js_files.len(), res_result.total_js_savings_kb
             ));
             
+            for warning in &res_result.font_warnings {
+                result.optimizations.push(format!("Font: {}", warning));
+            }
             if res_result.critical_css.is_some() {
                 result.optimizations.push("Critical CSS extracted and inlined".to_string());
             }
@@ -266,41 +1017,32 @@ pub async fn optimize(
                 combined_js: res_result.combined_js,
                 combined_css_filename: res_result.combined_css_filename,
                 combined_js_filename: res_result.combined_js_filename,
+                combined_css_br: res_result.combined_css_br,
+                combined_css_gz: res_result.combined_css_gz,
+                combined_js_br: res_result.combined_js_br,
+                combined_js_gz: res_result.combined_js_gz,
                 total_css_savings_kb: res_result.total_css_savings_kb,
                 total_js_savings_kb: res_result.total_js_savings_kb,
+                skipped_count: res_result.skipped_count,
+                external_css_eliminated: res_result.external_css_eliminated,
+                fonts: res_result.fonts,
+                preload_headers,
             })
         } else {
             None
         }
     } else {
         None
-    };
-
-    let response = OptimizeResponse {
-        success: true,
-        optimized_html: result.html,
-        original_size: result.original_size,
-        optimized_size: result.optimized_size,
-        reduction_percent: result.reduction_percent,
-        optimizations: result.optimizations,
-        images,
-        resources,
-    };
-
-    tracing::info!(
-        "Optimized: {} -> {} bytes ({:.1}% reduction)",
-        response.original_size,
-        response.optimized_size,
-        response.reduction_percent
-    );
-
-    Ok(Json(response))
+    }
 }
 
 /// Bulk optimization request
 #[derive(Deserialize)]
 pub struct BulkOptimizeRequest {
     pub pages: Vec<OptimizeRequest>,
+    /// Run as a background job and stream progress via `/api/v1/jobs/:id/stream`
+    #[serde(default)]
+    pub stream: bool,
 }
 
 #[derive(Serialize)]
@@ -308,75 +1050,642 @@ pub struct BulkOptimizeResponse {
     pub success: bool,
     pub results: Vec<OptimizeResponse>,
     pub total_reduction: f64,
+    /// HTML bytes in and out across all pages
+    pub total_original_bytes: usize,
+    pub total_optimized_bytes: usize,
+    /// Sum of every page's `saved_bytes` (HTML, images and CSS/JS)
+    pub total_saved_bytes: usize,
+}
+
+/// Returned instead of the results when a bulk request runs as a streaming job
+#[derive(Serialize)]
+pub struct BulkJobResponse {
+    pub success: bool,
+    pub job_id: String,
+    pub stream_url: String,
+}
+
+/// Optimize one page of a bulk request (full pipeline, network steps included),
+/// never failing the whole batch
+async fn optimize_page(page: OptimizeRequest, assets: AssetCache) -> OptimizeResponse {
+    let url = page.url.clone();
+    let html = page.html.clone();
+    match optimize_request(page, "bulk", None).await {
+        Ok(response) => {
+            if let Some(resources) = &response.resources {
+                assets.insert_resources(resources);
+            }
+            response
+        }
+        Err(e) => {
+            crate::metrics::record_optimization("bulk", false, 0, 0);
+            tracing::warn!("Failed to optimize {}: {}", url, e);
+            failed_page(html, e.to_string())
+        }
+    }
+}
+
+fn failed_page(html: String, error: String) -> OptimizeResponse {
+    OptimizeResponse {
+        success: false,
+        optimized_html: html,
+        original_size: 0,
+        optimized_size: 0,
+        reduction_percent: 0.0,
+        saved_bytes: 0,
+        original_node_count: 0,
+        optimized_node_count: 0,
+        optimizations: vec![],
+        changes_detail: Vec::new(),
+        images: None,
+        resources: None,
+        removed_selectors: vec![],
+        font_subset_hint: None,
+        request_id: None,
+        diff: None,
+        external_hosts: Vec::new(),
+        error: Some(error),
+    }
+}
+
+/// Pages of a bulk request optimized at most this many at a time
+const BULK_CONCURRENCY: usize = 4;
+
+/// Optimize bulk pages on their own tasks, a bounded number at a time, yielding results in input order
+/// Pages share one `BatchDownloads`, so CSS/JS/fonts common to the site are downloaded once
+fn optimize_pages(pages: Vec<OptimizeRequest>, assets: AssetCache) -> impl Stream<Item = OptimizeResponse> {
+    let downloads = crate::url_utils::BatchDownloads::default();
+    futures::stream::iter(pages)
+        .map(move |page| {
+            let page = downloads.scope(optimize_page(page, assets.clone()));
+            async move {
+                tokio::spawn(page)
+                    .await
+                    .unwrap_or_else(|e| {
+                        tracing::error!("Bulk page task failed: {}", e);
+                        failed_page(String::new(), "Internal error: optimization task failed".to_string())
+                    })
+            }
+        })
+        .buffered(BULK_CONCURRENCY)
+}
+
+fn total_reduction(total_original: usize, total_optimized: usize) -> f64 {
+    if total_original > 0 {
+        (1.0 - (total_optimized as f64 / total_original as f64)) * 100.0
+    } else {
+        0.0
+    }
 }
 
 /// Bulk optimization endpoint
 pub async fn optimize_bulk(
     State(state): State<AppState>,
+    in_flight: Option<Extension<InFlight>>,
     headers: HeaderMap,
     Json(req): Json<BulkOptimizeRequest>,
-) -> Result<Json<BulkOptimizeResponse>, AppError> {
-    // Check API Key
-    if let Some(ref key) = state.api_key {
-        let auth_header = headers
-            .get("Authorization")
-            .and_then(|h| h.to_str().ok())
-            .unwrap_or("");
-        
-        if auth_header != format!("Bearer {}", key) {
-            return Err(AppError::Unauthorized);
-        }
-    } else {
-        tracing::error!("Security Error: No API Key configured on server");
-        return Err(AppError::Internal("Server misconfiguration: API_KEY must be set".to_string()));
+) -> Result<Response, AppError> {
+    check_api_key(&state, &headers)?;
+
+    if req.stream {
+        let in_flight = in_flight.map(|Extension(in_flight)| in_flight).unwrap_or_default();
+        let job_id = start_bulk_job(&state, req.pages, in_flight);
+        return Ok(Json(BulkJobResponse {
+            success: true,
+            stream_url: format!("/api/v1/jobs/{}/stream", job_id),
+            job_id,
+        }).into_response());
     }
 
     let mut results = Vec::new();
     let mut total_original = 0usize;
     let mut total_optimized = 0usize;
+    let mut total_saved = 0usize;
+
+    let mut responses = std::pin::pin!(optimize_pages(req.pages, state.assets.clone()));
+    while let Some(response) = responses.next().await {
+        total_original += response.original_size;
+        total_optimized += response.optimized_size;
+        total_saved += response.saved_bytes;
+        results.push(response);
+    }
+
+    Ok(Json(BulkOptimizeResponse {
+        success: true,
+        results,
+        total_reduction: total_reduction(total_original, total_optimized),
+        total_original_bytes: total_original,
+        total_optimized_bytes: total_optimized,
+        total_saved_bytes: total_saved,
+    }).into_response())
+}
+
+/// Spawn a bulk job on a background task, publishing one event per page and a final summary
+/// The job holds the request's concurrency permit until its last page is done
+fn start_bulk_job(state: &AppState, pages: Vec<OptimizeRequest>, in_flight: InFlight) -> String {
+    let total = pages.len();
+    // One event per page plus the summary
+    let job_id = state.jobs.create(total + 1);
+    let jobs = state.jobs.clone();
+    let assets = state.assets.clone();
+    let id = job_id.clone();
+
+    tracing::info!("Bulk job {}: Starting {} pages", id, total);
+
+    tokio::spawn(async move {
+        let mut total_original = 0usize;
+        let mut total_optimized = 0usize;
+        let mut total_saved = 0usize;
+        let mut succeeded = 0usize;
 
-    for page in req.pages {
-        match optimizer::optimize_html(&page.html, &page.url, &page.options) {
-            Ok(result) => {
-                total_original += result.original_size;
-                total_optimized += result.optimized_size;
-
-                results.push(OptimizeResponse {
-                    success: true,
-                    optimized_html: result.html,
-                    original_size: result.original_size,
-                    optimized_size: result.optimized_size,
-                    reduction_percent: result.reduction_percent,
-                    optimizations: result.optimizations,
-                    images: None,
-                    resources: None,
-                });
+        let mut responses = std::pin::pin!(optimize_pages(pages, assets).enumerate());
+        while let Some((index, response)) = responses.next().await {
+            total_original += response.original_size;
+            total_optimized += response.optimized_size;
+            total_saved += response.saved_bytes;
+            if response.success {
+                succeeded += 1;
             }
-            Err(e) => {
-                tracing::warn!("Failed to optimize {}: {}", page.url, e);
-                results.push(OptimizeResponse {
-                    success: false,
-                    optimized_html: page.html,
-                    original_size: 0,
-                    optimized_size: 0,
-                    reduction_percent: 0.0,
-                    optimizations: vec![],
-                    images: None,
-                    resources: None,
-                });
+            jobs.publish(&id, JobEvent::page(json!({
+                "index": index,
+                "total": total,
+                "result": response,
+            })));
+        }
+
+        jobs.publish(&id, JobEvent::summary(json!({
+            "pages": total,
+            "succeeded": succeeded,
+            "failed": total - succeeded,
+            "total_reduction": total_reduction(total_original, total_optimized),
+            "total_original_bytes": total_original,
+            "total_optimized_bytes": total_optimized,
+            "total_saved_bytes": total_saved,
+        })));
+        tracing::info!("Bulk job {}: Finished ({}/{} succeeded)", id, succeeded, total);
+        drop(in_flight);
+
+        tokio::time::sleep(JOB_RETENTION).await;
+        jobs.remove(&id);
+    });
+
+    job_id
+}
+
+/// Server-Sent Events stream of a bulk job's progress
+/// Late subscribers first receive every event already published
+pub async fn job_stream(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(job_id): Path<String>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    check_api_key(&state, &headers)?;
+
+    let (history, receiver) = state
+        .jobs
+        .subscribe(&job_id)
+        .ok_or_else(|| AppError::NotFound(format!("Unknown job: {}", job_id)))?;
+
+    let live = futures::stream::unfold(receiver, |receiver| async move {
+        let mut receiver = receiver?;
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    let next = if event.is_final() { None } else { Some(receiver) };
+                    return Some((event, next));
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Job stream lagged, {} events skipped", skipped);
+                }
+                Err(RecvError::Closed) => return None,
             }
         }
+    });
+
+    let stream = futures::stream::iter(history)
+        .chain(live)
+        .map(|event| Ok(Event::default().event(event.name).data(event.data)));
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_state;
+
+    #[tokio::test]
+    async fn test_probe_connectivity_reports_ready() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new().route("/", axum::routing::get(|| async { "ok" }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let response = probe_connectivity(&client, &format!("http://{}/", addr), std::time::Duration::from_secs(2)).await;
+        assert!(response.ready);
+        assert!(response.latency_ms.is_some());
     }
 
-    let total_reduction = if total_original > 0 {
-        (1.0 - (total_optimized as f64 / total_original as f64)) * 100.0
-    } else {
-        0.0
-    };
+    #[tokio::test]
+    async fn test_probe_connectivity_reports_unreachable() {
+        // Bind then drop to get a port with nothing listening
+        let addr = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
 
-    Ok(Json(BulkOptimizeResponse {
-        success: true,
-        results,
-        total_reduction,
-    }))
+        let client = reqwest::Client::new();
+        let response = probe_connectivity(&client, &format!("http://{}/", addr), std::time::Duration::from_secs(2)).await;
+        assert!(!response.ready);
+        assert!(response.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_optimize_ws_streams_progress_then_result() {
+        use futures::SinkExt;
+        use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message as WsMessage};
+
+        let state = test_state();
+        let app = axum::Router::new()
+            .route("/api/v1/optimize/ws", axum::routing::get(optimize_ws))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut handshake = format!("ws://{}/api/v1/optimize/ws", addr).into_client_request().unwrap();
+        handshake.headers_mut().insert("Authorization", "Bearer secret".parse().unwrap());
+        let (mut socket, _) = tokio_tungstenite::connect_async(handshake).await.unwrap();
+        let request = json!({
+            "html": "<html><head></head><body><p>Hello</p></body></html>",
+            "url": "https://example.com/",
+            "options": { "convert_webp": false, "optimize_resources": false }
+        });
+        socket.send(WsMessage::Text(request.to_string())).await.unwrap();
+
+        let mut frames = Vec::new();
+        while let Some(Ok(WsMessage::Text(text))) = socket.next().await {
+            frames.push(serde_json::from_str::<serde_json::Value>(&text).unwrap());
+        }
+        assert_eq!(frames.first().unwrap()["type"], "progress");
+        assert_eq!(frames.first().unwrap()["stage"], "html");
+        let last = frames.last().unwrap();
+        assert_eq!(last["type"], "result");
+        assert_eq!(last["success"], true);
+    }
+
+    /// Sum of every `htmlwp_optimizations_total` sample for the single-page endpoint
+    fn single_optimizations(exposition: &str) -> f64 {
+        exposition.lines()
+            .filter(|line| line.starts_with(crate::metrics::OPTIMIZATIONS_TOTAL) && line.contains("endpoint=\"single\""))
+            .filter_map(|line| line.rsplit(' ').next()?.parse::<f64>().ok())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn test_metrics_count_optimizations() {
+        let state = test_state();
+        let app = axum::Router::new()
+            .route("/metrics", axum::routing::get(metrics))
+            .route("/api/v1/optimize", axum::routing::post(optimize))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // No Authorization header: /metrics is public
+        let client = reqwest::Client::new();
+        let scrape = || async {
+            client.get(format!("http://{}/metrics", addr)).send().await.unwrap().text().await.unwrap()
+        };
+        let before = single_optimizations(&scrape().await);
+
+        let response = client.post(format!("http://{}/api/v1/optimize", addr))
+            .header("Authorization", "Bearer secret")
+            .header("Content-Type", "application/json")
+            .body(json!({
+                "html": "<html><head></head><body><p>Hello</p></body></html>",
+                "url": "https://example.com/",
+                "options": { "convert_webp": false, "optimize_resources": false }
+            }).to_string())
+            .send().await.unwrap();
+        assert!(response.status().is_success());
+
+        let after = scrape().await;
+        assert!(single_optimizations(&after) >= before + 1.0);
+        assert!(after.contains("htmlwp_stage_duration_seconds_bucket"));
+        // Disabled stages still report their (near zero) timing
+        assert!(after.contains(r#"stage="images""#) && after.contains(r#"stage="resources""#), "{}", after);
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_echoed_or_generated() {
+        let state = test_state();
+        let app = crate::request_id::with_request_id(
+            axum::Router::new().route("/api/v1/optimize", axum::routing::post(optimize))
+        ).with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let client = reqwest::Client::new();
+        let send = |request_id: Option<&'static str>| {
+            let mut request = client.post(format!("http://{}/api/v1/optimize", addr))
+                .header("Authorization", "Bearer secret")
+                .header("Content-Type", "application/json")
+                .body(json!({ "html": "<p>Hi</p>", "url": "https://example.com/", "options": { "convert_webp": false, "optimize_resources": false } }).to_string());
+            if let Some(id) = request_id {
+                request = request.header("X-Request-Id", id);
+            }
+            request.send()
+        };
+
+        let response = send(Some("support-ticket-42")).await.unwrap();
+        assert_eq!(response.headers()["x-request-id"], "support-ticket-42");
+        let body: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(body["request_id"], "support-ticket-42");
+
+        let response = send(None).await.unwrap();
+        let generated = response.headers()["x-request-id"].to_str().unwrap().to_string();
+        assert!(uuid::Uuid::parse_str(&generated).is_ok());
+        let body: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert_eq!(body["request_id"], generated.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_cached_resources_are_served_with_content_types() {
+        let assets = crate::asset_cache::AssetCache::new(std::time::Duration::from_secs(60), 1024 * 1024);
+        assets.insert("styles.0badf00d.min.css", ".a{color:red}");
+        assets.insert("scripts.0badf00d.min.js", "var a=1");
+        let state = AppState {
+            assets,
+            ..test_state()
+        };
+        let app = axum::Router::new()
+            .route("/api/v1/resource/:filename", axum::routing::get(resource))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        // No Authorization header: CDNs fetch these directly
+        let get = |filename: &'static str| reqwest::get(format!("http://{}/api/v1/resource/{}", addr, filename));
+        let css = get("styles.0badf00d.min.css").await.unwrap();
+        assert_eq!(css.headers()["content-type"], "text/css; charset=utf-8");
+        assert!(css.headers()["cache-control"].to_str().unwrap().contains("immutable"));
+        assert_eq!(css.text().await.unwrap(), ".a{color:red}");
+
+        let js = get("scripts.0badf00d.min.js").await.unwrap();
+        assert_eq!(js.headers()["content-type"], "application/javascript; charset=utf-8");
+
+        assert_eq!(get("styles.deadbeef.min.css").await.unwrap().status(), 404);
+        assert_eq!(get("photo.webp").await.unwrap().status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_offline_skips_network_steps() {
+        let req: OptimizeRequest = serde_json::from_value(json!({
+            "html": r#"<html><head><link rel="stylesheet" href="http://127.0.0.1:1/style.css"></head><body><img src="http://127.0.0.1:1/photo.jpg"></body></html>"#,
+            "url": "https://example.com/",
+            "offline": true,
+            "options": { "convert_webp": true, "optimize_resources": true }
+        })).unwrap();
+
+        let before = crate::url_utils::DOWNLOAD_CLIENTS_BUILT.with(|built| built.get());
+        let response = optimize_request(req, "single", None).await.unwrap();
+        assert!(response.images.is_none());
+        assert!(response.resources.is_none());
+        assert_eq!(crate::url_utils::DOWNLOAD_CLIENTS_BUILT.with(|built| built.get()), before);
+    }
+
+    #[tokio::test]
+    async fn test_css_blacklist_removes_used_rule() {
+        let req: OptimizeRequest = serde_json::from_value(json!({
+            "html": r#"<html><head><style>.cookie-banner{position:fixed}.notice{color:red}</style></head><body><div class="cookie-banner notice">Hi</div></body></html>"#,
+            "url": "https://example.com/",
+            "options": { "css_blacklist": ["cookie-banner"] }
+        })).unwrap();
+        assert_eq!(req.options.css_blocklist, vec!["cookie-banner".to_string()]);
+
+        let response = optimize_request(req, "single", None).await.unwrap();
+        assert!(!response.optimized_html.contains(".cookie-banner{"));
+        assert!(response.optimized_html.contains(".notice{color:red}"));
+    }
+
+    #[test]
+    fn test_min_reduction_threshold() {
+        let options = OptimizeOptions {
+            min_reduction_percent: 5.0,
+            ..OptimizeOptions::default()
+        };
+        // 1% reduction is rejected, 10% kept, growth always rejected
+        assert!(!options.is_worthwhile_reduction(1000, 990));
+        assert!(options.is_worthwhile_reduction(1000, 900));
+        assert!(!options.is_worthwhile_reduction(1000, 1000));
+        assert!(OptimizeOptions::default().is_worthwhile_reduction(1000, 970));
+    }
+
+    #[test]
+    fn test_parse_page_url_accepts_http_and_https() {
+        assert_eq!(parse_page_url("https://example.com/blog/").unwrap().as_str(), "https://example.com/blog/");
+        assert!(parse_page_url("http://localhost:8080").is_ok());
+    }
+
+    #[test]
+    fn test_parse_page_url_rejects_other_schemes() {
+        for url in ["javascript:alert(1)", "file:///etc/passwd", "ftp://example.com/", "/relative/path", ""] {
+            assert!(matches!(parse_page_url(url), Err(AppError::BadRequest(_))), "{} should be rejected", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bulk_pages_keep_order_and_report_errors() {
+        let page = |url: &str| OptimizeRequest {
+            html: "<html><body><p>Hello</p></body></html>".to_string(),
+            url: url.to_string(),
+            options: OptimizeOptions::default(),
+            offline: true,
+        };
+        let results: Vec<_> = optimize_pages(vec![page("https://example.com/"), page("ftp://example.com/")], AssetCache::new(std::time::Duration::ZERO, 0))
+            .collect()
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].success);
+        assert!(results[0].error.is_none());
+        assert!(!results[1].success);
+        assert!(results[1].error.as_deref().unwrap().contains("Unsupported URL scheme"));
+        assert_eq!(results[1].optimized_html, "<html><body><p>Hello</p></body></html>");
+    }
+
+    #[test]
+    fn test_safe_mode_keeps_only_low_risk_passes() {
+        let mut options = OptimizeOptions {
+            safe_mode: true,
+            prune_attributes: vec!["class=wp-block-*".to_string()],
+            ..OptimizeOptions::default()
+        };
+        options.apply_safe_mode();
+        assert!(!options.remove_unused_css && !options.combine && !options.optimize_resources && !options.defer_js);
+        assert!(options.prune_attributes.is_empty());
+        assert!(options.minify_html && options.lazy_images && options.convert_webp);
+
+        let html = r#"<html><head><title>T</title><style>.unused { color: red; }</style></head><body><img src="a.jpg"><script>var  keep = 1;</script></body></html>"#;
+        let result = optimizer::optimize_html_only(html, "https://example.com/", &options);
+        assert!(result.html.contains(".unused { color: red; }"));
+        assert!(result.html.contains("var  keep = 1;"));
+        assert!(result.html.contains(r#"loading="lazy""#));
+        assert!(result.html.contains("alt="));
+    }
+
+    #[tokio::test]
+    async fn test_bulk_reports_saved_bytes() {
+        let state = test_state();
+        let html = format!("<html><head><title>Page</title></head><body>{}<p>Hello</p></body></html>", "\n    <!-- comment -->".repeat(200));
+        let req: BulkOptimizeRequest = serde_json::from_value(json!({
+            "pages": [
+                { "html": &html, "url": "https://example.com/a", "offline": true, "options": { "minify_css": false } },
+                { "html": &html, "url": "https://example.com/b", "offline": true },
+            ]
+        })).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", "Bearer secret".parse().unwrap());
+        let response = optimize_bulk(State(state), None, headers, Json(req)).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let saved = body["total_saved_bytes"].as_u64().unwrap();
+        assert!(saved > 0);
+        assert_eq!(body["total_original_bytes"].as_u64().unwrap(), 2 * html.len() as u64);
+        let per_page: u64 = body["results"].as_array().unwrap().iter().map(|r| r["saved_bytes"].as_u64().unwrap()).sum();
+        assert_eq!(per_page, saved);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_saved_bytes_include_images_and_resources() {
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(256, 256, |x, y| image::Rgb([x as u8, y as u8, (x ^ y) as u8])))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        let css = format!("{}\n.hero {{ color: red; }}\n", "/* theme notes */\n".repeat(50));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new()
+            .route("/photo.png", axum::routing::get(move || async move { png }))
+            .route("/style.css", axum::routing::get(move || async move { css }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let state = test_state();
+        let html = format!(
+            r#"<html><head><title>Page</title><link rel="stylesheet" href="http://{0}/style.css"></head><body><div class="hero">Hi</div><img src="http://{0}/photo.png" alt="Photo"></body></html>"#,
+            addr
+        );
+        let req: BulkOptimizeRequest = serde_json::from_value(json!({
+            "pages": [{ "html": &html, "url": format!("http://{}/", addr) }]
+        })).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", "Bearer secret".parse().unwrap());
+        let response = optimize_bulk(State(state), None, headers, Json(req)).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        let page = &body["results"][0];
+        let image = &page["images"]["images"][0];
+        let image_saved = image["original_size"].as_u64().unwrap() - image["webp_size"].as_u64().unwrap();
+        let css_file = &page["resources"]["css_files"][0];
+        let css_saved = css_file["original_size"].as_u64().unwrap() - css_file["optimized_size"].as_u64().unwrap();
+        assert!(image_saved > 0 && css_saved > 0, "{}", page);
+
+        let html_saved = page["original_size"].as_u64().unwrap().saturating_sub(page["optimized_size"].as_u64().unwrap());
+        assert_eq!(page["saved_bytes"].as_u64().unwrap(), html_saved + image_saved + css_saved);
+        assert_eq!(body["total_saved_bytes"], page["saved_bytes"]);
+    }
+
+    #[tokio::test]
+    async fn test_bulk_matches_single_and_shares_downloads() {
+        let hits = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = hits.clone();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new().route("/style.css", axum::routing::get(move || {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            async { ".hero { color: red; }\n.unused { color: blue; }" }
+        }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let html = format!(
+            r#"<html><head><title>Page</title><link rel="stylesheet" href="http://{}/style.css"></head><body><div class="hero">Hi</div></body></html>"#,
+            addr
+        );
+        let page = || OptimizeRequest {
+            html: html.clone(),
+            url: "https://example.com/".to_string(),
+            options: OptimizeOptions::default(),
+            offline: false,
+        };
+
+        let single = optimize_request(page(), "single", None).await.unwrap();
+        assert!(single.resources.is_some());
+        assert_eq!(hits.swap(0, std::sync::atomic::Ordering::SeqCst), 1);
+
+        let bulk: Vec<_> = optimize_pages(vec![page(), page(), page()], AssetCache::new(std::time::Duration::ZERO, 0))
+            .collect()
+            .await;
+        for response in &bulk {
+            assert_eq!(response.optimizations, single.optimizations);
+            assert_eq!(response.optimized_html, single.optimized_html);
+        }
+        assert_eq!(hits.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_include_diff_shows_added_alt() {
+        let req: OptimizeRequest = serde_json::from_value(json!({
+            "html": "<html>\n<head><title>Diff</title></head>\n<body>\n<img src=\"/hero-banner.jpg\">\n</body>\n</html>",
+            "url": "https://example.com/",
+            "offline": true,
+            "options": { "include_diff": true, "minify_html": false }
+        })).unwrap();
+        let response = optimize_request(req, "single", None).await.unwrap();
+        let diff = response.diff.unwrap();
+        assert!(diff.starts_with("--- original\n+++ optimized\n"));
+        assert!(diff.lines().any(|line| line.starts_with('+') && line.contains(r#"alt="Hero banner""#)), "{}", diff);
+
+        let req: OptimizeRequest = serde_json::from_value(json!({ "html": "<p>x</p>", "url": "https://example.com/", "offline": true })).unwrap();
+        assert!(optimize_request(req, "single", None).await.unwrap().diff.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_asset_caps_are_reported() {
+        // Nothing listens on port 1, and with both caps at zero nothing is downloaded anyway
+        let req: OptimizeRequest = serde_json::from_value(json!({
+            "html": r#"<html><head><link rel="stylesheet" href="http://127.0.0.1:1/a.css"><link rel="stylesheet" href="http://127.0.0.1:1/b.css"></head><body><img src="http://127.0.0.1:1/photo.jpg"></body></html>"#,
+            "url": "http://127.0.0.1:1/",
+            "options": { "max_images": 0, "max_resources": 0 }
+        })).unwrap();
+        let response = optimize_request(req, "single", None).await.unwrap();
+        assert!(response.optimizations.contains(&"Warning: 1 images not converted (max_images is 0)".to_string()));
+        assert!(response.optimizations.contains(&"Warning: 2 CSS/JS files not optimized (max_resources is 0)".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_pagination_links_from_request_options() {
+        let html = "<html><head><title>Archive</title></head><body><p>Posts</p></body></html>";
+        let req: OptimizeRequest = serde_json::from_value(json!({
+            "html": html,
+            "url": "https://example.com/blog/page/2/",
+            "offline": true,
+            "options": {
+                "prev_url": "https://example.com/blog/",
+                "next_url": "https://example.com/blog/page/3/"
+            }
+        })).unwrap();
+        let response = optimize_request(req, "single", None).await.unwrap();
+        assert!(response.optimized_html.contains("https://example.com/blog/page/3/"), "{}", response.optimized_html);
+        assert_eq!(response.optimized_html.matches("rel=prev").count() + response.optimized_html.matches("rel=\"prev\"").count(), 1);
+        assert_eq!(response.optimized_html.matches("rel=next").count() + response.optimized_html.matches("rel=\"next\"").count(), 1);
+
+        let req: OptimizeRequest = serde_json::from_value(json!({ "html": html, "url": "https://example.com/blog/", "offline": true })).unwrap();
+        let response = optimize_request(req, "single", None).await.unwrap();
+        assert!(!response.optimized_html.contains("prev") && !response.optimized_html.contains("next"));
+    }
 }
@@ -0,0 +1,413 @@
+//! HTML Utilities
+//! Small tag-level helpers shared by the string-based rewrite passes
+
+/// A parsed attribute inside a start tag
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attribute {
+    /// Lowercased attribute name
+    pub name: String,
+    /// Raw value (None for bare boolean attributes like `defer`)
+    pub value: Option<String>,
+    /// Byte range of the attribute in the tag, including its leading whitespace
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Elements whose content is raw text and must not be scanned for tags
+const RAW_TEXT_ELEMENTS: [&str; 4] = ["script", "style", "textarea", "title"];
+
+/// Find the end of a tag starting at `start` (index just past its `>`)
+/// Quoted attribute values may contain `>`
+pub fn find_tag_end(html: &str, start: usize) -> Option<usize> {
+    let mut quote: Option<char> = None;
+    let mut prev_significant = ' ';
+
+    for (offset, c) in html[start..].char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => {
+                if (c == '"' || c == '\'') && prev_significant == '=' {
+                    quote = Some(c);
+                } else if c == '>' {
+                    return Some(start + offset + 1);
+                }
+                if !c.is_whitespace() {
+                    prev_significant = c;
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Lowercased element name of a start tag
+pub fn tag_name(tag: &str) -> String {
+    tag.trim_start_matches('<')
+        .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .next()
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+/// Parse all attributes of a single start tag
+pub fn parse_attributes(tag: &str) -> Vec<Attribute> {
+    let mut attrs = Vec::new();
+    let bytes = tag.as_bytes();
+    let len = bytes.len();
+
+    // Skip `<name`
+    let mut i = 1;
+    while i < len && !bytes[i].is_ascii_whitespace() && bytes[i] != b'>' && bytes[i] != b'/' {
+        i += 1;
+    }
+
+    loop {
+        let start = i;
+        while i < len && (bytes[i].is_ascii_whitespace() || bytes[i] == b'/') {
+            i += 1;
+        }
+        if i >= len || bytes[i] == b'>' {
+            break;
+        }
+
+        let name_start = i;
+        while i < len && !bytes[i].is_ascii_whitespace() && !matches!(bytes[i], b'=' | b'>' | b'/') {
+            i += 1;
+        }
+        let name = tag[name_start..i].to_ascii_lowercase();
+
+        // Optional `= value`
+        let mut j = i;
+        while j < len && bytes[j].is_ascii_whitespace() {
+            j += 1;
+        }
+        let mut value = None;
+        if j < len && bytes[j] == b'=' {
+            j += 1;
+            while j < len && bytes[j].is_ascii_whitespace() {
+                j += 1;
+            }
+            if j < len && (bytes[j] == b'"' || bytes[j] == b'\'') {
+                let quote = bytes[j];
+                let value_start = j + 1;
+                let mut value_end = value_start;
+                while value_end < len && bytes[value_end] != quote {
+                    value_end += 1;
+                }
+                value = Some(tag[value_start..value_end].to_string());
+                i = (value_end + 1).min(len);
+            } else {
+                let value_start = j;
+                while j < len && !bytes[j].is_ascii_whitespace() && bytes[j] != b'>' {
+                    j += 1;
+                }
+                // A trailing `/` belongs to a self-closing tag, not the value
+                let mut value_end = j;
+                if value_end > value_start && bytes[value_end - 1] == b'/' && j < len && bytes[j] == b'>' {
+                    value_end -= 1;
+                }
+                value = Some(tag[value_start..value_end].to_string());
+                i = j;
+            }
+        }
+
+        if name.is_empty() {
+            // Guard against malformed input making no progress
+            i = i.max(start + 1);
+            continue;
+        }
+        attrs.push(Attribute { name, value, start, end: i });
+    }
+
+    attrs
+}
+
+/// Get an attribute value from a single tag string (e.g. `<img src="a.jpg" alt=x>`)
+/// Matches whole attribute names only, so `src` never matches `data-src`
+pub fn get_attribute(tag: &str, name: &str) -> Option<String> {
+    let name = name.to_ascii_lowercase();
+    parse_attributes(tag)
+        .into_iter()
+        .find(|attr| attr.name == name)
+        .map(|attr| attr.value.unwrap_or_default())
+}
+
+/// Whether a `type` attribute value (None when absent) marks a classic script. Modules,
+/// import maps, JSON and templates have their own loading and scope rules
+pub fn is_classic_script_type(script_type: Option<&str>) -> bool {
+    let script_type = script_type.unwrap_or_default().trim().to_ascii_lowercase();
+    script_type.is_empty() || script_type.contains("javascript") || script_type.contains("ecmascript")
+}
+
+/// Decode the character references found in attribute values (`&amp;`, `&quot;`, numeric)
+/// Unknown or malformed references are left as written
+pub fn decode_entities(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_string();
+    }
+
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let reference = rest[1..].find(';').filter(|&semi| semi > 0 && semi <= 8).and_then(|semi| {
+            let name = &rest[1..1 + semi];
+            let c = match name {
+                "amp" => Some('&'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                _ => match name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                    None => name.strip_prefix('#').and_then(|dec| dec.parse().ok()).and_then(char::from_u32),
+                },
+            }?;
+            Some((c, semi + 2))
+        });
+        match reference {
+            Some((c, len)) => {
+                decoded.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Remove an attribute from a tag
+pub fn remove_attribute(tag: &str, name: &str) -> String {
+    let name = name.to_ascii_lowercase();
+    match parse_attributes(tag).into_iter().find(|attr| attr.name == name) {
+        Some(attr) => format!("{}{}", &tag[..attr.start], &tag[attr.end..]),
+        None => tag.to_string(),
+    }
+}
+
+/// Set an attribute on a tag, replacing any existing value
+pub fn set_attribute(tag: &str, name: &str, value: &str) -> String {
+    let new_attr = format!(" {}=\"{}\"", name, value);
+    let lower_name = name.to_ascii_lowercase();
+    match parse_attributes(tag).into_iter().find(|attr| attr.name == lower_name) {
+        Some(attr) => format!("{}{}{}", &tag[..attr.start], new_attr, &tag[attr.end..]),
+        None => {
+            let name_end = 1 + tag_name(tag).len();
+            format!("{}{}{}", &tag[..name_end], new_attr, &tag[name_end..])
+        }
+    }
+}
+
+/// Walk every start tag in document order, skipping comments and raw-text content
+/// (script/style bodies). The callback receives the element name and the full tag,
+/// and may return a replacement tag.
+pub fn rewrite_start_tags<F>(html: &str, mut f: F) -> String
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    let lower = html.to_ascii_lowercase();
+    let bytes = html.as_bytes();
+    let mut result = String::with_capacity(html.len());
+    let mut copied = 0;
+    let mut pos = 0;
+
+    while let Some(offset) = html[pos..].find('<') {
+        let start = pos + offset;
+
+        if lower[start..].starts_with("<!--") {
+            pos = lower[start..].find("-->").map(|e| start + e + 3).unwrap_or(html.len());
+            continue;
+        }
+        if !bytes.get(start + 1).is_some_and(|b| b.is_ascii_alphabetic()) {
+            pos = start + 1;
+            continue;
+        }
+        let Some(end) = find_tag_end(html, start) else {
+            break;
+        };
+
+        let tag = &html[start..end];
+        let name = tag_name(tag);
+        if let Some(new_tag) = f(&name, tag) {
+            result.push_str(&html[copied..start]);
+            result.push_str(&new_tag);
+            copied = end;
+        }
+        pos = end;
+
+        if RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+            let closing = format!("</{}", name);
+            pos = lower[end..].find(&closing).map(|e| end + e).unwrap_or(html.len());
+        }
+    }
+
+    result.push_str(&html[copied..]);
+    result
+}
+
+/// Rewrite the text content of every `element` (a raw-text element such as `style`),
+/// skipping comments and the bodies of other raw-text elements (a `"<style>"` string in a
+/// script is not a block). The callback receives the opening tag and the content and may
+/// return replacement content; tags are kept verbatim.
+pub fn rewrite_raw_text<F>(html: &str, element: &str, mut f: F) -> String
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    let mut result = String::with_capacity(html.len());
+    let mut copied = 0;
+
+    for block in raw_text_elements(html, element) {
+        if let Some(new_content) = f(&html[block.start..block.open_end], &html[block.open_end..block.content_end]) {
+            result.push_str(&html[copied..block.open_end]);
+            result.push_str(&new_content);
+            copied = block.content_end;
+        }
+    }
+
+    result.push_str(&html[copied..]);
+    result
+}
+
+/// Remove every `element` (a raw-text element such as `script`) for which the callback,
+/// given the opening tag and the content, returns true. Found the same way as `rewrite_raw_text`
+pub fn remove_raw_text_elements<F>(html: &str, element: &str, mut f: F) -> String
+where
+    F: FnMut(&str, &str) -> bool,
+{
+    replace_raw_text_elements(html, element, |tag, content| f(tag, content).then(String::new))
+}
+
+/// Replace whole `element`s (tags included): the callback receives the opening tag and the
+/// content and may return the replacement markup. Found the same way as `rewrite_raw_text`
+pub fn replace_raw_text_elements<F>(html: &str, element: &str, mut f: F) -> String
+where
+    F: FnMut(&str, &str) -> Option<String>,
+{
+    let mut result = String::with_capacity(html.len());
+    let mut copied = 0;
+
+    for block in raw_text_elements(html, element) {
+        if let Some(replacement) = f(&html[block.start..block.open_end], &html[block.open_end..block.content_end]) {
+            result.push_str(&html[copied..block.start]);
+            result.push_str(&replacement);
+            copied = block.end;
+        }
+    }
+
+    result.push_str(&html[copied..]);
+    result
+}
+
+/// A raw-text element: `<tag>` at `start..open_end`, content up to `content_end` (its closing
+/// tag), and the whole element at `start..end`
+pub struct RawTextElement {
+    pub start: usize,
+    pub open_end: usize,
+    pub content_end: usize,
+    pub end: usize,
+}
+
+/// Every `element` in document order, skipping comments and other raw-text bodies
+pub fn raw_text_elements(html: &str, element: &str) -> Vec<RawTextElement> {
+    let lower = html.to_ascii_lowercase();
+    let bytes = html.as_bytes();
+    let mut elements = Vec::new();
+    let mut pos = 0;
+
+    while let Some(offset) = html[pos..].find('<') {
+        let start = pos + offset;
+
+        if lower[start..].starts_with("<!--") {
+            pos = lower[start..].find("-->").map(|e| start + e + 3).unwrap_or(html.len());
+            continue;
+        }
+        if !bytes.get(start + 1).is_some_and(|b| b.is_ascii_alphabetic()) {
+            pos = start + 1;
+            continue;
+        }
+        let Some(end) = find_tag_end(html, start) else {
+            break;
+        };
+
+        let name = tag_name(&html[start..end]);
+        pos = end;
+        if !RAW_TEXT_ELEMENTS.contains(&name.as_str()) {
+            continue;
+        }
+
+        let closing = format!("</{}", name);
+        let content_end = lower[end..].find(&closing).map(|e| end + e).unwrap_or(html.len());
+        if name == element {
+            let element_end = html[content_end..].find('>').map(|e| content_end + e + 1).unwrap_or(html.len());
+            elements.push(RawTextElement { start, open_end: end, content_end, end: element_end });
+        }
+        pos = content_end;
+    }
+
+    elements
+}
+
+/// Whether `pos` sits inside an open `<picture>` element
+pub fn is_inside_picture(lower_html: &str, pos: usize) -> bool {
+    let before = &lower_html[..pos];
+    match (before.rfind("<picture"), before.rfind("</picture")) {
+        (Some(open), Some(close)) => open > close,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_attribute() {
+        let tag = r#"<img data-src="/real.jpg" src='/placeholder.gif' alt=Hero width=10>"#;
+        assert_eq!(get_attribute(tag, "src").as_deref(), Some("/placeholder.gif"));
+        assert_eq!(get_attribute(tag, "data-src").as_deref(), Some("/real.jpg"));
+        assert_eq!(get_attribute(tag, "alt").as_deref(), Some("Hero"));
+        assert_eq!(get_attribute(tag, "height"), None);
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(decode_entities("/a.css?ver=1&amp;load=all"), "/a.css?ver=1&load=all");
+        assert_eq!(decode_entities("&#39;x&#x27;&quot;"), "'x'\"");
+        assert_eq!(decode_entities("a & b &unknown; &amp"), "a & b &unknown; &amp");
+    }
+
+    #[test]
+    fn test_set_and_remove_attribute() {
+        let tag = r#"<div style="color:red" class="a">"#;
+        assert_eq!(remove_attribute(tag, "style"), r#"<div class="a">"#);
+        assert_eq!(set_attribute(tag, "class", "a b"), r#"<div style="color:red" class="a b">"#);
+        assert_eq!(set_attribute("<p>", "id", "x"), r#"<p id="x">"#);
+    }
+
+    #[test]
+    fn test_remove_raw_text_elements() {
+        let html = r#"<p>a</p><!-- <script>keep()</script> --><script src="x.js"></script><script>drop()</script ><style>p{}</style>"#;
+        let result = remove_raw_text_elements(html, "script", |tag, content| get_attribute(tag, "src").is_some() || content.contains("drop"));
+        assert_eq!(result, "<p>a</p><!-- <script>keep()</script> --><style>p{}</style>");
+    }
+
+    #[test]
+    fn test_rewrite_start_tags_skips_comments_and_scripts() {
+        let html = r#"<!-- <div> --><div title="a>b"><script>var s = "<div>";</script></div>"#;
+        let mut names = Vec::new();
+        rewrite_start_tags(html, |name, tag| {
+            names.push((name.to_string(), tag.to_string()));
+            None
+        });
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0].1, r#"<div title="a>b">"#);
+        assert_eq!(names[1].0, "script");
+    }
+}
@@ -27,6 +27,63 @@ impl Default for CdnConfig {
     }
 }
 
+/// Image formats recognized by extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImgFmt {
+    Jpeg,
+    Png,
+    Gif,
+    Bmp,
+    Tiff,
+    Webp,
+    Avif,
+    Svg,
+}
+
+impl ImgFmt {
+    /// Already a modern compressed format - converting gains nothing
+    pub fn is_optimized(self) -> bool {
+        matches!(self, ImgFmt::Webp | ImgFmt::Avif)
+    }
+
+    /// Raster format we can decode and re-encode as WebP
+    pub fn is_convertible(self) -> bool {
+        !self.is_optimized() && self != ImgFmt::Svg
+    }
+
+    /// Canonical file extension
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImgFmt::Jpeg => "jpg",
+            ImgFmt::Png => "png",
+            ImgFmt::Gif => "gif",
+            ImgFmt::Bmp => "bmp",
+            ImgFmt::Tiff => "tiff",
+            ImgFmt::Webp => "webp",
+            ImgFmt::Avif => "avif",
+            ImgFmt::Svg => "svg",
+        }
+    }
+}
+
+/// Image format of a URL from its extension (query strings and fragments are ignored)
+pub fn image_extension(url: &str) -> Option<ImgFmt> {
+    let path = crate::url_utils::strip_query(url);
+    let file = path.rsplit('/').next().unwrap_or(path);
+    let (_, ext) = file.rsplit_once('.')?;
+    match ext.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" | "jpe" => Some(ImgFmt::Jpeg),
+        "png" => Some(ImgFmt::Png),
+        "gif" => Some(ImgFmt::Gif),
+        "bmp" => Some(ImgFmt::Bmp),
+        "tif" | "tiff" => Some(ImgFmt::Tiff),
+        "webp" => Some(ImgFmt::Webp),
+        "avif" => Some(ImgFmt::Avif),
+        "svg" => Some(ImgFmt::Svg),
+        _ => None,
+    }
+}
+
 /// Image optimization result
 pub struct ImageResult {
     pub optimizations: Vec<String>,
@@ -102,25 +159,19 @@ pub fn rewrite_images_for_cdn(html: &mut String, site_url: &str, cdn_config: &Cd
 
 /// Check if an image URL should be rewritten for CDN
 fn should_rewrite_image(url: &str, site_url: &str) -> bool {
-    let url_lower = url.to_lowercase();
+    let format = image_extension(url);
     
     // Skip data URLs, SVGs, external images, and already-CDN URLs
     if url.starts_with("data:") || 
-       url_lower.ends_with(".svg") ||
+       format == Some(ImgFmt::Svg) ||
        url.contains("cdn-cgi/image") ||
        url.contains("imgix.net") ||
        url.contains("cloudinary.com") {
         return false;
     }
     
-    // Only rewrite images with common formats
-    let is_image = url_lower.ends_with(".jpg") || 
-                   url_lower.ends_with(".jpeg") || 
-                   url_lower.ends_with(".png") || 
-                   url_lower.ends_with(".gif") ||
-                   url_lower.ends_with(".webp");
-    
-    if !is_image {
+    // Only rewrite raster images
+    if format.is_none() {
         return false;
     }
     
@@ -175,9 +226,8 @@ fn generate_cdn_url(original_url: &str, site_url: &str, config: &CdnConfig) -> S
 }
 
 /// Analyze images and add optimization hints
-pub fn analyze_images(html: &str) -> ImageResult {
+pub fn analyze_images(doc: &Html) -> ImageResult {
     tracing::debug!("Image analysis: Starting");
-    let doc = Html::parse_document(html);
     let mut webp_candidates = 0;
     let mut missing_dimensions = 0;
     let mut missing_lazy = 0;
@@ -188,9 +238,7 @@ pub fn analyze_images(html: &str) -> ImageResult {
             
             // Check for WebP conversion candidates
             if let Some(src) = attrs.attr("src") {
-                let src_lower = src.to_lowercase();
-                if src_lower.ends_with(".jpg") || src_lower.ends_with(".jpeg") || 
-                   src_lower.ends_with(".png") || src_lower.ends_with(".gif") {
+                if image_extension(src).is_some_and(ImgFmt::is_convertible) {
                     webp_candidates += 1;
                 }
             }
@@ -238,10 +286,8 @@ pub fn suggest_webp_conversion(html: &str) -> Vec<String> {
     if let Ok(selector) = Selector::parse("img[src]") {
         for element in doc.select(&selector) {
             if let Some(src) = element.value().attr("src") {
-                let src_lower = src.to_lowercase();
-                
-                // Skip if already WebP or SVG
-                if src_lower.ends_with(".webp") || src_lower.ends_with(".svg") {
+                // Skip if already WebP/AVIF, SVG or unknown
+                if !image_extension(src).is_some_and(ImgFmt::is_convertible) {
                     continue;
                 }
 
@@ -254,18 +300,14 @@ pub fn suggest_webp_conversion(html: &str) -> Vec<String> {
                     }
                 }
 
-                // Only suggest for common formats
-                if src_lower.ends_with(".jpg") || src_lower.ends_with(".jpeg") || 
-                   src_lower.ends_with(".png") {
-                    let webp_src = src.rsplit_once('.')
-                        .map(|(name, _)| format!("{}.webp", name))
-                        .unwrap_or_else(|| format!("{}.webp", src));
-                    
-                    suggestions.push(format!(
-                        "Convert {} to WebP: {}",
-                        src, webp_src
-                    ));
-                }
+                let webp_src = src.rsplit_once('.')
+                    .map(|(name, _)| format!("{}.webp", name))
+                    .unwrap_or_else(|| format!("{}.webp", src));
+                
+                suggestions.push(format!(
+                    "Convert {} to WebP: {}",
+                    src, webp_src
+                ));
             }
         }
     }
@@ -283,7 +325,7 @@ pub fn suggest_responsive_images(html: &str) -> Vec<String> {
             if let Some(src) = element.value().attr("src") {
                 // Skip small images, external images, SVGs
                 if src.contains("icon") || src.contains("logo") || 
-                   src.ends_with(".svg") || src.starts_with("data:") {
+                   image_extension(src) == Some(ImgFmt::Svg) || src.starts_with("data:") {
                     continue;
                 }
 
@@ -320,10 +362,60 @@ pub fn add_dimension_hints(html: &mut String) -> usize {
     count
 }
 
+/// Words in an image's filename or class names that mark it as site chrome rather than content
+const NON_CONTENT_IMAGE_HINTS: [&str; 10] = [
+    "favicon", "icon", "logo", "spacer", "pixel", "blank", "avatar", "gravatar", "emoji", "sprite",
+];
+
+/// Smallest width/height worth showing as a social preview (Facebook's minimum)
+const MIN_PREVIEW_DIMENSION: u32 = 200;
+
+/// Absolute URL of the image that best represents the page, for `og:image` and schema `image`:
+/// the first content image (icons, logos, spacers, avatars and data URIs skipped), preferring
+/// one whose `width`/`height` attributes say it is at least 200px. Images declared smaller are skipped.
+/// Relative sources resolve against `base_url`, the page's `url_utils::document_base`
+pub fn primary_image(doc: &Html, base_url: &str) -> Option<String> {
+    let selector = Selector::parse("img").ok()?;
+    let mut unsized_candidate = None;
+
+    for img in doc.select(&selector) {
+        let element = img.value();
+        // Lazy loaders keep the real URL in data-src behind a placeholder
+        let Some(src) = [element.attr("src"), element.attr("data-src")].into_iter()
+            .flatten()
+            .map(str::trim)
+            .find(|src| !src.is_empty() && !src.starts_with("data:"))
+        else {
+            continue;
+        };
+
+        // Whole words only, so `silicon-valley.jpg` or alt text about a logo don't count
+        let filename = crate::url_utils::strip_query(src).rsplit('/').next().unwrap_or_default();
+        let is_chrome = [filename, element.attr("class").unwrap_or_default()].iter()
+            .flat_map(|text| text.split(|c: char| !c.is_ascii_alphanumeric()))
+            .map(str::to_ascii_lowercase)
+            .any(|word| NON_CONTENT_IMAGE_HINTS.iter().any(|hint| word == *hint || word.strip_suffix('s') == Some(*hint)));
+        if is_chrome || src.to_ascii_lowercase().ends_with(".svg") {
+            continue;
+        }
+
+        let dimension = |name| element.attr(name).and_then(|v| v.trim().trim_end_matches("px").parse::<u32>().ok());
+        let dimensions = [dimension("width"), dimension("height")];
+        if dimensions.iter().flatten().any(|d| *d < MIN_PREVIEW_DIMENSION) {
+            continue;
+        }
+        let Ok(absolute) = crate::url_utils::resolve_url(base_url, src) else { continue };
+        if dimensions.iter().all(Option::is_some) {
+            return Some(absolute);
+        }
+        unsized_candidate.get_or_insert(absolute);
+    }
+
+    unsized_candidate
+}
+
 /// Check if LCP image has fetchpriority
-pub fn check_lcp_optimization(html: &str) -> Option<String> {
-    let doc = Html::parse_document(html);
-    
+pub fn check_lcp_optimization(doc: &Html) -> Option<String> {
     // First image is likely LCP
     if let Ok(selector) = Selector::parse("img") {
         if let Some(first_img) = doc.select(&selector).next() {
@@ -348,6 +440,41 @@ pub fn check_lcp_optimization(html: &str) -> Option<String> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_primary_image_skips_chrome() {
+        let html = r#"<html><body>
+            <img src="/wp-content/uploads/site-logo.png" width="180" height="60">
+            <img src="https://www.facebook.com/tr?id=1" width="1" height="1">
+            <img src="/wp-content/uploads/author.jpg" class="avatar avatar-96">
+            <img src="data:image/gif;base64,R0lGOD" data-src="uploads/hero.jpg">
+            <img src="/wp-content/uploads/chart.png" width="800" height="450">
+        </body></html>"#;
+        let doc = Html::parse_document(html);
+        // The first sized content image beats an earlier one of unknown size
+        assert_eq!(primary_image(&doc, "https://example.com/blog/post/").as_deref(), Some("https://example.com/wp-content/uploads/chart.png"));
+
+        let doc = Html::parse_document(r#"<img src="/logo.png"><img data-src="uploads/hero.jpg" src="data:,">"#);
+        assert_eq!(primary_image(&doc, "https://example.com/blog/post/").as_deref(), Some("https://example.com/blog/post/uploads/hero.jpg"));
+        assert_eq!(primary_image(&Html::parse_document(r#"<img src="/icon.png">"#), "https://example.com/"), None);
+
+        // Only whole words of the filename and classes count: not alt text, ids or directories
+        let doc = Html::parse_document(r#"<img src="/uploads/icons/silicon-valley.jpg" id="site-logo" alt="Our new logo on a billboard">"#);
+        assert_eq!(primary_image(&doc, "https://example.com/").as_deref(), Some("https://example.com/uploads/icons/silicon-valley.jpg"));
+        let doc = Html::parse_document(r#"<img src="/uploads/header.jpg" class="custom-logo">"#);
+        assert_eq!(primary_image(&doc, "https://example.com/"), None);
+    }
+
+    #[test]
+    fn test_image_extension() {
+        assert_eq!(image_extension("/uploads/a.JPEG?ver=2"), Some(ImgFmt::Jpeg));
+        assert_eq!(image_extension("https://example.com/b.avif#x"), Some(ImgFmt::Avif));
+        assert_eq!(image_extension("/scan.tif"), Some(ImgFmt::Tiff));
+        assert_eq!(image_extension("/v1.2/photo"), None);
+        assert!(ImgFmt::Avif.is_optimized() && !ImgFmt::Avif.is_convertible());
+        assert!(ImgFmt::Bmp.is_convertible());
+        assert!(!ImgFmt::Svg.is_convertible());
+    }
+
     #[test]
     fn test_analyze_images() {
         let html = r#"
@@ -356,7 +483,7 @@ mod tests {
             <img src="lazy.webp" loading="lazy">
         "#;
         
-        let result = analyze_images(html);
+        let result = analyze_images(&Html::parse_document(html));
         assert_eq!(result.webp_candidates, 2);
         assert_eq!(result.missing_dimensions, 2);
     }
@@ -0,0 +1,124 @@
+//! Bulk Job Registry
+//! Tracks background bulk optimization jobs and fans out their progress events
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// How long a finished job stays subscribable before it is dropped
+pub const JOB_RETENTION: std::time::Duration = std::time::Duration::from_secs(600);
+
+/// A single progress event, already serialized for the wire
+#[derive(Debug, Clone)]
+pub struct JobEvent {
+    /// SSE event name ("page" or "summary")
+    pub name: &'static str,
+    /// JSON payload
+    pub data: String,
+}
+
+impl JobEvent {
+    pub fn page(data: serde_json::Value) -> Self {
+        Self { name: "page", data: data.to_string() }
+    }
+
+    pub fn summary(data: serde_json::Value) -> Self {
+        Self { name: "summary", data: data.to_string() }
+    }
+
+    /// The summary is always the last event of a job
+    pub fn is_final(&self) -> bool {
+        self.name == "summary"
+    }
+}
+
+struct Job {
+    /// Every event published so far, replayed to late subscribers
+    events: Vec<JobEvent>,
+    finished: bool,
+    sender: broadcast::Sender<JobEvent>,
+}
+
+/// In-memory registry of running and recently finished bulk jobs
+#[derive(Clone, Default)]
+pub struct JobRegistry {
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a new job and return its id
+    /// `capacity` should cover every event the job will emit so slow subscribers never lag
+    pub fn create(&self, capacity: usize) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        self.jobs.lock().unwrap().insert(id.clone(), Job {
+            events: Vec::new(),
+            finished: false,
+            sender,
+        });
+        id
+    }
+
+    /// Record an event and forward it to live subscribers
+    pub fn publish(&self, id: &str, event: JobEvent) {
+        let mut jobs = self.jobs.lock().unwrap();
+        if let Some(job) = jobs.get_mut(id) {
+            if event.is_final() {
+                job.finished = true;
+            }
+            job.events.push(event.clone());
+            // No receivers is fine - the event is kept for replay
+            let _ = job.sender.send(event);
+        }
+    }
+
+    /// Subscribe to a job: returns the events so far, plus a receiver for the
+    /// remaining ones (None once the job has finished)
+    pub fn subscribe(&self, id: &str) -> Option<(Vec<JobEvent>, Option<broadcast::Receiver<JobEvent>>)> {
+        let jobs = self.jobs.lock().unwrap();
+        let job = jobs.get(id)?;
+        let receiver = if job.finished { None } else { Some(job.sender.subscribe()) };
+        Some((job.events.clone(), receiver))
+    }
+
+    pub fn remove(&self, id: &str) {
+        self.jobs.lock().unwrap().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_subscriber_gets_history_and_live_events() {
+        let registry = JobRegistry::new();
+        let id = registry.create(4);
+        registry.publish(&id, JobEvent::page(json!({"index": 0})));
+
+        let (history, receiver) = registry.subscribe(&id).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].name, "page");
+
+        registry.publish(&id, JobEvent::summary(json!({"pages": 1})));
+        let event = receiver.unwrap().recv().await.unwrap();
+        assert!(event.is_final());
+    }
+
+    #[test]
+    fn test_finished_job_replays_without_receiver() {
+        let registry = JobRegistry::new();
+        let id = registry.create(2);
+        registry.publish(&id, JobEvent::summary(json!({"pages": 0})));
+
+        let (history, receiver) = registry.subscribe(&id).unwrap();
+        assert_eq!(history.len(), 1);
+        assert!(receiver.is_none());
+        assert!(registry.subscribe("missing").is_none());
+    }
+}
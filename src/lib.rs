@@ -0,0 +1,28 @@
+//! HTMLWordPress optimizer library
+//! The API server lives in main.rs; the optimization passes are usable on their own.
+//! `optimizer::optimize_html_only` is the synchronous, network-free core.
+
+pub mod asset_cache;
+pub mod changes;
+pub mod concurrency;
+pub mod config;
+pub mod cors;
+pub mod handlers;
+pub mod optimizer;
+pub mod css_optimizer;
+pub mod seo_optimizer;
+pub mod schema_generator;
+pub mod image_optimizer;
+pub mod webp_converter;
+pub mod resource_optimizer;
+pub mod jobs;
+pub mod logging;
+pub mod metrics;
+pub mod progress;
+pub mod rate_limit;
+pub mod request_id;
+pub mod server;
+pub mod url_utils;
+pub mod html_utils;
+pub mod error;
+mod test_verification;
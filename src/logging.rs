@@ -0,0 +1,98 @@
+//! Logging setup
+//! `LOG_FORMAT=json` switches to one JSON object per line for log aggregation; `text` (default) stays human-readable
+
+use tracing::Subscriber;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, Layer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    /// Read `LOG_FORMAT`; unknown values fall back to text
+    pub fn from_env() -> Self {
+        match std::env::var("LOG_FORMAT").unwrap_or_default().to_ascii_lowercase().as_str() {
+            "json" => LogFormat::Json,
+            _ => LogFormat::Text,
+        }
+    }
+}
+
+/// Formatting layer for `format` writing to `writer`
+/// JSON output keeps event fields (url, sizes, timings) under `fields` and the
+/// request span (with `request_id`) under `span`
+pub fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+/// Install the global subscriber (`RUST_LOG` filter, `LOG_FORMAT` output)
+pub fn init() {
+    tracing_subscriber::registry()
+        .with(fmt_layer(LogFormat::from_env(), std::io::stdout))
+        .with(tracing_subscriber::EnvFilter::new(
+            std::env::var("RUST_LOG").unwrap_or_else(|_| "htmlwordpress_api=debug,info".into()),
+        ))
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'w> MakeWriter<'w> for Capture {
+        type Writer = Capture;
+
+        fn make_writer(&'w self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn test_json_log_line_has_structured_fields() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::registry().with(fmt_layer(LogFormat::Json, capture.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request", request_id = "abc-123");
+            let _guard = span.enter();
+            tracing::info!(url = "https://example.com/", images_ms = 42u64, "Optimized");
+        });
+
+        let output = String::from_utf8(capture.0.lock().unwrap().clone()).unwrap();
+        let line: serde_json::Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert_eq!(line["fields"]["message"], "Optimized");
+        assert_eq!(line["fields"]["url"], "https://example.com/");
+        assert_eq!(line["fields"]["images_ms"], 42);
+        assert_eq!(line["span"]["request_id"], "abc-123");
+    }
+}
@@ -1,57 +1,67 @@
 //! HTMLWordPress API Server
 //! High-performance WordPress optimization service
 
-pub mod config;
-pub mod handlers;
-pub mod optimizer;
-pub mod css_optimizer;
-pub mod seo_optimizer;
-pub mod schema_generator;
-pub mod image_optimizer;
-pub mod webp_converter;
-pub mod resource_optimizer;
-mod error;
-mod test_verification;
-
 use axum::{
+    middleware,
     routing::{get, post},
     Router,
 };
-use tower_http::cors::{Any, CorsLayer};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use htmlwordpress_api::{asset_cache, concurrency, config, cors, handlers, jobs, logging, metrics, rate_limit, request_id, server, url_utils};
 
 #[tokio::main]
 async fn main() {
+    // Load .env first so LOG_FORMAT / RUST_LOG from it apply
+    dotenvy::dotenv().ok();
+
     // Initialize logging
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "htmlwordpress_api=debug,info".into()),
-        ))
-        .init();
+    logging::init();
 
     // Load config
-    dotenvy::dotenv().ok();
     let config = config::Config::from_env();
 
-    tracing::info!("Starting HTMLWordPress API on {}", config.address());
+    tracing::info!(address = %config.address(), "Starting HTMLWordPress API");
+
+    // Recorder first so gauges set during startup are kept
+    let metrics_handle = metrics::install();
+
+    url_utils::set_crawl_delay(std::time::Duration::from_millis(config.crawl_delay_ms));
+    let downloads = url_utils::DownloadLimiter::new(config.max_concurrent_downloads);
+    url_utils::set_download_limiter(downloads.clone());
 
     let state = config::AppState {
         api_key: config.api_key.clone(),
+        jobs: jobs::JobRegistry::new(),
+        http_client: reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(30))
+            .build()
+            .expect("Failed to create HTTP client"),
+        ready_probe_url: config.ready_probe_url.clone(),
+        ready_probe_timeout: std::time::Duration::from_millis(config.ready_probe_timeout_ms),
+        metrics: metrics_handle,
+        rate_limiter: rate_limit::RateLimiter::new(config.rate_limit_per_minute, config.rate_limit_burst),
+        concurrency: concurrency::ConcurrencyLimiter::new(config.max_concurrent_optimizations),
+        downloads,
+        assets: asset_cache::AssetCache::new(
+            std::time::Duration::from_secs(config.asset_cache_ttl_secs),
+            config.asset_cache_max_bytes,
+        ),
     };
 
     // Build router
-    let app = Router::new()
+    let app = request_id::with_request_id(Router::new()
         .route("/health", get(handlers::health))
         .route("/api/v1/health", get(handlers::health))
+        .route("/api/v1/health/ready", get(handlers::ready))
+        .route("/metrics", get(handlers::metrics))
         .route("/api/v1/optimize", post(handlers::optimize))
+        .route("/api/v1/optimize/ws", get(handlers::optimize_ws))
         .route("/api/v1/optimize/bulk", post(handlers::optimize_bulk))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Any)
-                .allow_methods(Any)
-                .allow_headers(Any),
-        )
+        .route("/api/v1/jobs/:id/stream", get(handlers::job_stream))
+        .route("/api/v1/resource/:filename", get(handlers::resource))
+        .layer(middleware::from_fn_with_state(state.clone(), concurrency::enforce))
+        .layer(middleware::from_fn_with_state(state.clone(), rate_limit::enforce)))
+        .layer(cors::layer(&config.cors_origins))
         .with_state(state);
 
     // Start server
@@ -59,7 +69,7 @@ async fn main() {
         .await
         .expect("Failed to bind");
 
-    tracing::info!("Server listening on http://{}", config.address());
+    tracing::info!(address = %config.address(), "Server listening");
 
-    axum::serve(listener, app).await.expect("Server error");
+    server::serve(listener, app, config.server.clone()).await;
 }
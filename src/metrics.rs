@@ -0,0 +1,68 @@
+//! Metrics
+//! Prometheus counters/histograms recorded across the optimizers, served on `/metrics`
+
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+
+pub const OPTIMIZATIONS_TOTAL: &str = "htmlwp_optimizations_total";
+pub const BYTES_SAVED_TOTAL: &str = "htmlwp_bytes_saved_total";
+pub const STAGE_DURATION_SECONDS: &str = "htmlwp_stage_duration_seconds";
+pub const DOWNLOAD_FAILURES_TOTAL: &str = "htmlwp_download_failures_total";
+pub const DOWNLOADS_IN_FLIGHT: &str = "htmlwp_downloads_in_flight";
+pub const DOWNLOADS_MAX: &str = "htmlwp_downloads_max";
+
+/// Latency buckets (seconds) for stage histograms
+const STAGE_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0, 30.0];
+
+static HANDLE: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// Install the global Prometheus recorder (once per process) and return its handle
+pub fn install() -> PrometheusHandle {
+    HANDLE
+        .get_or_init(|| {
+            let recorder = PrometheusBuilder::new()
+                .set_buckets_for_metric(Matcher::Full(STAGE_DURATION_SECONDS.to_string()), &STAGE_BUCKETS)
+                .expect("Invalid histogram buckets")
+                .build_recorder();
+            let handle = recorder.handle();
+            metrics::set_global_recorder(recorder).expect("Metrics recorder already installed");
+            handle
+        })
+        .clone()
+}
+
+/// Count a finished page optimization and the bytes it saved
+pub fn record_optimization(endpoint: &'static str, success: bool, original_size: usize, optimized_size: usize) {
+    let status = if success { "success" } else { "failure" };
+    metrics::counter!(OPTIMIZATIONS_TOTAL, "endpoint" => endpoint, "status" => status).increment(1);
+    if success {
+        metrics::counter!(BYTES_SAVED_TOTAL).increment(original_size.saturating_sub(optimized_size) as u64);
+    }
+}
+
+/// Record how long a pipeline stage ("html", "images", "resources") took
+pub fn record_stage(stage: &'static str, started: Instant) {
+    metrics::histogram!(STAGE_DURATION_SECONDS, "stage" => stage).record(started.elapsed().as_secs_f64());
+}
+
+/// Count a failed image/CSS/JS download
+pub fn record_download_failure(kind: &'static str) {
+    metrics::counter!(DOWNLOAD_FAILURES_TOTAL, "kind" => kind).increment(1);
+}
+
+/// Publish the concurrent download cap (0 = unlimited)
+pub fn record_download_limit(max: usize) {
+    metrics::gauge!(DOWNLOADS_MAX).set(max as f64);
+}
+
+/// An outbound download took a slot
+pub fn record_download_started() {
+    metrics::gauge!(DOWNLOADS_IN_FLIGHT).increment(1.0);
+}
+
+/// An outbound download released its slot
+pub fn record_download_finished() {
+    metrics::gauge!(DOWNLOADS_IN_FLIGHT).decrement(1.0);
+}
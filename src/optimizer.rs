@@ -2,9 +2,10 @@
 
 use scraper::{Html, Selector};
 
+use crate::changes::Change;
 use crate::error::AppError;
 use crate::handlers::OptimizeOptions;
-use crate::css_optimizer::{CssOptimizer, minify_css};
+use crate::css_optimizer::CssOptimizer;
 use crate::seo_optimizer::{SeoOptimizer, add_alt_tags};
 
 pub struct OptimizeResult {
@@ -13,79 +14,221 @@ pub struct OptimizeResult {
     pub optimized_size: usize,
     pub reduction_percent: f64,
     pub optimizations: Vec<String>,
+    /// Selectors removed by tree-shaking (only collected with `debug_removed`)
+    pub removed_selectors: Vec<String>,
+    /// Characters the visible text needs (only with `font_subset_hint`)
+    pub font_subset_hint: Option<crate::css_optimizer::FontSubsetHint>,
+    /// Elements in the input and output documents
+    pub original_node_count: usize,
+    pub optimized_node_count: usize,
+    /// Element-level changes behind some of the `optimizations` sentences
+    pub changes: Vec<Change>,
 }
 
+impl OptimizeResult {
+    /// Record `changes` against the finished `html` and strip their markers
+    pub fn finalize_changes(&mut self) {
+        crate::changes::finalize(&mut self.html, &mut self.changes);
+    }
+}
+
+/// `src` of a tag, for `Change::target`
+fn tag_src(tag: &str) -> String {
+    crate::html_utils::get_attribute(tag, "src").unwrap_or_default()
+}
+
+/// Cap on reported removed selectors to keep responses small
+const MAX_REPORTED_SELECTORS: usize = 500;
+
 /// Main optimization function
 pub fn optimize_html(html: &str, url: &str, options: &OptimizeOptions) -> Result<OptimizeResult, AppError> {
+    Ok(optimize_html_only(html, url, options))
+}
+
+/// Run every synchronous pass over the HTML
+/// Guaranteed network-free and deterministic: `convert_webp` and `optimize_resources`
+/// are ignored here and applied on top by `handlers::apply_webp_conversion` and
+/// `handlers::apply_resource_optimization`
+pub fn optimize_html_only(html: &str, url: &str, options: &OptimizeOptions) -> OptimizeResult {
+    let mut result = optimize_html_marked(html, url, options);
+    result.finalize_changes();
+    result
+}
+
+/// `optimize_html_only` leaving the change markers in `html`, for callers that keep rewriting
+/// it; `OptimizeResult::finalize_changes` must run on the finished HTML
+pub fn optimize_html_marked(html: &str, url: &str, options: &OptimizeOptions) -> OptimizeResult {
+    let started = std::time::Instant::now();
     let original_size = html.len();
     let mut optimized = html.to_string();
     let mut optimizations = Vec::new();
+    let mut removed_selectors = Vec::new();
+    let (original_node_count, original_parse_errors) = parse_stats(&parse(html, options.fragment));
+    // Elements the passes remove on purpose, as each pass reports them; any other loss points at a bug
+    let mut removed_elements = 0;
+    let mut changes = Vec::new();
 
     tracing::debug!("Options: minify_css={}, minify_html={}, defer_js={}, lazy_images={}", 
         options.minify_css, options.minify_html, options.defer_js, options.lazy_images);
 
-    // 1. Aggressive CSS tree-shaking FIRST (before HTML minification)
+    // Fragments only get safe in-place transforms: minify, lazy images, defer, alt tags
+    // (tree-shaking a fragment would drop rules used by the rest of the page)
+    if options.fragment {
+        tracing::debug!("Fragment mode: skipping tree-shaking and head-insertion passes");
+    }
+
+    // Browser targets for CSS prefixing; entries that can't be honoured are reported
+    let (css_targets, target_warnings) = options.css_targets.as_deref()
+        .map(crate::css_optimizer::parse_css_targets)
+        .unwrap_or_default();
+    for warning in target_warnings {
+        optimizations.push(format!("Warning: {}", warning));
+    }
+
+    // 1. Aggressive CSS tree-shaking and minification FIRST (before HTML minification)
     if options.minify_css {
-        let css_result = optimize_and_treeshake_css(&mut optimized);
+        let css_result = optimize_and_treeshake_css(&mut optimized, options, css_targets);
+        if options.debug_removed {
+            removed_selectors = css_result.2;
+        }
         if css_result.0 > 0 {
             optimizations.push(format!("{} style blocks optimized ({}% reduction)", css_result.0, css_result.1));
         }
     }
 
-    // 2. Minify HTML (after CSS is processed)
-    if options.minify_html {
-        optimized = minify_html(&optimized);
-        optimizations.push("HTML minified".to_string());
+    // 1a. Drop @font-face rules no kept rule uses (needs tree-shaking to have run)
+    if options.remove_unused_fonts && options.remove_unused_css && options.minify_css && !options.fragment {
+        let removed = remove_unused_font_faces(&mut optimized);
+        if removed > 0 {
+            optimizations.push(format!("{} unused @font-face rules removed", removed));
+        }
     }
 
-    // 3. Add lazy loading to images
-    if options.lazy_images {
-        let count = add_lazy_loading(&mut optimized);
+    // 1b. Report what @font-face rules load; optionally add font-display:swap
+    let (font_warnings, swapped) = analyze_fonts(&mut optimized, options.font_display_swap);
+    for warning in font_warnings {
+        optimizations.push(format!("Font: {}", warning));
+    }
+    if swapped > 0 {
+        optimizations.push(format!("font-display: swap added to {} @font-face rules", swapped));
+    }
+
+    // Preserved inline scripts sit out the remaining passes as placeholders
+    let preserved_scripts = stash_preserved_scripts(&mut optimized, &options.preserve_scripts);
+
+    // 1c. Consolidate repeated inline styles into classes (opt-in)
+    if options.consolidate_inline_styles && !options.fragment {
+        let count = consolidate_inline_styles(&mut optimized);
         if count > 0 {
-            optimizations.push(format!("{} images lazy-loaded", count));
+            optimizations.push(format!("{} inline styles consolidated into classes", count));
         }
     }
 
-    // 4. Defer JavaScript
-    if options.defer_js {
-        let count = defer_scripts(&mut optimized);
+    // 1d. Prune configured attributes (after tree-shaking so only surviving CSS protects names)
+    if !options.prune_attributes.is_empty() {
+        let (count, bytes_saved) = prune_attributes(&mut optimized, &options.prune_attributes, options.fragment);
+        if count > 0 {
+            optimizations.push(format!("{} attributes pruned ({} bytes saved)", count, bytes_saved));
+        }
+    }
+
+    // 2. Minify HTML (after CSS is processed); prettify replaces it at the end
+    if options.minify_html && !options.prettify {
+        let cleaned = clean_attributes(&mut optimized);
+        if cleaned > 0 {
+            optimizations.push(format!("{} redundant attributes removed", cleaned));
+        }
+        optimized = minify_html(&optimized, !options.safe_mode);
+        optimizations.push("HTML minified".to_string());
+    }
+
+    // 2b. Video embeds become click-to-load facades (their thumbnails then get the image passes)
+    if options.lite_embeds {
+        let count = add_lite_embeds(&mut optimized, options.fragment);
         if count > 0 {
-            optimizations.push(format!("{} scripts deferred", count));
+            optimizations.push(format!("{} video embeds replaced with click-to-load facades", count));
         }
     }
 
-    // 5. Add image dimensions hint
-    let dims_count = count_images_without_dimensions(&optimized);
-    if dims_count > 0 {
-        optimizations.push(format!("{} images need dimensions", dims_count));
+    // 3. Image decoding hints first, so the LCP image's fetchpriority keeps it out of lazy loading
+    if options.image_decoding_hints {
+        let count = add_decoding_hints(&mut optimized, url, options.fragment);
+        if count > 0 {
+            optimizations.push(format!("{} images given decoding hints", count));
+        }
     }
 
-    // 6. Add preconnect hints for external resources
-    let preconnects = add_preconnect_hints(&mut optimized);
-    if preconnects > 0 {
-        optimizations.push(format!("{} preconnect hints added", preconnects));
+    // 3a. Add lazy loading to images
+    if options.lazy_images {
+        let lazy = add_lazy_loading(&mut optimized, options.noscript_fallback);
+        if !lazy.is_empty() {
+            optimizations.push(format!("{} images lazy-loaded", lazy.len()));
+        }
+        changes.extend(lazy);
     }
 
-    // 7. SEO Optimizations
-    let seo_optimizer = SeoOptimizer::new();
-    let seo_result = seo_optimizer.optimize(&mut optimized, url);
-    for change in seo_result.changes {
-        optimizations.push(format!("SEO: {}", change));
+    // 4. Defer JavaScript
+    if options.defer_js {
+        let deferred = defer_scripts(&mut optimized);
+        if !deferred.is_empty() {
+            optimizations.push(format!("{} scripts deferred", deferred.len()));
+        }
+        changes.extend(deferred);
     }
 
-    // 8. Schema.org structured data
-    let schemas_added = crate::schema_generator::inject_schema(&mut optimized, url);
-    if schemas_added > 0 {
-        optimizations.push(format!("{} Schema.org types added", schemas_added));
+    // 4b. Mixed content: http:// subresources on an https page are blocked by browsers
+    if url.starts_with("https://") {
+        let (found, upgraded) = fix_mixed_content(&mut optimized, url, options.upgrade_insecure);
+        if found > 0 {
+            optimizations.push(format!("{} mixed-content references found ({} upgraded to https)", found, upgraded));
+        }
     }
 
-    // 9. Image optimization analysis
-    let image_result = crate::image_optimizer::analyze_images(&optimized);
-    for opt in image_result.optimizations {
-        optimizations.push(format!("Image: {}", opt));
+    // 4c. Tracking scripts
+    if options.strip_trackers {
+        let removed = strip_tracking_scripts(&mut optimized, url, &options.tracker_domains);
+        removed_elements += removed.len();
+        if !removed.is_empty() {
+            optimizations.push(format!("{} tracking scripts removed ({})", removed.len(), removed.join(", ")));
+        }
     }
-    if let Some(lcp_hint) = crate::image_optimizer::check_lcp_optimization(&optimized) {
-        optimizations.push(format!("LCP: {}", lcp_hint));
+
+    if options.fragment {
+        // 7. SEO: only alt tags apply to a fragment
+        let alts = add_alt_tags(&mut optimized);
+        if !alts.is_empty() {
+            optimizations.push(format!("SEO: {} alt tags added", alts.len()));
+        }
+        changes.extend(alts);
+    } else {
+        // 6. Add preconnect hints for external resources
+        let preconnects = add_preconnect_hints(&mut optimized);
+        if preconnects > 0 {
+            optimizations.push(format!("{} preconnect hints added", preconnects));
+        }
+
+        // 7. SEO Optimizations
+        let mut seo_optimizer = SeoOptimizer::new();
+        seo_optimizer.prev_url = options.prev_url.clone();
+        seo_optimizer.next_url = options.next_url.clone();
+        seo_optimizer.twitter_site = options.twitter_site.clone();
+        seo_optimizer.twitter_creator = options.twitter_creator.clone();
+        let seo_result = seo_optimizer.optimize(&optimized, url);
+        optimized = seo_result.html;
+        removed_elements += seo_result.removed_elements;
+        changes.extend(seo_result.details);
+        for change in seo_result.changes {
+            optimizations.push(format!("SEO: {}", change));
+        }
+
+        // 8. Schema.org structured data
+        let (schemas_added, schema_warnings) = crate::schema_generator::inject_schema(&mut optimized, url, options);
+        for warning in schema_warnings {
+            optimizations.push(format!("Schema: {}", warning));
+        }
+        if schemas_added > 0 {
+            optimizations.push(format!("{} Schema.org types added", schemas_added));
+        }
     }
 
     // 10. CDN Image URL Rewriting - DISABLED (using Rust WebP conversion instead)
@@ -98,119 +241,434 @@ pub fn optimize_html(html: &str, url: &str, options: &OptimizeOptions) -> Result
     //     optimizations.push(format!("{} images rewritten for CDN ({})", cdn_count, cdn_config.provider));
     // }
 
-    let optimized_size = optimized.len();
+    if !preserved_scripts.is_empty() {
+        restore_preserved_scripts(&mut optimized, &preserved_scripts);
+        optimizations.push(format!("{} inline scripts preserved unmodified", preserved_scripts.len()));
+    }
+
+    // 11. Pretty-print for debugging
+    if options.prettify {
+        optimized = prettify_html(&optimized);
+        optimizations.push("HTML pretty-printed (debug)".to_string());
+    }
+
+    // Last line of defence for the string-based passes: output that lost more elements than the
+    // passes removed, or gained parse errors, is corrupt, so the original is returned instead.
+    // The same parse feeds the image reports below
+    let document = parse(&optimized, options.fragment);
+    let (mut optimized_node_count, optimized_parse_errors) = parse_stats(&document);
+    if optimized_node_count + removed_elements < original_node_count || optimized_parse_errors > original_parse_errors {
+        tracing::warn!(
+            url,
+            original_node_count,
+            optimized_node_count,
+            original_parse_errors,
+            optimized_parse_errors,
+            "Optimized HTML failed validation, returning the original"
+        );
+        optimized = html.to_string();
+        optimized_node_count = original_node_count;
+        optimizations = vec!["Warning: optimized HTML failed validation; original HTML returned".to_string()];
+        removed_selectors.clear();
+        changes.clear();
+    } else {
+        // 5. Add image dimensions hint
+        let dims_count = count_images_without_dimensions(&document);
+        if dims_count > 0 {
+            optimizations.push(format!("{} images need dimensions", dims_count));
+        }
+
+        // 9. Image optimization analysis
+        let image_result = crate::image_optimizer::analyze_images(&document);
+        for opt in image_result.optimizations {
+            optimizations.push(format!("Image: {}", opt));
+        }
+        if let Some(lcp_hint) = crate::image_optimizer::check_lcp_optimization(&document) {
+            optimizations.push(format!("LCP: {}", lcp_hint));
+        }
+    }
+
+    // Sizes and the font hint describe the HTML actually returned, fallback included
+    let optimized_size = optimized.len() - crate::changes::marker_len(&optimized);
     let reduction = if original_size > 0 {
         (1.0 - (optimized_size as f64 / original_size as f64)) * 100.0
     } else {
         0.0
     };
 
+    // 10b. Characters the page's fonts must cover (report only)
+    let font_subset_hint = options.font_subset_hint
+        .then(|| crate::css_optimizer::font_subset_hint(&optimized, options.fragment));
+
+    crate::metrics::record_stage("html", started);
+
     tracing::debug!(
         "Final stats: original={} optimized={} reduction={:.1}% optimizations={}",
         original_size, optimized_size, reduction, optimizations.len()
     );
 
-    Ok(OptimizeResult {
+    OptimizeResult {
         html: optimized,
         original_size,
         optimized_size,
         reduction_percent: (reduction * 10.0).round() / 10.0,
         optimizations,
-    })
+        removed_selectors,
+        font_subset_hint,
+        original_node_count,
+        optimized_node_count,
+        changes,
+    }
+}
+
+/// `html` parsed as a document, or as a fragment in fragment mode
+fn parse(html: &str, fragment: bool) -> Html {
+    if fragment { Html::parse_fragment(html) } else { Html::parse_document(html) }
+}
+
+/// Number of elements and of parse errors in the parsed document
+fn parse_stats(document: &Html) -> (usize, usize) {
+    let elements = document.tree.nodes().filter(|node| node.value().is_element()).count();
+    (elements, document.errors.len())
 }
 
 /// Optimize inline CSS with aggressive tree-shaking
-fn optimize_and_treeshake_css(html: &mut String) -> (usize, i32) {
+/// Returns (blocks optimized, average reduction %, removed selectors)
+fn optimize_and_treeshake_css(html: &mut String, options: &OptimizeOptions, targets: lightningcss::targets::Targets) -> (usize, i32, Vec<String>) {
     tracing::debug!("CSS tree-shake: Starting, HTML len = {}", html.len());
     
+    // Tree-shaking a fragment would drop rules used by the rest of the page; minifying is always safe
+    let tree_shake = options.remove_unused_css && !options.fragment;
+
     // First, extract all selectors used in HTML
     let mut css_optimizer = CssOptimizer::new();
-    css_optimizer.extract_used_selectors(html);
+    if tree_shake {
+        css_optimizer.set_blocklist(&options.css_blocklist);
+        css_optimizer.extract_used_selectors(html);
+        if options.scan_js_for_classes {
+            css_optimizer.extract_inline_script_classes(html);
+        }
+    }
 
     let mut count = 0;
     let mut total_reduction: i32 = 0;
-    let mut result = String::with_capacity(html.len());
-    let mut i = 0;
-    let chars: Vec<char> = html.chars().collect();
-    let len = chars.len();
+    let mut removed_selectors: Vec<String> = Vec::new();
+    // Comments and script strings containing "<style>" are not blocks
+    let result = crate::html_utils::rewrite_raw_text(html, "style", |open_tag, css_content| {
+        tracing::debug!("CSS tree-shake: Found {}", open_tag);
+        let original_len = css_content.len();
 
-    while i < len {
-        // Look for <style
-        if i + 5 < len {
-            let tag: String = chars[i..i+6].iter().collect();
-            if tag.to_lowercase() == "<style" {
-                tracing::debug!("CSS tree-shake: Found <style at position {}", i);
-                // Find end of opening tag
-                let start = i;
-                while i < len && chars[i] != '>' {
-                    i += 1;
+        // Skip tree-shaking for very large CSS blocks to prevent hangs
+        if original_len > options.max_inline_css_bytes {
+            tracing::warn!("Skipping CSS tree-shake for large block: {} bytes", original_len);
+            return None;
+        }
+
+        // Tree-shake the CSS - remove unused rules
+        let shaken = if tree_shake {
+            match css_optimizer.remove_unused_css_with_report(css_content) {
+                Ok((optimized, removed)) => {
+                    for selector in removed {
+                        if removed_selectors.len() < MAX_REPORTED_SELECTORS && !removed_selectors.contains(&selector) {
+                            removed_selectors.push(selector);
+                        }
+                    }
+                    optimized
                 }
-                if i < len {
-                    i += 1; // past >
+                Err(e) => {
+                    // Keep original on error
+                    tracing::warn!("CSS optimization failed: {}", e);
+                    css_content.to_string()
                 }
-                
-                let open_tag: String = chars[start..i].iter().collect();
-                result.push_str(&open_tag);
-                
-                // Find </style>
-                let css_start = i;
-                while i + 7 < len {
-                    let closing: String = chars[i..i+8].iter().collect();
-                    if closing.to_lowercase() == "</style>" {
-                        break;
+            }
+        } else {
+            css_content.to_string()
+        };
+
+        // Full lightningcss minification (tree-shaking only compacts rule bodies)
+        let optimized = crate::css_optimizer::minify_css(&shaken, targets).unwrap_or_else(|e| {
+            tracing::debug!("Inline CSS minification failed (keeping tree-shaken CSS): {}", e);
+            shaken
+        });
+
+        let new_len = optimized.len();
+        if original_len > 0 {
+            let reduction = ((original_len.saturating_sub(new_len)) as f64 / original_len as f64 * 100.0) as i32;
+            total_reduction += reduction;
+        }
+        count += 1;
+        tracing::debug!(
+            "CSS tree-shake: {} -> {} bytes ({}% reduction)",
+            original_len, new_len,
+            if original_len > 0 { original_len.saturating_sub(new_len) * 100 / original_len } else { 0 }
+        );
+        Some(optimized)
+    });
+
+    let avg_reduction = if count > 0 { total_reduction / count as i32 } else { 0 };
+    *html = result;
+    (count, avg_reduction, removed_selectors)
+}
+
+/// Collect `@font-face` warnings from inline style blocks, adding `font-display:swap` where
+/// missing when `swap` is set. Returns (warnings, rules given swap)
+fn analyze_fonts(html: &mut String, swap: bool) -> (Vec<String>, usize) {
+    let mut warnings = Vec::new();
+    let mut swapped = 0;
+    let result = crate::html_utils::rewrite_raw_text(html, "style", |_, css| {
+        for font in crate::css_optimizer::analyze_font_faces(css) {
+            if swap && font.font_display.is_none() {
+                // About to be fixed; only the format warning still applies
+                warnings.extend(font.warnings().into_iter().filter(|w| !w.contains("font-display")));
+            } else {
+                warnings.extend(font.warnings());
+            }
+        }
+        if !swap {
+            return None;
+        }
+        let (css, count) = crate::css_optimizer::add_font_display_swap(css);
+        swapped += count;
+        (count > 0).then_some(css)
+    });
+    *html = result;
+    (warnings, swapped)
+}
+
+/// Remove `@font-face` rules from inline style blocks when no style block or inline `style`
+/// attribute names their family. Skipped when external stylesheets may reference them
+/// (`resource_optimizer::optimize_external_resources` prunes those).
+fn remove_unused_font_faces(html: &mut String) -> usize {
+    use crate::html_utils::{get_attribute, rewrite_raw_text, rewrite_start_tags};
+
+    let mut has_external_css = false;
+    rewrite_start_tags(html, |name, tag| {
+        has_external_css |= name == "link"
+            && get_attribute(tag, "rel").is_some_and(|rel| rel.to_ascii_lowercase().split_whitespace().any(|r| r == "stylesheet"));
+        None
+    });
+    if has_external_css {
+        return 0;
+    }
+    let references = crate::css_optimizer::inline_font_references(html);
+
+    let mut removed = 0;
+    *html = rewrite_raw_text(html, "style", |_, css| {
+        let (pruned, count) = crate::css_optimizer::remove_unused_font_faces(css, &references);
+        removed += count;
+        (count > 0).then_some(pruned)
+    });
+    removed
+}
+
+/// Attributes whose values point at element IDs
+const ID_REFERENCE_ATTRIBUTES: [&str; 6] = ["for", "aria-labelledby", "aria-describedby", "aria-controls", "aria-owns", "list"];
+
+/// WordPress Interactivity API directives (`data-wp-bind--hidden`, `data-wp-on--click`): only a
+/// pattern that itself starts with this prefix prunes them, never a broader one like `data-*`
+const INTERACTIVITY_PREFIX: &str = "data-wp-";
+
+/// Remove attributes matching `patterns` (`name` or `name=value`, `*` wildcards; class values match per token)
+/// Classes/IDs referenced by inline CSS, inline scripts, `href="#id"` or ID-reference attributes are kept;
+/// class and ID pruning is skipped entirely for fragments and pages with external stylesheets.
+/// Returns (attributes removed, bytes saved)
+fn prune_attributes(html: &mut String, patterns: &[String], fragment: bool) -> (usize, usize) {
+    use crate::html_utils::{get_attribute, parse_attributes, remove_attribute, rewrite_raw_text, rewrite_start_tags, set_attribute};
+    use crate::url_utils::wildcard_match;
+
+    let patterns: Vec<(String, Option<&str>)> = patterns.iter()
+        .map(|pattern| match pattern.split_once('=') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), Some(value.trim())),
+            None => (pattern.trim().to_ascii_lowercase(), None),
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .collect();
+
+    // Everything that may still reference a class, ID or attribute
+    let mut has_external_css = false;
+    let mut inline_css = String::new();
+    let mut protected = std::collections::HashSet::new();
+    rewrite_start_tags(html, |name, tag| {
+        let is_stylesheet = name == "link"
+            && get_attribute(tag, "rel").is_some_and(|rel| rel.to_ascii_lowercase().split_whitespace().any(|r| r == "stylesheet"));
+        has_external_css |= is_stylesheet;
+        if let Some(target) = get_attribute(tag, "href").and_then(|href| href.strip_prefix('#').map(str::to_string)) {
+            protected.insert(format!("#{}", target.to_lowercase()));
+        }
+        for attr in ID_REFERENCE_ATTRIBUTES {
+            for id in get_attribute(tag, attr).unwrap_or_default().split_whitespace() {
+                protected.insert(format!("#{}", id.to_lowercase()));
+            }
+        }
+        None
+    });
+    rewrite_raw_text(html, "style", |_, css| {
+        inline_css.push_str(css);
+        None
+    });
+    protected.extend(crate::css_optimizer::referenced_names(&inline_css));
+    let mut script_refs = CssOptimizer::new();
+    script_refs.extract_inline_script_classes(html);
+    protected.extend(script_refs.used_selectors().iter().map(|name| name.to_lowercase()));
+
+    let can_prune_selectors = !fragment && !has_external_css;
+    let matches_value = |value_pattern: Option<&str>, value: &str| value_pattern.is_none_or(|p| wildcard_match(p, value));
+
+    let mut count = 0;
+    let original_len = html.len();
+    *html = rewrite_start_tags(html, |_, tag| {
+        let mut new_tag = tag.to_string();
+        for attr in parse_attributes(tag) {
+            let value = attr.value.as_deref().unwrap_or("");
+            let matching: Vec<Option<&str>> = patterns.iter()
+                .filter(|(name, _)| wildcard_match(name, &attr.name))
+                .filter(|(name, _)| !attr.name.starts_with(INTERACTIVITY_PREFIX) || name.starts_with(INTERACTIVITY_PREFIX))
+                .map(|(_, value_pattern)| *value_pattern)
+                .collect();
+            if matching.is_empty() || protected.contains(&format!("[{}", attr.name)) {
+                continue;
+            }
+
+            match attr.name.as_str() {
+                "class" if can_prune_selectors => {
+                    let kept: Vec<&str> = value.split_whitespace()
+                        .filter(|class| {
+                            protected.contains(&format!(".{}", class.to_lowercase()))
+                                || !matching.iter().any(|p| matches_value(*p, class))
+                        })
+                        .collect();
+                    let removed = value.split_whitespace().count() - kept.len();
+                    if removed > 0 {
+                        count += removed;
+                        new_tag = if kept.is_empty() {
+                            remove_attribute(&new_tag, "class")
+                        } else {
+                            set_attribute(&new_tag, "class", &kept.join(" "))
+                        };
                     }
-                    i += 1;
                 }
-                
-                let css_content: String = chars[css_start..i].iter().collect();
-                let original_len = css_content.len();
-                
-                // Skip tree-shaking for very large CSS blocks (>100KB) to prevent hangs
-                if original_len > 100_000 {
-                    tracing::warn!("Skipping CSS tree-shake for large block: {} bytes", original_len);
-                    result.push_str(&css_content);
-                    result.push_str("</style>");
-                    i += 8;
-                    continue;
-                }
-                
-                // Tree-shake the CSS - remove unused rules
-                match css_optimizer.remove_unused_css(&css_content) {
-                    Ok(optimized) => {
-                        let new_len = optimized.len();
-                        if original_len > 0 {
-                            let reduction = ((original_len.saturating_sub(new_len)) as f64 / original_len as f64 * 100.0) as i32;
-                            total_reduction += reduction;
-                        }
-                        result.push_str(&optimized);
+                "id" if can_prune_selectors => {
+                    let referenced = protected.contains(&format!("#{}", value.to_lowercase()));
+                    if !referenced && matching.iter().any(|p| matches_value(*p, value)) {
+                        new_tag = remove_attribute(&new_tag, "id");
                         count += 1;
-                        tracing::debug!(
-                            "CSS tree-shake: {} -> {} bytes ({}% reduction)",
-                            original_len, new_len, 
-                            if original_len > 0 { (original_len - new_len) * 100 / original_len } else { 0 }
-                        );
                     }
-                    Err(e) => {
-                        // Keep original on error
-                        tracing::warn!("CSS optimization failed: {}", e);
-                        result.push_str(&css_content);
+                }
+                "class" | "id" => {}
+                name => {
+                    if matching.iter().any(|p| matches_value(*p, value)) {
+                        new_tag = remove_attribute(&new_tag, name);
+                        count += 1;
                     }
                 }
-                
-                // Add closing tag
-                result.push_str("</style>");
-                i += 8; // skip </style>
+            }
+        }
+        (new_tag != tag).then_some(new_tag)
+    });
+
+    (count, original_len.saturating_sub(html.len()))
+}
+
+/// Minimum repeats before an inline style is worth turning into a class
+const INLINE_STYLE_MIN_REPEATS: usize = 3;
+
+/// Replace exact-match inline `style` attributes that appear 3+ times with generated classes
+/// Declarations get `!important` so the class keeps the precedence the inline style had
+fn consolidate_inline_styles(html: &mut String) -> usize {
+    use crate::html_utils::{get_attribute, remove_attribute, rewrite_start_tags, set_attribute};
+    use std::collections::HashMap;
+
+    let normalize = |style: &str| style.trim().trim_end_matches(';').trim().to_string();
+
+    // Count each distinct style, remembering first-seen order for stable class names
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut order = Vec::new();
+    rewrite_start_tags(html, |_, tag| {
+        if let Some(style) = get_attribute(tag, "style").map(|s| normalize(&s)) {
+            if !style.is_empty() {
+                let count = counts.entry(style.clone()).or_insert(0);
+                if *count == 0 {
+                    order.push(style);
+                }
+                *count += 1;
+            }
+        }
+        None
+    });
+
+    let mut classes: HashMap<String, String> = HashMap::new();
+    let mut css = String::new();
+    for style in order {
+        if counts[&style] < INLINE_STYLE_MIN_REPEATS {
+            continue;
+        }
+        let Some(body) = important_declarations(&style) else {
+            continue;
+        };
+        let class = format!("hwp-s{}", classes.len() + 1);
+        css.push_str(&format!(".{}{{{}}}", class, body));
+        classes.insert(style, class);
+    }
+
+    if classes.is_empty() {
+        return 0;
+    }
+
+    let mut replaced = 0;
+    let rewritten = rewrite_start_tags(html, |_, tag| {
+        let style = normalize(&get_attribute(tag, "style")?);
+        let class = classes.get(&style)?;
+        replaced += 1;
+        let without_style = remove_attribute(tag, "style");
+        let class_value = match get_attribute(&without_style, "class") {
+            Some(existing) if !existing.trim().is_empty() => format!("{} {}", existing.trim(), class),
+            _ => class.clone(),
+        };
+        Some(set_attribute(&without_style, "class", &class_value))
+    });
+
+    let style_block = format!("<style id=\"htmlwp-inline-styles\">{}</style>", css);
+    *html = rewritten;
+    match html.to_ascii_lowercase().find("</head>") {
+        Some(pos) => html.insert_str(pos, &style_block),
+        None => html.insert_str(0, &style_block),
+    }
+
+    replaced
+}
+
+/// Split a style attribute into declarations and mark each `!important`
+/// Returns None for anything we can't split safely (unbalanced parentheses)
+fn important_declarations(style: &str) -> Option<String> {
+    let mut declarations = Vec::new();
+    let mut depth = 0i32;
+    let mut current = String::new();
+
+    for c in style.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ';' if depth == 0 => {
+                declarations.push(std::mem::take(&mut current));
                 continue;
             }
+            _ => {}
         }
-        
-        result.push(chars[i]);
-        i += 1;
+        current.push(c);
     }
+    declarations.push(current);
 
-    let avg_reduction = if count > 0 { total_reduction / count as i32 } else { 0 };
-    *html = result;
-    (count, avg_reduction)
+    if depth != 0 {
+        return None;
+    }
+
+    let body: Vec<String> = declarations
+        .iter()
+        .map(|d| d.trim())
+        .filter(|d| !d.is_empty())
+        .map(|d| if d.contains("!important") { d.to_string() } else { format!("{}!important", d) })
+        .collect();
+    if body.is_empty() { None } else { Some(body.join(";")) }
 }
 
 /// Add preconnect hints for common external resources
@@ -253,9 +711,51 @@ fn add_preconnect_hints(html: &mut String) -> usize {
     hints_added
 }
 
+/// `type` of the empty script left in place of a preserved one (a data block, so no pass touches it)
+const PRESERVED_SCRIPT_TYPE: &str = "text/x-htmlwp-preserved";
+
+/// Replace inline scripts whose content contains any of `markers` with numbered placeholders.
+/// Returns the original elements, indexed by placeholder
+fn stash_preserved_scripts(html: &mut String, markers: &[String]) -> Vec<String> {
+    let markers: Vec<&str> = markers.iter().map(|m| m.trim()).filter(|m| !m.is_empty()).collect();
+    let mut stashed = Vec::new();
+    if markers.is_empty() {
+        return stashed;
+    }
+
+    let mut result = String::with_capacity(html.len());
+    let mut copied = 0;
+    for script in crate::html_utils::raw_text_elements(html, "script") {
+        let tag = &html[script.start..script.open_end];
+        let content = &html[script.open_end..script.content_end];
+        if crate::html_utils::get_attribute(tag, "src").is_some() || !markers.iter().any(|m| content.contains(m)) {
+            continue;
+        }
+        // The whole element, closing tag included, exactly as written
+        stashed.push(html[script.start..script.end].to_string());
+        result.push_str(&html[copied..script.start]);
+        result.push_str(&format!("<script type=\"{}\" data-index=\"{}\"></script>", PRESERVED_SCRIPT_TYPE, stashed.len() - 1));
+        copied = script.end;
+    }
+    result.push_str(&html[copied..]);
+    *html = result;
+    stashed
+}
+
+/// Put the scripts taken by `stash_preserved_scripts` back in place of their placeholders
+fn restore_preserved_scripts(html: &mut String, stashed: &[String]) {
+    *html = crate::html_utils::replace_raw_text_elements(html, "script", |tag, _| {
+        if crate::html_utils::get_attribute(tag, "type").as_deref() != Some(PRESERVED_SCRIPT_TYPE) {
+            return None;
+        }
+        let index: usize = crate::html_utils::get_attribute(tag, "data-index")?.parse().ok()?;
+        stashed.get(index).cloned()
+    });
+}
+
 /// Minify HTML by removing unnecessary whitespace and comments
-/// Minify HTML by removing unnecessary whitespace and comments
-fn minify_html(html: &str) -> String {
+/// `minify_inline` also minifies the contents of inline `<style>` and `<script>` elements
+fn minify_html(html: &str, minify_inline: bool) -> String {
     let mut cfg = minify_html::Cfg::new();
     cfg.do_not_minify_doctype = true;
     cfg.ensure_spec_compliant_unquoted_attribute_values = true;
@@ -263,8 +763,8 @@ fn minify_html(html: &str) -> String {
     cfg.keep_html_and_head_opening_tags = true;
     cfg.keep_spaces_between_attributes = true;
     cfg.keep_comments = false;
-    cfg.minify_css = true;
-    cfg.minify_js = true;
+    cfg.minify_css = minify_inline;
+    cfg.minify_js = minify_inline;
     cfg.remove_bangs = false;
     cfg.remove_processing_instructions = false;
 
@@ -276,57 +776,391 @@ fn minify_html(html: &str) -> String {
     }
 }
 
-/// Add lazy loading to images below the fold
-fn add_lazy_loading(html: &mut String) -> usize {
-    let mut count = 0;
-    
-    // Simple regex-like replacement for img tags
-    let mut result = String::with_capacity(html.len() + 1000);
-    let mut i = 0;
-    let chars: Vec<char> = html.chars().collect();
-    let len = chars.len();
+/// Elements without a closing tag
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source", "track", "wbr",
+];
 
-    while i < len {
-        // Look for <img
-        if i + 3 < len {
-            let tag: String = chars[i..i+4].iter().collect();
-            if tag.to_lowercase() == "<img" {
-                // Find the end of the tag
-                let start = i;
-                while i < len && chars[i] != '>' {
-                    i += 1;
-                }
-                if i < len {
-                    i += 1; // include >
+/// Elements whose content is emitted verbatim on the same line as their tags
+const VERBATIM_ELEMENTS: [&str; 4] = ["script", "style", "pre", "textarea"];
+
+/// Re-serialize HTML one tag or text run per line, indented by nesting depth
+/// Script, style, pre and textarea content is left untouched.
+fn prettify_html(html: &str) -> String {
+    use crate::html_utils::{find_tag_end, tag_name};
+
+    fn push_line(out: &mut String, depth: usize, line: &str) {
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(line);
+        out.push('\n');
+    }
+    fn push_text(out: &mut String, depth: usize, text: &str) {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !text.is_empty() {
+            push_line(out, depth, &text);
+        }
+    }
+
+    let lower = html.to_ascii_lowercase();
+    let bytes = html.as_bytes();
+    let mut out = String::with_capacity(html.len() * 2);
+    let mut depth = 0;
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    while let Some(offset) = html[pos..].find('<') {
+        let start = pos + offset;
+        let is_tag = bytes.get(start + 1).is_some_and(|b| b.is_ascii_alphabetic() || matches!(b, b'/' | b'!' | b'?'));
+        if !is_tag {
+            pos = start + 1;
+            continue;
+        }
+        push_text(&mut out, depth, &html[text_start..start]);
+
+        let end = if lower[start..].starts_with("<!--") {
+            lower[start..].find("-->").map(|e| start + e + 3).unwrap_or(html.len())
+        } else {
+            find_tag_end(html, start).unwrap_or(html.len())
+        };
+        let tag = &html[start..end];
+
+        if tag.starts_with("</") {
+            depth = depth.saturating_sub(1);
+            push_line(&mut out, depth, tag);
+            pos = end;
+        } else if tag.starts_with("<!") || tag.starts_with("<?") {
+            push_line(&mut out, depth, tag);
+            pos = end;
+        } else {
+            let name = tag_name(tag);
+            if VERBATIM_ELEMENTS.contains(&name.as_str()) {
+                let closing = format!("</{}", name);
+                let close_start = lower[end..].find(&closing).map(|e| end + e).unwrap_or(html.len());
+                let close_end = find_tag_end(html, close_start).unwrap_or(html.len()).max(close_start);
+                push_line(&mut out, depth, &html[start..close_end]);
+                pos = close_end;
+            } else {
+                push_line(&mut out, depth, tag);
+                if !VOID_ELEMENTS.contains(&name.as_str()) && !tag.ends_with("/>") {
+                    depth += 1;
                 }
-                
-                let img_tag: String = chars[start..i].iter().collect();
-                
-                // Skip if already has loading attribute or is likely LCP image
-                if !img_tag.contains("loading=") && !img_tag.contains("fetchpriority=") {
-                    // Add loading="lazy"
-                    let new_tag = img_tag.replacen("<img", "<img loading=\"lazy\"", 1);
-                    result.push_str(&new_tag);
-                    count += 1;
-                    continue;
-                } else {
-                    result.push_str(&img_tag);
-                    continue;
+                pos = end;
+            }
+        }
+        text_start = pos;
+    }
+
+    push_text(&mut out, depth, &html[text_start..]);
+    out
+}
+
+/// Diffs longer than this are replaced by a "too large" note
+const MAX_DIFF_BYTES: usize = 256 * 1024;
+
+/// Unified diff of the optimized HTML against the original, for auditing changes
+/// Minified output is one long line, so such diffs are mostly noise and quickly hit the cap
+pub fn html_diff(original: &str, optimized: &str) -> String {
+    let diff = similar::TextDiff::from_lines(original, optimized)
+        .unified_diff()
+        .context_radius(2)
+        .header("original", "optimized")
+        .to_string();
+    if diff.len() > MAX_DIFF_BYTES {
+        format!("Diff too large ({} bytes, limit {})", diff.len(), MAX_DIFF_BYTES)
+    } else {
+        diff
+    }
+}
+
+/// Attributes that do nothing when empty
+/// (`alt=""` and `value=""` are meaningful and deliberately absent)
+const REMOVABLE_EMPTY_ATTRIBUTES: [&str; 3] = ["class", "style", "id"];
+
+/// Boolean attributes whose value is redundant (`disabled="disabled"` -> `disabled`)
+const BOOLEAN_ATTRIBUTES: [&str; 22] = [
+    "allowfullscreen", "async", "autofocus", "autoplay", "checked", "controls", "default",
+    "defer", "disabled", "formnovalidate", "hidden", "ismap", "loop", "multiple", "muted",
+    "nomodule", "novalidate", "open", "readonly", "required", "reversed", "selected",
+];
+
+/// Remove empty and default-valued attributes and collapse boolean attributes
+fn clean_attributes(html: &mut String) -> usize {
+    let mut count = 0;
+
+    *html = crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        let attrs = crate::html_utils::parse_attributes(tag);
+        let is_stylesheet = attrs.iter().any(|attr| {
+            attr.name == "rel" && attr.value.as_deref().is_some_and(|v| v.eq_ignore_ascii_case("stylesheet"))
+        });
+
+        let mut new_tag = tag.to_string();
+        // Back to front so earlier byte ranges stay valid
+        for attr in attrs.iter().rev() {
+            let value = attr.value.as_deref().map(str::trim).unwrap_or_default().to_ascii_lowercase();
+            let remove = match attr.name.as_str() {
+                "type" => match name {
+                    "script" => value == "text/javascript" || value == "application/javascript",
+                    "style" => value == "text/css",
+                    "link" => is_stylesheet && value == "text/css",
+                    _ => false,
+                },
+                attr_name if REMOVABLE_EMPTY_ATTRIBUTES.contains(&attr_name) => {
+                    attr.value.is_some() && value.is_empty()
                 }
+                _ => false,
+            };
+
+            if remove {
+                new_tag.replace_range(attr.start..attr.end, "");
+                count += 1;
+            } else if BOOLEAN_ATTRIBUTES.contains(&attr.name.as_str())
+                && attr.value.is_some()
+                && (value.is_empty() || value == attr.name)
+            {
+                new_tag.replace_range(attr.start..attr.end, &format!(" {}", attr.name));
+                count += 1;
+            }
+        }
+
+        (new_tag != tag).then_some(new_tag)
+    });
+
+    count
+}
+
+/// Add lazy loading to images below the fold
+fn add_lazy_loading(html: &mut String, noscript_fallback: bool) -> Vec<Change> {
+    let lower = html.to_ascii_lowercase();
+    let mut result = String::with_capacity(html.len() + 1000);
+    let mut changes = Vec::new();
+    let mut copied = 0;
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find("<img") {
+        let start = pos + offset;
+        let Some(end) = crate::html_utils::find_tag_end(html, start) else { break };
+        pos = end;
+        let img_tag = &html[start..end];
+
+        // Skip if already has loading attribute or is likely LCP image, or is a no-JS fallback
+        let before = &lower[..start];
+        let in_noscript = before.rfind("<noscript").is_some_and(|open| before.rfind("</noscript").is_none_or(|close| open > close));
+        if img_tag.contains("loading=") || img_tag.contains("fetchpriority=") || in_noscript {
+            continue;
+        }
+        result.push_str(&html[copied..start]);
+        let new_tag = img_tag.replacen("<img", "<img loading=\"lazy\"", 1);
+        copied = end;
+        let change = Change::new("image_lazy_loaded", tag_src(img_tag), img_tag, &new_tag);
+        result.push_str(&change.mark(&new_tag));
+        changes.push(change);
+
+        // The real image for visitors without JS, unless a fallback follows already
+        // (<noscript> isn't allowed inside <picture>)
+        let has_fallback = lower[end..].trim_start().starts_with("<noscript");
+        if noscript_fallback && !has_fallback && !crate::html_utils::is_inside_picture(&lower, start) {
+            if let Some(fallback) = noscript_image(img_tag) {
+                result.push_str(&format!("<noscript>{}</noscript>", fallback));
             }
         }
-        
-        result.push(chars[i]);
-        i += 1;
     }
 
+    result.push_str(&html[copied..]);
     *html = result;
+    changes
+}
+
+/// `tag` loading its image directly, for a `<noscript>` fallback. `None` unless a script
+/// supplies the image: a `data-src`/`data-lazy-src` URL with a placeholder (or no) `src`
+fn noscript_image(tag: &str) -> Option<String> {
+    use crate::html_utils::{get_attribute, remove_attribute, set_attribute};
+    let lazy_attribute = |names: [&str; 2]| names.iter()
+        .find_map(|name| get_attribute(tag, name))
+        .filter(|value| !value.trim().is_empty());
+
+    let src = lazy_attribute(["data-lazy-src", "data-src"])?;
+    if get_attribute(tag, "src").is_some_and(|current| current.trim() == src.trim()) {
+        return None;
+    }
+    let mut fallback = set_attribute(tag, "src", &src.replace('"', "&quot;"));
+    if let Some(srcset) = lazy_attribute(["data-lazy-srcset", "data-srcset"]) {
+        fallback = set_attribute(&fallback, "srcset", &srcset.replace('"', "&quot;"));
+    }
+    for name in ["data-src", "data-lazy-src", "data-srcset", "data-lazy-srcset"] {
+        fallback = remove_attribute(&fallback, name);
+    }
+    Some(fallback)
+}
+
+/// `decoding="async"` on every image without a `decoding` attribute, except the LCP candidate
+/// (the image already marked `fetchpriority="high"`, else the page's primary content image,
+/// skipping logos and icons), which gets `decoding="sync"` and `fetchpriority="high"`.
+/// A fragment has no known LCP image. Returns the number of images changed
+fn add_decoding_hints(html: &mut String, page_url: &str, fragment: bool) -> usize {
+    use crate::html_utils::get_attribute;
+    let is_high_priority = |tag: &str| {
+        get_attribute(tag, "fetchpriority").is_some_and(|p| p.eq_ignore_ascii_case("high"))
+    };
+    let mut has_high_priority = false;
+    crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        has_high_priority |= name == "img" && is_high_priority(tag);
+        None
+    });
+    let base_url = crate::url_utils::document_base(html, page_url);
+    let primary = (!fragment && !has_high_priority)
+        .then(|| crate::image_optimizer::primary_image(&Html::parse_document(html), &base_url))
+        .flatten();
+    // Same source choice as `primary_image`: the real URL behind a lazy-load placeholder
+    let is_primary = |tag: &str| {
+        let src = ["src", "data-src"].into_iter()
+            .filter_map(|name| get_attribute(tag, name))
+            .find(|src| !src.trim().is_empty() && !src.trim().starts_with("data:"));
+        let resolved = src.and_then(|src| crate::url_utils::resolve_url(&base_url, &crate::html_utils::decode_entities(src.trim())).ok());
+        primary.is_some() && resolved == primary
+    };
+
+    let mut lcp_pending = !fragment;
+    let mut count = 0;
+    *html = crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        if name != "img" {
+            return None;
+        }
+        let is_lcp = lcp_pending && if has_high_priority { is_high_priority(tag) } else { is_primary(tag) };
+        if is_lcp {
+            lcp_pending = false;
+        }
+        let has_decoding = crate::html_utils::get_attribute(tag, "decoding").is_some();
+
+        let mut new_tag = tag.to_string();
+        if is_lcp {
+            if !has_decoding {
+                new_tag = crate::html_utils::set_attribute(&new_tag, "decoding", "sync");
+            }
+            if crate::html_utils::get_attribute(&new_tag, "fetchpriority").is_none() {
+                new_tag = crate::html_utils::set_attribute(&new_tag, "fetchpriority", "high");
+            }
+        } else if !has_decoding {
+            new_tag = crate::html_utils::set_attribute(&new_tag, "decoding", "async");
+        }
+        (new_tag != tag).then(|| {
+            count += 1;
+            new_tag
+        })
+    });
     count
 }
 
-/// Defer non-critical scripts
-fn defer_scripts(html: &mut String) -> usize {
+/// Styles and click handler shared by every lite embed: the facade keeps the iframe's box and
+/// a click swaps the real player in, autoplaying so one click is enough
+const LITE_EMBED_ASSETS: &str = concat!(
+    "<style id=\"htmlwp-lite-embed-css\">",
+    ".htmlwp-lite-embed{position:relative;display:block;width:100%;cursor:pointer;background:#000;overflow:hidden}",
+    ".htmlwp-lite-embed img{position:absolute;inset:0;width:100%;height:100%;object-fit:cover}",
+    ".htmlwp-lite-embed iframe{position:absolute;inset:0;width:100%;height:100%;border:0}",
+    ".htmlwp-lite-embed button{position:absolute;top:50%;left:50%;width:68px;height:48px;margin:-24px 0 0 -34px;",
+    "border:0;border-radius:12px;background:rgba(0,0,0,.7);cursor:pointer}",
+    ".htmlwp-lite-embed button:before{content:\"\";position:absolute;top:50%;left:55%;transform:translate(-50%,-50%);",
+    "border-style:solid;border-width:10px 0 10px 18px;border-color:transparent transparent transparent #fff}",
+    "</style>",
+    "<script id=\"htmlwp-lite-embed-js\">",
+    "document.addEventListener('click',function(e){",
+    "var f=e.target.closest&&e.target.closest('.htmlwp-lite-embed');if(!f)return;",
+    "var i=document.createElement('iframe');i.src=f.getAttribute('data-embed');i.title=f.getAttribute('data-title')||'';",
+    "i.allow='accelerometer; autoplay; clipboard-write; encrypted-media; gyroscope; picture-in-picture; fullscreen';",
+    "i.allowFullscreen=true;f.replaceChildren(i);});",
+    "</script>",
+);
+
+/// Player URL, video ID and thumbnail of a YouTube or Vimeo embed URL
+fn video_embed(src: &str) -> Option<(url::Url, String, String)> {
+    let src = src.trim();
+    let url = url::Url::parse(src).or_else(|_| url::Url::parse(&format!("https:{}", src))).ok()?;
+    let host = url.host_str()?.trim_start_matches("www.").to_ascii_lowercase();
+    let mut segments = url.path_segments()?;
+    let id = match (host.as_str(), segments.next(), segments.next()) {
+        ("youtube.com" | "youtube-nocookie.com", Some("embed"), Some(id)) => id.to_string(),
+        ("player.vimeo.com", Some("video"), Some(id)) => id.to_string(),
+        _ => return None,
+    };
+    // Playlists (`/embed/videoseries?list=`) have no single video to show a thumbnail for
+    if id.is_empty() || id == "videoseries" || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    // Vimeo has no static thumbnail URL; vumbnail.com is an unofficial third-party service
+    // that redirects to the video's poster image
+    let thumbnail = if host == "player.vimeo.com" {
+        format!("https://vumbnail.com/{}.jpg", id)
+    } else {
+        format!("https://i.ytimg.com/vi/{}/hqdefault.jpg", id)
+    };
+    Some((url, id, thumbnail))
+}
+
+/// Replace YouTube/Vimeo `<iframe>`s with a thumbnail and play button; clicking loads the
+/// original embed URL (query and `#t=` start times kept) with autoplay. The facade keeps the
+/// iframe's aspect ratio. The facade is a block-styled `<span>` so it stays valid where the
+/// iframe sat inside phrasing content such as `<p>`. Returns the number of embeds replaced
+fn add_lite_embeds(html: &mut String, fragment: bool) -> usize {
+    use crate::html_utils::{decode_entities, find_tag_end, get_attribute};
+    let escape = |value: &str| value.replace('&', "&amp;").replace('"', "&quot;").replace('<', "&lt;");
+
+    let lower = html.to_ascii_lowercase();
+    let mut result = String::with_capacity(html.len());
+    let mut copied = 0;
     let mut count = 0;
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find("<iframe") {
+        let start = pos + offset;
+        let Some(open_end) = find_tag_end(html, start) else { break };
+        let Some(close) = lower[open_end..].find("</iframe").map(|c| open_end + c) else { break };
+        let end = find_tag_end(html, close).unwrap_or(html.len());
+        pos = end;
+
+        let tag = &html[start..open_end];
+        let src = get_attribute(tag, "src").or_else(|| get_attribute(tag, "data-src")).map(|src| decode_entities(&src));
+        let Some((mut player, id, thumbnail)) = src.as_deref().and_then(video_embed) else { continue };
+
+        let query: Vec<(String, String)> = player.query_pairs()
+            .filter(|(key, _)| key != "autoplay")
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+        player.query_pairs_mut().clear().extend_pairs(&query).append_pair("autoplay", "1");
+
+        let title = get_attribute(tag, "title").map(|t| decode_entities(&t)).filter(|t| !t.trim().is_empty())
+            .unwrap_or_else(|| "Video".to_string());
+        let dimension = |name| get_attribute(tag, name).and_then(|v| v.trim().parse::<u32>().ok()).filter(|v| *v > 0);
+        let (width, height) = dimension("width").zip(dimension("height")).unwrap_or((16, 9));
+        let max_width = dimension("width").map(|w| format!(";max-width:{}px", w)).unwrap_or_default();
+
+        result.push_str(&html[copied..start]);
+        result.push_str(&format!(
+            concat!(
+                "<span class=\"htmlwp-lite-embed\" data-embed=\"{}\" data-title=\"{}\" data-video-id=\"{}\" style=\"display:block;aspect-ratio:{}/{}{}\">",
+                "<img src=\"{}\" alt=\"{}\" width=\"480\" height=\"360\">",
+                "<button type=\"button\" aria-label=\"Play video: {}\"></button></span>"
+            ),
+            escape(player.as_str()), escape(&title), id, width, height, max_width,
+            thumbnail, escape(&title), escape(&title),
+        ));
+        copied = end;
+        count += 1;
+    }
+    if count == 0 {
+        return 0;
+    }
+    result.push_str(&html[copied..]);
+
+    // Once per page, before </body> (a fragment carries it along at the end)
+    let body_close = (!fragment).then(|| result.to_ascii_lowercase().rfind("</body>")).flatten();
+    result.insert_str(body_close.unwrap_or(result.len()), LITE_EMBED_ASSETS);
+    *html = result;
+    count
+}
+
+/// Defer non-critical scripts
+fn defer_scripts(html: &mut String) -> Vec<Change> {
+    let mut changes = Vec::new();
     
     let mut result = String::with_capacity(html.len() + 500);
     let mut i = 0;
@@ -348,13 +1182,18 @@ fn defer_scripts(html: &mut String) -> usize {
                 
                 let script_tag: String = chars[start..i].iter().collect();
                 
-                // Skip if already has defer/async or is inline
+                // Skip if already has defer/async or is inline; modules are deferred already and
+                // import maps / JSON blocks aren't loaded at all
                 let lower = script_tag.to_lowercase();
-                if !lower.contains("defer") && !lower.contains("async") && lower.contains("src=") {
+                let classic = crate::html_utils::is_classic_script_type(
+                    crate::html_utils::get_attribute(&script_tag, "type").as_deref()
+                );
+                if classic && !lower.contains("defer") && !lower.contains("async") && lower.contains("src=") {
                     // Add defer
                     let new_tag = script_tag.replacen("<script", "<script defer", 1);
-                    result.push_str(&new_tag);
-                    count += 1;
+                    let change = Change::new("script_deferred", tag_src(&script_tag), &script_tag, &new_tag);
+                    result.push_str(&change.mark(&new_tag));
+                    changes.push(change);
                     continue;
                 } else {
                     result.push_str(&script_tag);
@@ -368,17 +1207,735 @@ fn defer_scripts(html: &mut String) -> usize {
     }
 
     *html = result;
-    count
+    changes
+}
+
+/// Hosts known to serve the same content over https
+const HTTPS_UPGRADEABLE_HOSTS: [&str; 10] = [
+    "fonts.googleapis.com", "fonts.gstatic.com", "ajax.googleapis.com", "cdnjs.cloudflare.com",
+    "cdn.jsdelivr.net", "unpkg.com", "code.jquery.com", "s.w.org", "secure.gravatar.com", "i0.wp.com",
+];
+
+/// Attributes that load a subresource (links in `<a>`/`<form>` aren't mixed content)
+const SUBRESOURCE_ATTRIBUTES: [&str; 3] = ["src", "data-src", "poster"];
+
+/// `<link>` relations whose `href` the browser fetches as a subresource (`canonical`,
+/// `alternate`, `pingback` and friends are plain references)
+const SUBRESOURCE_LINK_RELS: [&str; 6] = ["stylesheet", "preload", "modulepreload", "icon", "apple-touch-icon", "mask-icon"];
+
+/// Find `http://` subresources on an https page. With `upgrade`, those on the page's own host
+/// or a known https-capable host are rewritten to `https://`.
+/// Returns (found, upgraded)
+fn fix_mixed_content(html: &mut String, page_url: &str, upgrade: bool) -> (usize, usize) {
+    let page_host = url::Url::parse(page_url).ok().and_then(|u| u.host_str().map(str::to_ascii_lowercase));
+    let mut found = 0;
+    let mut upgraded = 0;
+
+    *html = crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        let loads_href = name == "link" && crate::html_utils::get_attribute(tag, "rel").is_some_and(|rel| {
+            rel.to_ascii_lowercase().split_whitespace().any(|rel| SUBRESOURCE_LINK_RELS.contains(&rel))
+        });
+        let mut new_tag = tag.to_string();
+        for attr in crate::html_utils::parse_attributes(tag) {
+            let is_subresource = SUBRESOURCE_ATTRIBUTES.contains(&attr.name.as_str()) || (loads_href && attr.name == "href");
+            let Some(value) = attr.value.filter(|_| is_subresource) else { continue };
+            if !value.trim_start().get(..7).is_some_and(|scheme| scheme.eq_ignore_ascii_case("http://")) {
+                continue;
+            }
+            found += 1;
+
+            let host = url::Url::parse(value.trim()).ok().and_then(|u| u.host_str().map(str::to_ascii_lowercase));
+            let upgradeable = host.is_some_and(|host| {
+                page_host.as_deref() == Some(host.as_str()) || HTTPS_UPGRADEABLE_HOSTS.contains(&host.as_str())
+            });
+            if upgrade && upgradeable {
+                let secure = format!("https://{}", &value.trim_start()[7..]);
+                new_tag = crate::html_utils::set_attribute(&new_tag, &attr.name, &secure);
+                upgraded += 1;
+            } else {
+                tracing::debug!("Mixed content: {} on {}", value, page_url);
+            }
+        }
+        (new_tag != tag).then_some(new_tag)
+    });
+
+    (found, upgraded)
+}
+
+/// Calls that only appear in tracker snippets
+const TRACKER_SNIPPETS: [&str; 5] = ["gtag(", "fbq(", "_gaq.push(", "ga('create'", "ga(\"create\""];
+
+/// Markers of consent-management platforms, whose scripts must keep running. Google consent
+/// mode alone (`gtag('consent', ...)`) is part of the tracker and goes with it
+const CONSENT_HINTS: [&str; 16] = [
+    "cookiebot", "cookieyes", "onetrust", "optanon", "complianz", "cmplz", "usercentrics", "iubenda",
+    "cookie-law", "cookielaw", "quantcast", "__tcfapi", "cookieconsent", "borlabs", "didomi", "termly",
+];
+
+/// Left where the `gtag` definition was removed when a kept (consent manager) script still
+/// calls `gtag(...)`, which would otherwise throw
+const GTAG_STUB: &str = "<script>window.dataLayer=window.dataLayer||[];function gtag(){dataLayer.push(arguments)}</script>";
+
+/// Remove scripts loaded from `tracker_domains`, inline scripts containing tracker snippets or
+/// loading from a tracker domain (tag manager containers), and `<noscript>` pixels pointing at
+/// one. A `tracker_domains` entry may carry a path (`facebook.com/tr`) to match only that path.
+/// Returns one entry per removed element: the host, or the snippet for inline scripts
+fn strip_tracking_scripts(html: &mut String, page_url: &str, tracker_domains: &[String]) -> Vec<String> {
+    let base = url::Url::parse(&crate::url_utils::document_base(html, page_url)).ok();
+    let tracker_host = |url: &str| {
+        let url = base.as_ref()?.join(url.trim()).ok()?;
+        let host = url.host_str()?.to_ascii_lowercase();
+        let is_tracker = tracker_domains.iter().any(|entry| {
+            let entry = entry.trim().to_ascii_lowercase();
+            let (domain, path) = entry.split_once('/').map_or((entry.as_str(), None), |(d, p)| (d, Some(p)));
+            let host_matches = !domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain)));
+            host_matches && path.is_none_or(|path| url.path().trim_start_matches('/').starts_with(path))
+        });
+        is_tracker.then_some(host)
+    };
+
+    let mut removed = Vec::new();
+    let mut gtag_removed = false;
+    *html = crate::html_utils::remove_raw_text_elements(html, "script", |tag, content| {
+        let lower = format!("{}{}", tag, content).to_ascii_lowercase();
+        if CONSENT_HINTS.iter().any(|hint| lower.contains(hint)) {
+            return false;
+        }
+        if let Some(src) = crate::html_utils::get_attribute(tag, "src") {
+            return match tracker_host(&src) {
+                Some(host) => {
+                    removed.push(host);
+                    true
+                }
+                None => false,
+            };
+        }
+        let is_data_block = crate::html_utils::get_attribute(tag, "type").is_some_and(|t| {
+            let t = t.trim().to_ascii_lowercase();
+            !t.is_empty() && !t.contains("javascript") && t != "module"
+        });
+        if is_data_block {
+            return false;
+        }
+        let found = match TRACKER_SNIPPETS.iter().find(|snippet| content.contains(*snippet)) {
+            Some(snippet) => Some(format!("inline {}", snippet)),
+            None => crate::url_utils::script_urls(content).into_iter()
+                .find_map(&tracker_host)
+                .map(|host| format!("inline {}", host)),
+        };
+        gtag_removed |= found.is_some() && lower.contains("function gtag(");
+        found.inspect(|what| removed.push(what.clone())).is_some()
+    });
+
+    // Consent managers calling gtag() need it defined even without Google's tag
+    if gtag_removed {
+        let mut stubbed = false;
+        *html = crate::html_utils::replace_raw_text_elements(html, "script", |tag, content| {
+            (!stubbed && content.contains("gtag(")).then(|| {
+                stubbed = true;
+                format!("{}{}{}</script>", GTAG_STUB, tag, content)
+            })
+        });
+    }
+
+    removed.extend(strip_tracking_noscripts(html, &tracker_host));
+    removed
+}
+
+/// Remove `<noscript>` blocks whose images and iframes all load from a tracker (the no-JS
+/// pixel or tag-manager iframe shipped with tracking snippets). Returns their hosts, one entry per block
+fn strip_tracking_noscripts(html: &mut String, tracker_host: &dyn Fn(&str) -> Option<String>) -> Vec<String> {
+    let lower = html.to_ascii_lowercase();
+    let mut result = String::with_capacity(html.len());
+    let mut removed = Vec::new();
+    let mut copied = 0;
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find("<noscript") {
+        let start = pos + offset;
+        let Some(open_end) = crate::html_utils::find_tag_end(html, start) else { break };
+        let Some(close) = lower[open_end..].find("</noscript").map(|c| open_end + c) else { break };
+        let end = crate::html_utils::find_tag_end(html, close).unwrap_or(html.len());
+        pos = end;
+
+        let mut hosts = Vec::new();
+        let mut other_content = false;
+        crate::html_utils::rewrite_start_tags(&html[open_end..close], |name, tag| {
+            if matches!(name, "img" | "iframe") {
+                match crate::html_utils::get_attribute(tag, "src").and_then(|src| tracker_host(&src)) {
+                    Some(host) => hosts.push(host),
+                    None => other_content = true,
+                }
+            }
+            None
+        });
+        if hosts.is_empty() || other_content {
+            continue;
+        }
+        result.push_str(&html[copied..start]);
+        copied = end;
+        hosts.dedup();
+        removed.push(hosts.join(" + "));
+    }
+
+    if !removed.is_empty() {
+        result.push_str(&html[copied..]);
+        *html = result;
+    }
+    removed
 }
 
 /// Count images without width/height (causes CLS)
-fn count_images_without_dimensions(html: &str) -> usize {
+fn count_images_without_dimensions(doc: &Html) -> usize {
     // For MVP, we'll just count images without dimensions
     // Full implementation would fetch image dimensions
-    let doc = Html::parse_document(html);
     let selector = Selector::parse("img:not([width]):not([height])").unwrap_or_else(|_| {
         Selector::parse("img").unwrap()
     });
     
     doc.select(&selector).count()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_removed_reports_unused_selectors() {
+        let html = "<html><head><style>.ghost{color:red}.real{color:blue}</style></head><body><p class=\"real\">Hi</p></body></html>";
+        let options = OptimizeOptions {
+            debug_removed: true,
+            ..OptimizeOptions::default()
+        };
+        let result = optimize_html_only(html, "https://example.com/", &options);
+        assert_eq!(result.removed_selectors, vec![".ghost".to_string()]);
+
+        let result = optimize_html_only(html, "https://example.com/", &OptimizeOptions::default());
+        assert!(result.removed_selectors.is_empty());
+    }
+
+    #[test]
+    fn test_unused_font_face_removed_from_page() {
+        let html = concat!(
+            "<html><head><style>@font-face{font-family:Used;src:url(a.woff2)}",
+            "@font-face{font-family:Unused;src:url(b.woff2)}.t{font-family:Used}</style></head>",
+            "<body><p class=\"t\">Hi</p></body></html>"
+        );
+        let options = OptimizeOptions {
+            remove_unused_fonts: true,
+            ..OptimizeOptions::default()
+        };
+        let result = optimize_html_only(html, "https://example.com/", &options);
+        assert!(!result.html.contains("b.woff2"), "{}", result.html);
+        assert!(result.html.contains("a.woff2"));
+
+        // Off by default
+        let result = optimize_html_only(html, "https://example.com/", &OptimizeOptions::default());
+        assert!(result.html.contains("b.woff2"));
+    }
+
+    #[test]
+    fn test_font_display_swap_option() {
+        let html = "<html><head><style>@font-face{font-family:Brand;src:url(brand.woff2)}p{font-family:Brand}</style></head><body><p>Hi</p></body></html>";
+        let result = optimize_html_only(html, "https://example.com/", &OptimizeOptions::default());
+        assert!(result.optimizations.iter().any(|o| o.starts_with("Font: Brand has no font-display")));
+
+        let options = OptimizeOptions {
+            font_display_swap: true,
+            ..OptimizeOptions::default()
+        };
+        let result = optimize_html_only(html, "https://example.com/", &options);
+        assert!(result.html.contains("font-display:swap"), "{}", result.html);
+        assert!(!result.optimizations.iter().any(|o| o.contains("has no font-display")));
+    }
+
+    #[test]
+    fn test_mixed_content_is_reported() {
+        let html = concat!(
+            r#"<link rel="stylesheet" href="http://example.com/style.css">"#,
+            r#"<link rel="shortcut icon" href="http://example.com/favicon.ico">"#,
+            r#"<link rel="alternate" type="application/rss+xml" href="http://example.com/feed/">"#,
+            r#"<link rel="pingback" href="http://example.com/xmlrpc.php">"#,
+            r#"<img src="http://other.example.net/photo.jpg"><a href="http://example.org/">Link</a>"#
+        );
+        let mut reported = html.to_string();
+        assert_eq!(fix_mixed_content(&mut reported, "https://example.com/post/", false), (3, 0), "feeds and pingbacks aren't loaded");
+        assert_eq!(reported, html);
+
+        let result = optimize_html_only(html, "http://example.com/", &OptimizeOptions::default());
+        assert!(!result.optimizations.iter().any(|o| o.contains("mixed-content")));
+    }
+
+    #[test]
+    fn test_mixed_content_upgrade_only_trusted_hosts() {
+        let html = concat!(
+            r#"<link rel="stylesheet" href="http://example.com/style.css">"#,
+            r#"<script src="http://code.jquery.com/jquery.js"></script>"#,
+            r#"<img src="http://other.example.net/photo.jpg">"#
+        );
+        let mut upgraded = html.to_string();
+        assert_eq!(fix_mixed_content(&mut upgraded, "https://example.com/", true), (3, 2));
+        assert!(upgraded.contains(r#"href="https://example.com/style.css""#));
+        assert!(upgraded.contains(r#"src="https://code.jquery.com/jquery.js""#));
+        assert!(upgraded.contains(r#"src="http://other.example.net/photo.jpg""#));
+    }
+
+    #[test]
+    fn test_strip_trackers_keeps_normal_and_consent_scripts() {
+        let html = concat!(
+            "<html><head>",
+            r#"<script async src="https://www.googletagmanager.com/gtag/js?id=G-123"></script>"#,
+            "<script>window.dataLayer=window.dataLayer||[];function gtag(){dataLayer.push(arguments)}gtag('js',new Date());gtag('config','G-123');</script>",
+            r#"<script src="https://consent.cookiebot.com/uc.js"></script>"#,
+            r#"<script src="/wp-content/themes/site/app.js"></script>"#,
+            "<script>var consentBanner = document.querySelector('.consent');</script>",
+            "</head><body></body></html>"
+        );
+        let options = OptimizeOptions { strip_trackers: true, ..OptimizeOptions::default() };
+        let result = optimize_html_only(html, "https://example.com/", &options);
+
+        assert!(!result.html.contains("googletagmanager.com"));
+        assert!(!result.html.contains("G-123"));
+        assert!(!result.html.contains(GTAG_STUB), "nothing left calls gtag()");
+        assert!(result.html.contains("consent.cookiebot.com"));
+        assert!(result.html.contains("/wp-content/themes/site/app.js"));
+        assert!(result.html.contains("consentBanner"));
+        assert!(result.optimizations.contains(&"2 tracking scripts removed (www.googletagmanager.com, inline gtag()".to_string()));
+
+        let kept = optimize_html_only(html, "https://example.com/", &OptimizeOptions::default());
+        assert!(kept.html.contains("googletagmanager.com"));
+    }
+
+    #[test]
+    fn test_strip_trackers_removes_consent_mode_without_cmp() {
+        // Consent mode on its own is part of Google's tag; it is not a consent manager
+        let mut html = concat!(
+            "<script>window.dataLayer=window.dataLayer||[];function gtag(){dataLayer.push(arguments)}gtag('consent','default',{analytics_storage:'denied'});</script>",
+            "<script>gtag('config','G-123');</script>",
+        ).to_string();
+        let removed = strip_tracking_scripts(&mut html, "https://example.com/", &OptimizeOptions::default().tracker_domains);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(html, "");
+    }
+
+    #[test]
+    fn test_strip_trackers_defines_gtag_for_kept_consent_manager() {
+        let mut html = concat!(
+            "<script>window.dataLayer=window.dataLayer||[];function gtag(){dataLayer.push(arguments)}gtag('config','G-123');</script>",
+            "<p>Content</p>",
+            "<script>document.addEventListener('cmplz_status_change',function(){gtag('consent','update',{analytics_storage:'granted'});});</script>",
+        ).to_string();
+        strip_tracking_scripts(&mut html, "https://example.com/", &OptimizeOptions::default().tracker_domains);
+        assert_eq!(html, format!(
+            "<p>Content</p>{}<script>document.addEventListener('cmplz_status_change',function(){{gtag('consent','update',{{analytics_storage:'granted'}});}});</script>",
+            GTAG_STUB
+        ));
+    }
+
+    #[test]
+    fn test_strip_trackers_removes_tag_manager_container_and_pixels() {
+        let html = concat!(
+            "<html><head><title>T</title>",
+            "<script>(function(w,d,s,l,i){w[l]=w[l]||[];w[l].push({'gtm.start':new Date().getTime(),event:'gtm.js'});",
+            "var f=d.getElementsByTagName(s)[0],j=d.createElement(s),dl=l!='dataLayer'?'&l='+l:'';j.async=true;",
+            "j.src='https://www.googletagmanager.com/gtm.js?id='+i+dl;f.parentNode.insertBefore(j,f);})(window,document,'script','dataLayer','GTM-ABC');</script>",
+            "</head><body>",
+            r#"<noscript><iframe src="https://www.googletagmanager.com/ns.html?id=GTM-ABC" height="0" width="0" style="display:none"></iframe></noscript>"#,
+            r#"<noscript><img height="1" width="1" style="display:none" src="https://www.facebook.com/tr?id=1&amp;ev=PageView&amp;noscript=1"></noscript>"#,
+            r#"<noscript><img src="/uploads/photo.jpg" alt="Photo"></noscript>"#,
+            r#"<a href="https://www.facebook.com/example">Facebook</a>"#,
+            "</body></html>"
+        );
+        let options = OptimizeOptions { strip_trackers: true, lazy_images: false, ..OptimizeOptions::default() };
+        let result = optimize_html_only(html, "https://example.com/", &options);
+
+        assert!(!result.html.contains("GTM-ABC"), "{}", result.html);
+        assert!(!result.html.contains("facebook.com/tr"), "{}", result.html);
+        assert!(result.html.contains("/uploads/photo.jpg"), "only tracker noscripts go");
+        assert!(result.html.contains("https://www.facebook.com/example"));
+        assert!(result.optimizations.iter().any(|o| o.starts_with("3 tracking scripts removed (inline www.googletagmanager.com")), "{:?}", result.optimizations);
+    }
+
+    #[test]
+    fn test_lazy_loading_leaves_noscript_images_alone() {
+        let mut html = concat!(
+            r#"<img src="/a.jpg" alt="A">"#,
+            r#"<img src="/b.jpg" loading="eager">"#,
+            r#"<img class="lazyload" data-src="/c.jpg"><noscript><img src="/c.jpg"></noscript>"#
+        ).to_string();
+        let mut changes = add_lazy_loading(&mut html, false);
+        assert_eq!(changes.len(), 2);
+        crate::changes::finalize(&mut html, &mut changes);
+        assert_eq!(html, concat!(
+            r#"<img loading="lazy" src="/a.jpg" alt="A">"#,
+            r#"<img src="/b.jpg" loading="eager">"#,
+            r#"<img loading="lazy" class="lazyload" data-src="/c.jpg"><noscript><img src="/c.jpg"></noscript>"#
+        ));
+    }
+
+    #[test]
+    fn test_noscript_fallback_follows_js_lazy_images() {
+        let mut html = concat!(
+            r#"<img class="lazyload" src="data:image/gif;base64,R0lGOD" data-src="/a.jpg" data-srcset="/a-2x.jpg 2x" alt="A">"#,
+            r#"<img src="/b.jpg" alt="B">"#,
+            r#"<img class="lazyload" data-lazy-src="/c.jpg"><noscript><img src="/c.jpg"></noscript>"#,
+            r#"<noscript><img class="lazyload" data-src="/d.jpg"></noscript>"#,
+            r#"<picture><source data-srcset="/e.webp"><img class="lazyload" data-src="/e.jpg"></picture>"#
+        ).to_string();
+        let mut changes = add_lazy_loading(&mut html, true);
+        assert_eq!(changes.len(), 4);
+        crate::changes::finalize(&mut html, &mut changes);
+        assert_eq!(html, concat!(
+            r#"<img loading="lazy" class="lazyload" src="data:image/gif;base64,R0lGOD" data-src="/a.jpg" data-srcset="/a-2x.jpg 2x" alt="A">"#,
+            r#"<noscript><img srcset="/a-2x.jpg 2x" class="lazyload" src="/a.jpg" alt="A"></noscript>"#,
+            r#"<img loading="lazy" src="/b.jpg" alt="B">"#,
+            r#"<img loading="lazy" class="lazyload" data-lazy-src="/c.jpg"><noscript><img src="/c.jpg"></noscript>"#,
+            r#"<noscript><img class="lazyload" data-src="/d.jpg"></noscript>"#,
+            r#"<picture><source data-srcset="/e.webp"><img loading="lazy" class="lazyload" data-src="/e.jpg"></picture>"#
+        ));
+
+        // Running again adds nothing
+        let again = html.clone();
+        add_lazy_loading(&mut html, true);
+        assert_eq!(html, again);
+    }
+
+    #[test]
+    fn test_node_counts_reported() {
+        let html = "<html><head><title>T</title></head><body><div><p>One</p><p>Two</p></div></body></html>";
+        let result = optimize_html_only(html, "https://example.com/", &OptimizeOptions::default());
+        // html, head, title, body, div, p, p
+        assert_eq!(result.original_node_count, 7);
+        // Head gains SEO tags; nothing is lost
+        assert!(result.optimized_node_count > result.original_node_count);
+
+        let options = OptimizeOptions { strip_trackers: true, ..OptimizeOptions::default() };
+        let tracked = concat!(
+            "<html><head><script>gtag('config','G-1')</script></head><body><p>x</p>",
+            r#"<noscript><img src="https://www.facebook.com/tr?id=1"><img src="https://www.facebook.com/tr?id=2"></noscript>"#,
+            "</body></html>"
+        );
+        let result = optimize_html_only(tracked, "https://example.com/", &options);
+        // The noscript's content is raw text to the parser, so it counts as one element
+        assert_eq!(result.original_node_count, 6);
+        assert!(!result.html.contains("gtag") && !result.html.contains("noscript"), "{}", result.html);
+        assert!(result.optimizations.iter().any(|o| o.starts_with("2 tracking scripts removed")), "{:?}", result.optimizations);
+    }
+
+    #[test]
+    fn test_preserved_scripts_are_untouched() {
+        let consent = "<script type=\"text/javascript\">\n  window.dataLayer = window.dataLayer || [];\n  gtag( 'consent', 'default', {} );  // keep\n</script >";
+        let html = format!(
+            "<html><head>{}<script type=\"text/javascript\">\n  var  other = 1;\n</script></head><body><p>x</p></body></html>",
+            consent
+        );
+        let options = OptimizeOptions {
+            strip_trackers: true,
+            defer_js: true,
+            preserve_scripts: vec!["dataLayer".to_string()],
+            ..OptimizeOptions::default()
+        };
+        let result = optimize_html_only(&html, "https://example.com/", &options);
+        assert!(result.html.contains(consent), "{}", result.html);
+        assert!(!result.html.contains("var  other"), "other inline scripts are still minified");
+        assert!(!result.html.contains(PRESERVED_SCRIPT_TYPE));
+        assert!(result.optimizations.contains(&"1 inline scripts preserved unmodified".to_string()));
+
+        let result = optimize_html_only(&html, "https://example.com/", &OptimizeOptions { preserve_scripts: Vec::new(), ..options });
+        assert!(!result.html.contains(consent), "without the marker the script is minified");
+    }
+
+    #[test]
+    fn test_seo_pass_output_is_shipped() {
+        let html = concat!(
+            "<html><head><title>Garden tips</title>",
+            "<meta name=\"description\" content=\"Old\"><meta name=\"description\" content=\"Spring planting guide\">",
+            "</head><body><img src=\"/uploads/tulips.jpg\" width=\"800\" height=\"600\">",
+            "<a href=\"https://other.example/\" target=\"_blank\">More</a></body></html>"
+        );
+        let options = OptimizeOptions { lazy_images: false, ..OptimizeOptions::default() };
+        let result = optimize_html_only(html, "https://example.com/garden/", &options);
+
+        assert!(result.html.contains("alt=Tulips") || result.html.contains("alt=\"Tulips\""), "{}", result.html);
+        assert!(result.html.contains("og:title"));
+        assert!(result.html.contains("twitter:card"));
+        assert!(result.html.contains("rel=canonical") || result.html.contains("rel=\"canonical\""));
+        assert!(result.html.contains("noopener"));
+        assert_eq!(result.html.matches("name=description").count() + result.html.matches("name=\"description\"").count(), 1);
+        assert!(!result.html.contains("content=Old"));
+    }
+
+    #[test]
+    fn test_changes_detail_matches_optimizations() {
+        let html = r#"<html><head><title>T</title><script src="/a.js"></script><script src="/b.js"></script></head><body><img src="/one.jpg"><img src="/two.jpg" alt="Two"><p>Text</p></body></html>"#;
+        let result = optimize_html_only(html, "https://example.com/post/", &OptimizeOptions::default());
+        let count = |kind: &str| result.changes.iter().filter(|change| change.kind == kind).count();
+        let reported = |sentence: &str| result.optimizations.iter().any(|o| o == sentence);
+
+        assert_eq!(count("script_deferred"), 2);
+        assert!(reported("2 scripts deferred"), "{:?}", result.optimizations);
+        assert_eq!(count("image_lazy_loaded"), 2);
+        assert!(reported("2 images lazy-loaded"));
+        assert_eq!(count("alt_added"), 1);
+        assert!(reported("SEO: 1 alt tags added"));
+        assert_eq!(count("canonical_added"), 1);
+        assert!(reported("SEO: Canonical URL added"));
+
+        let deferred = result.changes.iter().find(|change| change.kind == "script_deferred").unwrap();
+        assert_eq!(deferred.target, "/a.js");
+        assert_eq!(deferred.after, "<script defer src=/a.js>", "recorded after minification");
+        assert_eq!(deferred.bytes_saved, -6);
+    }
+
+    #[test]
+    fn test_changes_describe_final_markup() {
+        let html = r#"<html><head><title>T</title></head><body><p>Text</p><img src="/one.jpg"><img src="/two.jpg"></body></html>"#;
+        let options = OptimizeOptions { image_decoding_hints: true, ..OptimizeOptions::default() };
+        let result = optimize_html_only(html, "https://example.com/post/", &options);
+
+        assert!(!result.html.contains("data-htmlwp-change"), "{}", result.html);
+        assert_eq!(result.optimized_size, result.html.len());
+        let lazy = result.changes.iter().find(|change| change.kind == "image_lazy_loaded" && change.target == "/two.jpg").unwrap();
+        let alt = result.changes.iter().find(|change| change.kind == "alt_added" && change.target == "/two.jpg").unwrap();
+        assert_eq!(lazy.after, alt.after, "both describe the element as shipped");
+        assert!(lazy.after.contains("alt=") && lazy.after.contains("decoding="), "{}", lazy.after);
+        assert!(result.html.contains(&lazy.after));
+    }
+
+    #[test]
+    fn test_youtube_iframe_becomes_facade() {
+        let html = r#"<html><head><title>T</title></head><body><p>Watch:</p><iframe width="560" height="315" src="https://www.youtube.com/embed/dQw4w9WgXcQ?start=42&amp;rel=0" title="Launch video" allowfullscreen></iframe></body></html>"#;
+        let mut page = html.to_string();
+        assert_eq!(add_lite_embeds(&mut page, false), 1);
+
+        assert!(!page.contains("<iframe"));
+        assert!(page.contains(r#"<img src="https://i.ytimg.com/vi/dQw4w9WgXcQ/hqdefault.jpg" alt="Launch video""#), "{}", page);
+        assert!(page.contains(r#"data-embed="https://www.youtube.com/embed/dQw4w9WgXcQ?start=42&amp;rel=0&amp;autoplay=1""#), "{}", page);
+        assert!(page.contains("aspect-ratio:560/315;max-width:560px"));
+        assert_eq!(page.matches("htmlwp-lite-embed-js").count(), 1);
+        assert!(page.ends_with("</script></body></html>"));
+
+        // Other iframes are left alone
+        let mut map = r#"<iframe src="https://www.google.com/maps/embed?pb=1"></iframe>"#.to_string();
+        assert_eq!(add_lite_embeds(&mut map, true), 0);
+        assert!(map.starts_with("<iframe"));
+
+        // Playlists have no single thumbnail
+        let mut playlist = r#"<iframe src="https://www.youtube.com/embed/videoseries?list=PL1234"></iframe>"#.to_string();
+        assert_eq!(add_lite_embeds(&mut playlist, true), 0);
+    }
+
+    #[test]
+    fn test_lite_embed_inside_paragraph_passes_validation() {
+        let html = r#"<html><head><title>T</title></head><body><p><iframe src="https://player.vimeo.com/video/76979871" width="640" height="360"></iframe></p><p>After</p></body></html>"#;
+        let options = OptimizeOptions { lite_embeds: true, ..OptimizeOptions::default() };
+        let result = optimize_html_only(html, "https://example.com/", &options);
+
+        assert!(!result.optimizations.iter().any(|o| o.contains("failed validation")), "{:?}", result.optimizations);
+        assert!(!result.html.contains("<iframe"), "{}", result.html);
+        assert!(result.html.contains("vumbnail.com/76979871.jpg"), "{}", result.html);
+    }
+
+    #[test]
+    fn test_corrupted_output_falls_back_to_original() {
+        // Lazy loading matches the "<img" inside the attribute value and breaks its quoting
+        let html = r#"<html><body><div data-template="<img"><p>One</p><span>Two</span></div></body></html>"#;
+        let result = optimize_html_only(html, "https://example.com/", &OptimizeOptions::default());
+
+        assert_eq!(result.html, html);
+        assert_eq!(parse_stats(&parse(&result.html, false)), parse_stats(&parse(html, false)));
+        assert_eq!(result.optimizations, vec!["Warning: optimized HTML failed validation; original HTML returned".to_string()]);
+        assert_eq!(result.optimized_size, html.len());
+        assert_eq!(result.reduction_percent, 0.0);
+    }
+
+    #[test]
+    fn test_defer_skips_module_and_data_scripts() {
+        let html = concat!(
+            r#"<script type="importmap" src="/importmap.json"></script>"#,
+            r#"<script type="module" src="/app.mjs"></script>"#,
+            r#"<script type="application/ld+json" src="/schema.json"></script>"#,
+            r#"<script type="text/javascript" src="/legacy.js"></script>"#
+        );
+        let mut deferred = html.to_string();
+        let mut changes = defer_scripts(&mut deferred);
+        assert_eq!(changes.len(), 1);
+        crate::changes::finalize(&mut deferred, &mut changes);
+        assert_eq!(deferred, html.replacen(r#"<script type="text/javascript""#, r#"<script defer type="text/javascript""#, 1));
+    }
+
+    #[test]
+    fn test_optimize_html_only_is_deterministic_and_offline() {
+        // Remote assets on an unroutable host: any network access would hang or fail
+        let html = r#"<html><head><link rel="stylesheet" href="http://10.255.255.1/style.css"></head><body><img src="http://10.255.255.1/photo.jpg"></body></html>"#;
+        let options = OptimizeOptions {
+            convert_webp: true,
+            optimize_resources: true,
+            ..OptimizeOptions::default()
+        };
+
+        let first = optimize_html_only(html, "https://example.com/", &options);
+        let second = optimize_html_only(html, "https://example.com/", &options);
+        assert_eq!(first.html, second.html);
+        assert_eq!(first.optimizations, second.optimizations);
+        assert!(first.html.contains("http://10.255.255.1/photo.jpg"));
+    }
+
+    #[test]
+    fn test_inline_css_minified_without_tree_shaking() {
+        let html = r#"<html><head><style>
+            .unused { color: #ff0000; }
+            .box {
+                margin-top: 10px;
+                margin-right: 10px;
+                margin-bottom: 10px;
+                margin-left: 10px;
+                background-color: #FFFFFF;
+            }
+        </style></head><body><div class="box">Hi</div></body></html>"#;
+        let options = OptimizeOptions {
+            remove_unused_css: false,
+            minify_html: false,
+            ..OptimizeOptions::default()
+        };
+        let result = optimize_html_only(html, "https://example.com/", &options);
+        // Longhands merged and colors shortened: lightningcss, not whitespace stripping
+        assert!(result.html.contains("<style>.unused{color:red}.box{background-color:#fff;margin:10px}</style>"), "{}", result.html);
+    }
+
+    #[test]
+    fn test_media_style_keeps_attributes() {
+        let mut html = r#"<html><head><style media="print" nonce="abc">
+            .page  {  color : #000000 ; }
+        </style></head><body><div class="page">Hi</div></body></html>"#.to_string();
+        let (count, _, _) = optimize_and_treeshake_css(&mut html, &OptimizeOptions::default(), Default::default());
+        assert_eq!(count, 1);
+        assert!(html.contains(r#"<style media="print" nonce="abc">.page{color:#000}</style>"#), "{}", html);
+    }
+
+    #[test]
+    fn test_fake_style_in_comment_and_script_untouched() {
+        let original = r#"<html><head><!-- <style> .a  { color: red } </style> --><script>var s = "<style> .b  { color: red } </style>";</script><style>.c  { color: red }</style></head><body class="c"></body></html>"#;
+        let mut html = original.to_string();
+        let (count, _, _) = optimize_and_treeshake_css(&mut html, &OptimizeOptions::default(), Default::default());
+        assert_eq!(count, 1);
+        assert!(html.contains("<!-- <style> .a  { color: red } </style> -->"));
+        assert!(html.contains(r#"var s = "<style> .b  { color: red } </style>";"#));
+        assert!(html.contains("<style>.c{color:red}</style>"));
+    }
+
+    #[test]
+    fn test_prune_attributes_keeps_referenced_names() {
+        let html = r##"<html><head><style>.keep{color:red}#target{color:blue}[data-theme]{margin:0}</style></head><body>
+            <div class="wp-block-group keep" id="block-1" data-block="x" data-theme="dark" data-wp-interactive="core/navigation">
+            <a href="#anchor">Jump</a><h2 id="anchor">Title</h2><p id="target">Text</p></div></body></html>"##;
+        let patterns = vec!["class=wp-block-*".to_string(), "id".to_string(), "data-*".to_string()];
+
+        let mut pruned = html.to_string();
+        let (count, bytes_saved) = prune_attributes(&mut pruned, &patterns, false);
+        assert_eq!(count, 3);
+        assert_eq!(bytes_saved, html.len() - pruned.len());
+        assert!(pruned.contains(r#"<div class="keep" data-theme="dark" data-wp-interactive="core/navigation">"#), "{}", pruned);
+        assert!(pruned.contains(r#"<h2 id="anchor">"#));
+        assert!(pruned.contains(r#"<p id="target">"#));
+
+        // With an external stylesheet, classes and IDs can't be verified and stay put
+        let mut linked = html.replace("<head>", r#"<head><link rel="stylesheet" href="/theme.css">"#);
+        let (count, _) = prune_attributes(&mut linked, &patterns, false);
+        assert_eq!(count, 1);
+        assert!(linked.contains(r#"<div class="wp-block-group keep" id="block-1" data-theme="dark" data-wp-interactive="core/navigation">"#));
+
+        // Interactivity directives go only when asked for by name
+        let mut directives = html.to_string();
+        assert_eq!(prune_attributes(&mut directives, &["data-wp-*".to_string()], false).0, 1);
+        assert!(!directives.contains("data-wp-interactive"));
+    }
+
+    #[test]
+    fn test_prettify_indents_and_keeps_verbatim_content() {
+        let html = "<!DOCTYPE html><html><head><script>if (a < b) {\n  go();\n}</script></head><body><div><p>Hello   <b>world</b></p><br><pre>  keep\n  this</pre></div></body></html>";
+        let pretty = prettify_html(html);
+        assert_eq!(pretty, "<!DOCTYPE html>\n<html>\n  <head>\n    <script>if (a < b) {\n  go();\n}</script>\n  </head>\n  <body>\n    <div>\n      <p>\n        Hello\n        <b>\n          world\n        </b>\n      </p>\n      <br>\n      <pre>  keep\n  this</pre>\n    </div>\n  </body>\n</html>\n");
+
+        // prettify wins over minify_html
+        let options = OptimizeOptions {
+            prettify: true,
+            ..OptimizeOptions::default()
+        };
+        let result = optimize_html_only(html, "https://example.com/", &options);
+        assert!(result.html.contains("\n  <body>\n"));
+    }
+
+    #[test]
+    fn test_clean_attributes_removes_empty_and_default_values() {
+        let mut html = r#"<div class="" style=" " id="main"><script type="text/javascript" src="a.js"></script><style type="text/css">p{}</style><link rel="stylesheet" type="text/css" href="a.css"></div>"#.to_string();
+        assert_eq!(clean_attributes(&mut html), 5);
+        assert_eq!(html, r#"<div id="main"><script src="a.js"></script><style>p{}</style><link rel="stylesheet" href="a.css"></div>"#);
+    }
+
+    #[test]
+    fn test_clean_attributes_collapses_booleans_and_keeps_meaningful_empties() {
+        let mut html = r#"<input value="" disabled="disabled" checked=""><img src="d.png" alt=""><script type="module" async="async"></script>"#.to_string();
+        clean_attributes(&mut html);
+        assert_eq!(html, r#"<input value="" disabled checked><img src="d.png" alt=""><script type="module" async></script>"#);
+    }
+
+    #[test]
+    fn test_consolidate_repeated_inline_styles() {
+        let mut html = concat!(
+            "<html><head></head><body>",
+            "<div style=\"color: red; margin: 0\">a</div>",
+            "<div class=\"box\" style=\"color: red; margin: 0;\">b</div>",
+            "<p style=\"color: red; margin: 0\">c</p>",
+            "<p style=\"color: blue\">unique</p>",
+            "</body></html>"
+        ).to_string();
+
+        let count = consolidate_inline_styles(&mut html);
+        assert_eq!(count, 3);
+        assert!(html.contains("<style id=\"htmlwp-inline-styles\">.hwp-s1{color: red!important;margin: 0!important}</style></head>"));
+        assert!(html.contains("<div class=\"hwp-s1\">a</div>"));
+        assert!(html.contains("<div class=\"box hwp-s1\">b</div>"));
+        assert!(html.contains("style=\"color: blue\""), "Unique styles are left inline");
+    }
+
+    #[test]
+    fn test_decoding_hints_exclude_lcp_image() {
+        let mut html = concat!(
+            "<html><body>",
+            "<img src=\"/uploads/site-logo.png\" class=\"custom-logo\">",
+            "<img src=\"/hero.jpg\">",
+            "<img src=\"/a.jpg\">",
+            "<img src=\"/b.jpg\" decoding=\"sync\">",
+            "</body></html>"
+        ).to_string();
+        assert_eq!(add_decoding_hints(&mut html, "https://example.com/", false), 3);
+        assert!(html.contains(r#"<img decoding="async" src="/uploads/site-logo.png" class="custom-logo">"#), "logo isn't the LCP image: {}", html);
+        assert!(html.contains(r#"<img fetchpriority="high" decoding="sync" src="/hero.jpg">"#), "{}", html);
+        assert!(html.contains(r#"<img decoding="async" src="/a.jpg">"#));
+        assert!(html.contains(r#"<img src="/b.jpg" decoding="sync">"#), "existing decoding kept");
+
+        // An image already marked high priority is the LCP one; the hero then lazy-loads as usual
+        let mut html = r#"<img src="/logo.png"><img src="/hero.jpg" fetchpriority="high">"#.to_string();
+        add_decoding_hints(&mut html, "https://example.com/", false);
+        let mut changes = add_lazy_loading(&mut html, false);
+        crate::changes::finalize(&mut html, &mut changes);
+        assert!(html.contains(r#"<img loading="lazy" decoding="async" src="/logo.png">"#), "{}", html);
+        assert!(html.contains(r#"<img decoding="sync" src="/hero.jpg" fetchpriority="high">"#));
+    }
+
+    #[test]
+    fn test_relative_urls_resolve_against_base_href() {
+        let mut html = concat!(
+            r#"<html><head><base href="https://www.googletagmanager.com/"><script async src="gtag/js?id=G-1"></script></head>"#,
+            r#"<body><img src="/logo.png"><img src="uploads/hero.jpg"></body></html>"#
+        ).to_string();
+        let removed = strip_tracking_scripts(&mut html, "https://example.com/post/", &OptimizeOptions::default().tracker_domains);
+        assert_eq!(removed, vec!["www.googletagmanager.com".to_string()]);
+
+        // The primary image is found through the base too, so it still gets the LCP hints
+        let mut html = r#"<html><head><base href="https://cdn.example.net/site/"></head><body><img src="/logo.png"><img src="uploads/hero.jpg"></body></html>"#.to_string();
+        add_decoding_hints(&mut html, "https://example.com/post/", false);
+        assert!(html.contains(r#"<img fetchpriority="high" decoding="sync" src="uploads/hero.jpg">"#), "{}", html);
+    }
+}
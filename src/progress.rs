@@ -0,0 +1,24 @@
+//! Progress Reporting
+//! Optional per-step progress events for long-running optimizations
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+/// One unit of work finished within a stage ("html", "images", "css", "js")
+#[derive(Debug, Clone, Serialize)]
+pub struct Progress {
+    pub stage: &'static str,
+    pub current: usize,
+    pub total: usize,
+}
+
+/// Receiving end lives with the caller (e.g. the WebSocket handler)
+pub type ProgressSender = mpsc::UnboundedSender<Progress>;
+
+/// Send a progress event if anyone is listening
+pub fn report(progress: Option<&ProgressSender>, stage: &'static str, current: usize, total: usize) {
+    if let Some(sender) = progress {
+        // A dropped receiver just means nobody is watching anymore
+        let _ = sender.send(Progress { stage, current, total });
+    }
+}
@@ -0,0 +1,185 @@
+//! Rate Limiting
+//! In-process token buckets per client IP (and API key, when one is sent). Off unless
+//! `RATE_LIMIT_PER_MINUTE` is set
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::AppState;
+use crate::error::AppError;
+
+/// Above this many tracked clients, idle (fully refilled) buckets are dropped
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token-bucket limiter: `burst` requests at once, refilled at `per_minute`
+#[derive(Clone)]
+pub struct RateLimiter {
+    per_minute: u32,
+    burst: u32,
+    buckets: Arc<Mutex<HashMap<String, Bucket>>>,
+}
+
+impl RateLimiter {
+    /// `per_minute == 0` disables limiting
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            per_minute,
+            burst: burst.max(1),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.per_minute > 0
+    }
+
+    /// Take one token for `key`, or return how long until one is available
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let per_second = self.per_minute as f64 / 60.0;
+        let burst = self.burst as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(key) {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second < burst
+            });
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: burst, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+/// Health checks are never limited so orchestrators can always probe the server, nor are
+/// cached assets, which CDNs and browsers fetch without an API key
+fn is_exempt(path: &str) -> bool {
+    path == "/health" || path.starts_with("/api/v1/health") || path.starts_with("/api/v1/resource/")
+}
+
+/// Bucket key: the peer IP, combined with the API key when the request carries the valid one.
+/// Every site shares the one API key, so the key alone would put them all in one bucket;
+/// unknown tokens are not trusted as keys, or a client could rotate them to dodge the limit
+fn client_key(request: &Request, api_key: Option<&str>) -> String {
+    let ip = request.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| format!("ip:{}", addr.ip()))
+        .unwrap_or_else(|| "ip:unknown".to_string());
+
+    let token = request.headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    match (token, api_key) {
+        (Some(token), Some(key)) if token == key => format!("key:{}|{}", token, ip),
+        _ => ip,
+    }
+}
+
+/// Middleware returning 429 with `Retry-After` once a client exhausts its bucket
+pub async fn enforce(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !is_exempt(request.uri().path()) {
+        if let Err(wait) = state.rate_limiter.check(&client_key(&request, state.api_key.as_deref())) {
+            let retry_after_secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+            tracing::warn!(path = request.uri().path(), retry_after_secs, "Rate limit exceeded");
+            return AppError::TooManyRequests { retry_after_secs }.into_response();
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_state;
+
+    #[test]
+    fn test_bucket_exhausts_per_key() {
+        let limiter = RateLimiter::new(60, 2);
+        assert!(limiter.check("key:a").is_ok());
+        assert!(limiter.check("key:a").is_ok());
+        let wait = limiter.check("key:a").unwrap_err();
+        assert!(wait <= Duration::from_secs(1));
+
+        // Other clients have their own bucket
+        assert!(limiter.check("key:b").is_ok());
+        // Disabled limiter never rejects
+        let disabled = RateLimiter::new(0, 1);
+        assert!((0..10).all(|_| disabled.check("key:a").is_ok()));
+    }
+
+    #[test]
+    fn test_client_key_combines_api_key_and_ip() {
+        let request = |ip: [u8; 4], token: &str| {
+            let mut request = Request::builder()
+                .uri("/api/v1/optimize")
+                .header("Authorization", format!("Bearer {}", token))
+                .body(axum::body::Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(ConnectInfo(SocketAddr::from((ip, 443))));
+            request
+        };
+        let site_a = client_key(&request([10, 0, 0, 1], "secret"), Some("secret"));
+        let site_b = client_key(&request([10, 0, 0, 2], "secret"), Some("secret"));
+        assert_ne!(site_a, site_b, "sites sharing the API key get their own buckets");
+        assert_eq!(client_key(&request([10, 0, 0, 1], "guess"), Some("secret")), "ip:10.0.0.1");
+    }
+
+    #[tokio::test]
+    async fn test_middleware_returns_429_and_exempts_health_and_assets() {
+        let state = AppState {
+            rate_limiter: RateLimiter::new(1, 1),
+            ..test_state()
+        };
+        let app = axum::Router::new()
+            .route("/health", axum::routing::get(|| async { "ok" }))
+            .route("/api/v1/resource/:filename", axum::routing::get(|| async { "ok" }))
+            .route("/api/v1/optimize", axum::routing::post(|| async { "ok" }))
+            .layer(axum::middleware::from_fn_with_state(state.clone(), enforce))
+            .with_state(state);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap()
+        });
+
+        let client = reqwest::Client::new();
+        let post = || client.post(format!("http://{}/api/v1/optimize", addr)).header("Authorization", "Bearer secret").send();
+        assert_eq!(post().await.unwrap().status(), 200);
+        let limited = post().await.unwrap();
+        assert_eq!(limited.status(), 429);
+        assert!(limited.headers()["retry-after"].to_str().unwrap().parse::<u64>().unwrap() >= 1);
+
+        for _ in 0..3 {
+            let health = client.get(format!("http://{}/health", addr)).send().await.unwrap();
+            assert_eq!(health.status(), 200);
+            let asset = client.get(format!("http://{}/api/v1/resource/styles.0badf00d.min.css", addr)).send().await.unwrap();
+            assert_eq!(asset.status(), 200);
+        }
+    }
+}
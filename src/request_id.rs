@@ -0,0 +1,38 @@
+//! Request IDs
+//! Accept or generate an `X-Request-Id`, log it on the request span and echo it back
+
+use axum::{body::Body, http::{HeaderMap, Request}, Router};
+use tower_http::{
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Wrap a router so every request carries an id: a client-provided `X-Request-Id` is kept,
+/// otherwise a UUID is generated. The id is recorded on the tracing span and returned
+/// in the response header.
+pub fn with_request_id<S>(router: Router<S>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    router
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(TraceLayer::new_for_http().make_span_with(|request: &Request<Body>| {
+            tracing::info_span!(
+                "request",
+                method = %request.method(),
+                uri = %request.uri(),
+                request_id = from_headers(request.headers()).unwrap_or_default(),
+            )
+        }))
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+/// The request id set by `with_request_id`
+pub fn from_headers(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
@@ -2,11 +2,41 @@
 //! Fetches, optimizes, and prepares external CSS/JS for local storage
 
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
-use lightningcss::{
-    stylesheet::{StyleSheet, ParserOptions, MinifyOptions, PrinterOptions},
-    targets::Targets,
-};
 use scraper::{Html, Selector};
+use std::collections::{HashMap, HashSet};
+
+use crate::handlers::OptimizeOptions;
+use crate::progress::{report, ProgressSender};
+
+/// How the combined stylesheet is loaded once critical CSS is inlined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CssLoading {
+    /// Non-blocking: `media="print"` swapped to `all` on load, in place of the first stylesheet
+    #[default]
+    #[serde(alias = "print-onload")]
+    Async,
+    /// Non-blocking: `rel="preload"` switched to `stylesheet` on load, in place of the first
+    /// stylesheet (leaves `media` alone, which some consent managers and print styles rely on)
+    #[serde(alias = "preload-onload")]
+    Preload,
+    /// A regular stylesheet link moved to just before `</body>`
+    BodyEnd,
+    /// A regular (render-blocking) stylesheet link in place of the first stylesheet
+    #[serde(alias = "blocking")]
+    Preserve,
+}
+
+/// Where the combined script goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum JsPlacement {
+    /// In place of the first original script
+    #[default]
+    Preserve,
+    /// Just before `</body>`, followed by the inline scripts that came after it
+    BodyEnd,
+}
 
 /// Result of optimized CSS/JS for API response
 #[derive(Debug, Clone, serde::Serialize)]
@@ -20,10 +50,52 @@ pub struct OptimizedResources {
     pub combined_js: Option<String>,
     pub combined_css_filename: String,
     pub combined_js_filename: String,
+    /// Brotli/gzip versions of the combined files (only with `precompress`)
+    pub combined_css_br: Option<PrecompressedAsset>,
+    pub combined_css_gz: Option<PrecompressedAsset>,
+    pub combined_js_br: Option<PrecompressedAsset>,
+    pub combined_js_gz: Option<PrecompressedAsset>,
     pub total_css_savings_kb: f32,
     pub total_js_savings_kb: f32,
+    /// CSS/JS files left untouched because of the `max_resources` cap
+    pub skipped_count: usize,
+    /// The combined stylesheet is redundant: all CSS is inlined as critical (`inline_all_css`)
+    pub external_css_eliminated: bool,
+    /// `@font-face` files downloaded for local hosting (only with `localize_fonts`)
+    pub fonts: Vec<LocalizedFont>,
+    /// Font problems in the stylesheets (see `css_optimizer::FontFaceInfo::warnings`)
+    pub font_warnings: Vec<String>,
+}
+
+/// A font file referenced by `@font-face`, for the plugin to save next to the combined CSS
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct LocalizedFont {
+    /// Absolute URL the font was downloaded from
+    pub original_url: String,
+    pub filename: String,
+    pub base64: String,
+    pub size: usize,
+}
+
+/// Font formats superseded by woff2
+const LEGACY_FONT_EXTENSIONS: [&str; 4] = ["eot", "ttf", "otf", "svg"];
+
+/// Upper bound for inlining the whole stylesheet - larger inline CSS delays first paint
+const MAX_INLINE_CSS_BYTES: usize = 50 * 1024;
+
+/// Critical CSS budget: roughly what fits in the first round trip
+const MAX_CRITICAL_CSS_BYTES: usize = 14 * 1024;
+
+/// A precompressed sibling (`.br` / `.gz`) of a combined file
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PrecompressedAsset {
+    pub base64: String,
+    pub size: usize,
 }
 
+/// Files smaller than this aren't worth precompressing
+const PRECOMPRESS_MIN_BYTES: usize = 1024;
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct OptimizedCssFile {
     pub original_url: String,
@@ -44,33 +116,85 @@ pub struct OptimizedJsFile {
     pub reduction_percent: f32,
 }
 
-/// Download a resource from URL
+/// Download a resource from URL (shared across the pages of a bulk batch)
 pub async fn download_resource(url: &str) -> Result<String, String> {
     tracing::debug!("Resource optimizer: Downloading {}", url);
-    
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
 
-    let response = client
-        .get(url)
-        .header("User-Agent", "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36")
-        .send()
-        .await
-        .map_err(|e| format!("Failed to download resource: {}", e))?;
+    let fetched = crate::url_utils::fetch_shared(
+        url,
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36",
+    ).await?;
+    let text = decode_text(&fetched.body, fetched.content_type.as_deref());
+
+    tracing::debug!("Resource optimizer: Downloaded {} bytes from {}", text.len(), url);
+    Ok(text)
+}
+
+/// Decode a downloaded text asset: a BOM wins, then the `Content-Type` charset, then a CSS
+/// `@charset` rule, else UTF-8 (falling back to windows-1252 if that's invalid). Any BOM is
+/// stripped, since one in the middle of a combined bundle breaks it.
+fn decode_text(bytes: &[u8], content_type: Option<&str>) -> String {
+    let header_charset = content_type.and_then(|content_type| {
+        content_type.split(';').skip(1).find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim().eq_ignore_ascii_case("charset").then(|| value.trim().trim_matches('"').to_string())
+        })
+    });
+    let css_charset = || {
+        let rest = bytes.strip_prefix(b"@charset \"")?;
+        let end = rest.iter().position(|&b| b == b'"')?;
+        Some(String::from_utf8_lossy(&rest[..end]).into_owned())
+    };
+    let labelled = header_charset.or_else(css_charset)
+        .and_then(|label| encoding_rs::Encoding::for_label(label.as_bytes()));
+
+    // `decode` sniffs and removes a BOM, overriding the label
+    let encoding = labelled.unwrap_or(encoding_rs::UTF_8);
+    let (text, used, had_errors) = encoding.decode(bytes);
+    let text = if had_errors && labelled.is_none() && used == encoding_rs::UTF_8 {
+        tracing::debug!("Resource optimizer: Invalid UTF-8 without a charset, decoding as windows-1252");
+        encoding_rs::WINDOWS_1252.decode(bytes).0
+    } else {
+        text
+    };
+    text.trim_start_matches('\u{feff}').to_string()
+}
+
+/// Download a binary asset (fonts)
+async fn download_bytes(url: &str) -> Result<Vec<u8>, String> {
+    let fetched = crate::url_utils::fetch_shared(url, "HTMLWordPress/1.0").await?;
+    Ok(fetched.body.to_vec())
+}
+
+/// Produce brotli and gzip versions of a combined file
+/// Returns (None, None) for tiny files where compression isn't worthwhile
+pub fn precompress(content: &str) -> (Option<PrecompressedAsset>, Option<PrecompressedAsset>) {
+    use std::io::Write;
+
+    if content.len() < PRECOMPRESS_MIN_BYTES {
+        return (None, None);
+    }
 
-    if !response.status().is_success() {
-        return Err(format!("HTTP {}: {}", response.status(), url));
+    let mut br = Vec::new();
+    let br_ok = {
+        let mut writer = brotli::CompressorWriter::new(&mut br, 4096, 11, 22);
+        writer.write_all(content.as_bytes()).is_ok()
+    };
+    if !br_ok {
+        br.clear();
     }
 
-    let text = response
-        .text()
-        .await
-        .map_err(|e| format!("Failed to read response: {}", e))?;
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    let gz = gz.write_all(content.as_bytes()).and_then(|_| gz.finish()).unwrap_or_default();
 
-    tracing::debug!("Resource optimizer: Downloaded {} bytes from {}", text.len(), url);
-    Ok(text)
+    let to_asset = |bytes: Vec<u8>| {
+        if bytes.is_empty() {
+            None
+        } else {
+            Some(PrecompressedAsset { size: bytes.len(), base64: BASE64.encode(&bytes) })
+        }
+    };
+    (to_asset(br), to_asset(gz))
 }
 
 /// Extract external CSS links from HTML
@@ -87,12 +211,14 @@ pub fn extract_css_links(html: &str) -> Vec<String> {
 }
 
 /// Extract external JS script sources from HTML
+/// Only classic scripts: modules keep their own scope and loading, so they are never merged
 pub fn extract_js_sources(html: &str) -> Vec<String> {
     let document = Html::parse_document(html);
     let selector = Selector::parse("script[src]").unwrap();
 
     document
         .select(&selector)
+        .filter(|element| crate::html_utils::is_classic_script_type(element.value().attr("type")))
         .filter_map(|element| element.value().attr("src"))
         .filter(|src| !src.starts_with("data:") && !src.is_empty())
         .map(|src| src.to_string())
@@ -145,67 +271,56 @@ fn extract_attribute(tag: &str, attr_name: &str) -> Option<String> {
     None
 }
 
-/// Generate a hash-based filename
-fn generate_filename(url: &str, extension: &str) -> String {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    let mut hasher = DefaultHasher::new();
-    url.hash(&mut hasher);
-    let hash = hasher.finish();
-    format!("{:x}.{}", hash, extension)
-}
-
-/// Minify CSS using lightningcss
-pub fn minify_css(css: &str) -> Result<String, String> {
-    let stylesheet = StyleSheet::parse(css, ParserOptions::default())
-        .map_err(|e| format!("Failed to parse CSS: {:?}", e))?;
-
-    let result = stylesheet.to_css(PrinterOptions {
-        minify: true,
-        ..PrinterOptions::default()
-    }).map_err(|e| format!("Failed to minify CSS: {:?}", e))?;
-
-    Ok(result.code)
+/// Generate a hash-based filename for `content` generated from `url`
+fn generate_filename(url: &str, content: &[u8], extension: &str, options: &OptimizeOptions) -> String {
+    crate::url_utils::asset_filename(url, content, extension, options.filename_scheme, options.normalize_asset_urls)
 }
 
 /// Optimize a single external CSS file
-pub async fn optimize_css_file(url: &str, base_url: &str, used_selectors: &[String], minify: bool) -> Result<OptimizedCssFile, String> {
+pub async fn optimize_css_file(url: &str, base_url: &str, used_selectors: &[String], options: &OptimizeOptions) -> Result<OptimizedCssFile, String> {
     // Make URL absolute
-    let full_url = if url.starts_with("/") {
-        format!("{}{}", base_url.trim_end_matches('/'), url)
-    } else if url.starts_with("http") {
-        url.to_string()
-    } else {
-        format!("{}/{}", base_url.trim_end_matches('/'), url)
-    };
+    let full_url = crate::url_utils::resolve_url(base_url, url)?;
 
     // Download the CSS
-    let original_css = download_resource(&full_url).await?;
+    let original_css = download_resource(&full_url).await
+        .inspect_err(|_| crate::metrics::record_download_failure("css"))?;
     let original_size = original_css.len();
 
     // Skip very large files
-    if original_size > 500_000 {
+    if original_size > options.max_css_bytes {
         tracing::warn!("CSS optimizer: Skipping large file {} ({} KB)", url, original_size / 1024);
         return Err(format!("CSS file too large: {} KB", original_size / 1024));
     }
 
     // Minify Only (No Tree-Shaking for external files to prevent per-page fragmentation)
     // We use content-based hashing for deduplication
-    let minified = if minify {
-        minify_css(&original_css).unwrap_or(original_css)
+    let targets = options.css_targets.as_deref()
+        .map(|entries| crate::css_optimizer::parse_css_targets(entries).0)
+        .unwrap_or_default();
+    let minified = match options.minify_css.then(|| crate::css_optimizer::minify_css(&original_css, targets)) {
+        Some(Ok(css)) => css,
+        // Not minified (disabled or failed): comments can still go
+        _ if options.strip_comments => crate::css_optimizer::strip_css_comments(&original_css, !options.strip_license_comments),
+        _ => original_css,
+    };
+    let minified = if options.font_display_swap {
+        crate::css_optimizer::add_font_display_swap(&minified).0
     } else {
-        original_css
+        minified
     };
-    let optimized_size = minified.len();
 
-    // Skip if no improvement
-    if optimized_size >= original_size {
-        tracing::info!("CSS optimizer: No improvement for {}", url);
-        return Err("No size improvement".to_string());
+    // Skip if the improvement is negligible (judged before absolutizing, which only lengthens url()s)
+    if !options.is_worthwhile_reduction(original_size, minified.len()) {
+        tracing::info!("CSS optimizer: Reduction below {}% for {}", options.min_reduction_percent, url);
+        return Err("No significant size improvement".to_string());
     }
 
-    let reduction = ((original_size - optimized_size) as f32 / original_size as f32) * 100.0;
+    // The CSS will be served (or inlined as critical CSS) from elsewhere: pin its relative
+    // url()s to the stylesheet's own location
+    let minified = absolutize_css_urls(&minified, &full_url);
+    let optimized_size = minified.len();
+
+    let reduction = (original_size as f32 - optimized_size as f32) / original_size as f32 * 100.0;
 
     tracing::info!(
         "CSS optimizer: {} -> {} bytes ({:.1}% reduction)",
@@ -214,7 +329,7 @@ pub async fn optimize_css_file(url: &str, base_url: &str, used_selectors: &[Stri
 
     Ok(OptimizedCssFile {
         original_url: url.to_string(),
-        filename: generate_filename(url, "css"),
+        filename: generate_filename(url, minified.as_bytes(), "css", options),
         content: minified,
         original_size,
         optimized_size,
@@ -223,38 +338,40 @@ pub async fn optimize_css_file(url: &str, base_url: &str, used_selectors: &[Stri
 }
 
 /// Optimize a single external JS file (minification only for now)
-pub async fn optimize_js_file(url: &str, base_url: &str, minify: bool) -> Result<OptimizedJsFile, String> {
+/// `handler_names` are identifiers used by inline `on*=` handlers, which must survive minification
+pub async fn optimize_js_file(url: &str, base_url: &str, handler_names: &[String], options: &OptimizeOptions) -> Result<OptimizedJsFile, String> {
     // Make URL absolute
-    let full_url = if url.starts_with("/") {
-        format!("{}{}", base_url.trim_end_matches('/'), url)
-    } else if url.starts_with("http") {
-        url.to_string()
-    } else {
-        format!("{}/{}", base_url.trim_end_matches('/'), url)
-    };
+    let full_url = crate::url_utils::resolve_url(base_url, url)?;
 
     // Download the JS
-    let original_js = download_resource(&full_url).await?;
+    let original_js = download_resource(&full_url).await
+        .inspect_err(|_| crate::metrics::record_download_failure("js"))?;
     let original_size = original_js.len();
 
     // Skip very large files
-    if original_size > 1_000_000 {
+    if original_size > options.max_js_bytes {
         tracing::warn!("JS optimizer: Skipping large file {} ({} KB)", url, original_size / 1024);
         return Err(format!("JS file too large: {} KB", original_size / 1024));
     }
 
     // Basic minification check
-    let minified = if minify {
-        basic_js_minify(&original_js)
+    let minified = if options.minify_js {
+        basic_js_minify(&original_js, handler_names)
+    } else {
+        original_js.clone()
+    };
+    // Passed through un-minified (disabled, or minify-js failed/fell back): comments can still go
+    let minified = if options.strip_comments && minified == original_js {
+        strip_js_comments(&minified, !options.strip_license_comments)
     } else {
-        original_js
+        minified
     };
     let optimized_size = minified.len();
 
-    // Skip if no improvement
-    if optimized_size >= original_size {
-        tracing::info!("JS optimizer: No improvement for {}", url);
-        return Err("No size improvement".to_string());
+    // Skip if the improvement is negligible
+    if !options.is_worthwhile_reduction(original_size, optimized_size) {
+        tracing::info!("JS optimizer: Reduction below {}% for {}", options.min_reduction_percent, url);
+        return Err("No significant size improvement".to_string());
     }
 
     let reduction = ((original_size - optimized_size) as f32 / original_size as f32) * 100.0;
@@ -266,7 +383,7 @@ pub async fn optimize_js_file(url: &str, base_url: &str, minify: bool) -> Result
 
     Ok(OptimizedJsFile {
         original_url: url.to_string(),
-        filename: generate_filename(url, "js"),
+        filename: generate_filename(url, minified.as_bytes(), "js", options),
         content: minified,
         original_size,
         optimized_size,
@@ -274,8 +391,159 @@ pub async fn optimize_js_file(url: &str, base_url: &str, minify: bool) -> Result
     })
 }
 
+/// Words that can appear in a handler without naming anything the page's scripts define
+const HANDLER_KEYWORDS: &[&str] = &[
+    "this", "event", "window", "document", "return", "function", "var", "let", "const", "new",
+    "typeof", "void", "if", "else", "true", "false", "null", "undefined",
+];
+
+/// Identifiers referenced by inline `on*=` event handlers (the first name of each member chain)
+pub fn inline_handler_names(html: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    crate::html_utils::rewrite_start_tags(html, |_, tag| {
+        for attr in crate::html_utils::parse_attributes(tag) {
+            if attr.name.len() <= 2 || !attr.name.starts_with("on") {
+                continue;
+            }
+            let Some(code) = attr.value else { continue };
+            let bytes = code.as_bytes();
+            let mut i = 0;
+            while i < bytes.len() {
+                let c = bytes[i];
+                if c == b'\'' || c == b'"' {
+                    // Skip string literals
+                    i += 1;
+                    while i < bytes.len() && bytes[i] != c {
+                        i += 1;
+                    }
+                    i += 1;
+                } else if c.is_ascii_alphabetic() || c == b'_' || c == b'$' {
+                    let start = i;
+                    while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'$') {
+                        i += 1;
+                    }
+                    let word = &code[start..i];
+                    let is_member = code[..start].trim_end().ends_with('.');
+                    if !is_member && !HANDLER_KEYWORDS.contains(&word) && !names.iter().any(|n| n == word) {
+                        names.push(word.to_string());
+                    }
+                } else if c.is_ascii_digit() {
+                    while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.') {
+                        i += 1;
+                    }
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        None
+    });
+    names
+}
+
+/// Whether `name` occurs in `js` as a whole identifier
+fn contains_identifier(js: &str, name: &str) -> bool {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_' || c == '$';
+    js.match_indices(name).any(|(pos, _)| {
+        !js[..pos].chars().next_back().is_some_and(is_ident)
+            && !js[pos + name.len()..].chars().next().is_some_and(is_ident)
+    })
+}
+
+/// Keywords after which `/` starts a regex literal rather than a division
+const REGEX_PREFIX_KEYWORDS: &[&str] = &[
+    "return", "typeof", "instanceof", "in", "of", "new", "delete", "void", "throw", "case", "do", "else", "yield", "await",
+];
+
+/// Remove `//` and `/* */` comments, skipping strings, template literals and regex literals.
+/// With `keep_license`, `/*! ... */` comments stay. Line breaks are kept so ASI is unaffected
+fn strip_js_comments(js: &str, keep_license: bool) -> String {
+    let bytes = js.as_bytes();
+    let mut result = String::with_capacity(js.len());
+    let mut copied = 0;
+    let mut i = 0;
+    // Last significant byte and identifier, to tell a regex from a division
+    let mut prev: Option<u8> = None;
+    let mut word = "";
+
+    while i < bytes.len() {
+        match bytes[i] {
+            quote @ (b'"' | b'\'' | b'`') => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    if bytes[i] == b'\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+                prev = Some(quote);
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                let end = js[i..].find('\n').map(|e| i + e).unwrap_or(js.len());
+                result.push_str(&js[copied..i]);
+                copied = end;
+                i = end;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = js[i + 2..].find("*/").map(|e| i + 2 + e + 2).unwrap_or(js.len());
+                if !(keep_license && bytes.get(i + 2) == Some(&b'!')) {
+                    let multiline = js[i..end].contains('\n');
+                    result.push_str(&js[copied..i]);
+                    result.push_str(crate::css_optimizer::comment_gap(&js[..i], &js[end..], multiline));
+                    copied = end;
+                }
+                i = end;
+            }
+            b'/' if regex_may_start(prev, word) => {
+                i += 1;
+                let mut in_class = false;
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    match bytes[i] {
+                        b'\\' => i += 1,
+                        b'[' => in_class = true,
+                        b']' => in_class = false,
+                        b'/' if !in_class => break,
+                        _ => {}
+                    }
+                    i += 1;
+                }
+                i += 1;
+                prev = Some(b'/');
+            }
+            c if c.is_ascii_whitespace() => i += 1,
+            c if c.is_ascii_alphanumeric() || c == b'_' || c == b'$' => {
+                let start = i;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] == b'$') {
+                    i += 1;
+                }
+                word = &js[start..i];
+                prev = Some(c);
+            }
+            c => {
+                prev = Some(c);
+                i += 1;
+            }
+        }
+    }
+
+    result.push_str(&js[copied.min(js.len())..]);
+    result
+}
+
+/// Whether a `/` opens a regex literal, given the last significant byte and identifier before it
+fn regex_may_start(prev: Option<u8>, word: &str) -> bool {
+    match prev {
+        None => true,
+        Some(c) if c.is_ascii_alphanumeric() || c == b'_' || c == b'$' => REGEX_PREFIX_KEYWORDS.contains(&word),
+        Some(c) => b"(,=:[!&|?{};+-*%<>~^".contains(&c),
+    }
+}
+
 /// Robust JS minification using minify-js (AST-based)
-fn basic_js_minify(js: &str) -> String {
+/// minify-js can't be told to leave names alone, so if any of `preserved_names` is lost the
+/// original is kept
+fn basic_js_minify(js: &str, preserved_names: &[String]) -> String {
     let session = minify_js::Session::new();
     let mut out = Vec::new();
     match minify_js::minify(&session, minify_js::TopLevelMode::Global, js.as_bytes(), &mut out) {
@@ -284,7 +552,12 @@ fn basic_js_minify(js: &str) -> String {
             // It filters out invalid UTF-8 automatically usually, but we check
             match String::from_utf8(out) {
                 Ok(minified) => {
-                    if minified.len() < js.len() {
+                    let lost = preserved_names.iter()
+                        .find(|name| contains_identifier(js, name) && !contains_identifier(&minified, name));
+                    if let Some(name) = lost {
+                        tracing::debug!("JS minification renamed inline handler reference {} (using original)", name);
+                        js.to_string()
+                    } else if minified.len() < js.len() {
                         minified
                     } else {
                         js.to_string()
@@ -302,61 +575,141 @@ fn basic_js_minify(js: &str) -> String {
 
 /// Extract critical CSS (above-the-fold styles)
 pub fn extract_critical_css(full_css: &str, html: &str) -> String {
-    // Critical CSS extraction is complex and typically requires:
-    // 1. Rendering the page in a headless browser
-    // 2. Determining which elements are above-the-fold
-    // 3. Extracting only those CSS rules
-    
-    // For now, we'll use a heuristic approach:
-    // - Include all :root and html/body styles
-    // - Include header, nav, and hero section styles
-    // - Include font-face declarations
-    // - Limit to ~14KB (recommended critical CSS size)
-    
+    split_critical_css(full_css, html, OptimizeOptions::default().fold_element_count, MAX_CRITICAL_CSS_BYTES).0
+}
+
+/// Split CSS into critical (above-the-fold) and deferred rules
+///
+/// Heuristic (no rendering): the first `fold_element_count` elements of `<body>` in
+/// document order are treated as above the fold. A rule is critical when its subject
+/// (the last compound of a selector) matches one of those elements by tag/class/id,
+/// or when it is global (`html`, `body`, `:root`, `*`, `@font-face`). Conditional
+/// at-rules (`@media`, `@supports`) are kept whole if any rule inside is critical.
+/// Critical rules past `max_size` bytes are deferred with the rest.
+/// Inline `<style>` blocks are left where they are: they usually override the external rules
+pub fn split_critical_css(full_css: &str, html: &str, fold_element_count: usize, max_size: usize) -> (String, String) {
+    let fold = above_fold_selectors(html, fold_element_count);
+
     let mut critical = String::new();
-    let max_size = 14 * 1024; // 14KB limit
+    let mut deferred = String::new();
+    
+    for rule in crate::css_optimizer::split_top_level_rules(full_css) {
+        let is_critical = is_rule_above_fold(rule, &fold);
+        let target = if is_critical && critical.len() < max_size { &mut critical } else { &mut deferred };
+        target.push_str(rule);
+        target.push('\n');
+    }
     
-    // Split CSS into rules and filter
-    for rule in full_css.split('}') {
-        if critical.len() >= max_size {
-            break;
+    (critical, deferred)
+}
+
+/// Start of the first stylesheet `<link>` in `<head>`: where critical CSS from the external
+/// sheets goes, so inline `<style>` blocks after it keep overriding it
+fn first_head_stylesheet(html: &str) -> Option<usize> {
+    let lower = html.to_ascii_lowercase();
+    let head_end = lower.find("</head>")?;
+    let mut pos = 0;
+    while let Some(offset) = lower[pos..head_end].find("<link") {
+        let start = pos + offset;
+        let end = crate::html_utils::find_tag_end(html, start)?;
+        let tag = &html[start..end];
+        let rel = crate::html_utils::get_attribute(tag, "rel").unwrap_or_default().to_ascii_lowercase();
+        let preloaded_style = rel.contains("preload")
+            && crate::html_utils::get_attribute(tag, "as").is_some_and(|a| a.eq_ignore_ascii_case("style"));
+        if rel.split_ascii_whitespace().any(|r| r == "stylesheet") || preloaded_style {
+            return Some(start);
         }
-        
-        let rule = rule.trim();
-        if rule.is_empty() {
-            continue;
+        pos = end;
+    }
+    None
+}
+
+/// Tags, `.classes` and `#ids` of the first `count` body elements
+fn above_fold_selectors(html: &str, count: usize) -> HashSet<String> {
+    const NON_VISUAL: [&str; 7] = ["script", "style", "noscript", "template", "link", "meta", "br"];
+
+    let document = Html::parse_document(html);
+    let mut fold = HashSet::new();
+    let Some(body) = Selector::parse("body").ok().and_then(|s| document.select(&s).next()) else {
+        return fold;
+    };
+
+    let elements = body.descendants()
+        .filter_map(|node| node.value().as_element())
+        .filter(|el| !NON_VISUAL.contains(&el.name()))
+        .take(count);
+    for el in elements {
+        fold.insert(el.name().to_string());
+        for class in el.classes() {
+            fold.insert(format!(".{}", class));
         }
-        
-        let rule_with_brace = format!("{}}}", rule);
-        
-        // Include critical selectors
-        let is_critical = 
-            rule.contains("@font-face") ||
-            rule.contains(":root") ||
-            rule.contains("html") ||
-            rule.contains("body") ||
-            rule.contains("header") ||
-            rule.contains("nav") ||
-            rule.contains(".hero") ||
-            rule.contains("#hero") ||
-            rule.contains(".header") ||
-            rule.contains("#header") ||
-            rule.contains(".site-") ||
-            rule.contains("@media");
-        
-        if is_critical {
-            critical.push_str(&rule_with_brace);
-            critical.push('\n');
+        if let Some(id) = el.id() {
+            fold.insert(format!("#{}", id));
         }
     }
-    
-    critical
+    fold
+}
+
+/// Whether a top-level rule styles something above the fold
+fn is_rule_above_fold(rule: &str, fold: &HashSet<String>) -> bool {
+    let (prelude, body) = match rule.find('{') {
+        Some(open) => (rule[..open].trim(), rule[open + 1..].trim_end_matches('}')),
+        None => (rule.trim(), ""),
+    };
+
+    if prelude.starts_with('@') {
+        let lower = prelude.to_ascii_lowercase();
+        return if ["@font-face", "@import", "@charset", "@property"].iter().any(|at| lower.starts_with(at)) {
+            true
+        } else if ["@media", "@supports", "@container", "@layer"].iter().any(|at| lower.starts_with(at)) {
+            crate::css_optimizer::split_top_level_rules(body).iter().any(|inner| is_rule_above_fold(inner, fold))
+        } else {
+            false
+        };
+    }
+
+    prelude.split(',').any(|selector| selector_matches_fold(selector, fold))
+}
+
+/// Match a selector's subject compound (e.g. `a.button#cta` in `.hero > a.button#cta:hover`)
+fn selector_matches_fold(selector: &str, fold: &HashSet<String>) -> bool {
+    let subject = selector
+        .rsplit(|c: char| c.is_whitespace() || matches!(c, '>' | '+' | '~'))
+        .find(|part| !part.is_empty())
+        .unwrap_or("");
+    // Drop pseudo-classes/elements and attribute selectors
+    let subject = subject.split([':', '[']).next().unwrap_or("");
+
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    for c in subject.chars() {
+        if (c == '.' || c == '#') && !current.is_empty() {
+            tokens.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+
+    let is_global = tokens.is_empty()
+        || tokens.iter().any(|t| matches!(t.to_ascii_lowercase().as_str(), "*" | "html" | "body"));
+    is_global || tokens.iter().all(|t| fold.contains(t) || fold.contains(&t.to_ascii_lowercase()))
 }
 
 /// Optimize all external resources in HTML
-pub async fn optimize_external_resources(html: &str, base_url: &str, used_selectors: &[String], options: &crate::handlers::OptimizeOptions) -> OptimizedResources {
+/// Reports "css"/"js" progress events after each attempted file
+pub async fn optimize_external_resources(
+    html: &str,
+    base_url: &str,
+    used_selectors: &[String],
+    options: &OptimizeOptions,
+    progress: Option<&ProgressSender>,
+) -> OptimizedResources {
     tracing::info!("Resource optimizer: Starting external CSS/JS optimization");
-    
+    let page_url = base_url;
+    let base_url = &crate::url_utils::document_base(html, page_url);
+
     let mut css_files = Vec::new();
     let mut js_files = Vec::new();
     let mut total_css_original: usize = 0;
@@ -367,38 +720,66 @@ pub async fn optimize_external_resources(html: &str, base_url: &str, used_select
     // Extract and optimize CSS
     let css_links = extract_css_links(html);
     tracing::debug!("Resource optimizer: Found {} CSS links", css_links.len());
-    
-    for url in css_links {
-        // Skip external CDNs (Google Fonts, etc.)
-        if should_skip_external(&url) {
-            tracing::debug!("Resource optimizer: Skipping external {}", url);
-            continue;
-        }
-        
-        match optimize_css_file(&url, base_url, used_selectors, options.minify_css).await {
+
+    let (css_links, css_skipped) = cap_resources(css_links, options.max_resources, "CSS");
+    let mut skipped_count = css_skipped;
+
+    let css_total = css_links.len();
+    let mut css_failed = 0;
+    for (index, url) in css_links.into_iter().enumerate() {
+        let optimized = optimize_css_file(&url, base_url, used_selectors, options).await;
+        report(progress, "css", index + 1, css_total);
+        match optimized {
             Ok(optimized) => {
                 total_css_original += optimized.original_size;
                 total_css_optimized += optimized.optimized_size;
                 css_files.push(optimized);
             }
             Err(e) => {
+                css_failed += 1;
                 tracing::warn!("Resource optimizer: Failed to optimize CSS {}: {}", url, e);
             }
         }
     }
     
+    let fonts = if options.localize_fonts {
+        localize_fonts(&mut css_files, page_url, base_url, options).await
+    } else {
+        Vec::new()
+    };
+    let font_warnings: Vec<String> = css_files.iter()
+        .flat_map(|css| crate::css_optimizer::analyze_font_faces(&css.content))
+        .flat_map(|font| font.warnings())
+        .collect();
+
+    // Unused @font-face rules only go from the page-specific outputs (critical and combined CSS):
+    // each file's own copy is shared by every page using it. Any stylesheet may use a family
+    // another declares, so all of them must have been read
+    let font_references = (options.remove_unused_fonts && css_skipped == 0 && css_failed == 0).then(|| {
+        let mut references = crate::css_optimizer::inline_font_references(html);
+        for css in &css_files {
+            references.push_str(&crate::css_optimizer::font_family_references(&css.content));
+        }
+        references
+    });
+    let prune_fonts = |css: String| match &font_references {
+        Some(references) => crate::css_optimizer::remove_unused_font_faces(&css, references).0,
+        None => css,
+    };
+
     // Extract and optimize JS
     let js_sources = extract_js_sources(html);
     tracing::debug!("Resource optimizer: Found {} JS sources", js_sources.len());
-    
-    for url in js_sources {
-        // Skip external CDNs
-        if should_skip_external(&url) {
-            tracing::debug!("Resource optimizer: Skipping external {}", url);
-            continue;
-        }
-        
-        match optimize_js_file(&url, base_url, options.minify_js).await {
+
+    let (js_sources, js_skipped) = cap_resources(js_sources, options.max_resources, "JS");
+    skipped_count += js_skipped;
+
+    let handler_names = inline_handler_names(html);
+    let js_total = js_sources.len();
+    for (index, url) in js_sources.into_iter().enumerate() {
+        let optimized = optimize_js_file(&url, base_url, &handler_names, options).await;
+        report(progress, "js", index + 1, js_total);
+        match optimized {
             Ok(optimized) => {
                 total_js_original += optimized.original_size;
                 total_js_optimized += optimized.optimized_size;
@@ -410,28 +791,60 @@ pub async fn optimize_external_resources(html: &str, base_url: &str, used_select
         }
     }
     
-    // Calculate critical CSS from all optimized CSS
-    let all_css: String = css_files.iter().map(|f| f.content.as_str()).collect::<Vec<_>>().join("\n");
-    let critical_css = if !all_css.is_empty() {
-        Some(extract_critical_css(&all_css, html))
+    // Calculate critical CSS from all optimized CSS (also the combined stylesheet's content)
+    let all_css = prune_fonts(css_files.iter().map(|f| f.content.as_str()).collect::<Vec<_>>().join("\n"));
+    // Inlining everything lifts the critical budget to the inline limit, for CSS that fits in it
+    let critical_budget = if options.inline_all_css && all_css.len() <= MAX_INLINE_CSS_BYTES {
+        MAX_INLINE_CSS_BYTES
     } else {
-        None
+        MAX_CRITICAL_CSS_BYTES
+    };
+    let (critical_css, deferred_css) = if !all_css.is_empty() {
+        let (critical, deferred) = split_critical_css(&all_css, html, options.fold_element_count, critical_budget);
+        (Some(critical), deferred)
+    } else {
+        (None, String::new())
     };
+
+    // Small sites: when critical covers every rule, the external stylesheet is redundant
+    let external_css_eliminated = options.inline_all_css
+        && deferred_css.trim().is_empty()
+        && critical_css.as_ref().is_some_and(|css| !css.is_empty() && css.len() <= MAX_INLINE_CSS_BYTES);
+    if external_css_eliminated {
+        tracing::info!("Resource optimizer: All CSS inlined, dropping combined stylesheet");
+    }
     
     // Generate combined CSS (all CSS merged into one file)
-    let combined_css = if !css_files.is_empty() {
-        Some(css_files.iter().map(|f| f.content.as_str()).collect::<Vec<_>>().join("\n"))
+    // With `combine: false` each file is served on its own
+    let combined_css = if options.combine && !css_files.is_empty() {
+        Some(crate::css_optimizer::remove_empty_rules(&all_css))
     } else {
         None
     };
     
     // Generate combined JS (all JS merged into one file with semicolons for safety)
-    let combined_js = if !js_files.is_empty() {
+    let combined_js = if options.combine && !js_files.is_empty() {
         Some(js_files.iter().map(|f| f.content.as_str()).collect::<Vec<_>>().join(";\n"))
     } else {
         None
     };
     
+    // Precompressed siblings for the plugin to serve directly
+    let (combined_css_br, combined_css_gz) = match (&combined_css, options.precompress) {
+        (Some(css), true) => precompress(css),
+        _ => (None, None),
+    };
+    let (combined_js_br, combined_js_gz) = match (&combined_js, options.precompress) {
+        (Some(js), true) => precompress(js),
+        _ => (None, None),
+    };
+    if let Some(br) = &combined_css_br {
+        tracing::debug!("Resource optimizer: Combined CSS brotli {} bytes", br.size);
+    }
+    if let Some(br) = &combined_js_br {
+        tracing::debug!("Resource optimizer: Combined JS brotli {} bytes", br.size);
+    }
+
     let css_savings = total_css_original.saturating_sub(total_css_optimized) as f32 / 1024.0;
     let js_savings = total_js_original.saturating_sub(total_js_optimized) as f32 / 1024.0;
     
@@ -440,19 +853,251 @@ pub async fn optimize_external_resources(html: &str, base_url: &str, used_select
         css_files.len(), css_savings, js_files.len(), js_savings
     );
     
+    let combined_css_filename = combined_filename("styles", "css", combined_css.as_deref());
+    let combined_js_filename = combined_filename("scripts", "js", combined_js.as_deref());
+
     OptimizedResources {
         css_files,
         js_files,
         critical_css,
         combined_css,
         combined_js,
-        combined_css_filename: "styles.min.css".to_string(),
-        combined_js_filename: "scripts.min.js".to_string(),
+        combined_css_filename,
+        combined_js_filename,
+        combined_css_br,
+        combined_css_gz,
+        combined_js_br,
+        combined_js_gz,
         total_css_savings_kb: css_savings,
         total_js_savings_kb: js_savings,
+        skipped_count,
+        external_css_eliminated,
+        fonts,
+        font_warnings,
+    }
+}
+
+/// Name for a combined file, e.g. `styles.a1b2c3d4.min.css`
+/// The content hash busts browser caches whenever the combined content changes
+fn combined_filename(prefix: &str, extension: &str, content: Option<&str>) -> String {
+    match content {
+        Some(content) => format!(
+            "{}.{:08x}.min.{}",
+            prefix,
+            crate::url_utils::stable_hash(content.as_bytes()) >> 32,
+            extension
+        ),
+        None => format!("{}.min.{}", prefix, extension),
     }
 }
 
+/// Download the `@font-face` files of each stylesheet and point its CSS at the local copies
+/// Font URLs resolve against their stylesheet, not the page; stylesheet URLs against `base_url`
+async fn localize_fonts(
+    css_files: &mut [OptimizedCssFile],
+    page_url: &str,
+    base_url: &str,
+    options: &OptimizeOptions,
+) -> Vec<LocalizedFont> {
+    let template = match url::Url::parse(page_url) {
+        Ok(page_url) => options.path_template(&options.asset_path_template, &page_url),
+        Err(_) => options.asset_path_template.clone(),
+    };
+    let mut fonts: Vec<LocalizedFont> = Vec::new();
+
+    for css in css_files.iter_mut() {
+        let Ok(css_url) = crate::url_utils::resolve_url(base_url, &css.original_url) else { continue };
+        let mut local_urls: HashMap<String, String> = HashMap::new();
+
+        for url in font_face_sources(&css.content, options.keep_legacy_fonts) {
+            let Ok(full_url) = crate::url_utils::resolve_url(&css_url, &url) else { continue };
+            let filename = match fonts.iter().find(|font| font.original_url == full_url) {
+                Some(font) => font.filename.clone(),
+                None => match download_bytes(&full_url).await {
+                    Ok(data) => {
+                        let extension = font_extension(&url);
+                        let filename = generate_filename(&full_url, &data, &extension, options);
+                        fonts.push(LocalizedFont {
+                            original_url: full_url,
+                            filename: filename.clone(),
+                            size: data.len(),
+                            base64: base64::Engine::encode(&base64::engine::general_purpose::STANDARD, &data),
+                        });
+                        filename
+                    }
+                    Err(e) => {
+                        crate::metrics::record_download_failure("font");
+                        tracing::warn!("Resource optimizer: Failed to download font {}: {}", full_url, e);
+                        continue;
+                    }
+                },
+            };
+            local_urls.insert(url, crate::url_utils::expand_path_template(&template, &[("filename", &filename)]));
+        }
+
+        css.content = rewrite_font_faces(&css.content, options.keep_legacy_fonts, |url| local_urls.get(url).cloned());
+        css.optimized_size = css.content.len();
+    }
+
+    if !fonts.is_empty() {
+        tracing::info!("Resource optimizer: {} fonts localized", fonts.len());
+    }
+    fonts
+}
+
+/// `src` URLs of every `@font-face` rule, after dropping legacy alternates (see `rewrite_font_faces`)
+pub fn font_face_sources(css: &str, keep_legacy: bool) -> Vec<String> {
+    let mut urls = Vec::new();
+    rewrite_font_faces(css, keep_legacy, |url| {
+        if !urls.iter().any(|u| u == url) {
+            urls.push(url.to_string());
+        }
+        None
+    });
+    urls
+}
+
+/// Rewrite the `src` descriptors of `@font-face` rules: `f` may replace each font URL, and unless
+/// `keep_legacy` eot/ttf/otf/svg sources are dropped from rules that also offer woff2
+pub fn rewrite_font_faces<F>(css: &str, keep_legacy: bool, mut f: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let lower = css.to_ascii_lowercase();
+    let mut result = String::with_capacity(css.len());
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find("@font-face") {
+        let start = pos + offset;
+        let Some(open) = css[start..].find('{').map(|o| start + o + 1) else { break };
+        let Some(close) = css[open..].find('}').map(|c| open + c) else { break };
+        result.push_str(&css[pos..open]);
+
+        let block = &css[open..close];
+        let is_legacy = |entry: &str| {
+            css_url(entry).is_some_and(|(_, url)| LEGACY_FONT_EXTENSIONS.contains(&font_extension(url).as_str()))
+        };
+        let has_woff2 = split_top_level(block, ';').into_iter()
+            .filter_map(|decl| decl.split_once(':'))
+            .filter(|(name, _)| name.trim().eq_ignore_ascii_case("src"))
+            .flat_map(|(_, value)| split_top_level(value, ','))
+            .any(|entry| css_url(entry).is_some_and(|(_, url)| font_extension(url) == "woff2"));
+        let drop_legacy = has_woff2 && !keep_legacy;
+
+        let declarations: Vec<String> = split_top_level(block, ';').into_iter()
+            .filter_map(|decl| {
+                let Some((name, value)) = decl.split_once(':').filter(|(name, _)| name.trim().eq_ignore_ascii_case("src")) else {
+                    return Some(decl.to_string());
+                };
+                let entries: Vec<String> = split_top_level(value, ',').into_iter()
+                    .filter(|entry| !(drop_legacy && is_legacy(entry)))
+                    .map(|entry| match css_url(entry) {
+                        Some((raw, url)) if !url.starts_with("data:") => match f(url) {
+                            Some(new_url) => entry.replacen(raw, &format!("\"{}\"", new_url), 1),
+                            None => entry.to_string(),
+                        },
+                        _ => entry.to_string(),
+                    })
+                    .collect();
+                (!entries.is_empty()).then(|| format!("{}:{}", name, entries.join(",")))
+            })
+            .collect();
+        result.push_str(&declarations.join(";"));
+        pos = close;
+    }
+
+    result.push_str(&css[pos..]);
+    result
+}
+
+/// Resolve every relative `url(...)` in `css` against `stylesheet_url` (data URIs and
+/// `#fragment` references are left alone)
+fn absolutize_css_urls(css: &str, stylesheet_url: &str) -> String {
+    let lower = css.to_ascii_lowercase();
+    let mut result = String::with_capacity(css.len());
+    let mut copied = 0;
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find("url(") {
+        let start = pos + offset;
+        let Some((raw, url)) = css_url(&css[start..]) else { break };
+        let raw_start = start + 4;
+        pos = raw_start + raw.len();
+
+        let is_relative = !url.is_empty() && !url.starts_with('#') && url::Url::parse(url).is_err() && !url.starts_with("//");
+        if !is_relative {
+            continue;
+        }
+        let Ok(absolute) = crate::url_utils::resolve_url(stylesheet_url, url) else { continue };
+        result.push_str(&css[copied..raw_start]);
+        result.push_str(&format!("\"{}\"", absolute.replace('"', "%22")));
+        copied = pos;
+    }
+
+    result.push_str(&css[copied..]);
+    result
+}
+
+/// The first `url(...)` in a CSS value: its raw argument and the unquoted URL
+fn css_url(value: &str) -> Option<(&str, &str)> {
+    let start = value.to_ascii_lowercase().find("url(")? + 4;
+    let end = start + value[start..].find(')')?;
+    let raw = &value[start..end];
+    Some((raw, raw.trim().trim_matches(|c| c == '"' || c == '\'')))
+}
+
+/// Lowercased file extension of a font URL (`#iefix` and query strings ignored)
+fn font_extension(url: &str) -> String {
+    let path = crate::url_utils::strip_query(url);
+    path.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).unwrap_or_default()
+}
+
+/// Split CSS on `separator`, ignoring separators inside quotes or parentheses
+fn split_top_level(css: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut last = 0;
+    for (i, c) in css.char_indices() {
+        match (quote, c) {
+            (Some(q), _) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth = depth.saturating_sub(1),
+            (None, _) if c == separator && depth == 0 => {
+                parts.push(&css[last..i]);
+                last = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&css[last..]);
+    parts
+}
+
+/// Drop external CDN URLs, then keep the first `max` in document order
+/// Returns the kept URLs and how many were dropped by the cap
+fn cap_resources(urls: Vec<String>, max: usize, kind: &str) -> (Vec<String>, usize) {
+    let mut local: Vec<String> = urls
+        .into_iter()
+        .filter(|url| {
+            let skip = should_skip_external(url);
+            if skip {
+                tracing::debug!("Resource optimizer: Skipping external {}", url);
+            }
+            !skip
+        })
+        .collect();
+
+    let skipped = local.len().saturating_sub(max);
+    if skipped > 0 {
+        tracing::warn!("Resource optimizer: {} {} files exceed max_resources ({}), skipping them", skipped, kind, max);
+        local.truncate(max);
+    }
+    (local, skipped)
+}
+
 /// Check if URL should be skipped (external CDNs)
 fn should_skip_external(url: &str) -> bool {
     let lower = url.to_lowercase();
@@ -469,85 +1114,159 @@ fn should_skip_external(url: &str) -> bool {
 }
 
 /// Rewrite HTML to use combined CSS/JS files
-pub fn rewrite_html_with_optimized_resources(html: &mut String, resources: &OptimizedResources, _upload_base_url: &str) {
-    // Track if we've added the combined CSS link
+/// `path_template` is the asset path with only `{filename}` left to substitute
+pub fn rewrite_html_with_optimized_resources(
+    html: &mut String,
+    resources: &OptimizedResources,
+    path_template: &str,
+    options: &OptimizeOptions,
+) {
+    if !options.combine {
+        rewrite_individual_resources(html, resources, path_template, options.match_unversioned_urls);
+    }
+    let unversioned = options.match_unversioned_urls;
+
+    let css_loading = options.css_loading;
+    let combined_css_url = crate::url_utils::expand_path_template(path_template, &[("filename", &resources.combined_css_filename)]);
+    let combined_js_url = crate::url_utils::expand_path_template(path_template, &[("filename", &resources.combined_js_filename)]);
+
+    // Track if we've added the combined CSS link, and where the first original link was
     let mut combined_css_added = false;
+    let mut first_css_pos: Option<usize> = None;
     let mut combined_js_added = false;
     
     // Remove individual CSS links and replace with combined file
     // We only process CSS files that were successfully downloaded (in css_files)
     if resources.combined_css.is_some() && !resources.css_files.is_empty() {
         for css in &resources.css_files {
-            // Find and remove the link tag for this CSS file
-            // Look for patterns like: <link ... href="original_url" ...>
-            if let Some(start) = find_link_tag_start(html, &css.original_url) {
-                if let Some(end) = html[start..].find('>') {
-                    let tag_end = start + end + 1; // +1 to include the '>'
-                    
-                    // If we haven't added combined CSS yet, replace first tag with combined
-                    // Use non-blocking pattern: media="print" with onload to switch to "all"
-                    // Critical CSS (inlined) handles above-the-fold, this loads rest async
-                    if !combined_css_added {
-                        let combined_link = concat!(
-                            "<link rel=\"stylesheet\" href=\"./styles.min.css\" ",
-                            "id=\"htmlwp-combined-css\" media=\"print\" ",
-                            "onload=\"this.media='all'\">"
-                        );
-                        html.replace_range(start..tag_end, &combined_link);
-                        combined_css_added = true;
-                        tracing::debug!("Replaced CSS with combined: {}", css.original_url);
-                    } else {
-                        // Remove subsequent CSS tags entirely
-                        html.replace_range(start..tag_end, "");
-                        tracing::debug!("Removed CSS: {}", css.original_url);
-                    }
+            // Find and remove every link tag for this CSS file (it may be linked more than once,
+            // e.g. with different `?ver=` values). Look for patterns like: <link ... href="original_url" ...>
+            while let Some(start) = find_link_tag_start(html, &css.original_url, unversioned) {
+                // Quote-aware, so a `>` inside an attribute value doesn't end the tag early
+                let Some(tag_end) = crate::html_utils::find_tag_end(html, start) else { break };
+                first_css_pos = Some(match first_css_pos {
+                    Some(pos) if pos > start => pos.saturating_sub(tag_end - start).max(start),
+                    Some(pos) => pos,
+                    None => start,
+                });
+                // If we haven't added combined CSS yet, replace first tag with combined
+                // Use non-blocking pattern: media="print" with onload to switch to "all"
+                // Critical CSS (inlined) handles above-the-fold, this loads rest async
+                if resources.external_css_eliminated {
+                    // Everything is inlined below as critical CSS
+                    html.replace_range(start..tag_end, "");
+                    tracing::debug!("Removed CSS (inlined): {}", css.original_url);
+                } else if !combined_css_added && css_loading != CssLoading::BodyEnd {
+                    let combined_link = match css_loading {
+                        CssLoading::Async | CssLoading::Preload => {
+                            let mut link = if css_loading == CssLoading::Async {
+                                format!(
+                                    concat!(
+                                        "<link rel=\"stylesheet\" href=\"{}\" ",
+                                        "id=\"htmlwp-combined-css\" media=\"print\" ",
+                                        "onload=\"this.media='all'\">"
+                                    ),
+                                    combined_css_url
+                                )
+                            } else {
+                                format!(
+                                    concat!(
+                                        "<link rel=\"preload\" as=\"style\" href=\"{}\" ",
+                                        "id=\"htmlwp-combined-css\" ",
+                                        "onload=\"this.onload=null;this.rel='stylesheet'\">"
+                                    ),
+                                    combined_css_url
+                                )
+                            };
+                            // The swap needs JS; no-JS visitors get the plain link
+                            if options.noscript_css_fallback {
+                                link.push_str(&format!(
+                                    "<noscript><link rel=\"stylesheet\" href=\"{}\"></noscript>",
+                                    combined_css_url
+                                ));
+                            }
+                            link
+                        }
+                        _ => format!("<link rel=\"stylesheet\" href=\"{}\" id=\"htmlwp-combined-css\">", combined_css_url),
+                    };
+                    html.replace_range(start..tag_end, &combined_link);
+                    combined_css_added = true;
+                    tracing::debug!("Replaced CSS with combined: {}", css.original_url);
+                } else {
+                    // Remove subsequent CSS tags entirely
+                    html.replace_range(start..tag_end, "");
+                    tracing::debug!("Removed CSS: {}", css.original_url);
                 }
             }
         }
+
+        // Body-end: every original link is gone; the combined one goes after the page content
+        if css_loading == CssLoading::BodyEnd && !resources.external_css_eliminated {
+            let combined_link = format!("<link rel=\"stylesheet\" href=\"{}\" id=\"htmlwp-combined-css\">", combined_css_url);
+            let pos = html.to_ascii_lowercase().rfind("</body>").unwrap_or(html.len());
+            html.insert_str(pos, &combined_link);
+            combined_css_added = true;
+        }
     }
     
     // Remove individual JS scripts and replace with combined file
+    // (body-end: remove them all and remember where the first one was)
+    let body_end = options.js_placement == JsPlacement::BodyEnd;
+    let mut first_js_pos: Option<usize> = None;
     if resources.combined_js.is_some() && !resources.js_files.is_empty() {
+        let combined_script = format!(
+            "<script src=\"{}\" id=\"htmlwp-combined-js\"></script>",
+            combined_js_url
+        );
         for js in &resources.js_files {
-            // Find and remove the script tag for this JS file
-            if let Some(start) = find_script_tag_start(html, &js.original_url) {
+            // Find and remove every script tag for this JS file
+            while let Some(start) = find_script_tag_start(html, &js.original_url, unversioned) {
                 // Find end of script tag - could be self-closing or have </script>
-                if let Some(close_pos) = html[start..].find("</script>") {
-                    let tag_end = start + close_pos + 9; // +9 for "</script>"
-                    
-                    if !combined_js_added {
-                        let combined_script = format!(
-                            "<script src=\"./scripts.min.js\" id=\"htmlwp-combined-js\"></script>"
-                        );
-                        html.replace_range(start..tag_end, &combined_script);
-                        combined_js_added = true;
-                        tracing::debug!("Replaced JS with combined: {}", js.original_url);
-                    } else {
-                        html.replace_range(start..tag_end, "");
-                        tracing::debug!("Removed JS: {}", js.original_url);
-                    }
-                } else if let Some(end) = html[start..].find("/>") {
-                    let tag_end = start + end + 2;
-                    if !combined_js_added {
-                        let combined_script = format!(
-                            "<script src=\"./scripts.min.js\" id=\"htmlwp-combined-js\"></script>"
-                        );
-                        html.replace_range(start..tag_end, &combined_script);
-                        combined_js_added = true;
-                    } else {
-                        html.replace_range(start..tag_end, "");
-                    }
+                let Some(tag_end) = script_element_end(html, start) else { break };
+
+                if !combined_js_added && !body_end {
+                    html.replace_range(start..tag_end, &combined_script);
+                    combined_js_added = true;
+                    tracing::debug!("Replaced JS with combined: {}", js.original_url);
+                } else {
+                    html.replace_range(start..tag_end, "");
+                    first_js_pos = Some(match first_js_pos {
+                        Some(pos) if pos > start => pos.saturating_sub(tag_end - start).max(start),
+                        Some(pos) => pos,
+                        None => start,
+                    });
+                    tracing::debug!("Removed JS: {}", js.original_url);
                 }
             }
         }
+
+        if let Some(first_pos) = first_js_pos.filter(|_| body_end) {
+            move_combined_js_to_body_end(html, first_pos, &combined_js_url);
+            combined_js_added = true;
+        }
     }
     
     // Inject critical CSS if present
     if let Some(critical) = &resources.critical_css {
         if !critical.is_empty() {
-            // Find </head> and inject critical CSS before it
-            if let Some(pos) = html.to_lowercase().find("</head>") {
-                let critical_tag = format!("<style id=\"critical-css\">{}</style>\n", critical);
+            // In place of the first stylesheet (ahead of the inline styles that override it),
+            // else before </head>
+            let head_end = html.to_lowercase().find("</head>");
+            let pos = first_css_pos.filter(|pos| head_end.is_some_and(|end| *pos < end))
+                .or_else(|| first_head_stylesheet(html))
+                .or(head_end);
+            if let Some(pos) = pos {
+                let mut critical_tag = String::new();
+                if options.preload_fonts {
+                    // Start the font downloads before the critical CSS that needs them is parsed
+                    for url in critical_font_preloads(critical) {
+                        critical_tag.push_str(&format!(
+                            "<link rel=\"preload\" as=\"font\" type=\"font/woff2\" crossorigin href=\"{}\">\n",
+                            url
+                        ));
+                    }
+                }
+                critical_tag.push_str(&format!("<style id=\"critical-css\">{}</style>\n", critical));
                 html.insert_str(pos, &critical_tag);
                 tracing::debug!("Injected {} bytes of critical CSS", critical.len());
             }
@@ -560,46 +1279,167 @@ pub fn rewrite_html_with_optimized_resources(html: &mut String, resources: &Opti
     );
 }
 
-/// Find the start position of a <link> tag containing the given URL
-fn find_link_tag_start(html: &str, url: &str) -> Option<usize> {
-    let lower_html = html.to_lowercase();
-    let lower_url = url.to_lowercase();
-    
-    // Look for href="url", href='url', or href=url (unquoted)
-    for pattern in [
-        format!("href=\"{}\"", lower_url), 
-        format!("href='{}'", lower_url),
-        format!("href={}", lower_url)
-    ] {
-        if let Some(href_pos) = lower_html.find(&pattern) {
-            // Search backwards from href to find <link
-            let before = &lower_html[..href_pos];
-            if let Some(link_rel_pos) = before.rfind("<link") {
-                return Some(link_rel_pos);
+/// woff2 URLs of the `@font-face` rules in `critical_css` whose family the critical rules use, deduped
+/// (absolute: `optimize_css_file` resolves url()s against their stylesheet).
+/// Fonts without a woff2 source aren't preloaded
+pub fn critical_font_preloads(critical_css: &str) -> Vec<String> {
+    let references = crate::css_optimizer::font_family_references(critical_css);
+    let mut urls: Vec<String> = Vec::new();
+    for font in crate::css_optimizer::analyze_font_faces(critical_css) {
+        let Some(family) = font.family.as_deref() else { continue };
+        if !references.contains(&family.to_ascii_lowercase()) {
+            continue;
+        }
+        if let Some((url, _)) = font.sources.into_iter().find(|(_, format)| format == "woff2") {
+            if !urls.contains(&url) {
+                urls.push(url);
             }
         }
     }
-    None
+    urls
 }
 
-/// Find the start position of a <script> tag containing the given URL  
-fn find_script_tag_start(html: &str, url: &str) -> Option<usize> {
-    let lower_html = html.to_lowercase();
-    let lower_url = url.to_lowercase();
-    
-    // Look for src="url", src='url', or src=url (unquoted)
-    for pattern in [
-        format!("src=\"{}\"", lower_url), 
-        format!("src='{}'", lower_url),
-        format!("src={}", lower_url)
-    ] {
-        if let Some(src_pos) = lower_html.find(&pattern) {
-            // Search backwards from src to find <script
-            let before = &lower_html[..src_pos];
-            if let Some(script_pos) = before.rfind("<script") {
-                return Some(script_pos);
+/// `Link` header values preloading the rewritten stylesheet(s), script(s) and localized woff2 fonts,
+/// for the plugin to send server-side (`emit_preload_headers`)
+pub fn preload_headers(resources: &OptimizedResources, path_template: &str, options: &OptimizeOptions) -> Vec<String> {
+    let file_url = |filename: &str| crate::url_utils::expand_path_template(path_template, &[("filename", filename)]);
+    let mut headers = Vec::new();
+
+    if !resources.external_css_eliminated {
+        if resources.combined_css.is_some() {
+            headers.push(format!("<{}>; rel=preload; as=style", file_url(&resources.combined_css_filename)));
+        } else if !options.combine {
+            headers.extend(resources.css_files.iter().map(|css| format!("<{}>; rel=preload; as=style", file_url(&css.filename))));
+        }
+    }
+    if resources.combined_js.is_some() {
+        headers.push(format!("<{}>; rel=preload; as=script", file_url(&resources.combined_js_filename)));
+    } else if !options.combine {
+        headers.extend(resources.js_files.iter().map(|js| format!("<{}>; rel=preload; as=script", file_url(&js.filename))));
+    }
+    // Fonts are always fetched in CORS mode, so the preload must be too
+    headers.extend(resources.fonts.iter()
+        .filter(|font| font.filename.ends_with(".woff2"))
+        .map(|font| format!("<{}>; rel=preload; as=font; type=\"font/woff2\"; crossorigin", file_url(&font.filename))));
+
+    headers
+}
+
+/// Point each original `<link>`/`<script>` at its own minified file (`combine: false`)
+fn rewrite_individual_resources(html: &mut String, resources: &OptimizedResources, path_template: &str, unversioned: bool) {
+    use crate::html_utils::{get_attribute, set_attribute};
+
+    let file_url = |filename: &str| crate::url_utils::expand_path_template(path_template, &[("filename", filename)]);
+    let mut rewritten = 0;
+    *html = crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        let new_tag = match name {
+            "link" => {
+                let href = crate::html_utils::decode_entities(&get_attribute(tag, "href")?);
+                let css = resources.css_files.iter().find(|css| asset_urls_match(&css.original_url, &href, unversioned))?;
+                if resources.external_css_eliminated {
+                    // Everything is inlined as critical CSS
+                    String::new()
+                } else {
+                    set_attribute(tag, "href", &file_url(&css.filename))
+                }
+            }
+            "script" => {
+                let src = crate::html_utils::decode_entities(&get_attribute(tag, "src")?);
+                let js = resources.js_files.iter().find(|js| asset_urls_match(&js.original_url, &src, unversioned))?;
+                set_attribute(tag, "src", &file_url(&js.filename))
             }
+            _ => return None,
+        };
+        rewritten += 1;
+        Some(new_tag)
+    });
+    tracing::debug!("HTML rewrite: {} CSS/JS references pointed at individual files", rewritten);
+}
+
+/// Insert the combined script before `</body>`, moving the executable inline scripts that followed
+/// the first original script after it so they still run after the code they may depend on.
+/// With nothing to keep in order the script is `defer`red.
+fn move_combined_js_to_body_end(html: &mut String, first_pos: usize, combined_js_url: &str) {
+    let lower = html.to_ascii_lowercase();
+    let body_close = lower.rfind("</body>").filter(|&pos| pos >= first_pos).unwrap_or(html.len());
+
+    let mut moved: Vec<std::ops::Range<usize>> = Vec::new();
+    let mut pos = first_pos;
+    while let Some(offset) = lower[pos..body_close].find("<script") {
+        let start = pos + offset;
+        let Some(open_end) = crate::html_utils::find_tag_end(html, start) else { break };
+        let end = lower[open_end..].find("</script>").map(|e| open_end + e + 9).unwrap_or(body_close).min(body_close);
+        let tag = &html[start..open_end];
+        let is_data_block = crate::html_utils::get_attribute(tag, "type").is_some_and(|t| {
+            let t = t.trim().to_ascii_lowercase();
+            !t.is_empty() && !t.contains("javascript") && t != "module"
+        });
+        if crate::html_utils::get_attribute(tag, "src").is_none() && !is_data_block {
+            moved.push(start..end);
+        }
+        pos = end;
+    }
+
+    let defer = if moved.is_empty() { " defer" } else { "" };
+    let mut tail = format!("<script src=\"{}\" id=\"htmlwp-combined-js\"{}></script>", combined_js_url, defer);
+    for range in &moved {
+        tail.push_str(&html[range.clone()]);
+    }
+    html.insert_str(body_close, &tail);
+    for range in moved.iter().rev() {
+        html.replace_range(range.clone(), "");
+    }
+}
+
+/// End of the `<script>` element starting at `start`: past its `</script>`, or past the
+/// start tag itself when it is self-closing
+fn script_element_end(html: &str, start: usize) -> Option<usize> {
+    let open_end = crate::html_utils::find_tag_end(html, start)?;
+    if html[start..open_end].trim_end_matches('>').trim_end().ends_with('/') {
+        return Some(open_end);
+    }
+    let lower = html[open_end..].to_ascii_lowercase();
+    let close = lower.find("</script")?;
+    let close_end = lower[close..].find('>')?;
+    Some(open_end + close + close_end + 1)
+}
+
+/// Find the start position of a <link> tag whose href is the given URL
+fn find_link_tag_start(html: &str, url: &str, unversioned: bool) -> Option<usize> {
+    find_tag_with_url(html, "link", "href", url, unversioned)
+}
+
+/// Find the start position of a <script> tag whose src is the given URL
+fn find_script_tag_start(html: &str, url: &str, unversioned: bool) -> Option<usize> {
+    find_tag_with_url(html, "script", "src", url, unversioned)
+}
+
+/// Whether two asset URLs name the same file; `unversioned` ignores the `ver` parameter and
+/// fragment, so `style.css?ver=6.4` matches `style.css?ver=6.5` and `style.css`, but
+/// `load.php?load=a` never matches `load.php?load=b`
+fn asset_urls_match(a: &str, b: &str, unversioned: bool) -> bool {
+    let strip = |url: &str| if unversioned { crate::url_utils::strip_version_query(url) } else { url.to_string() };
+    strip(a).eq_ignore_ascii_case(&strip(b))
+}
+
+/// Start of the first `<element>` whose `attr` matches `url` once HTML entities are decoded
+/// (`extract_css_links` yields `?a=1&b=2` where the markup says `?a=1&amp;b=2`)
+fn find_tag_with_url(html: &str, element: &str, attr: &str, url: &str, unversioned: bool) -> Option<usize> {
+    let lower = html.to_ascii_lowercase();
+    let open = format!("<{}", element);
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find(&open) {
+        let start = pos + offset;
+        let end = crate::html_utils::find_tag_end(html, start)?;
+        let is_element = lower[start + open.len()..].starts_with(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/');
+        let matches = is_element
+            && crate::html_utils::get_attribute(&html[start..end], attr)
+                .is_some_and(|value| asset_urls_match(&crate::html_utils::decode_entities(value.trim()), url, unversioned));
+        if matches {
+            return Some(start);
         }
+        pos = end;
     }
     None
 }
@@ -622,6 +1462,9 @@ mod tests {
         let sources = extract_js_sources(html);
         assert_eq!(sources.len(), 2);
         assert!(sources.contains(&"/app.js".to_string()));
+
+        let html = r#"<script type="module" src="/app.mjs"></script><script type="text/javascript" src="/legacy.js"></script>"#;
+        assert_eq!(extract_js_sources(html), vec!["/legacy.js".to_string()]);
     }
 
     #[test]
@@ -633,17 +1476,583 @@ mod tests {
         assert_eq!(sources.len(), 1);
         assert_eq!(sources[0], url);
         
-        let pos = find_script_tag_start(html, url);
+        let pos = find_script_tag_start(html, url, false);
         assert!(pos.is_some(), "Failed to find script tag position");
     }
 
+    #[test]
+    fn test_combined_filename_tracks_content() {
+        let v1 = combined_filename("styles", "css", Some(".a{color:red}"));
+        let v2 = combined_filename("styles", "css", Some(".a{color:blue}"));
+        assert_ne!(v1, v2);
+        assert_eq!(v1, combined_filename("styles", "css", Some(".a{color:red}")));
+        assert!(v1.starts_with("styles.") && v1.ends_with(".min.css"), "{}", v1);
+        assert_eq!(v1.len(), "styles.12345678.min.css".len());
+    }
+
+    #[test]
+    fn test_generate_filename_ignores_version_query() {
+        let options = OptimizeOptions::default();
+        let v1 = generate_filename("https://example.com/style.css?ver=1", b"", "css", &options);
+        let v2 = generate_filename("https://example.com/style.css?ver=2", b"", "css", &options);
+        assert_eq!(v1, v2);
+        assert!(v1.ends_with(".css"));
+        assert_ne!(
+            generate_filename("https://example.com/photo.jpg?w=300", b"", "webp", &options),
+            generate_filename("https://example.com/photo.jpg?w=1024", b"", "webp", &options),
+            "size parameters name different files"
+        );
+
+        let options = OptimizeOptions { normalize_asset_urls: false, ..OptimizeOptions::default() };
+        let raw1 = generate_filename("https://example.com/style.css?ver=1", b"", "css", &options);
+        let raw2 = generate_filename("https://example.com/style.css?ver=2", b"", "css", &options);
+        assert_ne!(raw1, raw2);
+    }
+
+    fn sample_resources() -> OptimizedResources {
+        OptimizedResources {
+            css_files: vec![OptimizedCssFile {
+                original_url: "/style.css".to_string(),
+                filename: generate_filename("/style.css", b"", "css", &OptimizeOptions::default()),
+                content: ".a{color:red}".to_string(),
+                original_size: 20,
+                optimized_size: 13,
+                reduction_percent: 35.0,
+            }],
+            js_files: vec![OptimizedJsFile {
+                original_url: "/app.js".to_string(),
+                filename: generate_filename("/app.js", b"", "js", &OptimizeOptions::default()),
+                content: "var a=1".to_string(),
+                original_size: 12,
+                optimized_size: 7,
+                reduction_percent: 41.0,
+            }],
+            critical_css: None,
+            combined_css: Some(".a{color:red}".to_string()),
+            combined_js: Some("var a=1".to_string()),
+            combined_css_filename: "styles.min.css".to_string(),
+            combined_js_filename: "scripts.min.js".to_string(),
+            combined_css_br: None,
+            combined_css_gz: None,
+            combined_js_br: None,
+            combined_js_gz: None,
+            total_css_savings_kb: 0.0,
+            total_js_savings_kb: 0.0,
+            skipped_count: 0,
+            external_css_eliminated: false,
+            fonts: Vec::new(),
+            font_warnings: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_split_critical_css_and_inline_all() {
+        let html = r#"<html><body><header class="site-header"><div class="card">A</div></header><footer><div class="footer-widget">B</div></footer></body></html>"#;
+        let css = "body{margin:0}@media (min-width:600px){.card{padding:1em}}.footer-widget{color:#333}";
+        let (critical, deferred) = split_critical_css(css, html, 3, MAX_CRITICAL_CSS_BYTES);
+        assert!(critical.contains("@media (min-width:600px){.card{padding:1em}}"));
+        assert_eq!(deferred.trim(), ".footer-widget{color:#333}");
+
+        let mut resources = sample_resources();
+        resources.critical_css = Some(".a{color:red}".to_string());
+        resources.external_css_eliminated = true;
+        let mut html = r#"<html><head><link rel="stylesheet" href="/style.css"></head><body></body></html>"#.to_string();
+        rewrite_html_with_optimized_resources(&mut html, &resources, "./{filename}", &OptimizeOptions::default());
+        assert!(!html.contains("<link"));
+        assert!(html.contains("<style id=\"critical-css\">.a{color:red}</style>"));
+    }
+
+    #[tokio::test]
+    async fn test_inline_all_css_takes_stylesheets_past_critical_budget() {
+        // ~20KB, every rule above the fold
+        let css: String = (0..750).map(|i| format!("body.page-{}{{margin:{}px}}\n", i, i)).collect();
+        assert!(css.len() > 20 * 1024 && css.len() < MAX_INLINE_CSS_BYTES);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new().route("/style.css", axum::routing::get(move || async move { css }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let html = r#"<html><head><link rel="stylesheet" href="/style.css"></head><body><p>Hi</p></body></html>"#;
+        let base_url = format!("http://{}/", addr);
+        let options = OptimizeOptions { inline_all_css: true, min_reduction_percent: 0.0, ..OptimizeOptions::default() };
+        let result = optimize_external_resources(html, &base_url, &[], &options, None).await;
+        let critical = result.critical_css.unwrap();
+        assert!(critical.len() > MAX_CRITICAL_CSS_BYTES && critical.contains("body.page-749{"));
+        assert!(result.external_css_eliminated);
+
+        // Without it, critical CSS stays within its budget
+        let options = OptimizeOptions { min_reduction_percent: 0.0, ..OptimizeOptions::default() };
+        let result = optimize_external_resources(html, &base_url, &[], &options, None).await;
+        assert!(result.critical_css.unwrap().len() < MAX_CRITICAL_CSS_BYTES + 100);
+        assert!(!result.external_css_eliminated);
+    }
+
+    #[test]
+    fn test_css_urls_resolve_against_stylesheet() {
+        let css = concat!(
+            "@font-face{font-family:Brand;src:url(../fonts/brand.woff2) format(\"woff2\")}",
+            ".hero{background:url('img/hero.jpg')}.icon{background:url(data:image/png;base64,AAAA)}",
+            ".cdn{background:url(//cdn.example.net/a.png)}.abs{background:url(\"https://cdn.example.net/b.png\")}",
+            ".svg{fill:url(#gradient)}.root{background:url(/uploads/bg.png)}"
+        );
+        let absolute = absolutize_css_urls(css, "https://example.com/wp-content/themes/site/css/style.css");
+        assert_eq!(absolute, concat!(
+            "@font-face{font-family:Brand;src:url(\"https://example.com/wp-content/themes/site/fonts/brand.woff2\") format(\"woff2\")}",
+            ".hero{background:url(\"https://example.com/wp-content/themes/site/css/img/hero.jpg\")}.icon{background:url(data:image/png;base64,AAAA)}",
+            ".cdn{background:url(//cdn.example.net/a.png)}.abs{background:url(\"https://cdn.example.net/b.png\")}",
+            ".svg{fill:url(#gradient)}.root{background:url(\"https://example.com/uploads/bg.png\")}"
+        ));
+
+        // Critical CSS cut from the stylesheet then preloads the font from its real location
+        let critical = format!("{}body{{font-family:Brand}}", absolute);
+        assert_eq!(critical_font_preloads(&critical), vec!["https://example.com/wp-content/themes/site/fonts/brand.woff2".to_string()]);
+    }
+
+    #[test]
+    fn test_critical_fonts_preloaded() {
+        let critical = concat!(
+            "@font-face{font-family:\"Inter\";src:url(/fonts/inter.eot?#iefix) format(\"embedded-opentype\"),url(/fonts/inter.woff2) format(\"woff2\"),url(/fonts/inter.woff)}",
+            "@font-face{font-family:Inter;font-weight:700;src:url(/fonts/inter.woff2)}",
+            "@font-face{font-family:Lora;src:url(/fonts/lora.woff2)}",
+            "body{font-family:Inter,sans-serif}"
+        );
+        assert_eq!(critical_font_preloads(critical), vec!["/fonts/inter.woff2".to_string()]);
+
+        let mut resources = sample_resources();
+        resources.critical_css = Some(critical.to_string());
+        let options = OptimizeOptions { preload_fonts: true, ..OptimizeOptions::default() };
+        let mut html = r#"<html><head><link rel="stylesheet" href="/style.css"></head><body></body></html>"#.to_string();
+        rewrite_html_with_optimized_resources(&mut html, &resources, "./{filename}", &options);
+        assert!(html.contains(r#"<link rel="preload" as="font" type="font/woff2" crossorigin href="/fonts/inter.woff2">"#));
+        assert_eq!(html.matches("rel=\"preload\" as=\"font\"").count(), 1);
+        assert!(html.find("/fonts/inter.woff2\">").unwrap() < html.find("<style id=\"critical-css\">").unwrap());
+    }
+
+    #[test]
+    fn test_rewrite_uses_asset_path_template() {
+        let mut html = r#"<html><head><link rel="stylesheet" href="/style.css"></head><body><script src="/app.js"></script></body></html>"#.to_string();
+        rewrite_html_with_optimized_resources(&mut html, &sample_resources(), "https://cdn.example.com/assets/{filename}", &OptimizeOptions::default());
+        assert!(html.contains("href=\"https://cdn.example.com/assets/styles.min.css\""));
+        assert!(html.contains("src=\"https://cdn.example.com/assets/scripts.min.js\""));
+        assert!(!html.contains("/style.css\""));
+    }
+
+    #[test]
+    fn test_body_end_css_loading_moves_combined_link() {
+        let mut html = r#"<html><head><link rel="stylesheet" href="/style.css"></head><body><main>Content</main><footer>Last</footer></body></html>"#.to_string();
+        let options = OptimizeOptions {
+            css_loading: CssLoading::BodyEnd,
+            ..OptimizeOptions::default()
+        };
+        rewrite_html_with_optimized_resources(&mut html, &sample_resources(), "./{filename}", &options);
+        assert!(html.contains("<head></head>"));
+        assert!(html.ends_with(r#"<footer>Last</footer><link rel="stylesheet" href="./styles.min.css" id="htmlwp-combined-css"></body></html>"#), "{}", html);
+        assert!(!html.contains("media=\"print\""));
+    }
+
+    #[test]
+    fn test_css_load_strategy_markup() {
+        let html = r#"<html><head><link rel="stylesheet" href="/style.css"></head><body></body></html>"#;
+        let rewrite = |strategy: &str| {
+            let css_loading: CssLoading = serde_json::from_value(serde_json::json!(strategy)).unwrap();
+            let options = OptimizeOptions { css_loading, noscript_css_fallback: false, ..OptimizeOptions::default() };
+            let mut rewritten = html.to_string();
+            rewrite_html_with_optimized_resources(&mut rewritten, &sample_resources(), "./{filename}", &options);
+            rewritten
+        };
+
+        assert!(rewrite("print-onload").contains(
+            r#"<head><link rel="stylesheet" href="./styles.min.css" id="htmlwp-combined-css" media="print" onload="this.media='all'"></head>"#
+        ));
+        assert!(rewrite("preload-onload").contains(
+            r#"<head><link rel="preload" as="style" href="./styles.min.css" id="htmlwp-combined-css" onload="this.onload=null;this.rel='stylesheet'"></head>"#
+        ));
+        assert!(rewrite("blocking").contains(
+            r#"<head><link rel="stylesheet" href="./styles.min.css" id="htmlwp-combined-css"></head>"#
+        ));
+        assert_eq!(rewrite("async"), rewrite("print-onload"));
+    }
+
+    #[test]
+    fn test_versioned_links_to_same_file_are_all_rewritten() {
+        let html = r#"<html><head><link rel="stylesheet" href="/style.css?ver=1.0"><link rel="stylesheet" href="/style.css?ver=2.0"></head><body></body></html>"#;
+        let mut resources = sample_resources();
+        resources.css_files[0].original_url = "/style.css?ver=2.0".to_string();
+        let options = OptimizeOptions { css_loading: CssLoading::Preserve, ..OptimizeOptions::default() };
+
+        // Exact matching only touches the tag with the downloaded version
+        let mut rewritten = html.to_string();
+        rewrite_html_with_optimized_resources(&mut rewritten, &resources, "./{filename}", &options);
+        assert!(rewritten.contains("ver=1.0"));
+        assert!(!rewritten.contains("ver=2.0"));
+
+        let options = OptimizeOptions { match_unversioned_urls: true, ..options };
+        let mut rewritten = html.to_string();
+        rewrite_html_with_optimized_resources(&mut rewritten, &resources, "./{filename}", &options);
+        assert_eq!(
+            rewritten,
+            r#"<html><head><link rel="stylesheet" href="./styles.min.css" id="htmlwp-combined-css"></head><body></body></html>"#
+        );
+
+        // Uncombined: both links point at the optimized file
+        let options = OptimizeOptions { combine: false, ..options };
+        let mut rewritten = html.to_string();
+        rewrite_html_with_optimized_resources(&mut rewritten, &resources, "./{filename}", &options);
+        assert!(!rewritten.contains("?ver="), "{}", rewritten);
+
+        // Only the version is ignored: other parameters name a different file
+        assert!(asset_urls_match("/load.php?c=1&load=a&ver=1", "/load.php?c=1&load=a", true));
+        assert!(!asset_urls_match("/load.php?c=1&load=a", "/load.php?c=1&load=b", true));
+    }
+
+    #[test]
+    fn test_async_css_has_noscript_fallback() {
+        let html = r#"<html><head><link rel="stylesheet" href="/style.css"></head><body></body></html>"#;
+
+        let mut rewritten = html.to_string();
+        rewrite_html_with_optimized_resources(&mut rewritten, &sample_resources(), "./{filename}", &OptimizeOptions::default());
+        assert!(rewritten.contains(
+            r#"onload="this.media='all'"><noscript><link rel="stylesheet" href="./styles.min.css"></noscript>"#
+        ));
+
+        let options = OptimizeOptions {
+            noscript_css_fallback: false,
+            ..OptimizeOptions::default()
+        };
+        let mut rewritten = html.to_string();
+        rewrite_html_with_optimized_resources(&mut rewritten, &sample_resources(), "./{filename}", &options);
+        assert!(!rewritten.contains("<noscript>"));
+    }
+
+    #[test]
+    fn test_uncombined_files_keep_their_own_links() {
+        let mut resources = sample_resources();
+        resources.css_files.push(OptimizedCssFile {
+            original_url: "/theme.css".to_string(),
+            filename: "theme.css".to_string(),
+            content: ".b{color:blue}".to_string(),
+            original_size: 20,
+            optimized_size: 14,
+            reduction_percent: 30.0,
+        });
+        resources.css_files[0].filename = "style.css".to_string();
+        resources.combined_css = None;
+        resources.combined_js = None;
+        let options = OptimizeOptions {
+            combine: false,
+            ..OptimizeOptions::default()
+        };
+
+        let mut html = concat!(
+            r#"<html><head><link rel="stylesheet" href="/style.css"><link rel="stylesheet" href="/theme.css">"#,
+            r#"</head><body></body></html>"#
+        ).to_string();
+        rewrite_html_with_optimized_resources(&mut html, &resources, "./{filename}", &options);
+        assert!(html.contains(r#"<link rel="stylesheet" href="./style.css"><link rel="stylesheet" href="./theme.css">"#), "{}", html);
+        assert!(!html.contains("htmlwp-combined"));
+    }
+
+    #[test]
+    fn test_rewrite_finds_entity_encoded_urls() {
+        let html = concat!(
+            r#"<html><head><link rel="stylesheet" href="/style.css?ver=6.4&amp;load=all">"#,
+            r#"<script src="/app.js?a=1&#038;b=2"></script></head><body></body></html>"#
+        );
+        let links = extract_css_links(html);
+        assert_eq!(links, vec!["/style.css?ver=6.4&load=all".to_string()]);
+
+        let mut resources = sample_resources();
+        resources.css_files[0].original_url = links[0].clone();
+        resources.js_files[0].original_url = extract_js_sources(html)[0].clone();
+        let mut rewritten = html.to_string();
+        rewrite_html_with_optimized_resources(&mut rewritten, &resources, "./{filename}", &OptimizeOptions::default());
+        assert!(!rewritten.contains("/style.css"), "{}", rewritten);
+        assert!(!rewritten.contains("/app.js"), "{}", rewritten);
+        assert!(rewritten.contains("htmlwp-combined-css") && rewritten.contains("htmlwp-combined-js"));
+    }
+
+    #[test]
+    fn test_font_face_prefers_woff2() {
+        let css = concat!(
+            "@font-face{font-family:Brand;src:url(../fonts/brand.eot);",
+            "src:url('../fonts/brand.eot?#iefix') format('embedded-opentype'),",
+            "url(\"../fonts/brand.woff2\") format(\"woff2\"),url(../fonts/brand.ttf) format(\"truetype\")}",
+            "body{font-family:Brand}"
+        );
+        assert_eq!(font_face_sources(css, false), vec!["../fonts/brand.woff2".to_string()]);
+        assert_eq!(font_face_sources(css, true).len(), 4);
+
+        let rewritten = rewrite_font_faces(css, false, |url| Some(format!("/local/{}", url.rsplit('/').next().unwrap())));
+        assert_eq!(rewritten, concat!(
+            "@font-face{font-family:Brand;src:url(\"/local/brand.woff2\") format(\"woff2\")}",
+            "body{font-family:Brand}"
+        ));
+    }
+
+    #[test]
+    fn test_rewrite_handles_quoted_gt_and_multiline_tags() {
+        let html = concat!(
+            "<html><head><link\n    media=\"all\"\n    rel=\"stylesheet\"\n    href=\"/style.css?v=a>b\"\n>",
+            "<meta name=\"after-link\">",
+            "<SCRIPT\n  type=\"text/javascript\"\n  src=\"/app.js\"></SCRIPT><script>keep();</script>",
+            "</head><body></body></html>"
+        );
+        let mut resources = sample_resources();
+        resources.css_files[0].original_url = "/style.css?v=a>b".to_string();
+        let mut rewritten = html.to_string();
+        rewrite_html_with_optimized_resources(&mut rewritten, &resources, "./{filename}", &OptimizeOptions::default());
+
+        assert!(!rewritten.contains("a>b"), "{}", rewritten);
+        assert!(rewritten.contains(r#"onload="this.media='all'">"#), "{}", rewritten);
+        assert!(rewritten.contains("<meta name=\"after-link\">"), "{}", rewritten);
+        assert!(rewritten.contains("id=\"htmlwp-combined-js\"></script><script>keep();</script>"), "{}", rewritten);
+        assert!(!rewritten.contains("/app.js"));
+    }
+
+    #[test]
+    fn test_preload_headers_for_combined_files() {
+        let mut resources = sample_resources();
+        resources.fonts.push(LocalizedFont {
+            original_url: "https://example.com/fonts/brand.woff2".to_string(),
+            filename: "brand.woff2".to_string(),
+            base64: String::new(),
+            size: 10,
+        });
+        let headers = preload_headers(&resources, "/wp-content/htmlwp/{filename}", &OptimizeOptions::default());
+        assert_eq!(headers, vec![
+            "</wp-content/htmlwp/styles.min.css>; rel=preload; as=style".to_string(),
+            "</wp-content/htmlwp/scripts.min.js>; rel=preload; as=script".to_string(),
+            "</wp-content/htmlwp/brand.woff2>; rel=preload; as=font; type=\"font/woff2\"; crossorigin".to_string(),
+        ]);
+    }
+
+    #[test]
+    fn test_decode_text_strips_bom_and_honours_charset() {
+        assert_eq!(decode_text(b"\xEF\xBB\xBFvar a = 1;", None), "var a = 1;");
+        assert_eq!(decode_text(b"\xEF\xBB\xBFvar a = 1;", Some("application/javascript; charset=iso-8859-1")), "var a = 1;");
+        assert_eq!(decode_text(b"a{content:\"\xE9\"}", Some("text/css; charset=ISO-8859-1")), "a{content:\"\u{e9}\"}");
+        // Unlabelled invalid UTF-8 falls back to windows-1252 instead of replacement characters
+        assert_eq!(decode_text(b"/* caf\xE9 */", None), "/* caf\u{e9} */");
+        assert_eq!(decode_text(b"@charset \"iso-8859-1\";a{content:\"\xE9\"}", None), "@charset \"iso-8859-1\";a{content:\"\u{e9}\"}");
+    }
+
+    #[test]
+    fn test_body_end_js_placement_keeps_inline_order() {
+        let html = concat!(
+            "<html><head><script src=\"/app.js\"></script><script>app.init();</script>",
+            "<script type=\"application/ld+json\">{}</script></head>",
+            "<body><p>Content</p></body></html>"
+        );
+        let options = OptimizeOptions {
+            js_placement: JsPlacement::BodyEnd,
+            ..OptimizeOptions::default()
+        };
+
+        let mut rewritten = html.to_string();
+        rewrite_html_with_optimized_resources(&mut rewritten, &sample_resources(), "./{filename}", &options);
+        assert!(rewritten.contains("<head><script type=\"application/ld+json\">{}</script></head>"), "{}", rewritten);
+        assert!(rewritten.ends_with(concat!(
+            "<p>Content</p><script src=\"./scripts.min.js\" id=\"htmlwp-combined-js\"></script>",
+            "<script>app.init();</script></body></html>"
+        )));
+
+        // No dependent inline scripts: deferred
+        let mut rewritten = html.replace("<script>app.init();</script>", "");
+        rewrite_html_with_optimized_resources(&mut rewritten, &sample_resources(), "./{filename}", &options);
+        assert!(rewritten.contains("<script src=\"./scripts.min.js\" id=\"htmlwp-combined-js\" defer></script></body>"));
+    }
+
+    #[test]
+    fn test_inline_overrides_stay_after_critical_css() {
+        // A customizer override of a theme rule must still come later in the cascade
+        let html = concat!(
+            "<html><head><link rel=\"stylesheet\" href=\"/style.css\">",
+            "<style id=\"wp-custom-css\">.hero{color:blue}</style></head>",
+            "<body><div class=\"hero\">Hi</div></body></html>"
+        );
+        let (critical, _) = split_critical_css(".hero{color:red}", html, 10, MAX_CRITICAL_CSS_BYTES);
+        assert_eq!(critical.trim(), ".hero{color:red}");
+
+        let mut resources = sample_resources();
+        resources.critical_css = Some(critical);
+        for combine in [true, false] {
+            let options = OptimizeOptions { combine, ..OptimizeOptions::default() };
+            let mut rewritten = html.to_string();
+            rewrite_html_with_optimized_resources(&mut rewritten, &resources, "./{filename}", &options);
+            let theme = rewritten.find(".hero{color:red}").unwrap();
+            let custom = rewritten.find(".hero{color:blue}").unwrap();
+            assert!(theme < custom, "{}", rewritten);
+            assert_eq!(rewritten.matches("color:blue").count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_larger_fold_keeps_more_rules() {
+        let html = (1..=10)
+            .map(|n| format!("<section class=\"block-{}\"><p>Text</p></section>", n))
+            .collect::<String>();
+        let html = format!("<html><body>{}</body></html>", html);
+        let css = (1..=10).map(|n| format!(".block-{}{{padding:{}px}}", n, n)).collect::<String>();
+
+        let (mobile, _) = split_critical_css(&css, &html, 4, MAX_CRITICAL_CSS_BYTES);
+        let (desktop, _) = split_critical_css(&css, &html, 12, MAX_CRITICAL_CSS_BYTES);
+        assert_eq!(mobile.lines().count(), 2);
+        assert_eq!(desktop.lines().count(), 6);
+        assert!(desktop.contains(".block-6{"));
+        assert!(!mobile.contains(".block-3{"));
+    }
+
+    #[tokio::test]
+    async fn test_file_over_lowered_limit_is_skipped() {
+        let css = ".a{color:red}".repeat(100);
+        let size = css.len();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new()
+            .route("/style.css", axum::routing::get(move || async move { css }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let base = format!("http://{}/", addr);
+        let options = OptimizeOptions {
+            max_css_bytes: size - 1,
+            ..OptimizeOptions::default()
+        };
+        let err = optimize_css_file("/style.css", &base, &[], &options).await.unwrap_err();
+        assert!(err.contains("too large"), "got {}", err);
+
+        let options = OptimizeOptions {
+            max_css_bytes: size,
+            ..OptimizeOptions::default()
+        };
+        assert!(optimize_css_file("/style.css", &base, &[], &options).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_css_kept_when_absolute_urls_outgrow_the_original() {
+        let css: String = (0..40).map(|i| format!(".icon-{0} {{ background: url(img/icon-{0}.png) }}\n", i)).collect();
+        let original_size = css.len();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new().route("/css/icons.css", axum::routing::get(move || async move { css }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let optimized = optimize_css_file("/css/icons.css", &format!("http://{}/", addr), &[], &OptimizeOptions::default()).await.unwrap();
+        assert!(optimized.content.contains(&format!("url(\"http://{}/css/img/icon-39.png\")", addr)), "{}", optimized.content);
+        assert!(optimized.optimized_size > original_size, "minifying saved less than absolutizing added");
+    }
+
+    #[tokio::test]
+    async fn test_external_font_faces_swapped_and_pruned() {
+        let fonts = concat!(
+            "/*\n * Self-hosted site fonts. Body Sans is the text face; Old Display was the heading face\n * before the redesign and is still declared for the archive templates.\n */\n",
+            "@font-face {\n    font-family: \"Body Sans\";\n    src: url(/fonts/body.woff2) format(\"woff2\");\n}\n",
+            "@font-face {\n    font-family: \"Old Display\";\n    src: url(/fonts/old.woff2) format(\"woff2\");\n}\n"
+        );
+        let theme = "/* Theme */\nbody {\n    font-family: \"Body Sans\", sans-serif;\n    color: #333333;\n}\n";
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new()
+            .route("/fonts.css", axum::routing::get(move || async move { fonts }))
+            .route("/theme.css", axum::routing::get(move || async move { theme }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let html = r#"<html><head><link rel="stylesheet" href="/fonts.css"><link rel="stylesheet" href="/theme.css"></head><body><p>Hi</p></body></html>"#;
+        let options = OptimizeOptions {
+            remove_unused_fonts: true,
+            font_display_swap: true,
+            min_reduction_percent: 0.0,
+            ..OptimizeOptions::default()
+        };
+        let result = optimize_external_resources(html, &format!("http://{}/", addr), &[], &options, None).await;
+
+        let fonts_file = result.css_files.iter().find(|css| css.original_url == "/fonts.css").unwrap();
+        assert_eq!(fonts_file.content.matches("font-display:swap").count(), 2, "{}", fonts_file.content);
+        assert!(fonts_file.content.contains("Old Display"), "shared file keeps every face");
+        assert!(result.font_warnings.is_empty(), "{:?}", result.font_warnings);
+
+        let combined = result.combined_css.unwrap();
+        assert!(combined.contains("Body Sans"));
+        assert!(!combined.contains("Old Display"), "{}", combined);
+    }
+
+    #[test]
+    fn test_cap_resources_keeps_document_order() {
+        let urls: Vec<String> = ["/a.css", "https://fonts.googleapis.com/css", "/b.css", "/c.css"]
+            .iter().map(|u| u.to_string()).collect();
+        let (kept, skipped) = cap_resources(urls, 2, "CSS");
+        assert_eq!(kept, vec!["/a.css".to_string(), "/b.css".to_string()]);
+        assert_eq!(skipped, 1);
+    }
+
+    #[test]
+    fn test_precompress_brotli_roundtrip() {
+        use std::io::Read;
+
+        let css = (0..100).map(|i| format!(".item-{}{{color:red;margin:{}px}}", i, i)).collect::<String>();
+        let (br, gz) = precompress(&css);
+        let br = br.expect("brotli output present");
+        assert!(gz.is_some());
+        assert!(br.size < css.len());
+
+        let compressed = BASE64.decode(&br.base64).unwrap();
+        let mut decompressed = String::new();
+        brotli::Decompressor::new(compressed.as_slice(), 4096)
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, css);
+
+        assert!(matches!(precompress(".a{color:red}"), (None, None)));
+    }
+
     #[test]
     fn test_basic_js_minify() {
         let js = "// comment\nvar x = 1;\n/* multi\nline */\nvar y = 2;";
         // Verify pass-through for now
-        let minified = basic_js_minify(js);
+        let minified = basic_js_minify(js, &[]);
         assert_eq!(minified, js);
         // assert!(!minified.contains("comment")); // Disabled during pass-through mode
         // assert!(minified.contains("var x"));
     }
+
+    #[test]
+    fn test_strip_js_comments_is_string_aware() {
+        let js = concat!(
+            "/*! lib v1 | MIT */\n",
+            "var url = \"http://example.com/*x*/\"; // trailing\n",
+            "var s = 'it\\'s // not a comment';\n",
+            "var t = `line // kept ${a /* inner */}`;\n",
+            "var re = /\\/\\/[/*]+/g, half = total / 2 /* divide */ / 3;\n",
+            "if (x) return /*a*/ /b\\//.test(y);\n",
+            "a/*\n*/b"
+        );
+        let stripped = strip_js_comments(js, true);
+        assert_eq!(stripped, concat!(
+            "/*! lib v1 | MIT */\n",
+            "var url = \"http://example.com/*x*/\"; \n",
+            "var s = 'it\\'s // not a comment';\n",
+            "var t = `line // kept ${a /* inner */}`;\n",
+            "var re = /\\/\\/[/*]+/g, half = total / 2  / 3;\n",
+            "if (x) return  /b\\//.test(y);\n",
+            "a\nb"
+        ));
+        assert!(!strip_js_comments(js, false).contains("MIT"));
+    }
+
+    #[test]
+    fn test_inline_handler_names_survive_minify() {
+        let html = r#"<button onclick="foo(); return false">Go</button><a onmouseover='menu.open(this, "x")'>M</a>"#;
+        let names = inline_handler_names(html);
+        assert_eq!(names, vec!["foo".to_string(), "menu".to_string()]);
+
+        let js = "function foo() {\n  var message = 'clicked';\n  console.log(message);\n}\nvar menu = { open: function () {} };";
+        let minified = basic_js_minify(js, &names);
+        assert!(contains_identifier(&minified, "foo"), "{}", minified);
+        assert!(contains_identifier(&minified, "menu"), "{}", minified);
+        assert!(!contains_identifier("foobar()", "foo"));
+
+        // A handler name the minifier would rename keeps the whole file unminified
+        let scoped = "(function () {\n  function foo(message) {\n    console.log(message);\n  }\n  foo('loaded');\n})();";
+        assert!(!contains_identifier(&basic_js_minify(scoped, &[]), "foo"), "minify-js renames function-scoped names");
+        assert_eq!(basic_js_minify(scoped, &names), scoped);
+    }
 }
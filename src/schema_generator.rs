@@ -4,22 +4,31 @@
 use scraper::{Html, Selector};
 use serde_json::json;
 
+use crate::handlers::OptimizeOptions;
+
 /// Schema.org result
 pub struct SchemaResult {
     pub schemas_added: Vec<String>,
     pub json_ld: String,
+    /// Schemas skipped or trimmed by validation, with the reason
+    pub warnings: Vec<String>,
 }
 
 /// Generate Schema.org JSON-LD for a page
-pub fn generate_schema(html: &str, url: &str, page_type: &str) -> SchemaResult {
+/// With `use_graph` every item goes into one `@graph`, cross-referenced by `@id`.
+/// `currency` (ISO 4217) overrides the one detected from the price markup
+pub fn generate_schema(html: &str, url: &str, page_type: &str, use_graph: bool, currency: Option<&str>) -> SchemaResult {
     let mut schemas = Vec::new();
     let mut json_ld_items: Vec<serde_json::Value> = Vec::new();
 
-    // Extract page info
+    // Extract page info, preferring the values an SEO plugin already chose for Open Graph
     let doc = Html::parse_document(html);
-    let title = extract_title(&doc);
-    let description = extract_description(&doc);
-    let image = extract_first_image(&doc, url);
+    let open_graph = crate::seo_optimizer::extract_open_graph(html);
+    let title = open_graph.title.unwrap_or_else(|| extract_title(&doc));
+    let description = open_graph.description.unwrap_or_else(|| extract_description(&doc));
+    let image = open_graph.image
+        .or_else(|| crate::image_optimizer::primary_image(&doc, &crate::url_utils::document_base(html, url)))
+        .unwrap_or_default();
 
     match page_type {
         "article" | "post" => {
@@ -28,12 +37,20 @@ pub fn generate_schema(html: &str, url: &str, page_type: &str) -> SchemaResult {
             schemas.push("Article".to_string());
         }
         "product" => {
-            let product_schema = generate_product_schema(&doc, url);
+            let product_schema = generate_product_schema(&doc, url, &description, &image, currency);
             if let Some(schema) = product_schema {
                 json_ld_items.push(schema);
                 schemas.push("Product".to_string());
             }
         }
+        "recipe" => {
+            json_ld_items.push(generate_recipe_schema(&doc, url, &title, &description, &image));
+            schemas.push("Recipe".to_string());
+        }
+        "event" => {
+            json_ld_items.push(generate_event_schema(&doc, url, &title, &description, &image));
+            schemas.push("Event".to_string());
+        }
         _ => {
             // Default: WebPage schema
             let webpage_schema = generate_webpage_schema(&title, &description, url);
@@ -48,8 +65,29 @@ pub fn generate_schema(html: &str, url: &str, page_type: &str) -> SchemaResult {
         schemas.push("BreadcrumbList".to_string());
     }
 
+    // Drop empty values and items missing what their type requires
+    let mut warnings = Vec::new();
+    let (items, names): (Vec<_>, Vec<_>) = json_ld_items.into_iter()
+        .zip(schemas)
+        .filter_map(|(mut item, name)| match validate_schema(&mut item) {
+            Ok(trimmed) => {
+                warnings.extend(trimmed.into_iter().map(|field| format!("{} schema has no {}", name, field)));
+                Some((item, name))
+            }
+            Err(reason) => {
+                warnings.push(format!("{} schema skipped: {}", name, reason));
+                None
+            }
+        })
+        .unzip();
+    let (json_ld_items, schemas) = (items, names);
+
     // Combine all schemas
-    let json_ld = if json_ld_items.len() == 1 {
+    let json_ld = if json_ld_items.is_empty() {
+        String::new()
+    } else if use_graph {
+        serde_json::to_string_pretty(&build_graph(json_ld_items, url)).unwrap_or_default()
+    } else if json_ld_items.len() == 1 {
         serde_json::to_string_pretty(&json_ld_items[0]).unwrap_or_default()
     } else {
         serde_json::to_string_pretty(&json_ld_items).unwrap_or_default()
@@ -58,9 +96,109 @@ pub fn generate_schema(html: &str, url: &str, page_type: &str) -> SchemaResult {
     SchemaResult {
         schemas_added: schemas,
         json_ld,
+        warnings,
+    }
+}
+
+/// Fields a schema type can't be injected without (dotted paths into nested objects)
+fn required_fields(schema_type: &str) -> &'static [&'static str] {
+    match schema_type {
+        "Article" => &["headline"],
+        "Product" => &["name", "offers.price"],
+        "Recipe" | "Event" | "WebPage" => &["name"],
+        "BreadcrumbList" => &["itemListElement"],
+        _ => &[],
+    }
+}
+
+/// Recommended fields reported when missing (the schema is still injected)
+fn recommended_fields(schema_type: &str) -> &'static [&'static str] {
+    match schema_type {
+        "Product" => &["image", "offers.priceCurrency"],
+        "Article" | "Recipe" | "Event" => &["image"],
+        _ => &[],
     }
 }
 
+/// Remove null and empty-string values (recursively) so they aren't flagged by validators,
+/// then check the type's required fields. Ok lists the missing recommended fields
+fn validate_schema(item: &mut serde_json::Value) -> Result<Vec<String>, String> {
+    fn prune(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(object) => {
+                object.values_mut().for_each(prune);
+                object.retain(|_, v| !is_empty_value(v));
+            }
+            serde_json::Value::Array(array) => {
+                array.iter_mut().for_each(prune);
+                array.retain(|v| !is_empty_value(v));
+            }
+            _ => {}
+        }
+    }
+    fn is_empty_value(value: &serde_json::Value) -> bool {
+        match value {
+            serde_json::Value::Null => true,
+            serde_json::Value::String(s) => s.trim().is_empty(),
+            serde_json::Value::Array(a) => a.is_empty(),
+            _ => false,
+        }
+    }
+    let present = |item: &serde_json::Value, path: &str| {
+        path.split('.').try_fold(item, |value, key| value.get(key)).is_some()
+    };
+
+    prune(item);
+    let schema_type = item["@type"].as_str().unwrap_or_default().to_string();
+    if let Some(missing) = required_fields(&schema_type).iter().find(|field| !present(item, field)) {
+        return Err(format!("missing {}", missing));
+    }
+    Ok(recommended_fields(&schema_type).iter()
+        .filter(|field| !present(item, field))
+        .map(|field| field.to_string())
+        .collect())
+}
+
+/// Wrap schema items in a single `@graph`: one `@context`, an `@id` per item, the Article's
+/// author/publisher pointing at a shared Organization node and the WebPage at its breadcrumb
+fn build_graph(items: Vec<serde_json::Value>, url: &str) -> serde_json::Value {
+    let organization_id = url::Url::parse(url)
+        .map(|parsed| format!("{}/#organization", parsed.origin().ascii_serialization()))
+        .unwrap_or_else(|_| format!("{}#organization", url));
+    let breadcrumb_id = format!("{}#breadcrumb", url);
+    let has_breadcrumb = items.iter().any(|item| item["@type"] == "BreadcrumbList");
+
+    let mut graph = Vec::new();
+    for mut item in items {
+        if let Some(object) = item.as_object_mut() {
+            object.remove("@context");
+        }
+        let item_type = item["@type"].as_str().unwrap_or_default().to_string();
+        item["@id"] = json!(match item_type.as_str() {
+            "BreadcrumbList" => breadcrumb_id.clone(),
+            other => format!("{}#{}", url, other.to_lowercase()),
+        });
+        match item_type.as_str() {
+            "Article" => {
+                let name = item["publisher"]["name"].clone();
+                item["author"] = json!({ "@id": organization_id });
+                item["publisher"] = json!({ "@id": organization_id });
+                graph.push(item);
+                graph.push(json!({ "@type": "Organization", "@id": organization_id, "name": name }));
+                continue;
+            }
+            "WebPage" if has_breadcrumb => item["breadcrumb"] = json!({ "@id": breadcrumb_id }),
+            _ => {}
+        }
+        graph.push(item);
+    }
+
+    json!({
+        "@context": "https://schema.org",
+        "@graph": graph
+    })
+}
+
 /// Generate Article schema
 fn generate_article_schema(title: &str, description: &str, url: &str, image: &str) -> serde_json::Value {
     json!({
@@ -93,7 +231,7 @@ fn generate_webpage_schema(title: &str, description: &str, url: &str) -> serde_j
 }
 
 /// Generate Product schema (for WooCommerce)
-fn generate_product_schema(doc: &Html, url: &str) -> Option<serde_json::Value> {
+fn generate_product_schema(doc: &Html, url: &str, description: &str, image: &str, currency: Option<&str>) -> Option<serde_json::Value> {
     // Look for WooCommerce product indicators
     let lower_html = doc.root_element().html().to_lowercase();
     
@@ -103,11 +241,12 @@ fn generate_product_schema(doc: &Html, url: &str) -> Option<serde_json::Value> {
 
     // Extract product info
     let name = extract_product_name(doc).unwrap_or_else(|| extract_title(doc));
-    let price = extract_price(doc);
-    let description = extract_description(doc);
-    let image = extract_first_image(doc, url);
+    // Never guessed: without a request currency or an unambiguous symbol it is left out
+    let currency = currency.map(|c| c.trim().to_ascii_uppercase()).filter(|c| !c.is_empty());
+    let (price, detected_currency) = extract_price(doc, currency.as_deref()).unzip();
+    let currency = currency.or_else(|| detected_currency.flatten().map(str::to_string));
 
-    Some(json!({
+    let mut schema = json!({
         "@context": "https://schema.org",
         "@type": "Product",
         "name": name,
@@ -117,12 +256,132 @@ fn generate_product_schema(doc: &Html, url: &str) -> Option<serde_json::Value> {
         "offers": {
             "@type": "Offer",
             "price": price,
-            "priceCurrency": "USD",
+            "priceCurrency": currency,
             "availability": "https://schema.org/InStock"
         }
+    });
+    if let Some(rating) = extract_aggregate_rating(doc) {
+        schema["aggregateRating"] = rating;
+    }
+    Some(schema)
+}
+
+/// Containers holding the product's own rating; stars elsewhere (related products, review
+/// widgets, individual reviews) rate something else
+const RATING_SCOPES: [&str; 2] = [
+    ".woocommerce-product-rating",
+    "[itemscope][itemtype*=\"schema.org/Product\"] [itemprop=aggregateRating]",
+];
+
+/// `AggregateRating` from the product's microdata (`itemprop="ratingValue"`) or WooCommerce star
+/// markup ("Rated 4.50 out of 5 based on 2 customer ratings"). Only emitted when both the rating
+/// and the number of ratings are on the page: made-up ratings get a page penalized
+fn extract_aggregate_rating(doc: &Html) -> Option<serde_json::Value> {
+    let scoped = |inner: &str| -> Vec<Selector> {
+        RATING_SCOPES.iter()
+            .filter_map(|scope| Selector::parse(&format!("{} {}", scope, inner)).ok())
+            .collect()
+    };
+    let value_of = |selectors: &[&str]| -> Option<f64> {
+        selectors.iter()
+            .flat_map(|inner| scoped(inner))
+            .filter_map(|selector| doc.select(&selector).next())
+            .find_map(|element| {
+                let text = element.value().attr("content")
+                    .map(str::to_string)
+                    .unwrap_or_else(|| element.text().collect());
+                normalize_price(&text, None)?.parse::<f64>().ok()
+            })
+    };
+    // Pieces of the "Rated X out of Y based on N" sentence
+    let star_text = scoped(".star-rating").iter()
+        .filter_map(|selector| doc.select(selector).next())
+        .find_map(|element| {
+            let text = element.text().collect::<Vec<_>>().join(" ").split_whitespace().collect::<Vec<_>>().join(" ");
+            if text.is_empty() { element.value().attr("aria-label").map(str::to_string) } else { Some(text) }
+        })
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let after = |marker: &str| star_text.split_once(marker).and_then(|(_, rest)| normalize_price(rest, None)?.parse::<f64>().ok());
+
+    let rating = value_of(&["[itemprop=ratingValue]", ".star-rating strong.rating"])
+        .or_else(|| normalize_price(star_text.split("out of").next()?, None)?.parse().ok())
+        .filter(|rating| *rating > 0.0)?;
+    let count = value_of(&["[itemprop=reviewCount]", "[itemprop=ratingCount]", ".count"])
+        .or_else(|| after("based on"))
+        .filter(|count| *count >= 1.0)?;
+    let best = value_of(&["[itemprop=bestRating]"]).or_else(|| after("out of")).unwrap_or(5.0);
+    if rating > best {
+        return None;
+    }
+
+    Some(json!({
+        "@type": "AggregateRating",
+        "ratingValue": rating,
+        "bestRating": best,
+        "reviewCount": count as u64,
     }))
 }
 
+/// Generate Recipe schema (WP Recipe Maker, Tasty Recipes and similar cards)
+/// Times that can't be parsed are omitted rather than emitted malformed
+fn generate_recipe_schema(doc: &Html, url: &str, title: &str, description: &str, image: &str) -> serde_json::Value {
+    let name = extract_text(doc, &[".wprm-recipe-name", ".tasty-recipes-title", ".recipe-title"])
+        .unwrap_or_else(|| title.to_string());
+    let ingredients = extract_all_text(doc, ".wprm-recipe-ingredient, .tasty-recipes-ingredients li, [class*='recipe-ingredients'] li");
+
+    let mut schema = json!({
+        "@context": "https://schema.org",
+        "@type": "Recipe",
+        "name": name,
+        "description": description,
+        "image": image,
+        "url": url,
+    });
+    let times = [
+        ("prepTime", [".wprm-recipe-prep_time-container", ".tasty-recipes-prep-time", "[itemprop='prepTime']"]),
+        ("cookTime", [".wprm-recipe-cook_time-container", ".tasty-recipes-cook-time", "[itemprop='cookTime']"]),
+        ("totalTime", [".wprm-recipe-total_time-container", ".tasty-recipes-total-time", "[itemprop='totalTime']"]),
+    ];
+    for (field, selectors) in times {
+        if let Some(duration) = extract_time_value(doc, &selectors).and_then(|text| parse_duration(&text)) {
+            schema[field] = json!(duration);
+        }
+    }
+    if !ingredients.is_empty() {
+        schema["recipeIngredient"] = json!(ingredients);
+    }
+    schema
+}
+
+/// Generate Event schema (The Events Calendar and generic markup)
+fn generate_event_schema(doc: &Html, url: &str, title: &str, description: &str, image: &str) -> serde_json::Value {
+    let name = extract_text(doc, &[".tribe-events-single-event-title", ".event-title"])
+        .unwrap_or_else(|| title.to_string());
+
+    let mut schema = json!({
+        "@context": "https://schema.org",
+        "@type": "Event",
+        "name": name,
+        "description": description,
+        "image": image,
+        "url": url,
+    });
+    let dates = [
+        ("startDate", [".tribe-event-date-start", ".event-start", "[itemprop='startDate']"]),
+        ("endDate", [".tribe-event-date-end", ".event-end", "[itemprop='endDate']"]),
+    ];
+    for (field, selectors) in dates {
+        if let Some(date) = extract_time_value(doc, &selectors).and_then(|text| parse_datetime(&text)) {
+            schema[field] = json!(date);
+        }
+    }
+    if let Some(venue) = extract_text(doc, &[".tribe-venue", ".event-venue", "[itemprop='location']"]) {
+        schema["location"] = json!({ "@type": "Place", "name": venue });
+    }
+    schema
+}
+
 /// Generate BreadcrumbList schema
 fn generate_breadcrumb_schema(doc: &Html, url: &str) -> Option<serde_json::Value> {
     // Look for breadcrumb elements
@@ -184,22 +443,218 @@ fn extract_description(doc: &Html) -> String {
     String::new()
 }
 
-/// Extract first image URL
-fn extract_first_image(doc: &Html, base_url: &str) -> String {
-    if let Ok(selector) = Selector::parse("img[src]") {
-        if let Some(element) = doc.select(&selector).next() {
-            if let Some(src) = element.value().attr("src") {
-                if src.starts_with("http") {
-                    return src.to_string();
-                } else {
-                    // Make absolute
-                    let base = base_url.split('/').take(3).collect::<Vec<_>>().join("/");
-                    return format!("{}{}", base, src);
-                }
+/// Trimmed text of the first non-empty element matching one of `selectors`
+fn extract_text(doc: &Html, selectors: &[&str]) -> Option<String> {
+    selectors.iter()
+        .filter_map(|sel_str| Selector::parse(sel_str).ok())
+        .find_map(|selector| {
+            doc.select(&selector)
+                .map(|element| element.text().collect::<Vec<_>>().join(" ").split_whitespace().collect::<Vec<_>>().join(" "))
+                .find(|text| !text.is_empty())
+        })
+}
+
+/// Trimmed text of every element matching `selector`
+fn extract_all_text(doc: &Html, selector: &str) -> Vec<String> {
+    let Ok(selector) = Selector::parse(selector) else {
+        return Vec::new();
+    };
+    doc.select(&selector)
+        .map(|element| element.text().collect::<Vec<_>>().join(" ").split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|text| !text.is_empty())
+        .collect()
+}
+
+/// Time or date for the first element matching `selectors`: a `datetime`/`content` attribute
+/// (on the element or a `<time>` inside it) wins over the visible text
+fn extract_time_value(doc: &Html, selectors: &[&str]) -> Option<String> {
+    let time = Selector::parse("time[datetime]").ok()?;
+    for sel_str in selectors {
+        let Ok(selector) = Selector::parse(sel_str) else { continue };
+        let Some(element) = doc.select(&selector).next() else { continue };
+        let attribute = element.value().attr("datetime")
+            .or_else(|| element.value().attr("content"))
+            .or_else(|| element.select(&time).next().and_then(|t| t.value().attr("datetime")));
+        if let Some(value) = attribute {
+            return Some(value.trim().to_string());
+        }
+        let text: String = element.text().collect::<Vec<_>>().join(" ");
+        if !text.trim().is_empty() {
+            return Some(text.trim().to_string());
+        }
+    }
+    None
+}
+
+/// Convert a human time string ("15 mins", "1 hour 30 minutes", "1h30m", "PT1H") into an
+/// ISO-8601 duration (`PT1H30M`). Returns None when nothing recognizable is found.
+pub fn parse_duration(text: &str) -> Option<String> {
+    let text = text.trim();
+    let upper = text.to_ascii_uppercase();
+    if upper.starts_with('P') && is_iso_duration(&upper) {
+        return Some(upper);
+    }
+
+    let lower = text.to_lowercase();
+    let mut seconds = 0.0;
+    let mut found = false;
+    let mut rest = lower.as_str();
+    while let Some(start) = rest.find(|c: char| c.is_ascii_digit()) {
+        rest = &rest[start..];
+        let number_end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = rest[number_end..].trim_start_matches(|c: char| c.is_whitespace() || c == '-');
+
+        // "1:30" reads as hours:minutes
+        if let Some(minutes) = rest.strip_prefix(':') {
+            let minutes_end = minutes.find(|c: char| !c.is_ascii_digit()).unwrap_or(minutes.len());
+            let minutes_value: f64 = minutes[..minutes_end].parse().ok()?;
+            seconds += number * 3600.0 + minutes_value * 60.0;
+            found = true;
+            rest = &minutes[minutes_end..];
+            continue;
+        }
+
+        let unit_end = rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len());
+        let unit = &rest[..unit_end];
+        let multiplier = match unit {
+            "d" | "day" | "days" => 86400.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+            _ => continue,
+        };
+        seconds += number * multiplier;
+        found = true;
+        rest = &rest[unit_end..];
+    }
+
+    let total = seconds.round() as u64;
+    if !found || total == 0 {
+        return None;
+    }
+
+    let (days, hours, minutes, secs) = (total / 86400, total % 86400 / 3600, total % 3600 / 60, total % 60);
+    let mut duration = String::from("P");
+    if days > 0 {
+        duration.push_str(&format!("{}D", days));
+    }
+    if hours + minutes + secs > 0 {
+        duration.push('T');
+        for (value, unit) in [(hours, 'H'), (minutes, 'M'), (secs, 'S')] {
+            if value > 0 {
+                duration.push_str(&format!("{}{}", value, unit));
             }
         }
     }
-    String::new()
+    Some(duration)
+}
+
+/// Whether `text` (uppercase) is a well-formed ISO-8601 duration like `P1DT2H30M`
+fn is_iso_duration(text: &str) -> bool {
+    let Some(body) = text.strip_prefix('P') else { return false };
+    let (date, time) = match body.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (body, None),
+    };
+    let valid = |part: &str, units: &str| {
+        let mut last_unit = 0;
+        let mut digits = 0;
+        for c in part.chars() {
+            if c.is_ascii_digit() || c == '.' {
+                digits += 1;
+                continue;
+            }
+            let Some(position) = units.find(c).map(|p| p + 1) else { return false };
+            if digits == 0 || position <= last_unit {
+                return false;
+            }
+            last_unit = position;
+            digits = 0;
+        }
+        digits == 0
+    };
+    valid(date, "YMWD") && time.is_none_or(|t| !t.is_empty() && valid(t, "HMS")) && body != "T" && !body.is_empty()
+}
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+/// Convert a date/time string into ISO-8601 (`2024-05-01` or `2024-05-01T19:30`, keeping any
+/// offset of an ISO input). Accepts ISO values (as found in `<time datetime>`), "May 1, 2024",
+/// "1 May 2024" and an optional "7:30 pm"-style time. Returns None when it can't be parsed.
+pub fn parse_datetime(text: &str) -> Option<String> {
+    let text = text.trim();
+
+    // ISO date, optionally with a time and offset
+    if text.len() >= 10 && text.as_bytes()[4] == b'-' && text.as_bytes()[7] == b'-' {
+        let field = |range: std::ops::Range<usize>| text.get(range)?.parse::<u32>().ok();
+        let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+        let date = format_date(year, month, day)?;
+        let rest = text.get(10..)?.trim_start_matches(['T', ' ']);
+        if rest.is_empty() {
+            return Some(date);
+        }
+        let time_end = rest.find(|c: char| !(c.is_ascii_digit() || c == ':' || c == '.')).unwrap_or(rest.len());
+        let (hour, minute) = parse_clock(&rest[..time_end])?;
+        let offset = &rest[time_end..];
+        let valid_offset = offset.is_empty() || offset == "Z"
+            || (offset.len() == 6 && offset.starts_with(['+', '-']) && offset.as_bytes()[3] == b':');
+        return valid_offset.then(|| format!("{}T{:02}:{:02}{}", date, hour, minute, offset));
+    }
+
+    // English: "May 1, 2024 7:30 pm" or "1 May 2024 19:30"
+    let lower = text.to_lowercase().replace(',', " ");
+    let words: Vec<&str> = lower.split_whitespace().collect();
+    let month_index = words.iter().position(|w| MONTHS.iter().any(|m| w.starts_with(m)))?;
+    let month = MONTHS.iter().position(|m| words[month_index].starts_with(m))? as u32 + 1;
+    let number = |w: &str| w.trim_end_matches(|c: char| c.is_alphabetic()).parse::<u32>().ok();
+    let (day, year, time_start) = match words.get(month_index + 1).and_then(|w| number(w)) {
+        // "May 1 2024"
+        Some(day) if day <= 31 => (day, words.get(month_index + 2).and_then(|w| number(w))?, month_index + 3),
+        // "1 May 2024"
+        _ => (number(words[month_index.checked_sub(1)?])?, words.get(month_index + 1).and_then(|w| number(w))?, month_index + 2),
+    };
+    let date = format_date(year, month, day)?;
+
+    let time_words: Vec<&str> = words[time_start.min(words.len())..].iter().filter(|w| **w != "at" && **w != "@").copied().collect();
+    if time_words.is_empty() {
+        return Some(date);
+    }
+    let time = time_words.join("");
+    let (clock, meridiem) = match (time.strip_suffix("pm"), time.strip_suffix("am")) {
+        (Some(clock), _) => (clock, Some(12)),
+        (_, Some(clock)) => (clock, Some(0)),
+        _ => (time.as_str(), None),
+    };
+    let (mut hour, minute) = if clock.contains(':') { parse_clock(clock)? } else { (clock.parse().ok()?, 0) };
+    if let Some(add) = meridiem {
+        if hour == 0 || hour > 12 {
+            return None;
+        }
+        hour = hour % 12 + add;
+    }
+    (hour < 24).then(|| format!("{}T{:02}:{:02}", date, hour, minute))
+}
+
+/// `YYYY-MM-DD` for a plausible calendar date
+fn format_date(year: u32, month: u32, day: u32) -> Option<String> {
+    let days_in_month = match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    ((1000..=9999).contains(&year) && (1..=days_in_month).contains(&day))
+        .then(|| format!("{:04}-{:02}-{:02}", year, month, day))
+}
+
+/// `HH:MM[:SS]` into (hour, minute)
+fn parse_clock(clock: &str) -> Option<(u32, u32)> {
+    let mut parts = clock.split(':');
+    let hour: u32 = parts.next()?.parse().ok()?;
+    let minute: u32 = parts.next()?.parse().ok()?;
+    (hour < 24 && minute < 60).then_some((hour, minute))
 }
 
 /// Extract product name (WooCommerce)
@@ -224,45 +679,97 @@ fn extract_product_name(doc: &Html) -> Option<String> {
 }
 
 /// Extract price from page
-fn extract_price(doc: &Html) -> String {
+/// Price (normalized to `1234.56`) and the currency its markup shows, if any. The request's
+/// `currency`, else the detected one, decides how separators are read
+fn extract_price(doc: &Html, currency: Option<&str>) -> Option<(String, Option<&'static str>)> {
     let selectors = [
         ".price .amount",
         ".product-price",
         "[class*='price']",
     ];
+    let symbol_selector = Selector::parse(".woocommerce-Price-currencySymbol").ok()?;
 
     for sel_str in selectors {
         if let Ok(selector) = Selector::parse(sel_str) {
             if let Some(element) = doc.select(&selector).next() {
                 let text: String = element.text().collect();
-                // Extract numeric value
-                let price: String = text.chars()
-                    .filter(|c| c.is_ascii_digit() || *c == '.' || *c == ',')
-                    .collect();
-                if !price.is_empty() {
-                    return price.replace(',', "");
+                // WooCommerce wraps the symbol in its own span; otherwise look in the text
+                let detected = element.select(&symbol_selector).next()
+                    .and_then(|symbol| currency_from_text(&symbol.text().collect::<String>()))
+                    .or_else(|| currency_from_text(&text));
+                if let Some(price) = normalize_price(&text, currency.or(detected)) {
+                    return Some((price, detected));
                 }
             }
         }
     }
-    "0".to_string()
+    None
+}
+
+/// The first number in `text` with a `.` decimal point and no grouping, reading
+/// `1,234.56`, `1.234,56`, `19,99` and `1 234,56` alike. A lone `.` before three digits is
+/// grouping for `EUR` (`1.234 €`), whose prices write decimals with a comma
+fn normalize_price(text: &str, currency: Option<&str>) -> Option<String> {
+    let start = text.find(|c: char| c.is_ascii_digit())?;
+    let number: String = text[start..].chars()
+        .take_while(|c| c.is_ascii_digit() || matches!(c, '.' | ',' | ' ' | '\u{a0}' | '\u{202f}'))
+        .filter(|c| c.is_ascii_digit() || matches!(c, '.' | ','))
+        .collect();
+    let number = number.trim_end_matches(['.', ',']);
+
+    // The last separator is the decimal one when both appear; a lone separator is decimal
+    // unless it repeats (`1.234.567`) or is a grouping separator before exactly three digits
+    // (`1,234`, or `1.234` in a decimal-comma currency)
+    let group_separator = if currency == Some("EUR") { '.' } else { ',' };
+    let decimal = match (number.rfind('.'), number.rfind(',')) {
+        (Some(dot), Some(comma)) => Some(dot.max(comma)),
+        (Some(pos), None) | (None, Some(pos)) => {
+            let separator = number.as_bytes()[pos] as char;
+            let repeated = number.matches(separator).count() > 1;
+            let grouping = separator == group_separator && number.len() - pos - 1 == 3;
+            (!repeated && !grouping).then_some(pos)
+        }
+        (None, None) => None,
+    };
+
+    let (whole, fraction) = match decimal {
+        Some(pos) => (&number[..pos], &number[pos + 1..]),
+        None => (number, ""),
+    };
+    let whole: String = whole.chars().filter(char::is_ascii_digit).collect();
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    Some(if fraction.is_empty() { whole } else { format!("{}.{}", whole, fraction) })
+}
+
+/// ISO 4217 code for a currency symbol or code found in `text`. A bare `$` (USD, CAD, AUD...)
+/// and symbols not listed here (`kr`, `zł`) are ambiguous or unknown: None
+fn currency_from_text(text: &str) -> Option<&'static str> {
+    const CURRENCIES: [(&str, &str); 15] = [
+        ("€", "EUR"), ("£", "GBP"), ("¥", "JPY"), ("₹", "INR"), ("CHF", "CHF"),
+        ("EUR", "EUR"), ("GBP", "GBP"), ("USD", "USD"), ("CAD", "CAD"), ("AUD", "AUD"),
+        ("US$", "USD"), ("CA$", "CAD"), ("C$", "CAD"), ("AU$", "AUD"), ("A$", "AUD"),
+    ];
+    CURRENCIES.iter().find(|(symbol, _)| text.contains(symbol)).map(|(_, code)| *code)
 }
 
 /// Add Schema.org JSON-LD to HTML
-pub fn inject_schema(html: &mut String, url: &str) -> usize {
+/// Returns the number of schema types added and validation warnings
+pub fn inject_schema(html: &mut String, url: &str, options: &OptimizeOptions) -> (usize, Vec<String>) {
     // Check if schema already exists
     if html.contains("application/ld+json") {
-        return 0;
+        return (0, Vec::new());
     }
 
-    // Detect page type
-    let page_type = detect_page_type(html);
+    // Explicit type, then URL pattern, then keyword detection
+    let page_type = resolve_page_type(html, url, options);
     
     // Generate schema
-    let result = generate_schema(html, url, &page_type);
+    let result = generate_schema(html, url, &page_type, options.schema_graph, options.currency.as_deref());
     
     if result.json_ld.is_empty() {
-        return 0;
+        return (0, result.warnings);
     }
 
     // Inject before </head>
@@ -275,7 +782,24 @@ pub fn inject_schema(html: &mut String, url: &str) -> usize {
         html.insert_str(pos, &script);
     }
 
-    result.schemas_added.len()
+    (result.schemas_added.len(), result.warnings)
+}
+
+/// Pick the page type: `schema_type` override, matching URL pattern, or detection from HTML
+fn resolve_page_type(html: &str, url: &str, options: &OptimizeOptions) -> String {
+    if let Some(schema_type) = options.schema_type.as_deref().filter(|t| !t.trim().is_empty()) {
+        return schema_type.trim().to_lowercase();
+    }
+
+    let path = url::Url::parse(url)
+        .map(|parsed| parsed.path().to_string())
+        .unwrap_or_else(|_| url.to_string());
+    let pattern_type = options.schema_type_patterns.iter()
+        .filter(|(pattern, _)| crate::url_utils::wildcard_match(pattern, &path))
+        .max_by_key(|(pattern, _)| pattern.len())
+        .map(|(_, page_type)| page_type.to_lowercase());
+
+    pattern_type.unwrap_or_else(|| detect_page_type(html))
 }
 
 /// Detect page type from HTML
@@ -286,6 +810,14 @@ fn detect_page_type(html: &str) -> String {
         return "product".to_string();
     }
     
+    if lower.contains("wprm-recipe") || lower.contains("tasty-recipes") {
+        return "recipe".to_string();
+    }
+
+    if lower.contains("tribe-events") || lower.contains("tribe_events") {
+        return "event".to_string();
+    }
+    
     if lower.contains("hentry") || lower.contains("post-") || lower.contains("article") {
         return "article".to_string();
     }
@@ -303,4 +835,218 @@ mod tests {
         assert!(schema["@type"] == "WebPage");
         assert!(schema["name"] == "Test Page");
     }
+
+    #[test]
+    fn test_schema_type_override() {
+        let html = "<html><head><title>Hello</title></head><body><p>Plain content</p></body></html>";
+        assert_eq!(detect_page_type(html), "page");
+
+        let options = OptimizeOptions {
+            schema_type: Some("article".to_string()),
+            ..OptimizeOptions::default()
+        };
+        let mut forced = html.to_string();
+        inject_schema(&mut forced, "https://example.com/hello/", &options);
+        assert!(forced.contains("\"@type\": \"Article\""));
+
+        let options = OptimizeOptions {
+            schema_type_patterns: [
+                ("/blog/*".to_string(), "page".to_string()),
+                ("/blog/*/reviews/*".to_string(), "article".to_string()),
+            ].into_iter().collect(),
+            ..OptimizeOptions::default()
+        };
+        assert_eq!(resolve_page_type(html, "https://example.com/blog/2024/reviews/phone", &options), "article");
+        assert_eq!(resolve_page_type(html, "https://example.com/blog/hello", &options), "page");
+        assert!(!crate::url_utils::wildcard_match("/shop", "/shop/item"));
+    }
+
+    #[test]
+    fn test_schema_graph_wraps_and_links_items() {
+        let html = r#"<html><head><title>Post</title></head><body><nav class="breadcrumb">Home</nav><article>Text</article></body></html>"#;
+        let url = "https://example.com/blog/post/";
+
+        let result = generate_schema(html, url, "article", true, None);
+        let graph: serde_json::Value = serde_json::from_str(&result.json_ld).unwrap();
+        assert_eq!(graph["@context"], "https://schema.org");
+        let items = graph["@graph"].as_array().unwrap();
+        assert!(items.iter().all(|item| item.get("@context").is_none() && item.get("@id").is_some()));
+
+        let article = items.iter().find(|item| item["@type"] == "Article").unwrap();
+        let organization = items.iter().find(|item| item["@type"] == "Organization").unwrap();
+        assert_eq!(organization["@id"], "https://example.com/#organization");
+        assert_eq!(article["author"]["@id"], organization["@id"]);
+        assert_eq!(article["publisher"]["@id"], organization["@id"]);
+        assert!(items.iter().any(|item| item["@type"] == "BreadcrumbList" && item["@id"] == "https://example.com/blog/post/#breadcrumb"));
+        assert_eq!(result.json_ld.matches("@context").count(), 1);
+
+        let flat = generate_schema(html, url, "article", false, None);
+        let items: serde_json::Value = serde_json::from_str(&flat.json_ld).unwrap();
+        assert!(items.as_array().unwrap().iter().all(|item| item["@context"] == "https://schema.org"));
+    }
+
+    #[test]
+    fn test_parse_duration_phrasings() {
+        assert_eq!(parse_duration("15 mins").as_deref(), Some("PT15M"));
+        assert_eq!(parse_duration("1 hour 30 minutes").as_deref(), Some("PT1H30M"));
+        assert_eq!(parse_duration("1 hr and 5 min").as_deref(), Some("PT1H5M"));
+        assert_eq!(parse_duration("90 minutes").as_deref(), Some("PT1H30M"));
+        assert_eq!(parse_duration("1.5 hours").as_deref(), Some("PT1H30M"));
+        assert_eq!(parse_duration("1h30m").as_deref(), Some("PT1H30M"));
+        assert_eq!(parse_duration("1:15").as_deref(), Some("PT1H15M"));
+        assert_eq!(parse_duration("2 days").as_deref(), Some("P2D"));
+        assert_eq!(parse_duration("PT1H").as_deref(), Some("PT1H"));
+        assert_eq!(parse_duration("pt20m").as_deref(), Some("PT20M"));
+        assert_eq!(parse_duration("a while"), None);
+        assert_eq!(parse_duration("PTXM"), None);
+        assert_eq!(parse_duration("Serves 4"), None);
+    }
+
+    #[test]
+    fn test_parse_datetime_phrasings() {
+        assert_eq!(parse_datetime("2024-05-01").as_deref(), Some("2024-05-01"));
+        assert_eq!(parse_datetime("2024-05-01T19:30:00+02:00").as_deref(), Some("2024-05-01T19:30+02:00"));
+        assert_eq!(parse_datetime("2024-05-01 08:00").as_deref(), Some("2024-05-01T08:00"));
+        assert_eq!(parse_datetime("May 1, 2024").as_deref(), Some("2024-05-01"));
+        assert_eq!(parse_datetime("1 May 2024").as_deref(), Some("2024-05-01"));
+        assert_eq!(parse_datetime("September 14th, 2024 @ 7:30 pm").as_deref(), Some("2024-09-14T19:30"));
+        assert_eq!(parse_datetime("Dec 3 2024 9am").as_deref(), Some("2024-12-03T09:00"));
+        assert_eq!(parse_datetime("February 30, 2024"), None);
+        assert_eq!(parse_datetime("next Tuesday"), None);
+        // Multi-byte characters where the ISO fields would be sliced
+        assert_eq!(parse_datetime("2024-05-0é"), None);
+        assert_eq!(parse_datetime("2024-05-1日 10:00"), None);
+    }
+
+    #[test]
+    fn test_recipe_and_event_times() {
+        let recipe = Html::parse_document(r#"<div class="wprm-recipe">
+            <span class="wprm-recipe-prep_time-container">Prep: 15 mins</span>
+            <span class="wprm-recipe-cook_time-container"><time datetime="PT1H">1 hour</time></span>
+            <span class="wprm-recipe-total_time-container">until done</span>
+            <ul><li class="wprm-recipe-ingredient">2 eggs</li></ul></div>"#);
+        let schema = generate_recipe_schema(&recipe, "https://example.com/r/", "Eggs", "", "");
+        assert_eq!(schema["prepTime"], "PT15M");
+        assert_eq!(schema["cookTime"], "PT1H");
+        assert!(schema.get("totalTime").is_none(), "unparseable times are omitted");
+        assert_eq!(schema["recipeIngredient"][0], "2 eggs");
+
+        let event = Html::parse_document(r#"<div class="tribe-events"><span class="tribe-event-date-start">June 7, 2025 @ 6:00 pm</span></div>"#);
+        let schema = generate_event_schema(&event, "https://example.com/e/", "Gig", "", "");
+        assert_eq!(schema["startDate"], "2025-06-07T18:00");
+        assert!(schema.get("endDate").is_none());
+    }
+
+    #[test]
+    fn test_schema_prefers_existing_open_graph_values() {
+        let html = r#"<html><head><title>Post | Site</title>
+            <meta property="og:title" content="Post">
+            <meta property="og:image" content="https://cdn.example.com/og-share.jpg">
+        </head><body><article><img src="/inline.jpg">Text</article></body></html>"#;
+
+        let result = generate_schema(html, "https://example.com/post/", "article", false, None);
+        let article: serde_json::Value = serde_json::from_str(&result.json_ld).unwrap();
+        assert_eq!(article["image"], "https://cdn.example.com/og-share.jpg");
+        assert_eq!(article["headline"], "Post");
+    }
+
+    #[test]
+    fn test_product_without_price_is_skipped() {
+        let html = r#"<html><head><title>Blue Mug</title></head><body class="woocommerce"><h1 class="product_title">Blue Mug</h1><p>Out of stock</p></body></html>"#;
+        let options = OptimizeOptions {
+            schema_type: Some("product".to_string()),
+            ..OptimizeOptions::default()
+        };
+        let mut page = html.to_string();
+        let (added, warnings) = inject_schema(&mut page, "https://example.com/shop/blue-mug/", &options);
+        assert_eq!(added, 0);
+        assert!(!page.contains("application/ld+json"));
+        assert_eq!(warnings, vec!["Product schema skipped: missing offers.price".to_string()]);
+
+        let priced = html.replace("<p>Out of stock</p>", r#"<p class="price"><span class="amount">$12.50</span></p>"#);
+        let result = generate_schema(&priced, "https://example.com/shop/blue-mug/", "product", false, Some("USD"));
+        let product: serde_json::Value = serde_json::from_str(&result.json_ld).unwrap();
+        assert_eq!(product["offers"]["price"], "12.50");
+        assert!(product.get("description").is_none(), "empty values are dropped");
+        assert_eq!(result.warnings, vec!["Product schema has no image".to_string()]);
+    }
+
+    #[test]
+    fn test_product_aggregate_rating() {
+        let html = r##"<html><head><title>Mug</title></head><body class="woocommerce"><h1 class="product_title">Mug</h1>
+            <div class="woocommerce-product-rating">
+                <div class="star-rating" role="img" aria-label="Rated 4.50 out of 5"><span style="width:90%">Rated <strong class="rating">4.50</strong> out of 5 based on <span class="rating">12</span> customer ratings</span></div>
+                <a href="#reviews" class="woocommerce-review-link">(<span class="count">12</span> customer reviews)</a>
+            </div>
+            <p class="price"><span class="amount">$9.00</span></p></body></html>"##;
+        let result = generate_schema(html, "https://example.com/shop/mug/", "product", false, None);
+        let product: serde_json::Value = serde_json::from_str(&result.json_ld).unwrap();
+        assert_eq!(product["aggregateRating"]["@type"], "AggregateRating");
+        assert_eq!(product["aggregateRating"]["ratingValue"], 4.5);
+        assert_eq!(product["aggregateRating"]["reviewCount"], 12);
+        assert_eq!(product["aggregateRating"]["bestRating"], 5.0);
+
+        // A rating without a count isn't trusted
+        let unrated = r#"<html><body class="woocommerce"><h1 class="product_title">Mug</h1><div class="star-rating"><strong class="rating">5</strong></div><p class="price"><span class="amount">$9.00</span></p></body></html>"#;
+        let result = generate_schema(unrated, "https://example.com/shop/mug/", "product", false, None);
+        assert!(!result.json_ld.contains("aggregateRating"));
+
+        // Stars on related products and single reviews don't rate this product
+        let related = r#"<html><body class="woocommerce"><h1 class="product_title">Mug</h1><p class="price"><span class="amount">$9.00</span></p>
+            <section class="related products"><div class="star-rating" aria-label="Rated 5.00 out of 5"><span>Rated <strong class="rating">5.00</strong> out of 5 based on <span class="rating">3</span> customer ratings</span></div></section>
+            <div itemscope itemtype="https://schema.org/Review"><span itemprop="ratingValue">1</span><span itemprop="reviewCount">1</span></div></body></html>"#;
+        let result = generate_schema(related, "https://example.com/shop/mug/", "product", false, None);
+        assert!(!result.json_ld.contains("aggregateRating"), "{}", result.json_ld);
+
+        // Microdata inside the product's itemscope
+        let microdata = r#"<html><body><div itemscope itemtype="https://schema.org/Product"><h1 class="product_title">Mug</h1><p class="price"><span class="amount">$9.00</span></p>
+            <div itemprop="review" itemscope itemtype="https://schema.org/Review"><span itemprop="ratingValue">2</span></div>
+            <div itemprop="aggregateRating" itemscope itemtype="https://schema.org/AggregateRating"><span itemprop="ratingValue">4.2</span> from <span itemprop="reviewCount">31</span></div></div></body></html>"#;
+        let result = generate_schema(microdata, "https://example.com/shop/mug/", "product", false, Some("USD"));
+        let product: serde_json::Value = serde_json::from_str(&result.json_ld).unwrap();
+        assert_eq!(product["aggregateRating"]["ratingValue"], 4.2);
+        assert_eq!(product["aggregateRating"]["reviewCount"], 31);
+    }
+
+    #[test]
+    fn test_product_currency_and_decimal_comma() {
+        let html = r#"<html><head><title>Tasse</title></head><body class="woocommerce"><h1 class="product_title">Tasse</h1><p class="price"><span class="woocommerce-Price-amount amount"><bdi>19,99&nbsp;<span class="woocommerce-Price-currencySymbol">&euro;</span></bdi></span></p></body></html>"#;
+        let url = "https://example.de/shop/tasse/";
+        let product: serde_json::Value = serde_json::from_str(&generate_schema(html, url, "product", false, None).json_ld).unwrap();
+        assert_eq!(product["offers"]["price"], "19.99");
+        assert_eq!(product["offers"]["priceCurrency"], "EUR");
+
+        // The request's currency wins over the markup
+        let product: serde_json::Value = serde_json::from_str(&generate_schema(html, url, "product", false, Some("gbp")).json_ld).unwrap();
+        assert_eq!(product["offers"]["priceCurrency"], "GBP");
+
+        assert_eq!(normalize_price("£1,234.56", Some("GBP")).as_deref(), Some("1234.56"));
+        assert_eq!(normalize_price("1.234,56 €", Some("EUR")).as_deref(), Some("1234.56"));
+        assert_eq!(normalize_price("$1,234", None).as_deref(), Some("1234"));
+        assert_eq!(normalize_price("1 234,5 kr", None).as_deref(), Some("1234.5"));
+        assert_eq!(normalize_price("Sold out", None), None);
+        // Thousands in a decimal-comma currency; a decimal point elsewhere
+        assert_eq!(normalize_price("1.234 €", Some("EUR")).as_deref(), Some("1234"));
+        assert_eq!(normalize_price("$1.234", None).as_deref(), Some("1.234"));
+    }
+
+    #[test]
+    fn test_ambiguous_currency_is_not_guessed() {
+        assert_eq!(currency_from_text("$19.99"), None);
+        assert_eq!(currency_from_text("C$19.99"), Some("CAD"));
+        assert_eq!(currency_from_text("US$19.99"), Some("USD"));
+        assert_eq!(currency_from_text("199 kr"), None);
+        assert_eq!(currency_from_text("49,99 zł"), None);
+
+        let html = r#"<html><head><title>Mug</title></head><body class="woocommerce"><h1 class="product_title">Mug</h1><p class="price"><span class="woocommerce-Price-amount amount"><bdi><span class="woocommerce-Price-currencySymbol">&#36;</span>19.99</bdi></span></p></body></html>"#;
+        let result = generate_schema(html, "https://example.com/shop/mug/", "product", false, None);
+        let product: serde_json::Value = serde_json::from_str(&result.json_ld).unwrap();
+        assert_eq!(product["offers"]["price"], "19.99");
+        assert!(product["offers"].get("priceCurrency").is_none());
+        assert!(result.warnings.contains(&"Product schema has no offers.priceCurrency".to_string()), "{:?}", result.warnings);
+
+        let result = generate_schema(html, "https://example.com/shop/mug/", "product", false, Some("CAD"));
+        let product: serde_json::Value = serde_json::from_str(&result.json_ld).unwrap();
+        assert_eq!(product["offers"]["priceCurrency"], "CAD");
+    }
 }
@@ -4,12 +4,18 @@
 use scraper::{Html, Selector};
 use std::collections::HashMap;
 
+use crate::changes::Change;
+
 /// SEO analysis result
 pub struct SeoResult {
     pub html: String,
     pub changes: Vec<String>,
     pub warnings: Vec<String>,
     pub score: u8, // 0-100
+    /// Elements deliberately removed (duplicate meta/link tags)
+    pub removed_elements: usize,
+    /// Element-level records of the alt tag and canonical changes
+    pub details: Vec<Change>,
 }
 
 /// SEO Optimizer
@@ -18,6 +24,14 @@ pub struct SeoOptimizer {
     pub site_name: String,
     /// Default OG image
     pub default_og_image: Option<String>,
+    /// Previous page of a paginated archive
+    pub prev_url: Option<String>,
+    /// Next page of a paginated archive
+    pub next_url: Option<String>,
+    /// `twitter:site` handle (the site's account)
+    pub twitter_site: Option<String>,
+    /// `twitter:creator` handle (the author's account)
+    pub twitter_creator: Option<String>,
 }
 
 impl SeoOptimizer {
@@ -25,6 +39,10 @@ impl SeoOptimizer {
         Self {
             site_name: String::new(),
             default_og_image: None,
+            prev_url: None,
+            next_url: None,
+            twitter_site: None,
+            twitter_creator: None,
         }
     }
 
@@ -33,12 +51,20 @@ impl SeoOptimizer {
         let mut optimized = html.to_string();
         let mut changes = Vec::new();
         let mut warnings = Vec::new();
+        let mut details = Vec::new();
+
+        // 0. Drop duplicate head tags first, so the "exists" checks below see one of each
+        let duplicates = remove_duplicate_meta_tags(&mut optimized);
+        if duplicates > 0 {
+            changes.push(format!("{} duplicate meta tags removed", duplicates));
+        }
 
         // 1. Fix images without alt tags
-        let alt_count = add_alt_tags(&mut optimized);
-        if alt_count > 0 {
-            changes.push(format!("{} alt tags added", alt_count));
+        let alts = add_alt_tags(&mut optimized);
+        if !alts.is_empty() {
+            changes.push(format!("{} alt tags added", alts.len()));
         }
+        details.extend(alts);
 
         // 2. Check/add meta description
         let meta_result = ensure_meta_description(&mut optimized);
@@ -55,16 +81,34 @@ impl SeoOptimizer {
             changes.push(format!("{} Open Graph tags added", og_count));
         }
 
-        // 4. Add Twitter Card tags
-        let twitter_count = add_twitter_card_tags(&mut optimized);
+        // 4. Add Twitter Card tags, from the page's (possibly just completed) Open Graph values
+        let open_graph = extract_open_graph(&optimized);
+        let twitter_count = add_twitter_card_tags(
+            &mut optimized,
+            &open_graph,
+            self.twitter_site.as_deref(),
+            self.twitter_creator.as_deref(),
+        );
         if twitter_count > 0 {
             changes.push(format!("{} Twitter Card tags added", twitter_count));
         }
 
-        // 5. Add canonical URL
-        let canonical_added = add_canonical_url(&mut optimized, url);
-        if canonical_added {
+        // 5. Add canonical URL (self-referencing on paginated pages) and rel=prev/next
+        let paginated = self.prev_url.is_some() || self.next_url.is_some();
+        if paginated {
+            let retargeted = point_canonical_at_page(&mut optimized, url);
+            if !retargeted.is_empty() {
+                changes.push("Canonical URL pointed at current page".to_string());
+            }
+            details.extend(retargeted);
+        }
+        let pagination_links = add_pagination_links(&mut optimized, self.prev_url.as_deref(), self.next_url.as_deref());
+        if pagination_links > 0 {
+            changes.push(format!("{} pagination links added", pagination_links));
+        }
+        if let Some(canonical) = add_canonical_url(&mut optimized, url) {
             changes.push("Canonical URL added".to_string());
+            details.push(canonical);
         }
 
         // 6. Fix external links (add rel="noopener")
@@ -73,6 +117,9 @@ impl SeoOptimizer {
             changes.push(format!("{} external links secured", links_fixed));
         }
 
+        // 7. Check heading structure (analysis only)
+        warnings.extend(check_heading_structure(&optimized));
+
         // Calculate SEO score (simplified)
         let score = calculate_seo_score(&optimized);
 
@@ -81,6 +128,8 @@ impl SeoOptimizer {
             changes,
             warnings,
             score,
+            removed_elements: duplicates,
+            details,
         }
     }
 }
@@ -92,9 +141,58 @@ enum MetaResult {
     TooLong,
 }
 
+/// Open Graph properties that may legitimately appear more than once
+const REPEATABLE_OG_PREFIXES: [&str; 4] = ["og:image", "og:video", "og:audio", "og:locale:alternate"];
+
+/// What makes two head tags duplicates of each other, if they are of a deduplicated kind:
+/// canonical links, description/robots meta and (non-repeatable) Open Graph properties
+fn duplicate_key(name: &str, tag: &str) -> Option<String> {
+    match name {
+        "link" => {
+            let rel = crate::html_utils::get_attribute(tag, "rel")?;
+            rel.eq_ignore_ascii_case("canonical").then(|| "canonical".to_string())
+        }
+        "meta" => {
+            let key = crate::html_utils::get_attribute(tag, "property")
+                .or_else(|| crate::html_utils::get_attribute(tag, "name"))?
+                .trim()
+                .to_ascii_lowercase();
+            let og = key.starts_with("og:") && !REPEATABLE_OG_PREFIXES.iter().any(|prefix| key.starts_with(prefix));
+            (key == "description" || key == "robots" || og).then(|| format!("meta:{}", key))
+        }
+        _ => None,
+    }
+}
+
+/// Remove all but the last of each duplicated canonical, description, robots and OG tag
+/// (several SEO plugins on one site each emit their own). Returns the number removed
+fn remove_duplicate_meta_tags(html: &mut String) -> usize {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        if let Some(key) = duplicate_key(name, tag) {
+            *counts.entry(key).or_default() += 1;
+        }
+        None
+    });
+    if counts.values().all(|&count| count < 2) {
+        return 0;
+    }
+
+    let mut removed = 0;
+    *html = crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        let remaining = counts.get_mut(&duplicate_key(name, tag)?)?;
+        *remaining -= 1;
+        (*remaining > 0).then(|| {
+            removed += 1;
+            String::new()
+        })
+    });
+    removed
+}
+
 /// Add alt tags to images that don't have them
-pub fn add_alt_tags(html: &mut String) -> usize {
-    let mut count = 0;
+pub fn add_alt_tags(html: &mut String) -> Vec<Change> {
+    let mut changes = Vec::new();
     let mut result = String::with_capacity(html.len() + 2000);
     let chars: Vec<char> = html.chars().collect();
     let len = chars.len();
@@ -119,8 +217,10 @@ pub fn add_alt_tags(html: &mut String) -> usize {
                     // Extract filename from src for alt text
                     let alt_text = extract_alt_from_src(&img_tag);
                     let new_tag = img_tag.replacen("<img", &format!("<img alt=\"{}\"", alt_text), 1);
-                    result.push_str(&new_tag);
-                    count += 1;
+                    let src = crate::html_utils::get_attribute(&img_tag, "src").unwrap_or_default();
+                    let change = Change::new("alt_added", src, &img_tag, &new_tag);
+                    result.push_str(&change.mark(&new_tag));
+                    changes.push(change);
                     continue;
                 } else {
                     result.push_str(&img_tag);
@@ -134,44 +234,28 @@ pub fn add_alt_tags(html: &mut String) -> usize {
     }
 
     *html = result;
-    count
+    changes
 }
 
 /// Extract a reasonable alt text from image src
 fn extract_alt_from_src(img_tag: &str) -> String {
-    // Try to find src attribute
-    let lower = img_tag.to_lowercase();
-    if let Some(src_start) = lower.find("src=") {
-        let quote_start = src_start + 4;
-        let remaining = &img_tag[quote_start..];
-        
-        // Find the quote character used
-        let quote_char = remaining.chars().next().unwrap_or('"');
-        if quote_char == '"' || quote_char == '\'' {
-            let src_content = &remaining[1..];
-            if let Some(end) = src_content.find(quote_char) {
-                let src = &src_content[..end];
-                
-                // Extract filename without extension
-                if let Some(filename) = src.split('/').last() {
-                    let name = filename
-                        .split('.')
-                        .next()
-                        .unwrap_or("image")
-                        .replace('-', " ")
-                        .replace('_', " ");
-                    
-                    // Capitalize first letter
-                    let mut chars: Vec<char> = name.chars().collect();
-                    if !chars.is_empty() {
-                        chars[0] = chars[0].to_uppercase().next().unwrap_or(chars[0]);
-                    }
-                    return chars.into_iter().collect();
-                }
-            }
+    let src = crate::html_utils::get_attribute(img_tag, "src").unwrap_or_default();
+    let src = crate::url_utils::strip_query(&src);
+    if let Some(filename) = src.split('/').next_back().filter(|f| !f.is_empty()) {
+        let name = filename
+            .split('.')
+            .next()
+            .unwrap_or("image")
+            .replace(['-', '_'], " ");
+
+        // Capitalize first letter
+        let mut chars: Vec<char> = name.chars().collect();
+        if !chars.is_empty() {
+            chars[0] = chars[0].to_uppercase().next().unwrap_or(chars[0]);
+            return chars.into_iter().collect();
         }
     }
-    
+
     "Image".to_string()
 }
 
@@ -179,26 +263,22 @@ fn extract_alt_from_src(img_tag: &str) -> String {
 fn ensure_meta_description(html: &mut String) -> MetaResult {
     let lower = html.to_lowercase();
     
-    // Check if meta description exists
-    if lower.contains("name=\"description\"") || lower.contains("name='description'") {
+    // Check if meta description exists (attributes may be unquoted after minification)
+    let mut existing: Option<String> = None;
+    crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        let is_description = name == "meta"
+            && crate::html_utils::get_attribute(tag, "name").is_some_and(|n| n.trim().eq_ignore_ascii_case("description"));
+        if existing.is_none() && is_description {
+            existing = Some(crate::html_utils::get_attribute(tag, "content").unwrap_or_default());
+        }
+        None
+    });
+    if let Some(desc) = existing {
         // Check length
-        if let Some(start) = lower.find("name=\"description\"") {
-            let remaining = &html[start..];
-            if let Some(content_start) = remaining.to_lowercase().find("content=") {
-                let after_content = &remaining[content_start + 8..];
-                let quote_char = after_content.chars().next().unwrap_or('"');
-                if quote_char == '"' || quote_char == '\'' {
-                    let content = &after_content[1..];
-                    if let Some(end) = content.find(quote_char) {
-                        let desc = &content[..end];
-                        if desc.len() < 120 {
-                            return MetaResult::TooShort;
-                        } else if desc.len() > 160 {
-                            return MetaResult::TooLong;
-                        }
-                    }
-                }
-            }
+        if desc.len() < 120 {
+            return MetaResult::TooShort;
+        } else if desc.len() > 160 {
+            return MetaResult::TooLong;
         }
         return MetaResult::Exists;
     }
@@ -248,6 +328,49 @@ fn generate_description_from_content(html: &str) -> String {
     "".to_string()
 }
 
+/// `og:*` values already on the page (typically from Yoast or Rank Math)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct OpenGraph {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub url: Option<String>,
+    pub og_type: Option<String>,
+    pub site_name: Option<String>,
+}
+
+/// Parse the page's existing Open Graph tags (first non-empty value of each wins)
+/// Accepts `property="og:*"` and the common `name="og:*"` misspelling
+pub fn extract_open_graph(html: &str) -> OpenGraph {
+    let doc = Html::parse_document(html);
+    let mut og = OpenGraph::default();
+    let Ok(selector) = Selector::parse("meta[property], meta[name]") else {
+        return og;
+    };
+
+    for element in doc.select(&selector) {
+        let meta = element.value();
+        let Some(key) = meta.attr("property").or_else(|| meta.attr("name")) else {
+            continue;
+        };
+        let content = match meta.attr("content").map(str::trim) {
+            Some(content) if !content.is_empty() => content.to_string(),
+            _ => continue,
+        };
+        let slot = match key.trim().to_ascii_lowercase().as_str() {
+            "og:title" => &mut og.title,
+            "og:description" => &mut og.description,
+            "og:image" | "og:image:url" | "og:image:secure_url" => &mut og.image,
+            "og:url" => &mut og.url,
+            "og:type" => &mut og.og_type,
+            "og:site_name" => &mut og.site_name,
+            _ => continue,
+        };
+        slot.get_or_insert(content);
+    }
+    og
+}
+
 /// Add Open Graph tags
 fn add_open_graph_tags(html: &mut String, url: &str, site_name: &str) -> usize {
     let lower = html.to_lowercase();
@@ -291,24 +414,12 @@ fn add_open_graph_tags(html: &mut String, url: &str, site_name: &str) -> usize {
         }
     }
 
-    // og:image (from first image)
+    // og:image (from the first content image: logos, icons and tracking pixels make bad previews)
     if !lower.contains("og:image") {
         let doc = Html::parse_document(html);
-        if let Ok(selector) = Selector::parse("img[src]") {
-            if let Some(element) = doc.select(&selector).next() {
-                if let Some(src) = element.value().attr("src") {
-                    // Make absolute URL if relative
-                    let img_url = if src.starts_with("http") {
-                        src.to_string()
-                    } else if let Some(base) = url.split('/').take(3).collect::<Vec<_>>().join("/").into() {
-                        format!("{}{}", base, src)
-                    } else {
-                        src.to_string()
-                    };
-                    og_tags.push_str(&format!("<meta property=\"og:image\" content=\"{}\">\n", img_url));
-                    count += 1;
-                }
-            }
+        if let Some(img_url) = crate::image_optimizer::primary_image(&doc, &crate::url_utils::document_base(html, url)) {
+            og_tags.push_str(&format!("<meta property=\"og:image\" content=\"{}\">\n", img_url));
+            count += 1;
         }
     }
 
@@ -329,20 +440,32 @@ fn add_open_graph_tags(html: &mut String, url: &str, site_name: &str) -> usize {
 }
 
 /// Add Twitter Card tags
-fn add_twitter_card_tags(html: &mut String) -> usize {
+/// Title and description fall back to Open Graph reliably; the image and account handles don't,
+/// so `twitter:image` is copied from `og:image` and the handles come from the request.
+/// The card is a large-image one only when there is an `og:image` to show
+fn add_twitter_card_tags(html: &mut String, open_graph: &OpenGraph, site: Option<&str>, creator: Option<&str>) -> usize {
     let lower = html.to_lowercase();
     let mut count = 0;
     let mut twitter_tags = String::new();
+    let mut add = |name: &str, content: &str| {
+        if !lower.contains(&format!("twitter:{}", name)) {
+            twitter_tags.push_str(&format!(
+                "<meta name=\"twitter:{}\" content=\"{}\">\n",
+                name, content.replace('"', "&quot;")
+            ));
+            count += 1;
+        }
+    };
 
-    // twitter:card
-    if !lower.contains("twitter:card") {
-        twitter_tags.push_str("<meta name=\"twitter:card\" content=\"summary_large_image\">\n");
-        count += 1;
+    let card = if open_graph.image.is_some() { "summary_large_image" } else { "summary" };
+    add("card", card);
+    if let Some(image) = &open_graph.image {
+        add("image", image);
     }
-
-    // twitter:title (inherit from og:title if available)
-    if !lower.contains("twitter:title") && lower.contains("og:title") {
-        // Twitter uses OG fallback, so this is optional
+    for (name, handle) in [("site", site), ("creator", creator)] {
+        if let Some(handle) = handle.map(str::trim).filter(|handle| !handle.trim_start_matches('@').is_empty()) {
+            add(name, &format!("@{}", handle.trim_start_matches('@')));
+        }
     }
 
     // Insert Twitter tags
@@ -356,21 +479,82 @@ fn add_twitter_card_tags(html: &mut String) -> usize {
 }
 
 /// Add canonical URL if missing
-fn add_canonical_url(html: &mut String, url: &str) -> bool {
+fn add_canonical_url(html: &mut String, url: &str) -> Option<Change> {
     let lower = html.to_lowercase();
     
-    if lower.contains("rel=\"canonical\"") || lower.contains("rel='canonical'") {
-        return false;
+    if has_link_rel(html, "canonical") {
+        return None;
     }
 
-    let canonical = format!("<link rel=\"canonical\" href=\"{}\">\n", url);
+    let canonical = format!("<link rel=\"canonical\" href=\"{}\">", url);
     
-    if let Some(pos) = lower.find("</head>") {
-        html.insert_str(pos, &canonical);
-        return true;
+    let pos = lower.find("</head>")?;
+    let change = Change::new("canonical_added", url, "", &canonical);
+    html.insert_str(pos, &format!("{}\n", change.mark(&canonical)));
+    Some(change)
+}
+
+/// Whether the page has a `<link>` with the given `rel` (quoted or not)
+fn has_link_rel(html: &str, rel: &str) -> bool {
+    let mut found = false;
+    crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        found |= name == "link"
+            && crate::html_utils::get_attribute(tag, "rel").is_some_and(|r| r.split_ascii_whitespace().any(|r| r.eq_ignore_ascii_case(rel)));
+        None
+    });
+    found
+}
+
+/// Paginated pages are distinct documents: retarget an existing canonical (often the archive's
+/// first page) at `url`
+fn point_canonical_at_page(html: &mut String, url: &str) -> Vec<Change> {
+    use crate::html_utils::{get_attribute, set_attribute};
+
+    let mut changes = Vec::new();
+    *html = crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        if name != "link" || !get_attribute(tag, "rel").is_some_and(|rel| rel.eq_ignore_ascii_case("canonical")) {
+            return None;
+        }
+        if get_attribute(tag, "href").as_deref() == Some(url) {
+            return None;
+        }
+        let new_tag = set_attribute(tag, "href", url);
+        let change = Change::new("canonical_updated", url, tag, &new_tag);
+        let marked = change.mark(&new_tag);
+        changes.push(change);
+        Some(marked)
+    });
+    changes
+}
+
+/// Add `<link rel="prev">`/`<link rel="next">` for the given URLs unless the page already has them
+fn add_pagination_links(html: &mut String, prev_url: Option<&str>, next_url: Option<&str>) -> usize {
+    let mut existing: Vec<String> = Vec::new();
+    crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        if name == "link" {
+            if let Some(rel) = crate::html_utils::get_attribute(tag, "rel") {
+                existing.push(rel.to_ascii_lowercase());
+            }
+        }
+        None
+    });
+
+    let links: String = [("prev", prev_url), ("next", next_url)].into_iter()
+        .filter_map(|(rel, href)| Some((rel, href?)))
+        .filter(|(rel, _)| !existing.iter().any(|e| e == rel))
+        .map(|(rel, href)| format!("<link rel=\"{}\" href=\"{}\">\n", rel, href))
+        .collect();
+    if links.is_empty() {
+        return 0;
     }
 
-    false
+    match html.to_lowercase().find("</head>") {
+        Some(pos) => {
+            html.insert_str(pos, &links);
+            links.lines().count()
+        }
+        None => 0,
+    }
 }
 
 /// Fix external links to add rel="noopener noreferrer"
@@ -397,18 +581,17 @@ fn fix_external_links(html: &mut String) -> usize {
                 let lower = a_tag.to_lowercase();
                 
                 // Check if external link (has http and target="_blank")
-                let is_external = lower.contains("http") && 
-                    (lower.contains("target=\"_blank\"") || lower.contains("target='_blank'"));
+                let is_external = lower.contains("http") &&
+                    crate::html_utils::get_attribute(&a_tag, "target").is_some_and(|t| t.trim().eq_ignore_ascii_case("_blank"));
                 
                 // Check if already has noopener
                 let has_noopener = lower.contains("noopener");
                 
                 if is_external && !has_noopener {
                     // Add rel="noopener noreferrer"
-                    let new_tag = if lower.contains("rel=") {
+                    let new_tag = if let Some(rel) = crate::html_utils::get_attribute(&a_tag, "rel") {
                         // Append to existing rel
-                        a_tag.replace("rel=\"", "rel=\"noopener noreferrer ")
-                             .replace("rel='", "rel='noopener noreferrer ")
+                        crate::html_utils::set_attribute(&a_tag, "rel", &format!("noopener noreferrer {}", rel.trim()))
                     } else {
                         // Add new rel attribute
                         a_tag.replacen("<a", "<a rel=\"noopener noreferrer\"", 1)
@@ -431,10 +614,40 @@ fn fix_external_links(html: &mut String) -> usize {
     count
 }
 
+/// Check for exactly one H1 and no skipped heading levels (e.g. H2 -> H4)
+fn check_heading_structure(html: &str) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let doc = Html::parse_document(html);
+    let Ok(selector) = Selector::parse("h1, h2, h3, h4, h5, h6") else {
+        return warnings;
+    };
+
+    // Heading levels in document order
+    let levels: Vec<u8> = doc
+        .select(&selector)
+        .filter_map(|el| el.value().name()[1..].parse().ok())
+        .collect();
+
+    match levels.iter().filter(|&&level| level == 1).count() {
+        0 => warnings.push("No H1 heading found".to_string()),
+        1 => {}
+        n => warnings.push(format!("Multiple H1 headings found ({})", n)),
+    }
+
+    for pair in levels.windows(2) {
+        if pair[1] > pair[0] + 1 {
+            warnings.push(format!("Heading level skipped: H{} followed by H{}", pair[0], pair[1]));
+        }
+    }
+
+    warnings
+}
+
 /// Calculate a simple SEO score
 fn calculate_seo_score(html: &str) -> u8 {
     let lower = html.to_lowercase();
     let mut score: u8 = 50; // Base score
+    let doc = Html::parse_document(html);
 
     // Title exists (+10)
     if lower.contains("<title>") && lower.contains("</title>") {
@@ -442,13 +655,17 @@ fn calculate_seo_score(html: &str) -> u8 {
     }
 
     // Meta description (+10)
-    if lower.contains("name=\"description\"") {
+    if Selector::parse("meta[name=description]").is_ok_and(|selector| doc.select(&selector).next().is_some()) {
         score = score.saturating_add(10);
     }
 
-    // H1 tag exists (+10)
-    if lower.contains("<h1") && lower.contains("</h1>") {
-        score = score.saturating_add(10);
+    // Exactly one H1 (+10), multiple H1s (-5)
+    if let Ok(selector) = Selector::parse("h1") {
+        match doc.select(&selector).count() {
+            0 => {}
+            1 => score = score.saturating_add(10),
+            _ => score = score.saturating_sub(5),
+        }
     }
 
     // Open Graph tags (+10)
@@ -457,12 +674,11 @@ fn calculate_seo_score(html: &str) -> u8 {
     }
 
     // Canonical URL (+5)
-    if lower.contains("rel=\"canonical\"") {
+    if has_link_rel(html, "canonical") {
         score = score.saturating_add(5);
     }
 
     // All images have alt (+5)
-    let doc = Html::parse_document(html);
     if let Ok(selector) = Selector::parse("img:not([alt])") {
         if doc.select(&selector).count() == 0 {
             score = score.saturating_add(5);
@@ -483,11 +699,123 @@ mod tests {
         assert_eq!(alt, "Hero banner");
     }
 
+    #[test]
+    fn test_multiple_h1_warning() {
+        let html = "<html><body><h1>One</h1><h2>Sub</h2><h1>Two</h1></body></html>";
+        let warnings = check_heading_structure(html);
+        assert!(warnings.iter().any(|w| w.contains("Multiple H1")));
+
+        let single = "<html><body><h1>One</h1><h2>Sub</h2></body></html>";
+        assert!(check_heading_structure(single).is_empty());
+        assert!(calculate_seo_score(single) > calculate_seo_score(html));
+    }
+
+    #[test]
+    fn test_heading_level_jump_warning() {
+        let html = "<html><body><h1>Title</h1><h2>Section</h2><h4>Detail</h4></body></html>";
+        let warnings = check_heading_structure(html);
+        assert_eq!(warnings, vec!["Heading level skipped: H2 followed by H4".to_string()]);
+    }
+
+    #[test]
+    fn test_pagination_links_and_self_canonical() {
+        let html = r#"<html><head><link rel="canonical" href="https://example.com/blog/"></head><body></body></html>"#;
+        let mut seo = SeoOptimizer::new();
+        seo.prev_url = Some("https://example.com/blog/page/1/".to_string());
+        seo.next_url = Some("https://example.com/blog/page/3/".to_string());
+
+        let mut result = seo.optimize(html, "https://example.com/blog/page/2/");
+        crate::changes::finalize(&mut result.html, &mut result.details);
+        assert!(result.html.contains(r#"<link rel="canonical" href="https://example.com/blog/page/2/">"#));
+        assert!(result.html.contains(r#"<link rel="prev" href="https://example.com/blog/page/1/">"#));
+        assert!(result.html.contains(r#"<link rel="next" href="https://example.com/blog/page/3/">"#));
+        assert!(result.changes.contains(&"2 pagination links added".to_string()));
+
+        // Running again changes nothing
+        let again = seo.optimize(&result.html, "https://example.com/blog/page/2/");
+        assert_eq!(again.html, result.html);
+        assert_eq!(again.html.matches("rel=\"next\"").count(), 1);
+    }
+
+    #[test]
+    fn test_no_pagination_links_without_urls() {
+        let html = r#"<html><head><link rel="canonical" href="https://example.com/"></head><body></body></html>"#;
+        let result = SeoOptimizer::new().optimize(html, "https://example.com/?utm=1");
+        assert!(!result.html.contains("rel=\"prev\"") && !result.html.contains("rel=\"next\""));
+        assert!(result.html.contains(r#"href="https://example.com/">"#));
+    }
+
+    #[test]
+    fn test_og_image_skips_logo() {
+        let mut html = r#"<html><head><title>Post</title></head><body><header><img src="/wp-content/uploads/logo.png" class="custom-logo" alt="Acme"></header><article><img src="/wp-content/uploads/2024/05/garden.jpg" width="1200" height="800"></article></body></html>"#.to_string();
+        add_open_graph_tags(&mut html, "https://example.com/garden/", "");
+        assert!(html.contains(r#"<meta property="og:image" content="https://example.com/wp-content/uploads/2024/05/garden.jpg">"#), "{}", html);
+        assert!(!html.contains("content=\"https://example.com/wp-content/uploads/logo.png\""));
+    }
+
     #[test]
     fn test_add_alt_tags() {
         let mut html = r#"<img src="test.jpg"><img src="other.png" alt="exists">"#.to_string();
-        let count = add_alt_tags(&mut html);
-        assert_eq!(count, 1);
+        let changes = add_alt_tags(&mut html);
+        assert_eq!(changes.len(), 1);
         assert!(html.contains("alt=\"Test\""));
     }
+
+    #[test]
+    fn test_extract_open_graph_from_plugin_tags() {
+        let html = r#"<html><head>
+            <meta property="og:title" content="Plugin Title">
+            <meta property="og:image" content="https://cdn.example.com/share.jpg">
+            <meta property="og:image" content="https://cdn.example.com/second.jpg">
+            <meta name="og:site_name" content="Example">
+            <meta property="og:description" content="">
+        </head><body></body></html>"#;
+        let og = extract_open_graph(html);
+        assert_eq!(og.title.as_deref(), Some("Plugin Title"));
+        assert_eq!(og.image.as_deref(), Some("https://cdn.example.com/share.jpg"));
+        assert_eq!(og.site_name.as_deref(), Some("Example"));
+        assert!(og.description.is_none());
+
+        let mut page = html.to_string();
+        add_twitter_card_tags(&mut page, &og, None, None);
+        assert!(page.contains(r#"content="summary_large_image""#));
+        let mut plain = "<html><head></head></html>".to_string();
+        add_twitter_card_tags(&mut plain, &OpenGraph::default(), None, None);
+        assert!(plain.contains(r#"content="summary""#));
+    }
+
+    #[test]
+    fn test_duplicate_canonical_and_description_removed() {
+        let html = r#"<html><head>
+<link rel="canonical" href="https://example.com/old/">
+<meta name="description" content="First plugin">
+<meta property="og:image" content="https://example.com/a.jpg">
+<meta property="og:image" content="https://example.com/b.jpg">
+<link rel="canonical" href="https://example.com/post/">
+<meta name="description" content="Second plugin">
+</head><body></body></html>"#;
+
+        let result = SeoOptimizer::new().optimize(html, "https://example.com/post/");
+        assert_eq!(result.html.matches("rel=\"canonical\"").count(), 1);
+        assert!(result.html.contains(r#"<link rel="canonical" href="https://example.com/post/">"#));
+        assert_eq!(result.html.matches("name=\"description\"").count(), 1);
+        assert!(result.html.contains("Second plugin"));
+        assert_eq!(result.html.matches("property=\"og:image\"").count(), 2, "og:image may repeat");
+        assert_eq!(result.removed_elements, 2);
+        assert!(result.changes.contains(&"2 duplicate meta tags removed".to_string()));
+    }
+
+    #[test]
+    fn test_twitter_image_and_handles() {
+        let html = r#"<html><head><meta property="og:image" content="https://cdn.example.com/share.jpg"><meta name="twitter:site" content="@existing"></head><body></body></html>"#;
+        let mut seo = SeoOptimizer::new();
+        seo.twitter_site = Some("@ignored".to_string());
+        seo.twitter_creator = Some("author".to_string());
+
+        let result = seo.optimize(html, "https://example.com/post/");
+        assert!(result.html.contains(r#"<meta name="twitter:image" content="https://cdn.example.com/share.jpg">"#));
+        assert!(result.html.contains(r#"<meta name="twitter:creator" content="@author">"#));
+        assert_eq!(result.html.matches("twitter:site").count(), 1, "existing tags are kept");
+        assert!(!result.html.contains("@ignored"));
+    }
 }
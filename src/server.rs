@@ -0,0 +1,155 @@
+//! HTTP Server
+//! Accept loop on hyper's connection builders so HTTP/2 and keep-alive can be tuned
+//! (`axum::serve` exposes none of these knobs)
+
+use axum::{extract::connect_info::IntoMakeServiceWithConnectInfo, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use std::env;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tower::Service;
+
+/// Connection tuning, read from env by `Config::from_env`
+#[derive(Debug, Clone)]
+pub struct ServerSettings {
+    /// Accept HTTP/2 (h2c prior knowledge) alongside HTTP/1.1 - `HTTP2_ENABLED`, default true
+    pub http2: bool,
+    /// Parallel streams per HTTP/2 connection - `HTTP2_MAX_CONCURRENT_STREAMS`, default 256
+    pub http2_max_concurrent_streams: u32,
+    /// HTTP/2 PING interval keeping idle connections open - `HTTP2_KEEPALIVE_INTERVAL_SECS`, default 20 (0 disables)
+    pub http2_keep_alive_interval: Option<Duration>,
+    /// Close the connection when a PING is not acknowledged within this - `HTTP2_KEEPALIVE_TIMEOUT_SECS`, default 20
+    pub http2_keep_alive_timeout: Duration,
+    /// Reuse HTTP/1.1 connections between requests - `HTTP1_KEEPALIVE`, default true
+    pub http1_keep_alive: bool,
+    /// Drop HTTP/1.1 connections that do not send a full request head within this
+    /// (also bounds how long an idle keep-alive connection is held) - `HTTP1_HEADER_READ_TIMEOUT_SECS`, default 30
+    pub http1_header_read_timeout: Duration,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            http2: true,
+            http2_max_concurrent_streams: 256,
+            http2_keep_alive_interval: Some(Duration::from_secs(20)),
+            http2_keep_alive_timeout: Duration::from_secs(20),
+            http1_keep_alive: true,
+            http1_header_read_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ServerSettings {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        let flag = |name: &str, default: bool| match env::var(name).map(|v| v.to_ascii_lowercase()).as_deref() {
+            Ok("1") | Ok("true") | Ok("yes") => true,
+            Ok("0") | Ok("false") | Ok("no") => false,
+            _ => default,
+        };
+
+        Self {
+            http2: flag("HTTP2_ENABLED", defaults.http2),
+            http2_max_concurrent_streams: var("HTTP2_MAX_CONCURRENT_STREAMS")
+                .map(|n| n as u32)
+                .unwrap_or(defaults.http2_max_concurrent_streams),
+            http2_keep_alive_interval: match var("HTTP2_KEEPALIVE_INTERVAL_SECS") {
+                Some(0) => None,
+                Some(secs) => Some(Duration::from_secs(secs)),
+                None => defaults.http2_keep_alive_interval,
+            },
+            http2_keep_alive_timeout: var("HTTP2_KEEPALIVE_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.http2_keep_alive_timeout),
+            http1_keep_alive: flag("HTTP1_KEEPALIVE", defaults.http1_keep_alive),
+            http1_header_read_timeout: var("HTTP1_HEADER_READ_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.http1_header_read_timeout),
+        }
+    }
+}
+
+/// Serve `app` on `listener` until the process exits
+/// Handlers can extract `ConnectInfo<SocketAddr>` as with `into_make_service_with_connect_info`.
+pub async fn serve(listener: TcpListener, app: Router, settings: ServerSettings) {
+    let mut make_service: IntoMakeServiceWithConnectInfo<Router, SocketAddr> =
+        app.into_make_service_with_connect_info::<SocketAddr>();
+
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder.http1()
+        .keep_alive(settings.http1_keep_alive)
+        .timer(TokioTimer::new())
+        .header_read_timeout(settings.http1_header_read_timeout);
+    builder.http2()
+        .max_concurrent_streams(settings.http2_max_concurrent_streams)
+        .keep_alive_interval(settings.http2_keep_alive_interval)
+        .keep_alive_timeout(settings.http2_keep_alive_timeout)
+        .timer(TokioTimer::new());
+
+    loop {
+        let (stream, remote_addr) = match listener.accept().await {
+            Ok(conn) => conn,
+            Err(e) => {
+                // Usually EMFILE - back off instead of spinning
+                tracing::warn!(error = %e, "Failed to accept connection");
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+
+        let tower_service = match make_service.call(remote_addr).await {
+            Ok(service) => service,
+            Err(infallible) => match infallible {},
+        };
+        let service = TowerToHyperService::new(tower_service);
+        let io = TokioIo::new(stream);
+        let builder = builder.clone();
+        let http2 = settings.http2;
+
+        tokio::spawn(async move {
+            // Upgrades are needed for the WebSocket endpoint
+            let result = if http2 {
+                builder.serve_connection_with_upgrades(io, service).await
+            } else {
+                let mut http1 = hyper::server::conn::http1::Builder::new();
+                http1.keep_alive(settings.http1_keep_alive)
+                    .timer(TokioTimer::new())
+                    .header_read_timeout(settings.http1_header_read_timeout);
+                http1.serve_connection(io, service).with_upgrades().await.map_err(Into::into)
+            };
+            if let Err(e) = result {
+                tracing::debug!(error = %e, remote_addr = %remote_addr, "Connection closed with error");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serves_http1_clients() {
+        let app = Router::new().route("/health", axum::routing::get(|| async { "ok" }));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, app, ServerSettings::default()));
+
+        // Two requests on one client exercise keep-alive reuse
+        let client = reqwest::Client::new();
+        for _ in 0..2 {
+            let response = client.get(format!("http://{}/health", addr)).send().await.unwrap();
+            assert_eq!(response.version(), reqwest::Version::HTTP_11);
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.text().await.unwrap(), "ok");
+        }
+    }
+}
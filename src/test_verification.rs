@@ -34,6 +34,7 @@ mod tests {
             defer_js: false,
             lazy_images: false,
             optimize_resources: false,
+            ..OptimizeOptions::default()
         };
 
         let result = optimizer::optimize_html(html_input, "http://localhost", &options).expect("Optimization failed");
@@ -58,4 +59,25 @@ mod tests {
         // src=https://example.com/script.js is safe.
         assert!(optimized.contains("src=https://example.com/script.js") || optimized.contains("src=\"https://example.com/script.js\""), "Script src should be present");
     }
+
+    #[test]
+    fn test_fragment_gets_no_head_content() {
+        let fragment = r#"<div class="widget"><img src="/uploads/team-photo.jpg"><script src="/widget.js"></script></div>"#;
+        let options = OptimizeOptions {
+            fragment: true,
+            ..OptimizeOptions::default()
+        };
+
+        let result = optimizer::optimize_html(fragment, "https://example.com/", &options).expect("Optimization failed");
+        let optimized = result.html;
+
+        assert!(!optimized.contains("<head"), "Fragment must not gain a <head>");
+        assert!(!optimized.contains("og:"), "No Open Graph tags in fragments");
+        assert!(!optimized.contains("canonical"), "No canonical in fragments");
+        assert!(!optimized.contains("application/ld+json"), "No schema in fragments");
+        assert!(!optimized.contains("name=\"description\""), "No meta description in fragments");
+        assert!(optimized.contains("alt="), "Alt tags still added");
+        assert!(optimized.contains("loading=\"lazy\""), "Images still lazy-loaded");
+        assert!(optimized.contains("<script defer"), "Scripts still deferred");
+    }
 }
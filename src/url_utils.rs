@@ -0,0 +1,664 @@
+//! URL Utilities
+//! Shared helpers for resolving and normalizing asset URLs
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use url::Url;
+
+/// Resolve a (possibly relative) URL against the page URL
+/// Handles subdirectory installs (`example.com/blog/`), root-relative and protocol-relative URLs
+pub fn resolve_url(base_url: &str, url: &str) -> Result<String, String> {
+    let base = Url::parse(base_url)
+        .map_err(|e| format!("Invalid base URL '{}': {}", base_url, e))?;
+    base.join(url)
+        .map(|resolved| resolved.to_string())
+        .map_err(|e| format!("Failed to resolve '{}' against '{}': {}", url, base_url, e))
+}
+
+/// Base the document's relative URLs resolve against: its first `<base href>` (itself
+/// relative to the page), else the page URL
+pub fn document_base(html: &str, page_url: &str) -> String {
+    if !html.to_ascii_lowercase().contains("<base") {
+        return page_url.to_string();
+    }
+    let mut href = None;
+    crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        if href.is_none() && name == "base" {
+            href = crate::html_utils::get_attribute(tag, "href").filter(|href| !href.trim().is_empty());
+        }
+        None
+    });
+    href.and_then(|href| resolve_url(page_url, href.trim()).ok())
+        .unwrap_or_else(|| page_url.to_string())
+}
+
+/// Expand `{name}` placeholders in an asset path template
+/// Unknown placeholders are left untouched so templates can be expanded in stages
+pub fn expand_path_template(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut expanded = template.to_string();
+    for (name, value) in vars {
+        expanded = expanded.replace(&format!("{{{}}}", name), value.trim_end_matches('/'));
+    }
+    expanded
+}
+
+/// Strip the query string and fragment from a URL
+pub fn strip_query(url: &str) -> &str {
+    url.split(['?', '#']).next().unwrap_or(url)
+}
+
+/// A URL without its fragment and `ver` cache-buster (WordPress appends `?ver=6.4` to enqueued
+/// assets); every other query parameter, such as `?w=300`, is kept in order
+pub fn strip_version_query(url: &str) -> String {
+    let url = url.split('#').next().unwrap_or(url);
+    let Some((path, query)) = url.split_once('?') else {
+        return url.to_string();
+    };
+    let kept: Vec<&str> = query.split('&')
+        .filter(|param| !param.is_empty() && !param.split('=').next().unwrap_or_default().eq_ignore_ascii_case("ver"))
+        .collect();
+    if kept.is_empty() {
+        path.to_string()
+    } else {
+        format!("{}?{}", path, kept.join("&"))
+    }
+}
+
+/// How generated asset filenames are derived
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FilenameScheme {
+    /// Hash of the (normalized) source URL: one name per asset, replaced in place when it changes
+    #[default]
+    UrlHash,
+    /// Hash of the generated content: same content, same name, so files can be cached forever
+    ContentHash,
+}
+
+/// 64-bit FNV-1a hash
+/// Fixed by the algorithm, unlike `DefaultHasher`, so filenames survive toolchain upgrades
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Hash-based filename for a generated asset
+/// With `normalize`, `ver` cache-busters are ignored so versioned URLs share a URL-hash filename
+pub fn asset_filename(url: &str, content: &[u8], extension: &str, scheme: FilenameScheme, normalize: bool) -> String {
+    let hash = match scheme {
+        FilenameScheme::ContentHash => stable_hash(content),
+        FilenameScheme::UrlHash if normalize => stable_hash(normalize_asset_url(url).as_bytes()),
+        FilenameScheme::UrlHash => stable_hash(url.as_bytes()),
+    };
+    format!("{:016x}.{}", hash, extension)
+}
+
+/// Match `text` against a pattern where `*` matches any run of characters
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard: exact match
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// Normalize an asset URL for hashing: drop the `ver` cache-buster and fragment and lowercase the
+/// host, so `style.css?ver=6.4` and `style.css?ver=6.5` map to the same asset while size and
+/// format parameters (`photo.jpg?w=300`) keep naming distinct files
+pub fn normalize_asset_url(url: &str) -> String {
+    let url = strip_version_query(url);
+    // Protocol-relative URLs need a scheme to parse
+    let (parse_target, protocol_relative) = if url.starts_with("//") {
+        (format!("https:{}", url), true)
+    } else {
+        (url.clone(), false)
+    };
+
+    match Url::parse(&parse_target) {
+        Ok(parsed) => {
+            let normalized = parsed.to_string();
+            if protocol_relative {
+                normalized.trim_start_matches("https:").to_string()
+            } else {
+                normalized
+            }
+        }
+        // Relative URL - nothing to lowercase
+        Err(_) => url,
+    }
+}
+
+/// What a page loads from a host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HostUsage {
+    Script,
+    Style,
+    Image,
+    Font,
+    Other,
+}
+
+/// A third-party host the page contacts and what it loads from it
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct ExternalHost {
+    pub host: String,
+    pub usage: Vec<HostUsage>,
+}
+
+/// Absolute (`https://...`) and protocol-relative (`//host/...`) URLs written as string
+/// literals in script source, such as the loader URL of an inline tracking snippet
+pub fn script_urls(js: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut rest = js;
+    while let Some(open) = rest.find(['"', '\'', '`']) {
+        let quote = rest.as_bytes()[open];
+        let body = &rest[open + 1..];
+        // The matching quote, skipping escaped ones
+        let mut escaped = false;
+        let Some(close) = body.bytes().position(|b| {
+            let closes = b == quote && !escaped;
+            escaped = b == b'\\' && !escaped;
+            closes
+        }) else {
+            break;
+        };
+        let literal = &body[..close];
+        let scheme = literal.get(..8).unwrap_or(literal).to_ascii_lowercase();
+        let is_url = scheme.starts_with("https://") || scheme.starts_with("http://") || literal.starts_with("//");
+        if is_url && literal.len() > 2 && !literal.contains(char::is_whitespace) {
+            urls.push(literal);
+        }
+        rest = &body[close + 1..];
+    }
+    urls
+}
+
+/// Every host other than the page's own that the markup loads from: `src`/`href`/`srcset`
+/// of subresources, `url()` in inline CSS and URLs quoted in inline scripts (`<a>` links
+/// aren't contacted). In first-seen order
+pub fn external_hosts(html: &str, page_url: &str) -> Vec<ExternalHost> {
+    use crate::html_utils::get_attribute;
+
+    let base = Url::parse(&document_base(html, page_url)).ok();
+    let page_host = Url::parse(page_url).ok().and_then(|u| u.host_str().map(str::to_ascii_lowercase));
+    let mut hosts: Vec<ExternalHost> = Vec::new();
+    let mut add = |url: &str, usage: HostUsage| {
+        let url = crate::html_utils::decode_entities(url.trim());
+        let Some(host) = base.as_ref()
+            .and_then(|base| base.join(&url).ok())
+            .filter(|u| matches!(u.scheme(), "http" | "https"))
+            .and_then(|u| u.host_str().map(str::to_ascii_lowercase))
+        else {
+            return;
+        };
+        if page_host.as_deref() == Some(host.as_str()) {
+            return;
+        }
+        match hosts.iter_mut().find(|h| h.host == host) {
+            Some(entry) if !entry.usage.contains(&usage) => {
+                entry.usage.push(usage);
+                entry.usage.sort();
+            }
+            Some(_) => {}
+            None => hosts.push(ExternalHost { host, usage: vec![usage] }),
+        }
+    };
+    let css_usage = |url: &str| match strip_query(url).rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).as_deref() {
+        Some("woff2" | "woff" | "ttf" | "otf" | "eot") => HostUsage::Font,
+        Some("css") => HostUsage::Style,
+        _ => HostUsage::Image,
+    };
+
+    let mut css_blocks = Vec::new();
+    crate::html_utils::rewrite_start_tags(html, |name, tag| {
+        let attr = |name: &str| get_attribute(tag, name).filter(|value| !value.trim().is_empty());
+        match name {
+            "script" => {
+                if let Some(src) = attr("src") {
+                    add(&src, HostUsage::Script);
+                }
+            }
+            "link" => {
+                let rel = attr("rel").unwrap_or_default().to_ascii_lowercase();
+                let usage = match attr("as").unwrap_or_default().to_ascii_lowercase().as_str() {
+                    "script" => HostUsage::Script,
+                    "style" => HostUsage::Style,
+                    "image" => HostUsage::Image,
+                    "font" => HostUsage::Font,
+                    _ if rel.contains("stylesheet") => HostUsage::Style,
+                    _ if rel.contains("icon") => HostUsage::Image,
+                    _ if rel.contains("modulepreload") => HostUsage::Script,
+                    _ => HostUsage::Other,
+                };
+                // Page relations (canonical, alternate, prev/next...) are never fetched
+                let fetched = ["stylesheet", "icon", "preload", "prefetch", "preconnect", "dns-prefetch", "modulepreload", "manifest"]
+                    .iter()
+                    .any(|kind| rel.split_ascii_whitespace().any(|r| r == *kind || r.ends_with(kind)));
+                if let Some(href) = attr("href").filter(|_| fetched) {
+                    add(&href, usage);
+                }
+            }
+            "img" | "source" => {
+                for name in ["src", "data-src"] {
+                    if let Some(src) = attr(name) {
+                        add(&src, HostUsage::Image);
+                    }
+                }
+                for name in ["srcset", "data-srcset"] {
+                    for (url, _) in crate::webp_converter::parse_srcset(&attr(name).unwrap_or_default()) {
+                        if !url.starts_with("data:") {
+                            add(url, HostUsage::Image);
+                        }
+                    }
+                }
+            }
+            "iframe" | "video" | "audio" | "embed" | "track" => {
+                if let Some(src) = attr("src") {
+                    add(&src, HostUsage::Other);
+                }
+                if let Some(poster) = attr("poster") {
+                    add(&poster, HostUsage::Image);
+                }
+            }
+            "object" => {
+                if let Some(data) = attr("data") {
+                    add(&data, HostUsage::Other);
+                }
+            }
+            _ => {}
+        }
+        if let Some(style) = attr("style") {
+            css_blocks.push(style);
+        }
+        None
+    });
+    crate::html_utils::rewrite_raw_text(html, "style", |_, css| {
+        css_blocks.push(css.to_string());
+        None
+    });
+    // Loader snippets (pixels, tag managers) build their script URL inline
+    crate::html_utils::rewrite_raw_text(html, "script", |tag, js| {
+        let script_type = get_attribute(tag, "type");
+        if crate::html_utils::is_classic_script_type(script_type.as_deref())
+            || script_type.is_some_and(|t| t.trim().eq_ignore_ascii_case("module"))
+        {
+            for url in script_urls(js) {
+                let usage = match strip_query(url).rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()).as_deref() {
+                    Some("js" | "mjs") => HostUsage::Script,
+                    _ if crate::image_optimizer::image_extension(url).is_some() => HostUsage::Image,
+                    _ => HostUsage::Other,
+                };
+                add(url, usage);
+            }
+        }
+        None
+    });
+
+    for css in &css_blocks {
+        let lower = css.to_ascii_lowercase();
+        let mut pos = 0;
+        while let Some(offset) = lower[pos..].find("url(") {
+            let start = pos + offset + 4;
+            let Some(end) = css[start..].find(')').map(|e| start + e) else { break };
+            let url = css[start..end].trim().trim_matches(|c| c == '"' || c == '\'');
+            if !url.starts_with("data:") {
+                add(url, css_usage(url));
+            }
+            pos = end;
+        }
+        // `@import "..."` without url()
+        let mut pos = 0;
+        while let Some(offset) = lower[pos..].find("@import") {
+            let start = pos + offset + 7;
+            let rest = css[start..].trim_start();
+            if let Some(quote) = rest.chars().next().filter(|c| *c == '"' || *c == '\'') {
+                if let Some(url) = rest[1..].split(quote).next() {
+                    add(url, HostUsage::Style);
+                }
+            }
+            pos = start;
+        }
+    }
+
+    hosts
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Download clients built on this thread, so tests can assert a path stays offline
+    pub static DOWNLOAD_CLIENTS_BUILT: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// HTTP client for fetching page assets (images, CSS, JS)
+pub fn download_client() -> Result<reqwest::Client, String> {
+    #[cfg(test)]
+    DOWNLOAD_CLIENTS_BUILT.with(|built| built.set(built.get() + 1));
+
+    reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))
+}
+
+/// A downloaded asset body and its `Content-Type`
+#[derive(Clone, Debug)]
+pub struct Fetched {
+    pub body: Arc<[u8]>,
+    pub content_type: Option<String>,
+}
+
+/// Download `url` (crawl delay and download cap applied)
+pub async fn fetch(url: &str, user_agent: &str) -> Result<Fetched, String> {
+    let client = download_client()?;
+    crawl_delay(url).await;
+    let _permit = download_permit().await;
+
+    let response = client
+        .get(url)
+        .header("User-Agent", user_agent)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to download resource: {}", e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}: {}", response.status(), url));
+    }
+
+    let content_type = response.headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| format!("Failed to read response: {}", e))?;
+    Ok(Fetched { body: Arc::from(&body[..]), content_type })
+}
+
+type SharedFetch = Arc<tokio::sync::OnceCell<Result<Fetched, String>>>;
+
+/// Downloads shared by the pages of one bulk request, so the CSS, JS and fonts every page of a
+/// site links are fetched once per batch instead of once per page. Dropped with the batch
+#[derive(Clone, Default)]
+pub struct BatchDownloads {
+    responses: Arc<Mutex<HashMap<String, SharedFetch>>>,
+}
+
+tokio::task_local! {
+    static BATCH_DOWNLOADS: BatchDownloads;
+}
+
+impl BatchDownloads {
+    /// Run `page` with its `fetch_shared` downloads going through this batch
+    pub fn scope<F: std::future::Future>(&self, page: F) -> tokio::task::futures::TaskLocalFuture<BatchDownloads, F> {
+        BATCH_DOWNLOADS.scope(self.clone(), page)
+    }
+}
+
+/// `fetch`, reusing the response (or error) of an earlier or in-flight download of the same URL
+/// when running inside `BatchDownloads::scope`
+pub async fn fetch_shared(url: &str, user_agent: &str) -> Result<Fetched, String> {
+    let shared = BATCH_DOWNLOADS.try_with(|batch| {
+        let mut responses = batch.responses.lock().unwrap_or_else(|e| e.into_inner());
+        responses.entry(url.to_string()).or_default().clone()
+    });
+    match shared {
+        Ok(cell) => cell.get_or_init(|| fetch(url, user_agent)).await.clone(),
+        Err(_) => fetch(url, user_agent).await,
+    }
+}
+
+/// Minimum spacing between downloads from the same host
+/// Each host has its own gate, so a slow origin never holds up requests to other hosts
+#[derive(Debug, Default)]
+pub struct HostThrottle {
+    delay: Duration,
+    /// Host -> when its last download was let through
+    hosts: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<Instant>>>>>,
+}
+
+impl HostThrottle {
+    pub fn new(delay: Duration) -> Self {
+        Self { delay, hosts: Mutex::new(HashMap::new()) }
+    }
+
+    /// Wait until a download from `url`'s host is allowed
+    pub async fn wait(&self, url: &str) {
+        if self.delay.is_zero() {
+            return;
+        }
+        let Some(host) = Url::parse(url).ok().and_then(|u| u.host_str().map(str::to_ascii_lowercase)) else {
+            return;
+        };
+
+        let gate = {
+            let mut hosts = self.hosts.lock().unwrap_or_else(|e| e.into_inner());
+            hosts.entry(host).or_default().clone()
+        };
+        let mut last = gate.lock().await;
+        if let Some(ready_at) = last.map(|at| at + self.delay) {
+            tokio::time::sleep_until(ready_at.into()).await;
+        }
+        *last = Some(Instant::now());
+    }
+}
+
+static CRAWL_DELAY: OnceLock<HostThrottle> = OnceLock::new();
+
+/// Install the process-wide per-host download delay (CRAWL_DELAY_MS); first call wins
+pub fn set_crawl_delay(delay: Duration) {
+    let _ = CRAWL_DELAY.set(HostThrottle::new(delay));
+}
+
+/// Wait for the per-host crawl delay before downloading `url` (no-op unless configured)
+pub async fn crawl_delay(url: &str) {
+    if let Some(throttle) = CRAWL_DELAY.get() {
+        throttle.wait(url).await;
+    }
+}
+
+/// Cap on concurrent outbound asset downloads, shared by images, CSS and JS
+#[derive(Clone, Debug)]
+pub struct DownloadLimiter {
+    /// `None` when unlimited
+    permits: Option<Arc<tokio::sync::Semaphore>>,
+    max: usize,
+}
+
+/// Held for the duration of one download
+pub struct DownloadPermit {
+    _permit: Option<tokio::sync::OwnedSemaphorePermit>,
+}
+
+impl Drop for DownloadPermit {
+    fn drop(&mut self) {
+        crate::metrics::record_download_finished();
+    }
+}
+
+impl DownloadLimiter {
+    /// `max_downloads == 0` disables the limit
+    pub fn new(max_downloads: usize) -> Self {
+        Self {
+            permits: (max_downloads > 0).then(|| Arc::new(tokio::sync::Semaphore::new(max_downloads))),
+            max: max_downloads,
+        }
+    }
+
+    /// Configured limit (0 when unlimited)
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Wait for a free download slot
+    pub async fn acquire(&self) -> DownloadPermit {
+        let permit = match &self.permits {
+            // The semaphore is never closed
+            Some(permits) => permits.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        crate::metrics::record_download_started();
+        DownloadPermit { _permit: permit }
+    }
+}
+
+static DOWNLOAD_LIMITER: OnceLock<DownloadLimiter> = OnceLock::new();
+
+/// Install the process-wide download limiter (MAX_CONCURRENT_DOWNLOADS); first call wins
+pub fn set_download_limiter(limiter: DownloadLimiter) {
+    crate::metrics::record_download_limit(limiter.max());
+    let _ = DOWNLOAD_LIMITER.set(limiter);
+}
+
+/// Take a download slot from the process-wide limiter (unlimited unless configured)
+pub async fn download_permit() -> DownloadPermit {
+    match DOWNLOAD_LIMITER.get() {
+        Some(limiter) => limiter.acquire().await,
+        None => DownloadLimiter::new(0).acquire().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_hosts_lists_tracker_pixel() {
+        let html = r#"<html><head>
+            <link rel="stylesheet" href="https://fonts.googleapis.com/css2?family=Inter">
+            <link rel="canonical" href="https://elsewhere.example/">
+            <style>@font-face{src:url(https://fonts.gstatic.com/s/inter.woff2)}.hero{background:url('//cdn.example.net/hero.jpg')}</style>
+            <script src="/wp-includes/js/jquery.js"></script>
+            <script>!function(f,b,e,v,n,t,s){t=b.createElement(e);t.src=v}(window,document,'script','https://connect.facebook.net/en_US/fbevents.js');</script>
+        </head><body>
+            <noscript><img height="1" width="1" src="https://www.facebook.com/tr?id=1&amp;ev=PageView"></noscript>
+            <img src="https://example.com/logo.png" srcset="https://cdn.example.net/logo-2x.png 2x">
+            <a href="https://twitter.com/example">Follow</a>
+            <script type="application/ld+json">{"@id": "https://schema.org/WebPage"}</script>
+        </body></html>"#;
+        let hosts = external_hosts(html, "https://example.com/post/");
+        let usage = |host: &str| hosts.iter().find(|h| h.host == host).map(|h| h.usage.clone());
+
+        assert_eq!(usage("connect.facebook.net"), Some(vec![HostUsage::Script]));
+        assert_eq!(usage("www.facebook.com"), Some(vec![HostUsage::Image]));
+        assert_eq!(usage("fonts.googleapis.com"), Some(vec![HostUsage::Style]));
+        assert_eq!(usage("fonts.gstatic.com"), Some(vec![HostUsage::Font]));
+        assert_eq!(usage("cdn.example.net"), Some(vec![HostUsage::Image]));
+        assert_eq!(hosts.len(), 5, "own host, links, canonical and JSON-LD aren't contacted: {:?}", hosts);
+    }
+
+    #[test]
+    fn test_script_urls_finds_quoted_urls() {
+        let js = r#"(function(w,d,s,l,i){var j=d.createElement(s);j.src='https://www.googletagmanager.com/gtm.js?id='+i;})(window,document,'script','dataLayer','GTM-1');
+            ga.src = "//www.google-analytics.com/analytics.js"; var note = 'it\'s //not a url'; fetch(`/api/local`);"#;
+        assert_eq!(script_urls(js), vec!["https://www.googletagmanager.com/gtm.js?id=", "//www.google-analytics.com/analytics.js"]);
+    }
+
+    #[test]
+    fn test_strip_version_query_keeps_other_params() {
+        assert_eq!(strip_version_query("/style.css?ver=6.4"), "/style.css");
+        assert_eq!(strip_version_query("/style.css?ver=6.4#x"), "/style.css");
+        assert_eq!(strip_version_query("/photo.jpg?w=300&ver=2&h=200"), "/photo.jpg?w=300&h=200");
+        assert_eq!(strip_version_query("/css?family=Inter&version=2"), "/css?family=Inter&version=2");
+    }
+
+    #[test]
+    fn test_asset_filename_is_stable() {
+        // Pinned values: these must not change between builds or platforms
+        assert_eq!(stable_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(
+            asset_filename("https://example.com/a.css?ver=2", b"", "css", FilenameScheme::UrlHash, true),
+            asset_filename("https://example.com/a.css", b"", "css", FilenameScheme::UrlHash, false)
+        );
+
+        let by_content = |url| asset_filename(url, b".a{color:red}", "css", FilenameScheme::ContentHash, true);
+        assert_eq!(by_content("/one.css"), by_content("/two.css"));
+        assert_eq!(by_content("/one.css"), format!("{:016x}.css", stable_hash(b".a{color:red}")));
+    }
+
+    #[test]
+    fn test_resolve_url_subdirectory_install() {
+        let base = "https://example.com/blog/";
+        assert_eq!(resolve_url(base, "wp-content/themes/a/style.css").unwrap(), "https://example.com/blog/wp-content/themes/a/style.css");
+        assert_eq!(resolve_url("https://example.com/blog/my-post/", "../wp-includes/app.js").unwrap(), "https://example.com/blog/wp-includes/app.js");
+    }
+
+    #[test]
+    fn test_resolve_url_root_and_protocol_relative() {
+        let base = "https://example.com/blog/my-post/";
+        assert_eq!(resolve_url(base, "/wp-content/uploads/a.jpg").unwrap(), "https://example.com/wp-content/uploads/a.jpg");
+        assert_eq!(resolve_url(base, "//cdn.example.net/a.jpg").unwrap(), "https://cdn.example.net/a.jpg");
+        assert_eq!(resolve_url(base, "http://other.com/a.jpg").unwrap(), "http://other.com/a.jpg");
+    }
+
+    #[test]
+    fn test_expand_path_template() {
+        let template = expand_path_template("{cdn}/img/{filename}", &[("cdn", "https://cdn.example.com/")]);
+        assert_eq!(template, "https://cdn.example.com/img/{filename}");
+        assert_eq!(expand_path_template(&template, &[("filename", "a1b2.webp")]), "https://cdn.example.com/img/a1b2.webp");
+    }
+
+    #[test]
+    fn test_normalize_asset_url() {
+        assert_eq!(normalize_asset_url("https://Example.COM/style.css?ver=6.4"), "https://example.com/style.css");
+        assert_eq!(normalize_asset_url("//CDN.example.com/app.js?ver=2#x"), "//cdn.example.com/app.js");
+        assert_eq!(normalize_asset_url("/wp-content/style.css?ver=1"), "/wp-content/style.css");
+        // Size and format parameters pick a different file
+        assert_eq!(normalize_asset_url("https://Example.com/photo.jpg?w=300&ver=2"), "https://example.com/photo.jpg?w=300");
+        assert_eq!(normalize_asset_url("/photo.jpg?fm=webp&w=300"), "/photo.jpg?fm=webp&w=300");
+    }
+
+    #[tokio::test]
+    async fn test_host_throttle_spaces_same_host_only() {
+        let throttle = HostThrottle::new(Duration::from_millis(100));
+        throttle.wait("https://slow.example.com/a.css").await;
+
+        let started = Instant::now();
+        throttle.wait("https://cdn.example.net/b.css").await;
+        assert!(started.elapsed() < Duration::from_millis(100), "other hosts must not wait");
+
+        throttle.wait("https://SLOW.example.com/c.css").await;
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+
+    #[tokio::test]
+    async fn test_download_limiter_caps_concurrent_downloads() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let limiter = DownloadLimiter::new(2);
+        let active = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let tasks = (0..6).map(|_| {
+            let (limiter, active, peak) = (limiter.clone(), active.clone(), peak.clone());
+            tokio::spawn(async move {
+                let _permit = limiter.acquire().await;
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                active.fetch_sub(1, Ordering::SeqCst);
+            })
+        });
+        for task in futures::future::join_all(tasks).await {
+            task.unwrap();
+        }
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+    }
+}
@@ -5,6 +5,11 @@ use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use image::{DynamicImage, ImageFormat, ImageError};
 use std::io::Cursor;
 
+use crate::handlers::OptimizeOptions;
+use crate::image_optimizer::{image_extension, ImgFmt};
+use crate::progress::{report, ProgressSender};
+use crate::url_utils::strip_query;
+
 /// Result of WebP conversion
 #[derive(Debug, Clone)]
 pub struct ConvertedImage {
@@ -20,6 +25,23 @@ pub struct ConvertedImage {
     pub webp_size: usize,
     /// Reduction percentage
     pub reduction_percent: f32,
+    /// Hash-based filename for the kept original (only with `keep_original`)
+    pub original_filename: Option<String>,
+    /// Base64-encoded original bytes (only with `keep_original`)
+    pub original_base64: Option<String>,
+    /// Smaller WebP widths for a generated responsive `<picture>` (only with `responsive_images`)
+    pub variants: Vec<ResponsiveVariant>,
+    /// Lossy quality picked by `quality_mode: "ssim"` (None for the standard encoding)
+    pub quality: Option<u8>,
+}
+
+/// One downscaled WebP of an image, served below a breakpoint
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ResponsiveVariant {
+    pub width: u32,
+    pub filename: String,
+    pub base64: String,
+    pub size: usize,
 }
 
 /// WebP conversion result for API response
@@ -30,6 +52,8 @@ pub struct WebpConversionResult {
     pub total_webp_kb: f32,
     pub total_savings_kb: f32,
     pub average_reduction_percent: f32,
+    /// Images left unconverted because of the `max_images` cap
+    pub skipped_count: usize,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -40,42 +64,35 @@ pub struct ConvertedImageResponse {
     pub original_size: usize,
     pub webp_size: usize,
     pub reduction_percent: f32,
+    pub original_filename: Option<String>,
+    pub original_base64: Option<String>,
+    pub variants: Vec<ResponsiveVariant>,
+    pub quality: Option<u8>,
+}
+
+/// How the WebP encoding quality is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityMode {
+    /// One encoding for every image
+    #[default]
+    Fixed,
+    /// Encode lossy at each of `SSIM_QUALITIES` and keep the lowest quality whose SSIM against
+    /// the original reaches `ssim_threshold` (several encodes per image - CPU-heavy)
+    Ssim,
 }
 
 /// Quality setting for WebP conversion (1-100)
 const WEBP_QUALITY: u8 = 80;
 
-/// Maximum image dimension (resize if larger)
-const MAX_DIMENSION: u32 = 2048;
-
-/// Download an image from a URL
-pub async fn download_image(url: &str) -> Result<Vec<u8>, String> {
-    tracing::debug!("WebP converter: Downloading image from {}", url);
-    
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
-
-    let response = client
-        .get(url)
-        .header("User-Agent", "HTMLWordPress/1.0")
-        .send()
-        .await
-        .map_err(|e| format!("Failed to download image: {}", e))?;
-
-    if !response.status().is_success() {
-        return Err(format!("HTTP {}: {}", response.status(), url));
-    }
+/// Lossy qualities tried by `QualityMode::Ssim`, lowest first
+const SSIM_QUALITIES: [u8; 5] = [50, 60, 70, 80, 90];
 
-    let bytes = response
-        .bytes()
-        .await
-        .map_err(|e| format!("Failed to read image bytes: {}", e))?;
+/// Side of the square windows SSIM is averaged over
+const SSIM_WINDOW: u32 = 8;
 
-    tracing::debug!("WebP converter: Downloaded {} bytes from {}", bytes.len(), url);
-    Ok(bytes.to_vec())
-}
+/// Maximum image dimension (resize if larger)
+const MAX_DIMENSION: u32 = 2048;
 
 /// Convert image bytes to WebP format
 pub fn convert_to_webp(image_data: &[u8], quality: u8, resize: bool) -> Result<Vec<u8>, String> {
@@ -103,6 +120,78 @@ pub fn convert_to_webp(image_data: &[u8], quality: u8, resize: bool) -> Result<V
     Ok(webp_data)
 }
 
+/// Lossy WebP at the lowest `SSIM_QUALITIES` entry whose SSIM against the (resized) original
+/// reaches `threshold`, with the quality used; None when even the highest falls short
+pub fn convert_to_webp_ssim(image_data: &[u8], threshold: f32, resize: bool) -> Result<Option<(Vec<u8>, u8)>, String> {
+    let img = image::load_from_memory(image_data)
+        .map_err(|e| format!("Failed to decode image: {}", e))?;
+    let img = if resize {
+        resize_if_needed(img, MAX_DIMENSION)
+    } else {
+        img
+    };
+    let rgba = img.to_rgba8();
+    let reference = img.to_luma8();
+
+    for quality in SSIM_QUALITIES {
+        let encoded = webp::Encoder::from_rgba(&rgba, rgba.width(), rgba.height()).encode(f32::from(quality));
+        let decoded = webp::Decoder::new(&encoded)
+            .decode()
+            .ok_or_else(|| "Failed to decode lossy WebP".to_string())?;
+        let decoded = if decoded.is_alpha() {
+            image::RgbaImage::from_raw(decoded.width(), decoded.height(), decoded.to_vec()).map(DynamicImage::ImageRgba8)
+        } else {
+            image::RgbImage::from_raw(decoded.width(), decoded.height(), decoded.to_vec()).map(DynamicImage::ImageRgb8)
+        }
+        .ok_or_else(|| "Lossy WebP has unexpected dimensions".to_string())?;
+
+        let score = ssim(&reference, &decoded.to_luma8());
+        tracing::debug!("WebP converter: quality {} -> {} bytes, SSIM {:.4}", quality, encoded.len(), score);
+        if score >= threshold {
+            return Ok(Some((encoded.to_vec(), quality)));
+        }
+    }
+    Ok(None)
+}
+
+/// Mean structural similarity of two same-sized grayscale images (1.0 = identical),
+/// over non-overlapping `SSIM_WINDOW` squares (a smaller image is one window)
+pub fn ssim(a: &image::GrayImage, b: &image::GrayImage) -> f32 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    if a.dimensions() != b.dimensions() || a.width() == 0 || a.height() == 0 {
+        return 0.0;
+    }
+
+    let (width, height) = a.dimensions();
+    let window = |start: u32, len: u32| start..(start + SSIM_WINDOW).min(len);
+    let mut total = 0.0;
+    let mut windows = 0usize;
+    for y0 in (0..height).step_by(SSIM_WINDOW as usize) {
+        for x0 in (0..width).step_by(SSIM_WINDOW as usize) {
+            let pixels: Vec<(f64, f64)> = window(y0, height)
+                .flat_map(|y| window(x0, width).map(move |x| (x, y)))
+                .map(|(x, y)| (f64::from(a.get_pixel(x, y).0[0]), f64::from(b.get_pixel(x, y).0[0])))
+                .collect();
+            let n = pixels.len() as f64;
+            let (mean_a, mean_b) = pixels.iter().fold((0.0, 0.0), |(sa, sb), (pa, pb)| (sa + pa, sb + pb));
+            let (mean_a, mean_b) = (mean_a / n, mean_b / n);
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for (pa, pb) in &pixels {
+                var_a += (pa - mean_a) * (pa - mean_a);
+                var_b += (pb - mean_b) * (pb - mean_b);
+                covariance += (pa - mean_a) * (pb - mean_b);
+            }
+            let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    (total / windows as f64) as f32
+}
+
 /// Resize image if it exceeds max dimension
 fn resize_if_needed(img: DynamicImage, max_dim: u32) -> DynamicImage {
     let (width, height) = (img.width(), img.height());
@@ -115,46 +204,94 @@ fn resize_if_needed(img: DynamicImage, max_dim: u32) -> DynamicImage {
     }
 }
 
-/// Generate a hash-based filename
-fn generate_filename(url: &str, extension: &str) -> String {
-    use std::collections::hash_map::DefaultHasher;
-    use std::hash::{Hash, Hasher};
-    
-    let mut hasher = DefaultHasher::new();
-    url.hash(&mut hasher);
-    let hash = hasher.finish();
-    format!("{:x}.{}", hash, extension)
+/// Generate a hash-based filename for `content` generated from `url`
+fn generate_filename(url: &str, content: &[u8], extension: &str, options: &OptimizeOptions) -> String {
+    crate::url_utils::asset_filename(url, content, extension, options.filename_scheme, options.normalize_asset_urls)
 }
 
 /// Convert a single image from URL to WebP
-pub async fn convert_image_url(url: &str, base_url: &str, resize: bool) -> Result<ConvertedImage, String> {
+/// With `responsive`, also generates the `responsive_breakpoints` widths narrower than the image
+pub async fn convert_image_url(url: &str, base_url: &str, responsive: bool, options: &OptimizeOptions) -> Result<ConvertedImage, String> {
     // Make URL absolute if relative
-    let full_url = if url.starts_with("/") {
-        format!("{}{}", base_url.trim_end_matches('/'), url)
-    } else if url.starts_with("http") {
-        url.to_string()
-    } else {
-        format!("{}/{}", base_url.trim_end_matches('/'), url)
-    };
+    let full_url = crate::url_utils::resolve_url(base_url, url)?;
 
     // Download the image
-    let original_data = download_image(&full_url).await?;
+    let original_data = crate::url_utils::fetch_shared(&full_url, "HTMLWordPress/1.0").await
+        .inspect_err(|_| crate::metrics::record_download_failure("image"))?
+        .body;
+
+    // Encoding (and SSIM's repeated encodes) is CPU-bound: keep it off the async workers
+    let (url, options) = (url.to_string(), options.clone());
+    tokio::task::spawn_blocking(move || {
+        let mut converted = convert_image_data(&url, &original_data, &options)?;
+        if responsive {
+            converted.variants = generate_variants(&url, &original_data, &options);
+        }
+        Ok(converted)
+    })
+    .await
+    .map_err(|e| format!("Image conversion task failed: {}", e))?
+}
+
+/// Downscaled WebP copies at each breakpoint narrower than the image
+/// Empty when the image isn't wider than the smallest breakpoint
+fn generate_variants(url: &str, original_data: &[u8], options: &OptimizeOptions) -> Vec<ResponsiveVariant> {
+    let Ok(img) = image::load_from_memory(original_data) else {
+        return Vec::new();
+    };
+    let (width, height) = (img.width(), img.height());
+
+    let mut breakpoints: Vec<u32> = options.responsive_breakpoints.iter()
+        .copied()
+        .filter(|&breakpoint| breakpoint > 0 && breakpoint < width)
+        .collect();
+    breakpoints.sort_unstable();
+    breakpoints.dedup();
+
+    breakpoints.into_iter()
+        .filter_map(|breakpoint| {
+            let scaled_height = (u64::from(height) * u64::from(breakpoint) / u64::from(width)).max(1) as u32;
+            let resized = img.resize_exact(breakpoint, scaled_height, image::imageops::FilterType::Lanczos3);
+            let mut data = Vec::new();
+            resized.write_to(&mut Cursor::new(&mut data), ImageFormat::WebP)
+                .inspect_err(|e| tracing::warn!("WebP converter: {}w variant of {} failed: {}", breakpoint, url, e))
+                .ok()?;
+            Some(ResponsiveVariant {
+                width: breakpoint,
+                filename: generate_filename(url, &data, &format!("{}w.webp", breakpoint), options),
+                size: data.len(),
+                base64: BASE64.encode(&data),
+            })
+        })
+        .collect()
+}
+
+/// Convert downloaded image bytes, falling back to the original when WebP doesn't save
+/// at least `min_reduction_percent`
+fn convert_image_data(url: &str, original_data: &[u8], options: &OptimizeOptions) -> Result<ConvertedImage, String> {
     let original_size = original_data.len();
+    let original_extension = image_extension(url).map_or("jpg", ImgFmt::extension);
 
-    // Convert to WebP
-    let webp_data = convert_to_webp(&original_data, WEBP_QUALITY, resize)?;
+    // Convert to WebP (SSIM mode falls back to the standard encoding when no quality is good enough)
+    let tuned = match options.quality_mode {
+        QualityMode::Ssim => convert_to_webp_ssim(original_data, options.ssim_threshold, options.resize_images)?,
+        QualityMode::Fixed => None,
+    };
+    let (webp_data, quality) = match tuned {
+        Some((data, quality)) => (data, Some(quality)),
+        None => (convert_to_webp(original_data, WEBP_QUALITY, options.resize_images)?, None),
+    };
     let webp_size = webp_data.len();
 
-    // If WebP is larger (or equal), use ORIGINAL
-    if webp_size >= original_size {
+    // If WebP is larger or barely smaller, use ORIGINAL
+    if !options.is_worthwhile_reduction(original_size, webp_size) {
         tracing::info!(
-            "WebP converter: Skipping conversion for {} - WebP larger ({} -> {}). Using original.",
+            "WebP converter: Skipping conversion for {} - not enough saving ({} -> {}). Using original.",
             url, original_size, webp_size
         );
         
-        let extension = if url.to_lowercase().ends_with(".png") { "png" } else { "jpg" };
-        let filename = generate_filename(url, extension);
-        let base64_data = BASE64.encode(&original_data);
+        let filename = generate_filename(url, original_data, original_extension, options);
+        let base64_data = BASE64.encode(original_data);
 
         return Ok(ConvertedImage {
             original_url: url.to_string(),
@@ -163,6 +300,10 @@ pub async fn convert_image_url(url: &str, base_url: &str, resize: bool) -> Resul
             original_size,
             webp_size: original_size, // Effectively the same
             reduction_percent: 0.0,
+            original_filename: None,
+            original_base64: None,
+            variants: Vec::new(),
+            quality: None,
         });
     }
 
@@ -177,20 +318,41 @@ pub async fn convert_image_url(url: &str, base_url: &str, resize: bool) -> Resul
         original_size, webp_size, reduction
     );
 
+    // The original travels with the WebP so the plugin can serve both
+    let (original_filename, original_base64) = if options.keep_original {
+        (
+            Some(generate_filename(url, original_data, original_extension, options)),
+            Some(BASE64.encode(original_data)),
+        )
+    } else {
+        (None, None)
+    };
+
     Ok(ConvertedImage {
         original_url: url.to_string(),
         webp_base64,
-        filename: generate_filename(url, "webp"),
+        filename: generate_filename(url, &webp_data, "webp", options),
         original_size,
         webp_size,
         reduction_percent: reduction,
+        original_filename,
+        original_base64,
+        variants: Vec::new(),
+        quality,
     })
 }
 
 /// Extract image URLs from HTML and convert them to WebP
-pub async fn convert_images_in_html(html: &str, base_url: &str, resize: bool) -> WebpConversionResult {
+/// Reports an "images" progress event after each attempted conversion
+pub async fn convert_images_in_html(
+    html: &str,
+    base_url: &str,
+    options: &OptimizeOptions,
+    progress: Option<&ProgressSender>,
+) -> WebpConversionResult {
     tracing::info!("WebP converter: Starting image extraction from HTML");
-    
+    let base_url = &crate::url_utils::document_base(html, base_url);
+
     let mut images = Vec::new();
     let mut total_original: usize = 0;
     let mut total_webp: usize = 0;
@@ -200,14 +362,34 @@ pub async fn convert_images_in_html(html: &str, base_url: &str, resize: bool) ->
     
     tracing::debug!("WebP converter: Found {} image URLs", image_urls.len());
 
-    for url in image_urls {
-        // Skip small icons, SVGs, data URLs
-        if should_skip_image(&url) {
-            tracing::debug!("WebP converter: Skipping {}", url);
-            continue;
-        }
+    // Skip small icons, SVGs, data URLs
+    let candidates: Vec<String> = image_urls
+        .into_iter()
+        .filter(|url| {
+            let skip = should_skip_image(url);
+            if skip {
+                tracing::debug!("WebP converter: Skipping {}", url);
+            }
+            !skip
+        })
+        .collect();
 
-        match convert_image_url(&url, base_url, resize).await {
+    // Cap the work per page; earlier images are more likely above the fold
+    let skipped_count = candidates.len().saturating_sub(options.max_images);
+    if skipped_count > 0 {
+        tracing::warn!(
+            "WebP converter: {} images exceed max_images ({}), skipping them",
+            skipped_count, options.max_images
+        );
+    }
+
+    let responsive_urls = if options.responsive_images { responsive_candidates(html) } else { Vec::new() };
+
+    let total = candidates.len().min(options.max_images);
+    for (index, url) in candidates.into_iter().take(options.max_images).enumerate() {
+        let converted = convert_image_url(&url, base_url, responsive_urls.contains(&url), options).await;
+        report(progress, "images", index + 1, total);
+        match converted {
             Ok(converted) => {
                 total_original += converted.original_size;
                 total_webp += converted.webp_size;
@@ -219,6 +401,10 @@ pub async fn convert_images_in_html(html: &str, base_url: &str, resize: bool) ->
                     original_size: converted.original_size,
                     webp_size: converted.webp_size,
                     reduction_percent: converted.reduction_percent,
+                    original_filename: converted.original_filename,
+                    original_base64: converted.original_base64,
+                    variants: converted.variants,
+                    quality: converted.quality,
                 });
             }
             Err(e) => {
@@ -247,104 +433,78 @@ pub async fn convert_images_in_html(html: &str, base_url: &str, resize: bool) ->
         total_webp_kb: total_webp as f32 / 1024.0,
         total_savings_kb: total_savings as f32 / 1024.0,
         average_reduction_percent: avg_reduction,
+        skipped_count,
     }
 }
 
-/// Extract image URLs from HTML (src and srcset)
+/// Attributes lazy-load plugins use to hold the real image URL
+const LAZY_SRC_ATTRIBUTES: [&str; 2] = ["data-src", "data-lazy-src"];
+const LAZY_SRCSET_ATTRIBUTES: [&str; 2] = ["data-srcset", "data-lazy-srcset"];
+/// Attributes holding a srcset on `<source>`/`<img>`, plain first
+const SRCSET_ATTRIBUTES: [&str; 3] = ["srcset", "data-srcset", "data-lazy-srcset"];
+
+/// Extract image URLs from HTML (src/srcset and their lazy-load `data-*` variants)
+/// When a lazy-load attribute holds the real URL, the placeholder `src`/`srcset` is ignored.
+/// `<noscript>` fallbacks are regular markup to the tag scanner, so their images are found too.
+/// `<picture>` elements that already offer a WebP source are left alone.
 fn extract_image_urls(html: &str) -> Vec<String> {
+    use crate::html_utils::{get_attribute, rewrite_start_tags};
+
+    let mut scannable = String::with_capacity(html.len());
+    let mut pos = 0;
+    for range in picture_ranges(html) {
+        if has_webp_source(&html[range.clone()]) {
+            scannable.push_str(&html[pos..range.start]);
+            pos = range.end;
+        }
+    }
+    scannable.push_str(&html[pos..]);
+
     let mut urls = Vec::new();
-    let chars: Vec<char> = html.chars().collect();
-    let len = chars.len();
-    let mut i = 0;
-
-    while i < len {
-        // Look for src="
-        if i + 5 <= len {
-            let tag: String = chars[i..i+5].iter().collect();
-            if tag.to_lowercase() == "src=\"" || tag.to_lowercase() == "src='" {
-                let quote_char = chars[i+4];
-                i += 5;
-                let url_start = i;
-                while i < len && chars[i] != quote_char {
-                    i += 1;
-                }
-                let url: String = chars[url_start..i].iter().collect();
-                if is_image_url(&url) {
-                    urls.push(url);
-                }
-                continue;
+    rewrite_start_tags(&scannable, |_, tag| {
+        let lazy_src: Vec<String> = LAZY_SRC_ATTRIBUTES.iter().filter_map(|attr| get_attribute(tag, attr)).collect();
+        let lazy_srcset: Vec<String> = LAZY_SRCSET_ATTRIBUTES.iter().filter_map(|attr| get_attribute(tag, attr)).collect();
+
+        let src = get_attribute(tag, "src").filter(|_| lazy_src.is_empty());
+        let srcset = get_attribute(tag, "srcset").filter(|_| lazy_srcset.is_empty());
+
+        for url in src.into_iter().chain(lazy_src) {
+            if is_image_url(&url) {
+                urls.push(url);
             }
         }
-
-        // Look for srcset="
-        if i + 8 <= len {
-            let tag: String = chars[i..i+8].iter().collect();
-            if tag.to_lowercase() == "srcset=\"" || tag.to_lowercase() == "srcset='" {
-                let quote_char = chars[i+7];
-                i += 8;
-                let val_start = i;
-                while i < len && chars[i] != quote_char {
-                    i += 1;
-                }
-                let srcset_val: String = chars[val_start..i].iter().collect();
-                // tracing::debug!("WebP converter: Parse srcset: '{}'", srcset_val);
-                
-                // Parse srcset: "url1 1x, url2 2x"
-                for part in srcset_val.split(',') {
-                    let part = part.trim();
-                    if let Some(url_end) = part.find(' ') {
-                        let url = &part[..url_end];
-                        if is_image_url(url) {
-                            // tracing::debug!("WebP converter: Found srcset URL: {}", url);
-                            urls.push(url.to_string());
-                        }
-                    } else if !part.is_empty() {
-                         // Fallback for no descriptor
-                         if is_image_url(part) {
-                            // tracing::debug!("WebP converter: Found srcset URL (no descriptor): {}", part);
-                            urls.push(part.to_string());
-                         }
-                    }
+        for value in srcset.into_iter().chain(lazy_srcset) {
+            for (url, _) in parse_srcset(&value) {
+                if is_image_url(url) {
+                    urls.push(url.to_string());
                 }
-                continue;
             }
         }
-        
-        i += 1;
-    }
+        None
+    });
 
-    // Dedup
-    urls.sort();
-    urls.dedup();
+    // Dedup, keeping document order
+    let mut seen = std::collections::HashSet::new();
+    urls.retain(|url| seen.insert(url.clone()));
     urls
 }
 
-/// Check if URL is an image
+/// Check if URL is an image (query strings like `?ver=2` are ignored)
 fn is_image_url(url: &str) -> bool {
-    let lower = url.to_lowercase();
-    lower.ends_with(".jpg") || 
-    lower.ends_with(".jpeg") || 
-    lower.ends_with(".png") || 
-    lower.ends_with(".gif") ||
-    lower.ends_with(".webp")
+    image_extension(url).is_some_and(|format| format != ImgFmt::Svg)
 }
 
-/// Check if image should be skipped (already WebP, SVG, data URL, etc.)
+/// Check if image should be skipped (already WebP/AVIF, SVG, data URL, etc.)
 fn should_skip_image(url: &str) -> bool {
-    let lower = url.to_lowercase();
+    let lower = strip_query(url).to_lowercase();
     
     // Skip data URLs
     if url.starts_with("data:") {
         return true;
     }
     
-    // Skip already WebP
-    if lower.ends_with(".webp") {
-        return true;
-    }
-    
-    // Skip SVGs
-    if lower.ends_with(".svg") {
+    // Skip already-optimized formats and SVGs
+    if !image_extension(url).is_some_and(ImgFmt::is_convertible) {
         return true;
     }
     
@@ -357,15 +517,320 @@ fn should_skip_image(url: &str) -> bool {
 }
 
 /// Rewrite HTML to use local WebP paths
-pub fn rewrite_html_with_webp(html: &mut String, images: &[ConvertedImageResponse], upload_base_url: &str) {
-    for image in images {
-        let webp_url = format!("{}/images/{}", upload_base_url.trim_end_matches('/'), image.webp_filename);
-        
-        // Replace old URL with new WebP URL
-        *html = html.replace(&image.original_url, &webp_url);
-        
-        tracing::debug!("WebP rewrite: {} -> {}", image.original_url, webp_url);
+/// `path_template` is the image path with only `{filename}` left to substitute
+/// With `keep_original`, `<img>` tags are wrapped in `<picture>` instead of having their URL replaced.
+/// Existing `<picture>` elements keep their sources and `<img>` as fallbacks and gain WebP `<source>`s.
+/// Images with responsive variants become a `<picture>` with one `<source>` per breakpoint.
+pub fn rewrite_html_with_webp(html: &mut String, images: &[ConvertedImageResponse], path_template: &str, keep_original: bool) {
+    if images.iter().any(|image| !image.variants.is_empty()) {
+        let built = build_responsive_pictures(html, images, path_template, keep_original);
+        tracing::debug!("WebP rewrite: {} responsive <picture> elements generated", built);
+    }
+
+    if keep_original {
+        let wrapped = wrap_images_in_picture(html, images, path_template);
+        tracing::debug!("WebP rewrite: {} images wrapped in <picture>", wrapped);
+    }
+
+    // Remaining references (srcsets, images outside a <picture>) are rewritten per attribute
+    let webp_urls: std::collections::HashMap<&str, String> = images.iter()
+        .map(|image| {
+            let webp_url = crate::url_utils::expand_path_template(path_template, &[("filename", &image.webp_filename)]);
+            tracing::debug!("WebP rewrite: {} -> {}", image.original_url, webp_url);
+            (image.original_url.as_str(), webp_url)
+        })
+        .collect();
+
+    // A `<source type="image/webp">` may only offer real WebP output, not an image kept in its
+    // original format because converting it saved too little
+    let webp_sources: std::collections::HashMap<&str, String> = images.iter()
+        .filter(|image| image.webp_filename.ends_with(".webp"))
+        .filter_map(|image| Some((image.original_url.as_str(), webp_urls.get(image.original_url.as_str())?.clone())))
+        .collect();
+
+    let rewrite_tag = |_: &str, tag: &str| {
+        let mut new_tag = tag.to_string();
+        for attr in crate::html_utils::parse_attributes(tag) {
+            let Some(value) = attr.value else { continue };
+            let replacement = match attr.name.as_str() {
+                "srcset" | "imagesrcset" | "data-srcset" | "data-lazy-srcset" => rewrite_srcset(&value, &webp_urls),
+                _ => webp_urls.get(value.as_str()).cloned(),
+            };
+            if let Some(replacement) = replacement {
+                new_tag = crate::html_utils::set_attribute(&new_tag, &attr.name, &replacement);
+            }
+        }
+        (new_tag != tag).then_some(new_tag)
+    };
+
+    let mut result = String::with_capacity(html.len());
+    let mut pos = 0;
+    let mut pictures = 0;
+    for range in picture_ranges(html) {
+        result.push_str(&crate::html_utils::rewrite_start_tags(&html[pos..range.start], &rewrite_tag));
+        let picture = &html[range.clone()];
+        match add_webp_sources(picture, &webp_sources) {
+            Some(rewritten) => {
+                result.push_str(&rewritten);
+                pictures += 1;
+            }
+            None => result.push_str(picture),
+        }
+        pos = range.end;
+    }
+    result.push_str(&crate::html_utils::rewrite_start_tags(&html[pos..], &rewrite_tag));
+    *html = result;
+
+    if pictures > 0 {
+        tracing::debug!("WebP rewrite: {} <picture> elements gained WebP sources", pictures);
+    }
+}
+
+/// Byte ranges of each `<picture>...</picture>` element
+fn picture_ranges(html: &str) -> Vec<std::ops::Range<usize>> {
+    let lower = html.to_ascii_lowercase();
+    let mut ranges = Vec::new();
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find("<picture") {
+        let start = pos + offset;
+        let is_picture = lower[start + 8..].starts_with(|c: char| c == '>' || c.is_ascii_whitespace());
+        if !is_picture {
+            pos = start + 8;
+            continue;
+        }
+        let Some(close) = lower[start..].find("</picture") else {
+            break;
+        };
+        let end = lower[start + close..].find('>').map(|e| start + close + e + 1).unwrap_or(html.len());
+        ranges.push(start..end);
+        pos = end;
+    }
+    ranges
+}
+
+/// Whether a `<picture>` already offers a WebP `<source>`
+fn has_webp_source(picture: &str) -> bool {
+    let mut found = false;
+    crate::html_utils::rewrite_start_tags(picture, |name, tag| {
+        found |= name == "source"
+            && crate::html_utils::get_attribute(tag, "type").is_some_and(|t| t.trim().eq_ignore_ascii_case("image/webp"));
+        None
+    });
+    found
+}
+
+/// Insert a `<source type="image/webp">` before every converted `<source>` and before the `<img>`,
+/// leaving the originals as fallbacks. Pictures that already have a WebP source are skipped.
+/// Returns None when nothing in the picture was converted.
+fn add_webp_sources(picture: &str, webp_urls: &std::collections::HashMap<&str, String>) -> Option<String> {
+    use crate::html_utils::{get_attribute, rewrite_start_tags, set_attribute};
+
+    if has_webp_source(picture) {
+        return None;
+    }
+
+    let mut changed = false;
+    let rewritten = rewrite_start_tags(picture, |name, tag| {
+        let webp_source = match name {
+            "source" => SRCSET_ATTRIBUTES.iter().find_map(|attr| {
+                let srcset = rewrite_srcset(&get_attribute(tag, attr)?, webp_urls)?;
+                Some(set_attribute(&set_attribute(tag, attr, &srcset), "type", "image/webp"))
+            }),
+            "img" => {
+                // Lazy-load attributes first: the plain src/srcset may be a placeholder
+                let from_srcset = SRCSET_ATTRIBUTES.iter().rev().find_map(|attr| {
+                    Some((*attr, rewrite_srcset(&get_attribute(tag, attr)?, webp_urls)?))
+                });
+                let from_src = || {
+                    [("data-lazy-src", "data-lazy-srcset"), ("data-src", "data-srcset"), ("src", "srcset")]
+                        .into_iter()
+                        .find_map(|(src_attr, srcset_attr)| {
+                            Some((srcset_attr, webp_urls.get(get_attribute(tag, src_attr)?.as_str())?.clone()))
+                        })
+                };
+                from_srcset.or_else(from_src).map(|(attr, srcset)| {
+                    let sizes = get_attribute(tag, "sizes")
+                        .map(|sizes| format!(" sizes=\"{}\"", sizes))
+                        .unwrap_or_default();
+                    format!("<source type=\"image/webp\" {}=\"{}\"{}>", attr, srcset, sizes)
+                })
+            }
+            _ => None,
+        }?;
+        changed = true;
+        Some(format!("{}{}", webp_source, tag))
+    });
+
+    changed.then_some(rewritten)
+}
+
+/// `src` URLs of `<img>` tags that could become a generated responsive `<picture>`:
+/// not already in a `<picture>` and without a srcset of their own
+fn responsive_candidates(html: &str) -> Vec<String> {
+    use crate::html_utils::get_attribute;
+
+    let lower = html.to_ascii_lowercase();
+    let mut urls = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = lower[pos..].find("<img") {
+        let start = pos + offset;
+        let Some(end) = crate::html_utils::find_tag_end(html, start) else { break };
+        let tag = &html[start..end];
+        let has_srcset = SRCSET_ATTRIBUTES.iter().any(|attr| get_attribute(tag, attr).is_some());
+        if !has_srcset && !crate::html_utils::is_inside_picture(&lower, start) {
+            if let Some(src) = get_attribute(tag, "src") {
+                urls.push(src);
+            }
+        }
+        pos = end;
     }
+    urls
+}
+
+/// Replace `<img>` tags that have responsive variants with a `<picture>`: a `(max-width)` WebP `<source>`
+/// per breakpoint, the full-size WebP, then the `<img>` itself as fallback
+fn build_responsive_pictures(html: &mut String, images: &[ConvertedImageResponse], path_template: &str, keep_original: bool) -> usize {
+    let expand = |filename: &str| crate::url_utils::expand_path_template(path_template, &[("filename", filename)]);
+    let mut count = 0;
+    let mut result = String::with_capacity(html.len());
+    let lower = html.to_ascii_lowercase();
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find("<img") {
+        let start = pos + offset;
+        let Some(end) = crate::html_utils::find_tag_end(html, start) else { break };
+        result.push_str(&html[pos..start]);
+
+        let tag = &html[start..end];
+        let image = crate::html_utils::get_attribute(tag, "src").and_then(|src| {
+            images.iter().find(|img| img.original_url == src && !img.variants.is_empty())
+        });
+        match image {
+            Some(image) if !crate::html_utils::is_inside_picture(&lower, start) => {
+                result.push_str("<picture>");
+                for variant in &image.variants {
+                    result.push_str(&format!(
+                        "<source type=\"image/webp\" media=\"(max-width: {}px)\" srcset=\"{}\">",
+                        variant.width,
+                        expand(&variant.filename)
+                    ));
+                }
+                if image.webp_filename.ends_with(".webp") {
+                    result.push_str(&format!("<source type=\"image/webp\" srcset=\"{}\">", expand(&image.webp_filename)));
+                }
+                match image.original_filename.as_deref().filter(|_| keep_original) {
+                    Some(original) => result.push_str(&tag.replacen(&image.original_url, &expand(original), 1)),
+                    None => result.push_str(tag),
+                }
+                result.push_str("</picture>");
+                count += 1;
+            }
+            _ => result.push_str(tag),
+        }
+        pos = end;
+    }
+
+    result.push_str(&html[pos..]);
+    *html = result;
+    count
+}
+
+/// Wrap converted `<img>` tags in `<picture>` with a WebP `<source>` and the kept original as fallback
+/// Images already inside a `<picture>` are left to the regular rewrite
+fn wrap_images_in_picture(html: &mut String, images: &[ConvertedImageResponse], path_template: &str) -> usize {
+    let mut count = 0;
+    let mut result = String::with_capacity(html.len() + images.len() * 80);
+    let lower = html.to_ascii_lowercase();
+    let mut pos = 0;
+
+    while let Some(offset) = lower[pos..].find("<img") {
+        let start = pos + offset;
+        let Some(end) = crate::html_utils::find_tag_end(html, start) else { break };
+        result.push_str(&html[pos..start]);
+
+        let tag = &html[start..end];
+        let image = crate::html_utils::get_attribute(tag, "src").and_then(|src| {
+            images.iter().find(|img| img.original_url == src && img.original_filename.is_some())
+        });
+
+        match image {
+            Some(image) if !crate::html_utils::is_inside_picture(&lower, start) => {
+                let webp_url = crate::url_utils::expand_path_template(path_template, &[("filename", &image.webp_filename)]);
+                let original_url = crate::url_utils::expand_path_template(
+                    path_template,
+                    &[("filename", image.original_filename.as_deref().unwrap_or_default())],
+                );
+                result.push_str(&format!(
+                    "<picture><source type=\"image/webp\" srcset=\"{}\">{}</picture>",
+                    webp_url,
+                    tag.replacen(&image.original_url, &original_url, 1)
+                ));
+                count += 1;
+            }
+            _ => result.push_str(tag),
+        }
+        pos = end;
+    }
+
+    result.push_str(&html[pos..]);
+    *html = result;
+    count
+}
+
+/// Split a srcset into `(url, descriptor)` candidates, e.g. `a.jpg 480w, b.jpg 2x`
+/// URLs are whitespace-delimited, so commas inside a URL don't split the candidate
+pub fn parse_srcset(srcset: &str) -> Vec<(&str, Option<&str>)> {
+    let mut candidates = Vec::new();
+    let mut rest = srcset;
+
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        if rest.is_empty() {
+            break;
+        }
+
+        let url_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let url = &rest[..url_end];
+        // A URL directly followed by a comma has no descriptor
+        if let Some(stripped) = url.strip_suffix(',') {
+            candidates.push((stripped.trim_end_matches(','), None));
+            rest = &rest[url_end..];
+            continue;
+        }
+
+        rest = &rest[url_end..];
+        let descriptor_end = rest.find(',').unwrap_or(rest.len());
+        let descriptor = rest[..descriptor_end].trim();
+        candidates.push((url, (!descriptor.is_empty()).then_some(descriptor)));
+        rest = &rest[descriptor_end..];
+    }
+
+    candidates
+}
+
+/// Rebuild a srcset with converted candidates swapped for their WebP URL,
+/// keeping each descriptor. Returns None when nothing was converted.
+fn rewrite_srcset(srcset: &str, webp_urls: &std::collections::HashMap<&str, String>) -> Option<String> {
+    let mut changed = false;
+    let candidates: Vec<String> = parse_srcset(srcset)
+        .into_iter()
+        .map(|(url, descriptor)| {
+            let url = match webp_urls.get(url) {
+                Some(webp_url) => {
+                    changed = true;
+                    webp_url.as_str()
+                }
+                None => url,
+            };
+            match descriptor {
+                Some(descriptor) => format!("{} {}", url, descriptor),
+                None => url.to_string(),
+            }
+        })
+        .collect();
+
+    changed.then(|| candidates.join(", "))
 }
 
 #[cfg(test)]
@@ -381,6 +846,225 @@ mod tests {
         assert!(urls.contains(&"/images/photo-2x.png".to_string()));
     }
 
+    #[test]
+    fn test_image_url_with_query_string() {
+        assert!(is_image_url("/uploads/photo.jpg?ver=2"));
+        assert!(is_image_url("https://example.com/photo.PNG?w=800#top"));
+        assert!(!is_image_url("/script.js?file=photo.jpg"));
+        assert!(should_skip_image("/uploads/logo.svg?ver=1"));
+
+        let urls = extract_image_urls(r#"<img src="/uploads/photo.jpg?ver=2">"#);
+        assert_eq!(urls, vec!["/uploads/photo.jpg?ver=2".to_string()]);
+    }
+
+    #[test]
+    fn test_picture_source_srcsets() {
+        let html = r#"<picture>
+            <source media="(min-width: 800px)" type="image/jpeg" srcset="/uploads/wide.jpg 1x, /uploads/wide-2x.jpg 2x">
+            <source media="(max-width: 799px)" type="image/jpeg" srcset="/uploads/narrow.jpg">
+            <img src="/uploads/narrow.jpg" alt="Hero">
+        </picture>"#;
+        let urls = extract_image_urls(html);
+        assert_eq!(urls.len(), 3, "fallback img duplicating a source must not be converted twice");
+        assert!(urls.contains(&"/uploads/wide.jpg".to_string()));
+        assert!(urls.contains(&"/uploads/wide-2x.jpg".to_string()));
+        assert!(urls.contains(&"/uploads/narrow.jpg".to_string()));
+
+        let images: Vec<ConvertedImageResponse> = urls.iter().map(|url| ConvertedImageResponse {
+            original_url: url.clone(),
+            webp_filename: generate_filename(url, b"", "webp", &OptimizeOptions::default()),
+            webp_base64: String::new(),
+            original_size: 100,
+            webp_size: 50,
+            reduction_percent: 50.0,
+            original_filename: None,
+            original_base64: None,
+            variants: Vec::new(),
+            quality: None,
+        }).collect();
+        let mut rewritten = html.to_string();
+        rewrite_html_with_webp(&mut rewritten, &images, "./images/{filename}", false);
+        // Each JPEG source and the <img> gain a WebP sibling; the originals stay as fallbacks
+        assert_eq!(rewritten.matches("type=\"image/webp\"").count(), 3);
+        assert_eq!(rewritten.matches("type=\"image/jpeg\"").count(), 2);
+        assert!(rewritten.contains(r#"<img src="/uploads/narrow.jpg" alt="Hero">"#));
+    }
+
+    #[test]
+    fn test_picture_gains_webp_source() {
+        let html = r#"<picture><img src="/uploads/hero.jpg" sizes="100vw" alt="Hero"></picture><picture><source type="image/webp" srcset="/uploads/done.webp"><img src="/uploads/done.jpg"></picture>"#;
+        let urls = extract_image_urls(html);
+        assert_eq!(urls, vec!["/uploads/hero.jpg".to_string()], "pictures with a WebP source are skipped");
+
+        let images = vec![ConvertedImageResponse {
+            original_url: "/uploads/hero.jpg".to_string(),
+            webp_filename: "hero.webp".to_string(),
+            webp_base64: String::new(),
+            original_size: 100,
+            webp_size: 50,
+            reduction_percent: 50.0,
+            original_filename: None,
+            original_base64: None,
+            variants: Vec::new(),
+            quality: None,
+        }];
+        let mut rewritten = html.to_string();
+        rewrite_html_with_webp(&mut rewritten, &images, "./images/{filename}", false);
+        assert!(rewritten.starts_with(r#"<picture><source type="image/webp" srcset="./images/hero.webp" sizes="100vw"><img src="/uploads/hero.jpg""#), "{}", rewritten);
+        assert!(rewritten.ends_with(r#"<picture><source type="image/webp" srcset="/uploads/done.webp"><img src="/uploads/done.jpg"></picture>"#));
+    }
+
+    #[test]
+    fn test_srcset_rewrite_keeps_descriptors() {
+        let html = r#"<img src="/uploads/photo.jpg" srcset="/uploads/photo.jpg 480w, /uploads/photo-large.jpg 1200w, /uploads/logo.svg 2x" sizes="(max-width: 600px) 480px, 1200px">"#;
+        let urls = extract_image_urls(html);
+        assert_eq!(urls, vec!["/uploads/photo.jpg".to_string(), "/uploads/photo-large.jpg".to_string()]);
+
+        // Only the shorter URL is converted: it must not clobber the longer one it prefixes
+        let images = vec![ConvertedImageResponse {
+            original_url: "/uploads/photo.jpg".to_string(),
+            webp_filename: "photo.webp".to_string(),
+            webp_base64: String::new(),
+            original_size: 100,
+            webp_size: 50,
+            reduction_percent: 50.0,
+            original_filename: None,
+            original_base64: None,
+            variants: Vec::new(),
+            quality: None,
+        }];
+        let mut rewritten = html.to_string();
+        rewrite_html_with_webp(&mut rewritten, &images, "./images/{filename}", false);
+        assert_eq!(
+            rewritten,
+            r#"<img src="./images/photo.webp" srcset="./images/photo.webp 480w, /uploads/photo-large.jpg 1200w, /uploads/logo.svg 2x" sizes="(max-width: 600px) 480px, 1200px">"#
+        );
+    }
+
+    #[test]
+    fn test_lazy_loaded_images_use_real_url() {
+        let html = concat!(
+            r#"<img src="/wp-content/plugins/lazy/placeholder.gif" data-src="/uploads/real.jpg" "#,
+            r#"data-srcset="/uploads/real.jpg 1x, /uploads/real@2x.jpg 2x" class="lazyload">"#,
+            r#"<noscript><img src="/uploads/real.jpg"></noscript>"#,
+            r#"<img src="/uploads/banner.png" data-lazy-src="/uploads/banner-full.png">"#,
+        );
+        let urls = extract_image_urls(html);
+        assert_eq!(urls, vec![
+            "/uploads/real.jpg".to_string(),
+            "/uploads/real@2x.jpg".to_string(),
+            "/uploads/banner-full.png".to_string(),
+        ]);
+
+        let images: Vec<ConvertedImageResponse> = urls.iter().map(|url| ConvertedImageResponse {
+            original_url: url.clone(),
+            webp_filename: generate_filename(url, b"", "webp", &OptimizeOptions::default()),
+            webp_base64: String::new(),
+            original_size: 100,
+            webp_size: 50,
+            reduction_percent: 50.0,
+            original_filename: None,
+            original_base64: None,
+            variants: Vec::new(),
+            quality: None,
+        }).collect();
+        let mut rewritten = html.to_string();
+        rewrite_html_with_webp(&mut rewritten, &images, "./images/{filename}", false);
+        assert!(rewritten.contains("src=\"/wp-content/plugins/lazy/placeholder.gif\""));
+        assert!(!rewritten.contains("/uploads/real"));
+        assert!(!rewritten.contains("banner-full.png"));
+        assert_eq!(rewritten.matches(".webp 2x").count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_max_images_cap() {
+        let html = (1..=5)
+            .map(|n| format!("<img src=\"/uploads/photo-{}.jpg\">", n))
+            .collect::<String>();
+        let options = OptimizeOptions {
+            max_images: 2,
+            ..OptimizeOptions::default()
+        };
+
+        // Nothing listens on port 1, so the two attempted downloads fail fast
+        let result = convert_images_in_html(&html, "http://127.0.0.1:1/", &options, None).await;
+        assert_eq!(result.skipped_count, 3);
+        assert!(result.images.is_empty());
+
+        let urls = extract_image_urls(&html);
+        assert_eq!(urls[0], "/uploads/photo-1.jpg", "URLs stay in document order");
+    }
+
+    #[test]
+    fn test_keep_original_returns_both_assets() {
+        // A flat-colour PNG compresses far better as lossless WebP
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(256, 256)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let options = OptimizeOptions {
+            keep_original: true,
+            ..OptimizeOptions::default()
+        };
+
+        let converted = convert_image_data("/uploads/flat.png", &png, &options).unwrap();
+        assert!(converted.filename.ends_with(".webp"));
+        let original_filename = converted.original_filename.clone().expect("original kept");
+        assert!(original_filename.ends_with(".png"));
+        assert_eq!(converted.original_base64.as_deref(), Some(BASE64.encode(&png).as_str()));
+
+        let images = vec![ConvertedImageResponse {
+            original_url: converted.original_url,
+            webp_filename: converted.filename.clone(),
+            webp_base64: converted.webp_base64,
+            original_size: converted.original_size,
+            webp_size: converted.webp_size,
+            reduction_percent: converted.reduction_percent,
+            original_filename: converted.original_filename,
+            original_base64: converted.original_base64,
+            variants: Vec::new(),
+            quality: None,
+        }];
+        let mut html = r#"<p><img src="/uploads/flat.png" alt="Flat > round"></p>"#.to_string();
+        rewrite_html_with_webp(&mut html, &images, "./images/{filename}", true);
+        assert!(html.contains(&format!("<picture><source type=\"image/webp\" srcset=\"./images/{}\">", converted.filename)));
+        assert!(html.contains(&format!("<img src=\"./images/{}\" alt=\"Flat > round\"></picture></p>", original_filename)), "{}", html);
+    }
+
+    #[test]
+    fn test_min_reduction_keeps_original_image() {
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(64, 64)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let options = OptimizeOptions {
+            min_reduction_percent: 100.0,
+            ..OptimizeOptions::default()
+        };
+
+        let converted = convert_image_data("/uploads/flat.png", &png, &options).unwrap();
+        assert!(converted.filename.ends_with(".png"));
+        assert_eq!(converted.reduction_percent, 0.0);
+
+        // The kept PNG is never offered as a WebP source
+        let images = vec![ConvertedImageResponse {
+            original_url: converted.original_url,
+            webp_filename: converted.filename,
+            webp_base64: String::new(),
+            original_size: converted.original_size,
+            webp_size: converted.webp_size,
+            reduction_percent: converted.reduction_percent,
+            original_filename: None,
+            original_base64: None,
+            variants: Vec::new(),
+            quality: None,
+        }];
+        let picture = r#"<picture><source media="(min-width: 800px)" srcset="/uploads/flat.png 2x"><img src="/uploads/flat.png" data-src="/uploads/flat.png" alt="Flat"></picture>"#;
+        let mut html = picture.to_string();
+        rewrite_html_with_webp(&mut html, &images, "./images/{filename}", false);
+        assert_eq!(html, picture);
+    }
+
     #[test]
     fn test_should_skip_image() {
         assert!(should_skip_image("data:image/png;base64,..."));
@@ -391,8 +1075,113 @@ mod tests {
 
     #[test]
     fn test_generate_filename() {
-        let filename = generate_filename("/uploads/test.jpg", "webp");
+        let filename = generate_filename("/uploads/test.jpg", b"", "webp", &OptimizeOptions::default());
         assert!(filename.ends_with(".webp"));
         assert!(filename.len() > 10);
     }
+
+    #[test]
+    fn test_responsive_picture_from_single_image() {
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(1200, 600)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let options = OptimizeOptions {
+            responsive_images: true,
+            responsive_breakpoints: vec![768, 480, 1600],
+            ..OptimizeOptions::default()
+        };
+
+        let variants = generate_variants("/uploads/hero.png", &png, &options);
+        let widths: Vec<u32> = variants.iter().map(|v| v.width).collect();
+        assert_eq!(widths, vec![480, 768], "breakpoints at or above the image width are skipped");
+        assert!(variants[0].filename.ends_with(".480w.webp"));
+        let small = image::load_from_memory(&BASE64.decode(&variants[0].base64).unwrap()).unwrap();
+        assert_eq!((small.width(), small.height()), (480, 240));
+
+        let html = r#"<img src="/uploads/hero.png" alt="Hero"><picture><img src="/uploads/hero.png"></picture><img src="/uploads/hero.png" srcset="/uploads/hero.png 1x">"#;
+        assert_eq!(responsive_candidates(html), vec!["/uploads/hero.png".to_string()]);
+
+        let images = vec![ConvertedImageResponse {
+            original_url: "/uploads/hero.png".to_string(),
+            webp_filename: "hero.webp".to_string(),
+            webp_base64: String::new(),
+            original_size: 100,
+            webp_size: 50,
+            reduction_percent: 50.0,
+            original_filename: None,
+            original_base64: None,
+            variants,
+            quality: None,
+        }];
+        let mut html = r#"<p><img src="/uploads/hero.png" alt="Hero"></p>"#.to_string();
+        rewrite_html_with_webp(&mut html, &images, "./{filename}", false);
+        assert_eq!(html, format!(
+            concat!(
+                "<p><picture><source type=\"image/webp\" media=\"(max-width: 480px)\" srcset=\"./{}\">",
+                "<source type=\"image/webp\" media=\"(max-width: 768px)\" srcset=\"./{}\">",
+                "<source type=\"image/webp\" srcset=\"./hero.webp\">",
+                "<img src=\"/uploads/hero.png\" alt=\"Hero\"></picture></p>"
+            ),
+            images[0].variants[0].filename, images[0].variants[1].filename
+        ));
+
+        // Too small for any breakpoint: nothing generated
+        let mut tiny = Vec::new();
+        DynamicImage::new_rgb8(300, 200).write_to(&mut Cursor::new(&mut tiny), ImageFormat::Png).unwrap();
+        assert!(generate_variants("/uploads/tiny.png", &tiny, &options).is_empty());
+    }
+
+    #[test]
+    fn test_ssim_mode_picks_lowest_quality_meeting_threshold() {
+        // Smooth gradients survive lossy encoding well; noise-free so low qualities can pass
+        let gradient = image::RgbImage::from_fn(128, 128, |x, y| image::Rgb([(x * 2) as u8, (y * 2) as u8, 128]));
+        let mut png = Vec::new();
+        DynamicImage::ImageRgb8(gradient.clone())
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let reference = DynamicImage::ImageRgb8(gradient).to_luma8();
+        assert!((ssim(&reference, &reference) - 1.0).abs() < 1e-6);
+
+        let (data, quality) = convert_to_webp_ssim(&png, 0.95, true).unwrap().expect("a quality meets 0.95");
+        assert!(SSIM_QUALITIES.contains(&quality));
+        let decoded = image::load_from_memory(&data).unwrap().to_luma8();
+        assert!(ssim(&reference, &decoded) >= 0.95);
+        // A stricter target never settles for a lower quality
+        if let Some((_, strict)) = convert_to_webp_ssim(&png, 0.995, true).unwrap() {
+            assert!(strict >= quality);
+        }
+
+        let options = OptimizeOptions {
+            quality_mode: QualityMode::Ssim,
+            min_reduction_percent: 0.0,
+            ..OptimizeOptions::default()
+        };
+        let converted = convert_image_data("/uploads/gradient.png", &png, &options).unwrap();
+        assert_eq!(converted.quality, Some(quality));
+        assert_eq!(convert_image_data("/uploads/gradient.png", &png, &OptimizeOptions::default()).unwrap().quality, None);
+    }
+
+    #[tokio::test]
+    async fn test_relative_images_resolve_against_base_href() {
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(256, 256)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = axum::Router::new()
+            .route("/blog/img/photo.png", axum::routing::get(move || async move { png }));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let page_url = format!("http://{}/2024/01/post/", addr);
+        let html = r#"<html><head><base href="/blog/"></head><body><img src="img/photo.png"></body></html>"#;
+        assert_eq!(crate::url_utils::document_base(html, &page_url), format!("http://{}/blog/", addr));
+        assert_eq!(crate::url_utils::document_base("<p>No base</p>", &page_url), page_url);
+
+        let result = convert_images_in_html(html, &page_url, &OptimizeOptions::default(), None).await;
+        assert_eq!(result.images.len(), 1, "img/photo.png must be fetched from /blog/, not the post's directory");
+        assert_eq!(result.images[0].original_url, "img/photo.png");
+    }
 }